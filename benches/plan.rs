@@ -0,0 +1,46 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use renovate::{testing::generate_sql, SqlLoader};
+
+const SIZES: [usize; 3] = [10, 100, 1000];
+
+fn load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("load");
+    for tables in SIZES {
+        let sql = generate_sql(tables, 2);
+        group.bench_with_input(BenchmarkId::from_parameter(tables), &sql, |b, sql| {
+            b.iter(|| SqlLoader::new(black_box(sql.as_str())).parse().unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn plan(c: &mut Criterion) {
+    let mut group = c.benchmark_group("plan");
+    for tables in SIZES {
+        // every table gets a new index, and the last 10% of the tables are new
+        let remote = SqlLoader::new(generate_sql(tables - tables / 10, 2))
+            .parse()
+            .unwrap();
+        let local = SqlLoader::new(generate_sql(tables, 3)).parse().unwrap();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(tables),
+            &(local, remote),
+            |b, (local, remote)| b.iter(|| local.plan(black_box(remote), false).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn unchanged(c: &mut Criterion) {
+    let mut group = c.benchmark_group("unchanged");
+    for tables in SIZES {
+        let schema = SqlLoader::new(generate_sql(tables, 2)).parse().unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(tables), &schema, |b, schema| {
+            b.iter(|| schema.plan(black_box(schema), false).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, load, plan, unchanged);
+criterion_main!(benches);
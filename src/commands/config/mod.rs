@@ -0,0 +1,22 @@
+mod_pub_use!(schema);
+
+use super::{Args, CommandExecutor};
+use clap_utils::prelude::*;
+
+#[derive(Parser, Debug, Clone)]
+pub struct ActionConfigCommand {
+    #[clap(subcommand)]
+    pub cmd: Config,
+}
+
+#[async_trait]
+impl CommandExecutor for ActionConfigCommand {
+    async fn execute(&self, args: &Args) -> Result<(), Error> {
+        self.cmd.execute(args).await
+    }
+}
+
+subcmd!(
+    Config,
+    [Schema = "print the JSON Schema of renovate.yml for editor validation"]
+);
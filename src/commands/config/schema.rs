@@ -0,0 +1,14 @@
+use super::{Args, CommandExecutor};
+use crate::RenovateConfig;
+use clap_utils::prelude::*;
+
+#[derive(Parser, Debug, Clone)]
+pub struct ConfigSchemaCommand {}
+
+#[async_trait]
+impl CommandExecutor for ConfigSchemaCommand {
+    async fn execute(&self, _args: &Args) -> Result<(), Error> {
+        println!("{}", RenovateConfig::json_schema());
+        Ok(())
+    }
+}
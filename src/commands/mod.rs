@@ -1,13 +1,20 @@
+mod config;
 mod generate;
+mod progress;
+mod prompt;
 mod schema;
 
 use clap_utils::prelude::*;
+use config::*;
 use generate::*;
+use progress::ProgressObserver;
+use prompt::Prompt;
 use schema::*;
+use tracing_subscriber::{filter::Targets, prelude::*};
 
 /// Dispatch and execute the command. Make sure to add the new command enum into the enum_dispatch macro below.
 #[async_trait]
-#[enum_dispatch(Action, Generate, Schema)] // <- [new group] put the new group enum here
+#[enum_dispatch(Action, Config, Generate, Schema)] // <- [new group] put the new group enum here
 pub trait CommandExecutor {
     async fn execute(&self, args: &Args) -> Result<(), Error>;
 }
@@ -24,10 +31,83 @@ pub struct Args {
     /// drop database on exit (for testing purpose only)
     #[clap(long, global = true, value_parser, default_value = "false")]
     pub drop_on_exit: bool,
+
+    /// environment defined in renovate.yml to use
+    #[clap(long, global = true, value_parser)]
+    pub env: Option<String>,
+
+    /// database defined under `databases` in renovate.yml to use
+    #[clap(long, global = true, value_parser)]
+    pub db: Option<String>,
+
+    /// parse the local schema without the cache
+    #[clap(long, global = true, action)]
+    pub no_cache: bool,
+
+    /// skip the git dirty check and auto commits
+    #[clap(long, global = true, action)]
+    pub no_git: bool,
+
+    /// never wait for input, fail the confirmations unless --yes
+    #[clap(long, global = true, action)]
+    pub no_input: bool,
+
+    /// answer yes to all the confirmations
+    #[clap(short, long, global = true, action)]
+    pub yes: bool,
+
+    /// log format (text or json)
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value = "text",
+        hide_possible_values = true
+    )]
+    pub log_format: LogFormat,
+
+    /// print debug logs
+    #[clap(short, long, global = true, action)]
+    pub verbose: bool,
+}
+
+/// Format of the diagnostic logs written to stderr
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl Args {
+    /// Install the tracing subscriber. Logs go to stderr so that they don't mix with the output.
+    pub fn init_tracing(&self) {
+        let level = if self.verbose {
+            tracing::Level::DEBUG
+        } else {
+            tracing::Level::WARN
+        };
+        // server notices are logged by sqlx with the `log` crate at info level, which `init`
+        // bridges with a `LogTracer` capped at the max level below, let them through so that
+        // apply could capture them
+        let filter = Targets::new()
+            .with_default(level)
+            .with_target(crate::NOTICE_TARGET, tracing::Level::INFO);
+        let builder = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_writer(std::io::stderr);
+        match self.log_format {
+            LogFormat::Text => builder.finish().with(filter).init(),
+            LogFormat::Json => builder.json().finish().with(filter).init(),
+        }
+    }
 }
 
 subcmd!(
     Action,
     // [new group] add the new command enum here
-    [Generate = "generate something", Schema = "Schema migration"]
+    [
+        Config = "renovate.yml utilities",
+        Generate = "generate something",
+        Schema = "Schema migration"
+    ]
 );
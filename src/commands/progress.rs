@@ -0,0 +1,77 @@
+use crate::{ChangeKind, ConsoleObserver, Observer};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::{collections::BTreeMap, sync::Mutex};
+
+/// Observer used by the cli commands: diffs and notices are printed like ConsoleObserver does,
+/// while the objects fetched and the statements applied are shown as progress bars. The bars
+/// are hidden if stderr is not a terminal.
+#[derive(Default)]
+pub(crate) struct ProgressObserver {
+    /// spinner and the number of objects fetched by type
+    fetch: Mutex<Option<(ProgressBar, BTreeMap<String, usize>)>>,
+    /// progress bar of the statements applied, with the elapsed time of the whole apply
+    apply: Mutex<Option<ProgressBar>>,
+}
+
+impl ProgressObserver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop the fetch spinner and leave the final object counts on the screen.
+    pub fn finish_fetch(&self) {
+        if let Some((bar, counts)) = self.fetch.lock().unwrap().take() {
+            bar.finish_with_message(format!("Fetched {}", summary(&counts)));
+        }
+    }
+}
+
+impl Observer for ProgressObserver {
+    fn on_object_fetched(&self, type_name: &str, _id: &str) {
+        let mut fetch = self.fetch.lock().unwrap();
+        let (bar, counts) =
+            fetch.get_or_insert_with(|| (ProgressBar::new_spinner(), BTreeMap::new()));
+        *counts.entry(type_name.to_owned()).or_default() += 1;
+        bar.set_message(format!("Fetching {}", summary(counts)));
+        bar.tick();
+    }
+
+    fn on_diff_computed(&self, type_name: &str, id: &str, kind: ChangeKind, diff: &str) {
+        ConsoleObserver.on_diff_computed(type_name, id, kind, diff)
+    }
+
+    fn on_statement_applied(&self, sql: &str, index: usize, total: usize) {
+        self.finish_fetch();
+        let mut apply = self.apply.lock().unwrap();
+        let bar = apply.get_or_insert_with(|| {
+            let bar = ProgressBar::new(total as u64);
+            let style =
+                ProgressStyle::with_template("[{elapsed_precise}] {bar:40} {pos}/{len} {msg}")
+                    .expect("should be a valid template");
+            bar.set_style(style);
+            bar
+        });
+        bar.set_message(sql.lines().next().unwrap_or_default().to_owned());
+        bar.set_position(index as u64);
+        if index == total {
+            bar.finish();
+            *apply = None;
+        }
+    }
+
+    fn on_notice(&self, sql: &str, notice: &str) {
+        match &*self.apply.lock().unwrap() {
+            Some(bar) => bar.println(format!("{}\n  while applying: {}", notice, sql)),
+            None => ConsoleObserver.on_notice(sql, notice),
+        }
+    }
+}
+
+/// e.g. `3 function(s), 12 table(s)`
+fn summary(counts: &BTreeMap<String, usize>) -> String {
+    counts
+        .iter()
+        .map(|(type_name, count)| format!("{} {}(s)", count, type_name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,60 @@
+use super::Args;
+use clap_utils::{
+    dialoguer::{theme::ColorfulTheme, Confirm},
+    prelude::*,
+};
+use std::env;
+
+/// Answer the confirmations of the commands. `--yes` or `RENOVATE_ASSUME_YES=1` accepts all of
+/// them; without those they fail instead of waiting for input if `--no-input` is set or the
+/// terminal is not interactive, e.g. in CI.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Prompt {
+    assume_yes: bool,
+    interactive: bool,
+}
+
+impl Prompt {
+    pub fn new(args: &Args) -> Self {
+        let assume_yes = args.yes
+            || env::var("RENOVATE_ASSUME_YES")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false);
+        let interactive =
+            !args.no_input && atty::is(atty::Stream::Stdin) && atty::is(atty::Stream::Stderr);
+        Self {
+            assume_yes,
+            interactive,
+        }
+    }
+
+    pub fn confirm(&self, prompt: &str) -> Result<bool, Error> {
+        if self.assume_yes {
+            eprintln!("{} yes", prompt.trim());
+            return Ok(true);
+        }
+        self.ask(prompt)
+    }
+
+    /// Confirm dropping populated objects. `--yes` doesn't answer it, it is skipped by
+    /// `--force-data-loss` only.
+    pub fn confirm_data_loss(&self, prompt: &str) -> Result<bool, Error> {
+        if self.interactive {
+            self.ask(prompt)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn ask(&self, prompt: &str) -> Result<bool, Error> {
+        if !self.interactive {
+            bail!(
+                "{} Confirmation is required, but input is not available. Use --yes or RENOVATE_ASSUME_YES=1 to confirm.",
+                prompt.trim()
+            );
+        }
+        Ok(Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .interact()?)
+    }
+}
@@ -1,46 +1,201 @@
-use super::{generate_plan, git_commit, git_dirty, Args, CommandExecutor};
-use crate::{utils::load_config, DatabaseRepo};
-use clap_utils::{
-    dialoguer::{theme::ColorfulTheme, Confirm},
-    prelude::*,
+use super::{
+    env_name,
+    fleet::{apply_fleet, ALL_ENVS},
+    generate_plan, print_plan, vcs, Args, CommandExecutor, ProgressObserver, Prompt, ScopeArgs,
 };
+use crate::{
+    plan::split_phases, utils::load_apply_config, ApplyFailed, ApplyReport, Renovate, SavedPlan,
+};
+use clap_utils::prelude::*;
+use console::Style;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::fs;
 
 #[derive(Parser, Debug, Clone)]
 pub struct SchemaApplyCommand {
     #[clap(long, value_parser, default_value = "false")]
     remote: bool,
+    /// drop populated tables and columns without asking for an extra confirmation
+    #[clap(long, action)]
+    force_data_loss: bool,
+    /// apply a plan saved by `plan --out` instead of planning again. Refused if the target
+    /// database changed since then
+    #[clap(long, value_parser)]
+    plan: Option<PathBuf>,
+    /// plan again if the target database of the saved plan has changed
+    #[clap(long, action, requires = "plan")]
+    replan: bool,
+    /// apply the plan to a temporary clone of the target database instead, and report how each
+    /// statement went
+    #[clap(long, action)]
+    dry_run: bool,
+    /// number of databases planned and applied at the same time with `--env all`
+    #[clap(short, long, value_parser, default_value = "4")]
+    jobs: usize,
+    /// apply the plan up to the given phase only. With `expand`, the objects and columns dropped
+    /// for good and the revoked privileges are deferred until `--resume`, e.g. once the
+    /// application no longer using them is deployed
+    #[clap(long, value_enum)]
+    stop_after: Option<Phase>,
+    /// apply the contract phase deferred by `--stop-after expand`
+    #[clap(long, action, conflicts_with_all = &["stop_after", "plan", "dry_run"])]
+    resume: bool,
+    #[clap(flatten)]
+    scope: ScopeArgs,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// the changes the running application is compatible with
+    Expand,
 }
 
 #[async_trait]
 impl CommandExecutor for SchemaApplyCommand {
-    async fn execute(&self, _args: &Args) -> Result<(), Error> {
-        let plan = generate_plan(self.remote).await?;
+    async fn execute(&self, args: &Args) -> Result<(), Error> {
+        if args.env.as_deref() == Some(ALL_ENVS) {
+            if self.plan.is_some()
+                || self.dry_run
+                || self.stop_after.is_some()
+                || self.resume
+                || self.scope.detect_renames
+            {
+                bail!("--plan, --dry-run, --stop-after, --resume and --detect-renames apply to a single database, they can't be used with --env all.");
+            }
+            return apply_fleet(
+                args,
+                self.remote,
+                self.force_data_loss,
+                self.jobs,
+                &self.scope,
+            )
+            .await;
+        }
+        let config = load_apply_config(args).await?;
+        let progress = Arc::new(ProgressObserver::new());
+        let renovate = Renovate::new(config.clone()).observer(progress.clone());
+        let checkpoint = if self.dry_run {
+            None
+        } else {
+            renovate.checkpoint(self.remote).await?
+        };
+        match (&checkpoint, self.resume) {
+            (Some(checkpoint), false) => bail!(
+                "The contract phase of the apply at {} is not applied yet. Use --resume to apply it first.",
+                checkpoint.created_at
+            ),
+            (None, true) => bail!("There is no contract phase to resume."),
+            _ => {}
+        }
+        // the contract phase is applied as recorded instead of planned again, so that the local
+        // changes made since the expand phase don't slip into it
+        let plan = match (&self.plan, checkpoint) {
+            (_, Some(checkpoint)) => {
+                print_plan(renovate.config(), &checkpoint.statements, &[])?;
+                checkpoint.statements
+            }
+            (Some(path), None) => self.load_plan(&renovate, path, args).await?,
+            (None, None) => generate_plan(args, self.remote, &self.scope).await?,
+        };
+        progress.finish_fetch();
+        let (plan, deferred) = match self.stop_after {
+            Some(Phase::Expand) => split_phases(&plan),
+            None => (plan, vec![]),
+        };
+        if !deferred.is_empty() {
+            println!("\nThe following statement(s) of the contract phase are deferred to `--resume`:\n");
+            for sql in &deferred {
+                println!("  {}", sql);
+            }
+        }
         if plan.is_empty() {
+            if !deferred.is_empty() && !self.dry_run {
+                renovate.save_checkpoint(self.remote, &deferred).await?;
+                println!("\nThe expand phase is empty. Run `apply --resume` to apply the contract phase.");
+            }
             return Ok(());
         }
-        let config = load_config().await?;
-        let db_repo = DatabaseRepo::new(&config);
+        if self.dry_run {
+            return dry_run(&renovate, &plan, self.remote).await;
+        }
+        let vcs = vcs(args, &config);
+        let prompt = Prompt::new(args);
 
-        if git_dirty()? {
-            if confirm("\nYour repo is dirty. Do you want to commit it first?") {
-                git_commit("automatically commit the schema changes before applying the plan")?;
+        if vcs.is_dirty()? {
+            if prompt.confirm("\nYour repo is dirty. Do you want to commit it first?")? {
+                vcs.commit("automatically commit the schema changes before applying the plan")?;
             } else {
                 bail!("Your repo is dirty. Please commit the changes before applying.");
             }
         }
 
-        if confirm("Do you want to perform this update?") {
-            db_repo.apply(plan, self.remote).await?;
-            git_commit("automatically commit the changes applied to remote server")?;
+        if !self.force_data_loss {
+            let at_risk = renovate.data_at_risk(&plan, self.remote).await?;
+            if !at_risk.is_empty() {
+                println!("\nThe plan drops data from the following populated objects:\n");
+                for (dropped, rows) in &at_risk {
+                    println!("  {} (~{} rows)", dropped, rows);
+                }
+                println!();
+                if !prompt.confirm_data_loss("Do you really want to lose this data?")? {
+                    bail!("Data loss not confirmed. Use --force-data-loss to skip this check.");
+                }
+            }
+        }
+
+        if prompt.confirm("Do you want to perform this update?")? {
             let url = if self.remote {
                 &config.remote_url
             } else {
                 &config.url
             };
+            let result = match deferred.is_empty() {
+                true => renovate.apply(plan, self.remote).await,
+                // the local repo is fetched once the contract phase is applied
+                false => renovate.apply_expand(plan, self.remote).await,
+            };
+            let report = match result {
+                Ok(report) => report,
+                Err(e) => {
+                    // the statements committed before the failure are archived too
+                    if let (Some(path), Some(failed)) =
+                        (&config.apply.report, e.downcast_ref::<ApplyFailed>())
+                    {
+                        write_report(path, &failed.report).await?;
+                    }
+                    return Err(e);
+                }
+            };
+            print_slow_statements(&report);
+            if !deferred.is_empty() {
+                renovate.save_checkpoint(self.remote, &deferred).await?;
+            } else if self.resume {
+                renovate.clear_checkpoint(self.remote).await?;
+            }
+            if let Some(path) = &config.apply.report {
+                write_report(path, &report).await?;
+            }
+            vcs.commit(&config.git.apply_message(
+                "automatically commit the changes applied to remote server",
+                &[&report],
+            ))?;
+            // the apply is done by then, so it's not failed over the bookkeeping
+            if let Err(e) = renovate
+                .record_sync(env_name(args), self.remote, vcs.as_ref())
+                .await
+            {
+                eprintln!("Failed to record the sync of the local repo: {:#}", e);
+            }
             println!(
                 "Successfully applied migration to {}.\nYour repo is updated with the latest schema. See `git diff HEAD~1` for details.",
                 url
             );
+            if !deferred.is_empty() {
+                println!("Run `apply --resume` to apply the contract phase once the application is deployed.");
+            }
         } else {
             println!("Database schema update has been cancelled.");
         }
@@ -49,9 +204,100 @@ impl CommandExecutor for SchemaApplyCommand {
     }
 }
 
-pub(crate) fn confirm(prompt: &'static str) -> bool {
-    Confirm::with_theme(&ColorfulTheme::default())
-        .with_prompt(prompt)
-        .interact()
-        .expect("confirm UI should work")
+impl SchemaApplyCommand {
+    /// the statements of the saved plan, or a new plan if the target changed and `--replan` is set
+    async fn load_plan(
+        &self,
+        renovate: &Renovate,
+        path: &Path,
+        args: &Args,
+    ) -> Result<Vec<String>, Error> {
+        let content = fs::read_to_string(path).await?;
+        let saved: SavedPlan = serde_json::from_str(&content)?;
+        if saved.remote != self.remote {
+            let target = |remote| if remote { "remote" } else { "local" };
+            bail!(
+                "The plan targets the {} database, but the {} one is to be applied.",
+                target(saved.remote),
+                target(self.remote)
+            );
+        }
+
+        if renovate.verify_plan(&saved).await? {
+            print_plan(renovate.config(), &saved.statements, &[])?;
+            Ok(saved.statements)
+        } else if self.replan {
+            println!("The target database has changed since the plan was made, planning again.\n");
+            Ok(generate_plan(args, self.remote, &self.scope).await?)
+        } else {
+            bail!(
+                "The target database has changed since the plan was made. Plan again, or use --replan."
+            );
+        }
+    }
+}
+
+/// Write the report of an apply, failed or not, to `path`
+pub(super) async fn write_report(path: &Path, report: &ApplyReport) -> Result<(), Error> {
+    fs::write(path, serde_json::to_string_pretty(report)?).await?;
+    println!("The apply report is written to {}.", path.display());
+    Ok(())
+}
+
+/// List the statements slower than `apply.slow_statement_ms`, in yellow, or in red if they took
+/// 10 times as long.
+fn print_slow_statements(report: &ApplyReport) {
+    let slow: Vec<_> = report.statements.iter().filter(|s| s.slow).collect();
+    if slow.is_empty() {
+        return;
+    }
+    println!(
+        "\n{} statement(s) took longer than {}ms:\n",
+        slow.len(),
+        report.slow_statement_ms
+    );
+    for statement in slow {
+        let style = if statement.elapsed_ms > report.slow_statement_ms * 10 {
+            Style::new().red()
+        } else {
+            Style::new().yellow()
+        };
+        println!(
+            "  {}  {}",
+            style.apply_to(format!("{:>8}ms", statement.elapsed_ms)),
+            statement.sql.lines().next().unwrap_or_default()
+        );
+    }
+    println!();
+}
+
+async fn dry_run(renovate: &Renovate, plan: &[String], remote: bool) -> Result<(), Error> {
+    let report = renovate.dry_run(plan, remote).await?;
+    println!("\nDry run on a clone of the target database:\n");
+    for statement in &report {
+        let status = if statement.error.is_some() {
+            "FAILED"
+        } else {
+            "ok"
+        };
+        println!(
+            "  {:<6} {:>6}ms  {}",
+            status,
+            statement.elapsed.as_millis(),
+            statement.sql
+        );
+        if let Some(error) = &statement.error {
+            println!("         {}", error);
+        }
+    }
+
+    if report.iter().any(|s| s.error.is_some()) {
+        bail!(
+            "The dry run failed, {} of {} statement(s) applied.",
+            report.len() - 1,
+            plan.len()
+        );
+    }
+    println!("\nAll {} statement(s) applied to the clone, which is dropped.", plan.len());
+    Ok(())
 }
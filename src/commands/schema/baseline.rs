@@ -0,0 +1,44 @@
+use super::{vcs, Args, CommandExecutor, Prompt};
+use crate::{repo::load_migrations, utils::load_config, DatabaseRepo};
+use clap_utils::prelude::*;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug, Clone)]
+pub struct SchemaBaselineCommand {
+    /// directory of flyway (`V1__init.sql`) or golang-migrate (`1_init.up.sql`) migrations
+    #[clap(long, value_parser)]
+    pub from_migrations: PathBuf,
+}
+
+#[async_trait]
+impl CommandExecutor for SchemaBaselineCommand {
+    async fn execute(&self, args: &Args) -> Result<(), Error> {
+        let config = load_config(args).await?;
+        let (count, sql) = load_migrations(&self.from_migrations).await?;
+        if count == 0 {
+            bail!("no migrations found in {}", self.from_migrations.display());
+        }
+
+        if Prompt::new(args).confirm("This will overwrite the local schema files. Continue?")? {
+            let vcs = vcs(args, &config);
+            vcs.commit("commit schema changes before baseline")?;
+
+            // replay the migrations in a temp database so that every ALTER is applied
+            let repo = DatabaseRepo::new(&config);
+            let schema = repo.normalize(&sql).await?;
+            repo.save_schema(&schema).await?;
+
+            vcs.commit(&format!(
+                "baseline schema from {}",
+                self.from_migrations.display()
+            ))?;
+            println!(
+                "{} migrations from {} have been replayed into {}.",
+                count,
+                self.from_migrations.display(),
+                config.output.path.display()
+            );
+        }
+        Ok(())
+    }
+}
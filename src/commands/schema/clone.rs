@@ -0,0 +1,32 @@
+use super::{init::parse_url, use_cache, Args, CommandExecutor};
+use crate::{utils::load_config, DatabaseSchema, Renovate};
+use clap_utils::prelude::*;
+use url::Url;
+
+#[derive(Parser, Debug, Clone)]
+pub struct SchemaCloneCommand {
+    /// url of the empty database to create the schema in, e.g. for a review app
+    #[clap(long, value_parser = parse_url)]
+    pub to: Url,
+}
+
+#[async_trait]
+impl CommandExecutor for SchemaCloneCommand {
+    async fn execute(&self, args: &Args) -> Result<(), Error> {
+        let config = load_config(args).await?;
+        let renovate = Renovate::new(config).cache(use_cache(args));
+        let local = renovate.normalize_local().await?;
+        let report = renovate.clone_to(&local, self.to.as_str()).await?;
+
+        println!(
+            "Cloned the schema to {} with {} statement(s) in {}ms:\n",
+            report.target,
+            report.statements.len(),
+            report.elapsed_ms
+        );
+        for (type_name, ids) in local.missing_in(&DatabaseSchema::default()) {
+            println!("  {} ({})", type_name, ids.len());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+use super::{use_cache, Args, CommandExecutor};
+use crate::{deps::DependencyGraph, parser::SchemaId, utils::load_config, Renovate};
+use clap_utils::prelude::*;
+
+#[derive(Parser, Debug, Clone)]
+pub struct SchemaDepsCommand {
+    /// the table, view, function, sequence or type, e.g. `public.users`
+    #[clap(value_parser)]
+    pub name: String,
+    /// print the objects depending on it instead, i.e. what breaks if it's dropped or changed
+    #[clap(long, action)]
+    pub reverse: bool,
+    /// use the schema of the remote database instead of the local repo
+    #[clap(long, action)]
+    pub remote: bool,
+}
+
+#[async_trait]
+impl CommandExecutor for SchemaDepsCommand {
+    async fn execute(&self, args: &Args) -> Result<(), Error> {
+        let config = load_config(args).await?;
+        let renovate = Renovate::new(config.clone()).cache(use_cache(args));
+        let schema = if self.remote {
+            renovate.load_database(true).await?
+        } else {
+            renovate.load_local().await?
+        };
+
+        let default_schema = config.diff.default_schema();
+        let name = match self.name.split_once('.') {
+            Some((schema, name)) => SchemaId::new(schema, name),
+            None => SchemaId::new(default_schema, &self.name),
+        };
+        let graph = DependencyGraph::new(&schema, default_schema);
+        let node = match graph.find(&name.to_string()) {
+            Some(node) => node,
+            None => bail!("{} is not a table, view, function, sequence or type", name),
+        };
+        print!("{}", graph.tree(node, self.reverse));
+        Ok(())
+    }
+}
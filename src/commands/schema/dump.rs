@@ -0,0 +1,38 @@
+use super::{use_cache, Args, CommandExecutor};
+use crate::{utils::load_config, Renovate};
+use clap_utils::prelude::*;
+
+#[derive(Parser, Debug, Clone)]
+pub struct SchemaDumpCommand {
+    /// output format of the dumped schema
+    #[clap(long, value_enum, default_value = "json")]
+    pub format: DumpFormat,
+    /// dump the schema of the remote database instead of the local repo
+    #[clap(long, action)]
+    pub remote: bool,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    Json,
+    Sql,
+}
+
+#[async_trait]
+impl CommandExecutor for SchemaDumpCommand {
+    async fn execute(&self, args: &Args) -> Result<(), Error> {
+        let config = load_config(args).await?;
+        let renovate = Renovate::new(config).cache(use_cache(args));
+        let schema = if self.remote {
+            renovate.load_database(true).await?
+        } else {
+            renovate.load_local().await?
+        };
+
+        match self.format {
+            DumpFormat::Json => println!("{}", serde_json::to_string_pretty(&schema)?),
+            DumpFormat::Sql => println!("{}", schema.sql(true)),
+        }
+        Ok(())
+    }
+}
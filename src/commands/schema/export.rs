@@ -0,0 +1,37 @@
+use super::{use_cache, Args, CommandExecutor};
+use crate::{utils::load_config, Renovate};
+use clap_utils::prelude::*;
+
+#[derive(Parser, Debug, Clone)]
+pub struct SchemaExportCommand {
+    /// format of the exported catalog
+    #[clap(long, value_enum, default_value = "dbml")]
+    pub format: ExportFormat,
+    /// export the schema of the remote database instead of the local repo
+    #[clap(long, action)]
+    pub remote: bool,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// tables and relationships for dbdiagram.io
+    Dbml,
+}
+
+#[async_trait]
+impl CommandExecutor for SchemaExportCommand {
+    async fn execute(&self, args: &Args) -> Result<(), Error> {
+        let config = load_config(args).await?;
+        let renovate = Renovate::new(config).cache(use_cache(args));
+        let schema = if self.remote {
+            renovate.load_database(true).await?
+        } else {
+            renovate.load_local().await?
+        };
+
+        match self.format {
+            ExportFormat::Dbml => print!("{}", schema.to_dbml_with(&renovate.table_stats()?)),
+        }
+        Ok(())
+    }
+}
@@ -1,20 +1,198 @@
-use super::{confirm, git_commit, Args, CommandExecutor};
-use crate::{utils::load_config, DatabaseRepo};
+use super::{env_name, print_diagnostics, vcs, Args, CommandExecutor, Prompt, ProgressObserver};
+use crate::{
+    repo::sync_base,
+    schema::Drift,
+    utils::{load_config, render_diff},
+    ChangeKind, DatabaseRepo, DatabaseSchema, LocalRepo, MemoryStore, OverlayStore, Renovate,
+    RenovateConfig, SchemaLoader, SqlLoader,
+};
 use clap_utils::prelude::*;
 
 #[derive(Parser, Debug, Clone)]
-pub struct SchemaFetchCommand {}
+pub struct SchemaFetchCommand {
+    /// number of concurrent pg_dump processes, the schemas are split among them
+    #[clap(short, long, value_parser, default_value = "1")]
+    pub jobs: usize,
+    /// commit the fetched schema to a new branch (e.g. `schema-sync/2023-01-01`) for review,
+    /// instead of the current one
+    #[clap(long, value_parser)]
+    pub branch: Option<String>,
+    /// print the files which would be created, modified or deleted, without writing them
+    #[clap(long, action, conflicts_with = "branch")]
+    pub dry_run: bool,
+    /// walk through the objects changed on the remote one by one, and only take in the accepted
+    /// ones
+    #[clap(short, long, action, conflicts_with = "dry_run")]
+    pub interactive: bool,
+}
 
 #[async_trait]
 impl CommandExecutor for SchemaFetchCommand {
-    async fn execute(&self, _args: &Args) -> Result<(), Error> {
-        let config = load_config().await?;
-        let repo = DatabaseRepo::new(&config);
+    async fn execute(&self, args: &Args) -> Result<(), Error> {
+        let config = load_config(args).await?;
+        let repo = DatabaseRepo::new(&config).with_jobs(self.jobs);
+        if self.dry_run {
+            return preview(&repo, &config).await;
+        }
 
-        if confirm("This will overwrite the local schema files. Continue?") {
-            git_commit("commit schema changes before fetching")?;
-            repo.fetch().await?;
+        let vcs = vcs(args, &config);
+        if let Some(branch) = &self.branch {
+            vcs.check_branch(branch)?;
+        }
+        if Prompt::new(args).confirm("This will overwrite the local schema files. Continue?")? {
+            let base = match &self.branch {
+                // the drift alone should be on the branch, so local changes aren't committed there
+                Some(_) if vcs.is_dirty()? => {
+                    bail!("The local schema has uncommitted changes. Commit or stash them before fetching to a branch.")
+                }
+                Some(_) => None,
+                None => {
+                    let base = sync_base(&config.output.path, env_name(args), vcs.as_ref())?;
+                    vcs.commit("commit schema changes before fetching")?;
+                    base
+                }
+            };
+            let (schema, mut diagnostics) = repo.load_with_diagnostics(false).await?;
+            let (schema, kept) = repo.keep_local_unparsable(schema).await?;
+            diagnostics.extend(kept);
+            print_diagnostics(&diagnostics, args)?;
+            let progress = ProgressObserver::new();
+            let updated = match (base, self.interactive) {
+                (_, true) => {
+                    let schema = accept_drift(&repo, schema, &config, &Prompt::new(args)).await?;
+                    schema.notify_fetched(&progress);
+                    progress.finish_fetch();
+                    repo.save_schema(&schema).await?
+                }
+                (Some(base), false) => {
+                    // the synced files are layered on the current base like the local repo
+                    let base = match &config.output.base {
+                        Some(dir) => {
+                            OverlayStore::new(LocalRepo::new(dir), MemoryStore::from(base))
+                                .load()
+                                .await?
+                        }
+                        None => MemoryStore::from(base).load().await?,
+                    };
+                    let local = repo.load_local().await?;
+                    let merge = local.merge(&base, &schema)?;
+                    merge.schema.notify_fetched(&progress);
+                    progress.finish_fetch();
+                    let updated = repo.save_merge(&merge).await?;
+                    if !merge.conflicts.is_empty() {
+                        print_conflicts(&merge.conflicts);
+                        return Ok(());
+                    }
+                    updated
+                }
+                (None, false) => {
+                    schema.notify_fetched(&progress);
+                    progress.finish_fetch();
+                    repo.save_schema(&schema).await?
+                }
+            };
+            match updated {
+                0 => println!("Local schema is up to date."),
+                1 => println!("1 file updated."),
+                n => println!("{} files updated.", n),
+            }
+            match &self.branch {
+                Some(branch) if updated > 0 => {
+                    vcs.branch(branch)?;
+                    vcs.commit("sync the schema changed out of band on the remote database")?;
+                    println!(
+                        "The changes are committed to branch {}. Push it for review: git push -u origin {}",
+                        branch, branch
+                    );
+                }
+                Some(_) => {}
+                // committed so that the next fetch merges the local changes made since from it
+                None => vcs.commit("sync the schema fetched from the database")?,
+            }
+            Renovate::new(config.clone())
+                .record_sync(env_name(args), false, vcs.as_ref())
+                .await?;
         }
         Ok(())
     }
 }
+
+/// Tell which objects are changed differently in the local schema and on the remote, and left
+/// between conflict markers.
+fn print_conflicts(conflicts: &[Drift]) {
+    if conflicts.is_empty() {
+        return;
+    }
+    println!(
+        "{} object(s) are changed both locally and on the remote:",
+        conflicts.len()
+    );
+    for conflict in conflicts {
+        println!("  {} {}", conflict.type_name, conflict.id);
+    }
+    println!("The local schema files don't load until the conflict markers are resolved by hand, so plan, apply, status and fetch fail until then.");
+}
+
+async fn preview(repo: &DatabaseRepo, config: &RenovateConfig) -> Result<(), Error> {
+    let schema = repo.load().await?;
+    let changes = repo.preview_save(&schema).await?;
+    if changes.is_empty() {
+        println!("Local schema is up to date.");
+        return Ok(());
+    }
+    for change in &changes {
+        let action = match change.kind {
+            ChangeKind::Added => "created",
+            ChangeKind::Removed => "deleted",
+            ChangeKind::Changed => "modified",
+        };
+        println!("{} would be {}:\n", change.path.display(), action);
+        println!(
+            "{}",
+            render_diff(&change.old, &change.new, &config.diff.render)?
+        );
+    }
+    println!("{} file(s) would be changed. Nothing is written.", changes.len());
+    Ok(())
+}
+
+/// Ask for every object which differs between the remote schema and the local repo, and return
+/// the local schema with the accepted differences taken in.
+async fn accept_drift(
+    repo: &DatabaseRepo,
+    remote: DatabaseSchema,
+    config: &RenovateConfig,
+    prompt: &Prompt,
+) -> Result<DatabaseSchema, Error> {
+    // normalized like the remote one, so that only the actual differences are asked for
+    let local = repo.normalize(&repo.load_local().await?.sql(true)).await?;
+    let drift = local.drift(&remote);
+    let format: sqlformat::FormatOptions = config.output.format.unwrap_or_default().into();
+    let mut accepted = Vec::new();
+    for (i, d) in drift.iter().enumerate() {
+        let action = match d.kind {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Changed => "changed",
+        };
+        println!(
+            "[{}/{}] {} {} is {} on the remote:\n",
+            i + 1,
+            drift.len(),
+            d.type_name,
+            d.id,
+            action
+        );
+        let local_sql = sqlformat::format(&d.local, &Default::default(), format);
+        let remote_sql = sqlformat::format(&d.remote, &Default::default(), format);
+        println!(
+            "{}",
+            render_diff(&local_sql, &remote_sql, &config.diff.render)?
+        );
+        if prompt.confirm("Accept this change into the local schema?")? {
+            accepted.push(d.clone());
+        }
+    }
+    println!("{} of {} change(s) accepted.", accepted.len(), drift.len());
+    Ok(SqlLoader::new(local.accept_drift(&accepted)).load().await?)
+}
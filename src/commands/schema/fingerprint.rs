@@ -0,0 +1,49 @@
+use super::{env_name, Args, CommandExecutor};
+use crate::{utils::load_config, Renovate};
+use clap_utils::prelude::*;
+
+#[derive(Parser, Debug, Clone)]
+pub struct SchemaFingerprintCommand {
+    /// hash the local database instead of the remote one
+    #[clap(long, action)]
+    pub local: bool,
+    /// fail if the overall hash differs from this one, e.g. the output of the last run
+    #[clap(long, value_parser)]
+    pub expect: Option<String>,
+    /// fail if the catalog changed since the last fetch or apply of the environment, hashing the
+    /// database it synced with
+    #[clap(long, action, conflicts_with_all = &["local", "expect"])]
+    pub since_sync: bool,
+}
+
+#[async_trait]
+impl CommandExecutor for SchemaFingerprintCommand {
+    async fn execute(&self, args: &Args) -> Result<(), Error> {
+        let config = load_config(args).await?;
+        let renovate = Renovate::new(config);
+        let (remote, expect) = match self.since_sync {
+            true => match renovate.last_sync(env_name(args))? {
+                Some(state) if state.fingerprint.is_some() => (state.remote, state.fingerprint),
+                Some(_) => bail!(
+                    "The last sync of {} has no catalog fingerprint.",
+                    env_name(args)
+                ),
+                None => bail!("No fetch or apply of {} is recorded yet.", env_name(args)),
+            },
+            false => (!self.local, self.expect.clone()),
+        };
+        let fingerprint = renovate.catalog_fingerprint(remote).await?;
+
+        println!("{}", fingerprint.overall);
+        for (schema, hash) in &fingerprint.schemas {
+            println!("  {:<32} {}", schema, hash);
+        }
+        match &expect {
+            Some(expected) if expected != &fingerprint.overall => bail!(
+                "The catalog changed since {}. Run `renovate schema plan` to see the drift.",
+                expected
+            ),
+            _ => Ok(()),
+        }
+    }
+}
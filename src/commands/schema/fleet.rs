@@ -0,0 +1,257 @@
+use super::{apply::write_report, print_plan, vcs, Args, Prompt, ScopeArgs};
+use crate::{utils::load_db_config, ApplyFailed, ApplyReport, Renovate};
+use clap_utils::prelude::*;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::task::JoinSet;
+
+/// `--env` value to apply to every environment defined in renovate.yml
+pub(super) const ALL_ENVS: &str = "all";
+
+/// Plan and apply against every environment, e.g. the shard / tenant databases sharing the same
+/// schema. At most `jobs` databases are worked on at the same time, and a failed database
+/// doesn't stop the others; the result of each one is printed at the end. Like a single apply,
+/// the local repo must be clean first, the report of each database is written next to
+/// `apply.report`, and the changes are committed once all of them are applied.
+pub(super) async fn apply_fleet(
+    args: &Args,
+    remote: bool,
+    force_data_loss: bool,
+    jobs: usize,
+    scope: &ScopeArgs,
+) -> Result<(), Error> {
+    let config = load_db_config(args.db.as_deref()).await?;
+    if config.environments.is_empty() {
+        bail!("No environments are defined in renovate.yml.");
+    }
+    let vcs = vcs(args, &config);
+    let prompt = Prompt::new(args);
+    if vcs.is_dirty()? {
+        if prompt.confirm("\nYour repo is dirty. Do you want to commit it first?")? {
+            vcs.commit("automatically commit the schema changes before applying the plan")?;
+        } else {
+            bail!("Your repo is dirty. Please commit the changes before applying.");
+        }
+    }
+
+    let mut targets = Vec::new();
+    for env in config.environments.keys() {
+        let mut env_config = config.clone().for_env(env)?.for_apply();
+        env_config.resolve_credentials().await?;
+        env_config.resolve_search_path().await;
+        targets.push((
+            env.clone(),
+            Arc::new(scope.apply(Renovate::new(env_config), args)),
+        ));
+    }
+    let renovates: BTreeMap<_, _> = targets.iter().cloned().collect();
+
+    let plans = for_each_env(jobs, targets, |renovate| async move {
+        renovate.plan(remote).await
+    })
+    .await?;
+
+    let mut results = BTreeMap::new();
+    let mut pending = Vec::new();
+    for (env, plan) in plans {
+        match plan {
+            Ok(plan) if plan.is_empty() => {
+                results.insert(env, Ok("up to date".to_owned()));
+            }
+            Ok(plan) => {
+                println!("-- {}", env);
+                print_plan(&config, &plan, &[])?;
+                println!();
+                pending.push((env.clone(), (env.clone(), renovates[&env].clone(), plan)));
+            }
+            Err(e) => {
+                results.insert(env, Err(format!("plan failed: {:#}", e)));
+            }
+        }
+    }
+
+    let question = format!(
+        "Do you want to apply the plans to {} database(s)?",
+        pending.len()
+    );
+    if !pending.is_empty() {
+        if !prompt.confirm(&question)? {
+            println!("Database schema update has been cancelled.");
+            return Ok(());
+        }
+        let applied = for_each_env(jobs, pending, |(env, renovate, plan)| async move {
+            if !force_data_loss {
+                let at_risk = renovate.data_at_risk(&plan, remote).await?;
+                if !at_risk.is_empty() {
+                    bail!(
+                        "the plan drops data from {} populated object(s), use --force-data-loss to apply it",
+                        at_risk.len()
+                    );
+                }
+            }
+            let result = renovate.apply(plan, remote).await;
+            if let Some(path) = &renovate.config().apply.report {
+                let path = env_report_path(path, &env);
+                let report = match &result {
+                    Ok(report) => Some(report),
+                    // the statements committed before the failure are archived too
+                    Err(e) => e.downcast_ref::<ApplyFailed>().map(|f| &f.report),
+                };
+                if let Some(report) = report {
+                    write_report(&path, report).await?;
+                }
+            }
+            result
+        })
+        .await?;
+
+        // one commit for the whole fleet, the databases share the local repo
+        let done: Vec<_> = applied
+            .iter()
+            .filter_map(|(env, r)| r.as_ref().ok().map(|r| (env, r)))
+            .collect();
+        if !done.is_empty() {
+            let reports: Vec<_> = done.iter().map(|(_, r)| *r).collect();
+            vcs.commit(&config.git.apply_message(
+                &format!(
+                    "automatically commit the changes applied to {} database(s)",
+                    done.len()
+                ),
+                &reports,
+            ))?;
+            for (env, _) in &done {
+                // the apply is done by then, so it's not failed over the bookkeeping
+                if let Err(e) = renovates[*env].record_sync(env, remote, vcs.as_ref()).await {
+                    eprintln!("{}: failed to record the sync: {:#}", env, e);
+                }
+            }
+        }
+
+        for (env, report) in applied {
+            let result = report
+                .map(|r: ApplyReport| {
+                    let slow = r.statements.iter().filter(|s| s.slow).count();
+                    let mut message = format!(
+                        "applied {} statement(s) in {}ms",
+                        r.statements.len(),
+                        r.elapsed_ms
+                    );
+                    if slow > 0 {
+                        message.push_str(&format!(", {} slow", slow));
+                    }
+                    message
+                })
+                .map_err(|e| format!("apply failed: {:#}", e));
+            results.insert(env, result);
+        }
+    }
+
+    let width = results
+        .keys()
+        .map(|env| env.len())
+        .max()
+        .unwrap_or_default();
+    println!();
+    for (env, result) in &results {
+        match result {
+            Ok(message) => println!("  {:<width$}  ok      {}", env, message, width = width),
+            Err(message) => println!("  {:<width$}  FAILED  {}", env, message, width = width),
+        }
+    }
+    let failed = results.values().filter(|r| r.is_err()).count();
+    if failed > 0 {
+        bail!("{} of {} database(s) failed.", failed, results.len());
+    }
+    Ok(())
+}
+
+/// `apply.report` of the given environment, e.g. `report.json` becomes `report.shard-1.json`, so
+/// that the databases applied at the same time don't overwrite each other's report
+fn env_report_path(path: &Path, env: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, env, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, env),
+    };
+    path.with_file_name(name)
+}
+
+/// run `f` for every environment, at most `jobs` of them at the same time
+async fn for_each_env<I, T, F, Fut>(
+    jobs: usize,
+    targets: Vec<(String, I)>,
+    f: F,
+) -> Result<BTreeMap<String, anyhow::Result<T>>, Error>
+where
+    F: Fn(I) -> Fut,
+    Fut: Future<Output = anyhow::Result<T>> + Send + 'static,
+    T: Send + 'static,
+{
+    let mut results = BTreeMap::new();
+    let mut set = JoinSet::new();
+    for (env, input) in targets {
+        if set.len() >= jobs.max(1) {
+            if let Some(joined) = set.join_next().await {
+                let (env, result) = joined?;
+                results.insert(env, result);
+            }
+        }
+        let task = f(input);
+        set.spawn(async move { (env, task.await) });
+    }
+    while let Some(joined) = set.join_next().await {
+        let (env, result) = joined?;
+        results.insert(env, result);
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn report_path_should_be_per_env() {
+        assert_eq!(
+            env_report_path(Path::new("reports/apply.json"), "shard-1"),
+            Path::new("reports/apply.shard-1.json")
+        );
+        assert_eq!(
+            env_report_path(Path::new("apply-report"), "eu"),
+            Path::new("apply-report.eu")
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_env_should_not_stop_the_others() -> Result<(), Error> {
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let targets = (0..5).map(|i| (format!("env{}", i), i)).collect();
+        let results = for_each_env(2, targets, |i| {
+            let running = running.clone();
+            let peak = peak.clone();
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::task::yield_now().await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                match i {
+                    2 => anyhow::bail!("connection refused"),
+                    _ => Ok(i * 10),
+                }
+            }
+        })
+        .await?;
+
+        assert_eq!(results.len(), 5);
+        assert!(results["env2"].is_err());
+        assert_eq!(results["env4"].as_ref().unwrap(), &40);
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+        Ok(())
+    }
+}
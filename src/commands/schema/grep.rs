@@ -0,0 +1,47 @@
+use super::{use_cache, Args, CommandExecutor};
+use crate::{utils::load_config, Renovate};
+use clap_utils::prelude::*;
+
+#[derive(Parser, Debug, Clone)]
+pub struct SchemaGrepCommand {
+    /// identifier or sql text to search for, case-insensitively. An identifier, e.g. `user_id`,
+    /// only matches whole identifiers
+    #[clap(value_parser)]
+    pub pattern: String,
+    /// search the schema of the remote database instead of the local repo
+    #[clap(long, action)]
+    pub remote: bool,
+}
+
+#[async_trait]
+impl CommandExecutor for SchemaGrepCommand {
+    async fn execute(&self, args: &Args) -> Result<(), Error> {
+        let config = load_config(args).await?;
+        let renovate = Renovate::new(config.clone()).cache(use_cache(args));
+        let schema = if self.remote {
+            renovate.load_database(true).await?
+        } else {
+            renovate.load_local().await?
+        };
+
+        let matches = schema.grep(&self.pattern, &config.output);
+        if matches.is_empty() {
+            println!("No objects match `{}`.", self.pattern);
+            return Ok(());
+        }
+        for m in &matches {
+            match &m.file {
+                Some(file) => println!(
+                    "{} {} ({})",
+                    m.type_name,
+                    m.id,
+                    config.output.path.join(file).display()
+                ),
+                None => println!("{} {}", m.type_name, m.id),
+            }
+            println!("    {}", m.excerpt);
+        }
+        println!("\n{} object(s) match `{}`.", matches.len(), self.pattern);
+        Ok(())
+    }
+}
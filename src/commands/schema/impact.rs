@@ -0,0 +1,55 @@
+use super::{use_cache, vcs, Args, CommandExecutor};
+use crate::{utils::load_config, ChangeKind, Renovate};
+use clap_utils::prelude::*;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug, Clone)]
+pub struct SchemaImpactCommand {
+    /// the changed sql file of the local repo, e.g. `schemas/public/04_tables.sql`
+    #[clap(long, value_parser)]
+    pub file: PathBuf,
+    /// the commit the file changed since, e.g. `origin/main`
+    #[clap(long, value_parser, default_value = "HEAD")]
+    pub since: String,
+}
+
+#[async_trait]
+impl CommandExecutor for SchemaImpactCommand {
+    async fn execute(&self, args: &Args) -> Result<(), Error> {
+        let config = load_config(args).await?;
+        let vcs = vcs(args, &config);
+        let impact = Renovate::new(config)
+            .cache(use_cache(args))
+            .impact(&self.file, &self.since, vcs.as_ref())
+            .await?;
+        if impact.is_empty() {
+            println!(
+                "{} changes no objects since {}.",
+                self.file.display(),
+                self.since
+            );
+            return Ok(());
+        }
+
+        for (kind, tree) in &impact {
+            let kind = match kind {
+                ChangeKind::Added => "added",
+                ChangeKind::Changed => "changed",
+                ChangeKind::Removed => "removed",
+            };
+            println!("{} {}", kind, tree.node);
+            for child in &tree.children {
+                for line in child.to_string().lines() {
+                    println!("    {}", line);
+                }
+            }
+        }
+        let affected: usize = impact.iter().map(|(_, tree)| tree.children.len()).sum();
+        println!(
+            "\n{} object(s) changed, affecting {} object(s) directly.",
+            impact.len(),
+            affected
+        );
+        Ok(())
+    }
+}
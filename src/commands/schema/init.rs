@@ -1,4 +1,4 @@
-use super::{git_commit, Args, CommandExecutor};
+use super::{vcs, Args, CommandExecutor};
 use crate::{DatabaseRepo, RenovateConfig};
 use clap_utils::prelude::*;
 use std::{env::set_current_dir, fs, path::PathBuf};
@@ -12,7 +12,7 @@ pub struct SchemaInitCommand {
 
 #[async_trait]
 impl CommandExecutor for SchemaInitCommand {
-    async fn execute(&self, _args: &Args) -> Result<(), Error> {
+    async fn execute(&self, args: &Args) -> Result<(), Error> {
         let path = PathBuf::from(format!(".{}", self.url.path()));
         if path.exists() && path.read_dir()?.next().is_some() {
             bail!("directory {} already exists and not empty", path.display());
@@ -29,7 +29,7 @@ impl CommandExecutor for SchemaInitCommand {
 
         db_repo.fetch().await?;
 
-        git_commit(format!("init schema migration repo for {}", self.url))?;
+        vcs(args, &config).commit(&format!("init schema migration repo for {}", self.url))?;
 
         println!(
             "Database schema for {} has successfully dumped into {}.",
@@ -40,7 +40,7 @@ impl CommandExecutor for SchemaInitCommand {
     }
 }
 
-fn parse_url(s: &str) -> Result<Url, Error> {
+pub(super) fn parse_url(s: &str) -> Result<Url, Error> {
     let url = Url::parse(s)?;
     if url.scheme() != "postgres" {
         bail!("only postgres url is supported");
@@ -52,7 +52,7 @@ fn parse_url(s: &str) -> Result<Url, Error> {
 }
 
 #[allow(dead_code)]
-async fn fetch_and_save(url: &Url) -> Result<()> {
+async fn fetch_and_save(args: &Args, url: &Url) -> Result<()> {
     let config = RenovateConfig::new(url.clone());
     config.save("renovate.yml").await?;
 
@@ -61,6 +61,6 @@ async fn fetch_and_save(url: &Url) -> Result<()> {
 
     db_repo.fetch().await?;
 
-    git_commit(format!("init schema migration repo for {}", url))?;
+    vcs(args, &config).commit(&format!("init schema migration repo for {}", url))?;
     Ok(())
 }
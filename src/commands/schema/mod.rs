@@ -1,6 +1,24 @@
-mod_pub_use!(apply, fetch, init, normalize, plan);
+mod_pub_use!(
+    apply,
+    baseline,
+    clone,
+    deps,
+    dump,
+    export,
+    fetch,
+    fingerprint,
+    grep,
+    impact,
+    init,
+    normalize,
+    plan,
+    status,
+    tag
+);
+mod fleet;
 
-use super::{Args, CommandExecutor};
+use super::{Args, CommandExecutor, LogFormat, ProgressObserver, Prompt};
+use crate::{repo::DEFAULT_ENV, Diagnostic, ObjectKind, Renovate, RenovateConfig, Vcs};
 use clap_utils::prelude::*;
 
 #[derive(Parser, Debug, Clone)]
@@ -20,39 +38,101 @@ subcmd!(
     Schema,
     [
         Apply = "apply the migration plan to the remote database server",
+        Baseline = "write the local schema replayed from an existing migrations directory",
+        Clone = "create the local schema, without any data, in another empty database",
+        Deps = "print the objects an object depends on, or the ones depending on it",
+        Dump = "dump the parsed schema for external tools",
+        Export = "export the tables and their relationships, e.g. as DBML for dbdiagram.io",
         Fetch = "fetch the most recent schema from the remote database server",
+        Fingerprint = "hash the catalog of the remote database to detect drift cheaply",
+        Grep = "search the local repo or the remote database for a name or sql",
+        Impact = "list the objects a change of a local file touches, and their dependents",
         Init = "init a database migration repo",
         Normalize = "normalize local schema via a temp local database",
-        Plan = "diff the local change and remote state, then make a migration plan"
+        Plan = "diff the local change and remote state, then make a migration plan",
+        Status = "list the objects only in the remote database or only in the local repo",
+        Tag = "record the local schema as of the current commit under a name"
     ]
 );
 
+/// `--only` / `--skip` / `--schema` of plan and apply, to roll out some of the objects
+/// separately, and `--detect-renames`
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct ScopeArgs {
+    /// plan only these kinds of objects, e.g. `tables,indexes,privileges`
+    #[clap(long, value_parser, value_delimiter = ',')]
+    pub only: Vec<ObjectKind>,
+    /// don't plan these kinds of objects, e.g. `functions`
+    #[clap(long, value_parser, value_delimiter = ',')]
+    pub skip: Vec<ObjectKind>,
+    /// plan only the objects in these schemas, e.g. `billing`
+    #[clap(long, value_parser, value_delimiter = ',')]
+    pub schema: Vec<String>,
+    /// ask whether the tables dropped while a table with the same definition is created under
+    /// another name are renamed instead
+    #[clap(long, action)]
+    pub detect_renames: bool,
+}
+
+impl ScopeArgs {
+    fn apply(&self, renovate: Renovate, args: &Args) -> Renovate {
+        let renovate = if self.detect_renames {
+            let prompt = Prompt::new(args);
+            renovate.detect_renames(move |old, new| {
+                let question = format!(
+                    "Table {} looks renamed to {}. Rename it instead of dropping it?",
+                    old, new
+                );
+                prompt.confirm(&question).unwrap_or(false)
+            })
+        } else {
+            renovate
+        };
+        let renovate = if self.only.is_empty() && self.skip.is_empty() {
+            renovate
+        } else {
+            renovate.kinds(&self.only, &self.skip)
+        };
+        if self.schema.is_empty() {
+            renovate
+        } else {
+            renovate.schemas(&self.schema)
+        }
+    }
+}
+
 #[cfg(feature = "cli-test")]
-fn git_commit(_msg: impl AsRef<str>) -> Result<()> {
-    Ok(())
+fn vcs(_args: &Args, _config: &RenovateConfig) -> Box<dyn Vcs> {
+    Box::new(crate::NoopVcs)
 }
 
 #[cfg(not(feature = "cli-test"))]
-fn git_commit(msg: impl AsRef<str>) -> Result<()> {
-    use crate::GitRepo;
-    let repo = if std::path::Path::new(".git").exists() {
-        GitRepo::open(".")?
+fn vcs(args: &Args, config: &RenovateConfig) -> Box<dyn Vcs> {
+    if args.no_git || !config.git.enabled {
+        Box::new(crate::NoopVcs)
     } else {
-        GitRepo::init(".")?
-    };
-    if repo.is_dirty() {
-        repo.commit(msg)?;
+        config.vcs.open(&config.output.path)
     }
+}
 
-    Ok(())
+/// the environment selected by `--env`, the last sync is recorded under
+fn env_name(args: &Args) -> &str {
+    args.env.as_deref().unwrap_or(DEFAULT_ENV)
 }
-#[cfg(feature = "cli-test")]
-fn git_dirty() -> Result<bool> {
-    Ok(false)
+
+/// the cli tests use throwaway databases, so never reuse the parsed schema there
+fn use_cache(args: &Args) -> bool {
+    !cfg!(feature = "cli-test") && !args.no_cache
 }
 
-#[cfg(not(feature = "cli-test"))]
-fn git_dirty() -> Result<bool> {
-    let repo = crate::GitRepo::open(".")?;
-    Ok(repo.is_dirty())
+/// Print the warnings collected while loading, fetching and planning to stderr, one json object
+/// per line with `--log-format json`
+fn print_diagnostics(diagnostics: &[Diagnostic], args: &Args) -> Result<(), Error> {
+    for diagnostic in diagnostics {
+        match args.log_format {
+            LogFormat::Text => eprintln!("{}", diagnostic),
+            LogFormat::Json => eprintln!("{}", serde_json::to_string(diagnostic)?),
+        }
+    }
+    Ok(())
 }
@@ -1,4 +1,4 @@
-use super::{git_commit, Args, CommandExecutor};
+use super::{vcs, Args, CommandExecutor};
 use crate::{utils::load_config, DatabaseRepo, LocalRepo, SchemaLoader, SqlSaver};
 use clap_utils::prelude::*;
 
@@ -7,10 +7,14 @@ pub struct SchemaNormalizeCommand {}
 
 #[async_trait]
 impl CommandExecutor for SchemaNormalizeCommand {
-    async fn execute(&self, _args: &Args) -> Result<(), Error> {
-        let config = load_config().await?;
+    async fn execute(&self, args: &Args) -> Result<(), Error> {
+        let config = load_config(args).await?;
+        if config.output.base.is_some() {
+            bail!("normalize would write the base schema into the overlay directory, it isn't supported with output.base yet.");
+        }
+        let vcs = vcs(args, &config);
 
-        git_commit("commit schema changes before nomalization")?;
+        vcs.commit("commit schema changes before nomalization")?;
 
         let local_repo = LocalRepo::new(&config.output.path);
         let schema = local_repo.load().await?;
@@ -20,7 +24,7 @@ impl CommandExecutor for SchemaNormalizeCommand {
         let schema = repo.normalize(&sql).await?;
         schema.save(&config.output).await?;
 
-        git_commit("commit schema changes after nomalization")?;
+        vcs.commit("commit schema changes after nomalization")?;
 
         Ok(())
     }
@@ -1,43 +1,209 @@
-use super::{Args, CommandExecutor};
-use crate::{utils::load_config, DatabaseRepo, LocalRepo, SchemaLoader, SqlLoader};
+use super::{print_diagnostics, use_cache, vcs, Args, CommandExecutor, ScopeArgs};
+use crate::{
+    plan::{table_rewrite, table_size, ChangeCollector},
+    repo::load_stats,
+    utils::load_config,
+    DmlEstimate, Plan, Renovate, RenovateConfig,
+};
 use clap_utils::{highlight_text, prelude::*};
+use itertools::Itertools;
+use std::{path::PathBuf, sync::Arc};
+use tokio::fs;
 
 #[derive(Parser, Debug, Clone)]
-pub struct SchemaPlanCommand {}
+pub struct SchemaPlanCommand {
+    /// diff against a schema-only pg_dump file instead of the database (offline planning)
+    #[clap(long, value_parser)]
+    pub against: Option<PathBuf>,
+    /// diff the schema in this directory, instead of the database, against `--local-new`
+    #[clap(
+        long,
+        value_parser,
+        requires = "local_new",
+        conflicts_with_all = &["against", "remote", "out", "validate"]
+    )]
+    pub local_old: Option<PathBuf>,
+    /// the directory of the new schema to plan the migration to, see `--local-old`
+    #[clap(long, value_parser, requires = "local_old")]
+    pub local_new: Option<PathBuf>,
+    /// output format of the plan (text or markdown)
+    #[clap(
+        long,
+        value_enum,
+        default_value = "text",
+        hide_possible_values = true
+    )]
+    pub format: PlanFormat,
+    /// plan the migration from the local database to the remote one, i.e. what
+    /// `apply --remote` applies
+    #[clap(long, action)]
+    pub remote: bool,
+    /// save the plan, pinned to the current state of the target database, for
+    /// `apply --plan`. The offline plans have no database to pin it to.
+    #[clap(long, value_parser, conflicts_with = "against")]
+    pub out: Option<PathBuf>,
+    /// run the plan against the target database in a transaction which is rolled back, to catch
+    /// the errors before apply
+    #[clap(long, action, conflicts_with = "against")]
+    pub validate: bool,
+    /// plan the migration of the local database back to the schema tagged by `renovate schema tag`.
+    /// It plans against the local database only, and has no database state to save with `--out`
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with_all = &["against", "local_old", "remote", "out"]
+    )]
+    pub to_tag: Option<String>,
+    #[clap(flatten)]
+    pub scope: ScopeArgs,
+}
+
+/// Output format of the migration plan
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanFormat {
+    Text,
+    /// summary and sql in markdown, e.g. for pull request comments
+    Markdown,
+}
 
 #[async_trait]
 impl CommandExecutor for SchemaPlanCommand {
-    async fn execute(&self, _args: &Args) -> Result<(), Error> {
-        generate_plan(false).await?;
+    async fn execute(&self, args: &Args) -> Result<(), Error> {
+        if self.format == PlanFormat::Text
+            && self.against.is_none()
+            && self.local_old.is_none()
+            && self.to_tag.is_none()
+            && self.out.is_none()
+        {
+            let plan = generate_plan(args, self.remote, &self.scope).await?;
+            if self.validate {
+                let config = load_config(args).await?;
+                validate_plan(&config, &plan, self.remote).await?;
+            }
+            return Ok(());
+        }
+
+        let config = load_config(args).await?;
+        let collector = Arc::new(ChangeCollector::default());
+        let renovate = self
+            .scope
+            .apply(Renovate::new(config.clone()).cache(use_cache(args)), args);
+        let renovate = match self.format {
+            PlanFormat::Text => renovate.verbose(true),
+            PlanFormat::Markdown => renovate.observer(collector.clone()),
+        };
+        // the offline plans have no database to estimate the data migrations against
+        let mut saved = None;
+        let (statements, estimates) = match (&self.against, &self.local_old, &self.local_new) {
+            (Some(dump), _, _) => (renovate.plan_against(dump).await?, vec![]),
+            (_, Some(old), Some(new)) => (renovate.plan_between(old, new).await?, vec![]),
+            _ => {
+                let (statements, remote) = match (&self.to_tag, &self.out) {
+                    (Some(name), _) => (
+                        renovate.plan_to_tag(name, vcs(args, &config).as_ref()).await?,
+                        false,
+                    ),
+                    (None, Some(_)) => {
+                        let plan = renovate.saved_plan(self.remote).await?;
+                        let statements = plan.statements.clone();
+                        saved = Some(plan);
+                        (statements, self.remote)
+                    }
+                    (None, None) => (renovate.plan(self.remote).await?, self.remote),
+                };
+                let estimates = renovate
+                    .explain_data_migrations(&statements, remote)
+                    .await?;
+                (statements, estimates)
+            }
+        };
+        match self.format {
+            PlanFormat::Text => {
+                print_plan(&config, &statements, &estimates)?;
+                print_notes(&renovate, &statements).await?;
+            }
+            PlanFormat::Markdown => {
+                let plan = Plan::new(statements.clone(), collector.take())
+                    .with_estimates(estimates)
+                    .with_stats(&renovate.table_stats()?)
+                    .with_services(renovate.affected_services(&statements).await?)
+                    .with_suppressed(renovate.suppressed_objects()?);
+                print!("{}", plan.to_markdown());
+            }
+        }
+        print_diagnostics(&renovate.diagnostics(), args)?;
+        if self.validate {
+            validate_plan(&config, &statements, self.remote).await?;
+        }
+        if let (Some(out), Some(plan)) = (&self.out, saved) {
+            fs::write(out, serde_json::to_string_pretty(&plan)?).await?;
+            eprintln!("\nThe plan is saved to {}.", out.display());
+        }
         Ok(())
     }
 }
 
-pub(super) async fn generate_plan(remote: bool) -> Result<Vec<String>> {
-    let config = load_config().await?;
-    let db_repo = DatabaseRepo::new(&config);
-
-    let local_schema = if !remote {
-        let sql = LocalRepo::new(&config.output.path).load_sql().await?;
-        db_repo.normalize(&sql).await?
-    } else {
-        db_repo.load().await?
-    };
-    let remote_schema = if !remote {
-        db_repo.load().await?
-    } else {
-        let sql = db_repo.load_sql_string(remote).await?;
-        SqlLoader::new(&sql).load().await?
-    };
-    let plan = local_schema.plan(&remote_schema, true)?;
+async fn validate_plan(config: &RenovateConfig, plan: &[String], remote: bool) -> Result<()> {
+    if plan.is_empty() {
+        return Ok(());
+    }
+    let report = Renovate::new(config.clone()).validate(plan, remote).await?;
+    if let Some(failed) = report.iter().find(|s| s.error.is_some()) {
+        bail!(
+            "The plan failed to validate against the target database at:\n\n  {}\n\n{}",
+            failed.sql,
+            failed.error.as_deref().unwrap_or_default()
+        );
+    }
+    println!(
+        "\nValidated {} statement(s) against the target database, rolled back.",
+        report.len()
+    );
+    Ok(())
+}
 
+pub(super) async fn generate_plan(
+    args: &Args,
+    remote: bool,
+    scope: &ScopeArgs,
+) -> Result<Vec<String>> {
+    let config = load_config(args).await?;
+    let renovate = Renovate::new(config.clone())
+        .cache(use_cache(args))
+        .verbose(true);
+    let renovate = scope.apply(renovate, args);
+    let plan = renovate.plan(remote).await?;
+    let estimates = renovate.explain_data_migrations(&plan, remote).await?;
+    print_plan(&config, &plan, &estimates)?;
+    print_notes(&renovate, &plan).await?;
+    print_diagnostics(&renovate.diagnostics(), args)?;
+    Ok(plan)
+}
+
+/// Print the plan, with the warnings of the statements rewriting a whole table, along with its
+/// size as of the last fetch, and the estimates of the data migrations in comments
+pub(super) fn print_plan(
+    config: &RenovateConfig,
+    plan: &[String],
+    estimates: &[DmlEstimate],
+) -> Result<()> {
     if plan.is_empty() {
         println!("No changes detected.");
-        return Ok(vec![]);
+        return Ok(());
     }
 
+    let stats = load_stats(&config.output.path)?;
     println!("The following SQLs will be applied:\n");
     for item in plan.iter() {
+        if let Some(reason) = table_rewrite(item) {
+            match table_size(item, &stats) {
+                Some(size) => println!("-- WARNING: rewrites the whole table ({}, {})", reason, size),
+                None => println!("-- WARNING: rewrites the whole table ({})", reason),
+            }
+        }
+        if let Some(estimate) = estimates.iter().find(|e| &e.statement == item) {
+            println!("-- ESTIMATE: {}", estimate);
+        }
         let formatted = sqlformat::format(
             item,
             &Default::default(),
@@ -49,5 +215,25 @@ pub(super) async fn generate_plan(remote: bool) -> Result<Vec<String>> {
             println!("{};", formatted);
         }
     }
-    Ok(plan)
+    Ok(())
+}
+
+/// the services to redeploy along with the plan and the objects left out of it, see
+/// `Renovate::affected_services` and `Renovate::suppressed_objects`
+async fn print_notes(renovate: &Renovate, plan: &[String]) -> Result<()> {
+    let services = renovate.affected_services(plan).await?;
+    if !services.is_empty() {
+        println!("\nThe plan changes the objects of these services, redeploy them along with it:\n");
+        for (service, objects) in &services {
+            println!("  {}: {}", service, objects.iter().join(", "));
+        }
+    }
+    let suppressed = renovate.suppressed_objects()?;
+    if !suppressed.is_empty() {
+        println!("\nThese objects are annotated with `-- renovate:ignore`, and left out of the plan:\n");
+        for name in &suppressed {
+            println!("  {}", name);
+        }
+    }
+    Ok(())
 }
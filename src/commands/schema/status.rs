@@ -0,0 +1,97 @@
+use super::{env_name, print_diagnostics, use_cache, vcs, Args, CommandExecutor};
+use crate::{utils::load_config, Renovate, SyncState, Vcs};
+use clap_utils::prelude::*;
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Parser, Debug, Clone)]
+pub struct SchemaStatusCommand {}
+
+#[async_trait]
+impl CommandExecutor for SchemaStatusCommand {
+    async fn execute(&self, args: &Args) -> Result<(), Error> {
+        let config = load_config(args).await?;
+        let vcs = vcs(args, &config);
+        let renovate = Renovate::new(config).cache(use_cache(args));
+        if let Some(state) = renovate.last_sync(env_name(args))? {
+            println!(
+                "Last synced {} from {}.",
+                ago(state.synced_at),
+                env_name(args)
+            );
+            if unchanged_since(&renovate, &state, vcs.as_ref()).await? {
+                println!("Neither the local repo nor the remote database changed since.");
+                return Ok(());
+            }
+            println!();
+        }
+        let local = renovate.normalize_local().await?;
+        let remote = renovate.load_database(true).await?;
+        print_diagnostics(&renovate.diagnostics(), args)?;
+
+        let remote_only = remote.missing_in(&local);
+        let local_only = local.missing_in(&remote);
+        if remote_only.is_empty() && local_only.is_empty() {
+            println!("The local repo and the remote database have the same objects.");
+            return Ok(());
+        }
+
+        print_group("Remote objects not in the local repo", &remote_only);
+        print_group("Local objects not in the remote database", &local_only);
+        Ok(())
+    }
+}
+
+/// Whether both the remote database and the local repo are as of the last sync, by the catalog
+/// fingerprint and the commit, without loading either schema
+async fn unchanged_since(
+    renovate: &Renovate,
+    state: &SyncState,
+    vcs: &dyn Vcs,
+) -> Result<bool, Error> {
+    // the base directory isn't part of the commit
+    if !state.remote || renovate.config().output.base.is_some() {
+        return Ok(false);
+    }
+    match (&state.fingerprint, &state.commit) {
+        (Some(fingerprint), Some(commit)) => {
+            if vcs.is_dirty()? || vcs.head()?.as_ref() != Some(commit) {
+                return Ok(false);
+            }
+            Ok(&renovate.catalog_fingerprint(true).await?.overall == fingerprint)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// how long ago the unix time `at` is, e.g. `3 days ago`
+fn ago(at: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let secs = now.saturating_sub(at);
+    let (n, unit) = match secs {
+        0..=59 => return "just now".to_owned(),
+        60..=3599 => (secs / 60, "minute"),
+        3600..=86399 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+    format!("{} {}{} ago", n, unit, if n == 1 { "" } else { "s" })
+}
+
+fn print_group(title: &str, groups: &BTreeMap<String, Vec<String>>) {
+    if groups.is_empty() {
+        return;
+    }
+    println!("{}:\n", title);
+    for (type_name, ids) in groups {
+        println!("  {} ({}):", type_name, ids.len());
+        for id in ids {
+            println!("    {}", id);
+        }
+    }
+    println!();
+}
@@ -0,0 +1,27 @@
+use super::{use_cache, vcs, Args, CommandExecutor};
+use crate::{utils::load_config, Renovate};
+use clap_utils::prelude::*;
+
+#[derive(Parser, Debug, Clone)]
+pub struct SchemaTagCommand {
+    /// name of the tag, e.g. `v1.4.0`
+    #[clap(value_parser)]
+    pub name: String,
+}
+
+#[async_trait]
+impl CommandExecutor for SchemaTagCommand {
+    async fn execute(&self, args: &Args) -> Result<(), Error> {
+        let config = load_config(args).await?;
+        let vcs = vcs(args, &config);
+        let tag = Renovate::new(config)
+            .cache(use_cache(args))
+            .tag(&self.name, vcs.as_ref())
+            .await?;
+        println!(
+            "Tagged the local schema ({}) at commit {} as {}.",
+            tag.fingerprint, tag.commit, tag.name
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,401 @@
+use crate::{parser::SchemaId, Dialect, Pooler};
+use anyhow::{bail, Result};
+use pg_query::{
+    protobuf::{AlterTableType, ConstrType, ObjectType, RoleSpecType},
+    NodeEnum, NodeRef,
+};
+use std::fmt;
+
+/// A privilege the connected role needs to execute a statement of the plan
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Requirement {
+    /// ownership of the table, view, sequence or index
+    Owner(String),
+    /// CREATE on the schema
+    Create(String),
+    /// CREATE on the current database, to create schemas
+    CreateSchema,
+    /// membership of the role, to transfer ownership to it
+    Member(String),
+    /// the feature is reserved to superusers
+    Superuser(&'static str),
+}
+
+/// Make sure the migration plan could be applied to the server with the given
+/// `server_version_num` (e.g. 110005 for 11.5). All the incompatible statements are reported.
+pub fn check_server_version(plan: &[String], version: u32) -> Result<()> {
+    let mut errors = Vec::new();
+    for sql in plan {
+        let parsed = pg_query::parse(sql)?;
+        for (node, _, _) in parsed.protobuf.nodes() {
+            if let Some((feature, required)) = required_version(node) {
+                if version < required {
+                    errors.push(format!(
+                        "  {}: {} requires PostgreSQL {}",
+                        sql,
+                        feature,
+                        required / 10000
+                    ));
+                }
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!(
+            "The migration plan can't be applied to PostgreSQL {}:\n\n{}",
+            display_version(version),
+            errors.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// Make sure the migration plan only uses the features supported by the dialect. All the
+/// unsupported statements are reported.
+pub fn check_dialect(plan: &[String], dialect: Dialect) -> Result<()> {
+    if dialect == Dialect::Postgres {
+        return Ok(());
+    }
+
+    let mut errors = Vec::new();
+    for sql in plan {
+        let parsed = pg_query::parse(sql)?;
+        for (node, _, _) in parsed.protobuf.nodes() {
+            if let Some(feature) = cockroach_unsupported(node) {
+                errors.push(format!("  {}: {} is not supported", sql, feature));
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!(
+            "The migration plan can't be applied to CockroachDB:\n\n{}",
+            errors.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// Make sure the migration plan doesn't rely on the session when the database is behind
+/// pgbouncer in transaction pooling mode, e.g. the `SET` of a data migration would leak to the
+/// other clients of the server connection. All the offending statements are reported.
+pub fn check_pooler(plan: &[String], pooler: Pooler) -> Result<()> {
+    if pooler == Pooler::None {
+        return Ok(());
+    }
+
+    let mut errors = Vec::new();
+    for sql in plan {
+        let parsed = pg_query::parse(sql)?;
+        for (node, depth, _) in parsed.protobuf.nodes() {
+            if let Some(feature) = session_feature(node, depth) {
+                errors.push(format!("  {}: {}", sql, feature));
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!(
+            "The migration plan uses session level features, which don't work behind pgbouncer in transaction pooling mode:\n\n{}",
+            errors.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// The privileges required by each statement of the plan, as (statement index, requirement).
+/// The check happens before the plan is applied, so that all the missing privileges are reported
+/// at once instead of the transaction failing at the first one.
+pub fn required_privileges(plan: &[String]) -> Result<Vec<(usize, Requirement)>> {
+    let mut requirements = Vec::new();
+    for (i, sql) in plan.iter().enumerate() {
+        let parsed = pg_query::parse(sql)?;
+        for (node, _, _) in parsed.protobuf.nodes() {
+            requirements.extend(requirement(node).into_iter().map(|r| (i, r)));
+        }
+    }
+    requirements.dedup();
+    Ok(requirements)
+}
+
+fn requirement(node: NodeRef) -> Vec<Requirement> {
+    let owner = |rv: Option<&pg_query::protobuf::RangeVar>| match rv {
+        Some(rv) => vec![Requirement::Owner(SchemaId::from(rv).to_string())],
+        None => vec![],
+    };
+    let create = |rv: Option<&pg_query::protobuf::RangeVar>| match rv {
+        Some(rv) => vec![Requirement::Create(SchemaId::from(rv).schema)],
+        None => vec![],
+    };
+    // CURRENT_USER and the like are the role itself
+    let member = |role: Option<&pg_query::protobuf::RoleSpec>| match role {
+        Some(role) if role.roletype() == RoleSpecType::RolespecCstring => {
+            vec![Requirement::Member(role.rolename.clone())]
+        }
+        _ => vec![],
+    };
+    match node {
+        NodeRef::AlterTableStmt(stmt) => owner(stmt.relation.as_ref()),
+        NodeRef::IndexStmt(stmt) => owner(stmt.relation.as_ref()),
+        NodeRef::CreateTrigStmt(stmt) => owner(stmt.relation.as_ref()),
+        NodeRef::CreatePolicyStmt(stmt) => owner(stmt.table.as_ref()),
+        NodeRef::CreateStmt(stmt) => create(stmt.relation.as_ref()),
+        NodeRef::CreateSeqStmt(stmt) => create(stmt.sequence.as_ref()),
+        NodeRef::ViewStmt(stmt) => create(stmt.view.as_ref()),
+        NodeRef::CreateTableAsStmt(stmt) => {
+            create(stmt.into.as_ref().and_then(|into| into.rel.as_ref()))
+        }
+        // the schema could be created for another owner
+        NodeRef::CreateSchemaStmt(stmt) => {
+            let mut requirements = vec![Requirement::CreateSchema];
+            requirements.extend(member(stmt.authrole.as_ref()));
+            requirements
+        }
+        NodeRef::AlterOwnerStmt(stmt) => {
+            let mut requirements = owner(stmt.relation.as_ref());
+            requirements.extend(member(stmt.newowner.as_ref()));
+            requirements
+        }
+        NodeRef::DropStmt(stmt)
+            if matches!(
+                stmt.remove_type(),
+                ObjectType::ObjectTable
+                    | ObjectType::ObjectView
+                    | ObjectType::ObjectMatview
+                    | ObjectType::ObjectSequence
+                    | ObjectType::ObjectIndex
+            ) =>
+        {
+            stmt.objects
+                .iter()
+                .filter_map(|object| match object.node.as_ref() {
+                    Some(NodeEnum::List(list)) => {
+                        let names: Vec<_> = list
+                            .items
+                            .iter()
+                            .filter_map(|n| match n.node.as_ref() {
+                                Some(NodeEnum::String(s)) => Some(s.str.as_str()),
+                                _ => None,
+                            })
+                            .collect();
+                        Some(Requirement::Owner(SchemaId::new_with(&names).to_string()))
+                    }
+                    _ => None,
+                })
+                .collect()
+        }
+        NodeRef::AlterTableCmd(cmd) if cmd.subtype() == AlterTableType::AtChangeOwner => {
+            member(cmd.newowner.as_ref())
+        }
+        NodeRef::CreateEventTrigStmt(_) => vec![Requirement::Superuser("event trigger")],
+        NodeRef::CreateFdwStmt(_) => vec![Requirement::Superuser("foreign data wrapper")],
+        NodeRef::AlterSystemStmt(_) => vec![Requirement::Superuser("ALTER SYSTEM")],
+        _ => vec![],
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Owner(name) => write!(f, "must be the owner of {}", name),
+            Self::Create(schema) => write!(f, "needs CREATE on schema {}", schema),
+            Self::CreateSchema => write!(f, "needs CREATE on the database"),
+            Self::Member(role) => write!(f, "must be a member of role {}", role),
+            Self::Superuser(feature) => write!(f, "{} requires superuser", feature),
+        }
+    }
+}
+
+/// `server_version_num` to the human readable version, e.g. 110005 => 11.5, 90605 => 9.6.5
+fn display_version(version: u32) -> String {
+    if version >= 100000 {
+        format!("{}.{}", version / 10000, version % 10000)
+    } else {
+        format!(
+            "{}.{}.{}",
+            version / 10000,
+            version / 100 % 100,
+            version % 100
+        )
+    }
+}
+
+/// the feature used by the node and the minimal server version supporting it
+fn required_version(node: NodeRef) -> Option<(&'static str, u32)> {
+    match node {
+        // the plan is applied in a transaction
+        NodeRef::AlterEnumStmt(stmt) if stmt.old_val.is_empty() => {
+            Some(("ALTER TYPE ... ADD VALUE inside a transaction", 120000))
+        }
+        NodeRef::AlterEnumStmt(_) => Some(("ALTER TYPE ... RENAME VALUE", 100000)),
+        NodeRef::Constraint(c) if c.contype() == ConstrType::ConstrGenerated => {
+            Some(("GENERATED ... STORED column", 120000))
+        }
+        NodeRef::Constraint(c) if !c.including.is_empty() => {
+            Some(("INCLUDE in constraint", 110000))
+        }
+        NodeRef::IndexStmt(stmt) if !stmt.index_including_params.is_empty() => {
+            Some(("INCLUDE in index", 110000))
+        }
+        _ => None,
+    }
+}
+
+/// the postgres feature used by the node which CockroachDB lacks
+fn cockroach_unsupported(node: NodeRef) -> Option<&'static str> {
+    match node {
+        NodeRef::CreateTrigStmt(_) => Some("trigger"),
+        NodeRef::CreatePolicyStmt(_) | NodeRef::AlterPolicyStmt(_) => {
+            Some("row level security policy")
+        }
+        NodeRef::AlterTableCmd(cmd)
+            if matches!(
+                cmd.subtype(),
+                AlterTableType::AtEnableRowSecurity | AlterTableType::AtForceRowSecurity
+            ) =>
+        {
+            Some("row level security")
+        }
+        NodeRef::CreateFunctionStmt(stmt) if stmt.is_procedure => Some("procedure"),
+        _ => None,
+    }
+}
+
+/// the session level feature used by the node, with the alternative if any. The `SET` options
+/// of functions are nested in their definition, so only the top level ones count.
+fn session_feature(node: NodeRef, depth: i32) -> Option<&'static str> {
+    match node {
+        NodeRef::VariableSetStmt(stmt)
+            if depth == 0 && !stmt.is_local && stmt.name != "TRANSACTION" =>
+        {
+            Some("SET without LOCAL lasts for the session, use SET LOCAL")
+        }
+        NodeRef::PrepareStmt(_) | NodeRef::ExecuteStmt(_) | NodeRef::DeallocateStmt(_) => {
+            Some("prepared statements last for the session")
+        }
+        NodeRef::ListenStmt(_) => Some("LISTEN lasts for the session"),
+        NodeRef::FuncCall(call) => {
+            let name = match call.funcname.last().and_then(|n| n.node.as_ref()) {
+                Some(NodeEnum::String(s)) => s.str.as_str(),
+                _ => return None,
+            };
+            match name {
+                "pg_advisory_lock"
+                | "pg_advisory_lock_shared"
+                | "pg_try_advisory_lock"
+                | "pg_try_advisory_lock_shared"
+                | "pg_advisory_unlock"
+                | "pg_advisory_unlock_shared"
+                | "pg_advisory_unlock_all" => {
+                    Some("advisory locks are held by the session, use pg_advisory_xact_lock")
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_server_version_should_reject_unsupported_features() {
+        let plan = vec![
+            "ALTER TYPE public.status ADD VALUE 'archived'".to_owned(),
+            "CREATE INDEX todos_title_idx ON public.todos USING btree (title) INCLUDE (id)"
+                .to_owned(),
+        ];
+        assert!(check_server_version(&plan, 120000).is_ok());
+
+        let err = check_server_version(&plan, 110000).unwrap_err().to_string();
+        assert!(err.contains("ADD VALUE"));
+        assert!(!err.contains("INCLUDE in index"));
+
+        let err = check_server_version(&plan, 100000).unwrap_err().to_string();
+        assert!(err.contains("INCLUDE in index requires PostgreSQL 11"));
+    }
+
+    #[test]
+    fn check_server_version_should_reject_generated_columns_before_12() {
+        let plan = vec![
+            "ALTER TABLE ONLY public.todos ADD COLUMN len int GENERATED ALWAYS AS (length(title)) STORED"
+                .to_owned(),
+        ];
+        assert!(check_server_version(&plan, 110000).is_err());
+        assert!(check_server_version(&plan, 150002).is_ok());
+    }
+
+    #[test]
+    fn check_dialect_should_reject_features_cockroach_lacks() {
+        let plan = vec![
+            "CREATE TABLE public.todos (id int PRIMARY KEY, title text)".to_owned(),
+            "ALTER TABLE public.todos ENABLE ROW LEVEL SECURITY".to_owned(),
+            "CREATE TRIGGER todos_audit AFTER INSERT ON public.todos FOR EACH ROW EXECUTE FUNCTION audit()"
+                .to_owned(),
+        ];
+        assert!(check_dialect(&plan, Dialect::Postgres).is_ok());
+
+        let err = check_dialect(&plan, Dialect::Cockroach)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("row level security is not supported"));
+        assert!(err.contains("trigger is not supported"));
+        assert!(!err.contains("CREATE TABLE"));
+    }
+
+    #[test]
+    fn check_pooler_should_reject_session_level_features() {
+        let plan = vec![
+            "CREATE FUNCTION public.touch() RETURNS trigger LANGUAGE plpgsql SET search_path = public AS $$ BEGIN RETURN NEW; END $$"
+                .to_owned(),
+            "SET LOCAL lock_timeout = '5s'".to_owned(),
+            "SET statement_timeout = 0".to_owned(),
+            "SELECT pg_advisory_lock(42)".to_owned(),
+            "SELECT pg_advisory_xact_lock(42)".to_owned(),
+        ];
+        assert!(check_pooler(&plan, Pooler::None).is_ok());
+
+        let err = check_pooler(&plan, Pooler::Pgbouncer)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("SET statement_timeout = 0: SET without LOCAL"));
+        assert!(err.contains("SELECT pg_advisory_lock(42): advisory locks"));
+        assert!(!err.contains("lock_timeout"));
+        assert!(!err.contains("search_path"));
+        assert!(!err.contains("SELECT pg_advisory_xact_lock"));
+    }
+
+    #[test]
+    fn required_privileges_should_cover_each_statement() {
+        let plan = vec![
+            "CREATE SCHEMA app".to_owned(),
+            "CREATE TABLE app.todos (id int)".to_owned(),
+            "ALTER TABLE public.users ADD COLUMN name text".to_owned(),
+            "ALTER TABLE public.users OWNER TO admin".to_owned(),
+            "DROP INDEX public.users_idx".to_owned(),
+            "CREATE EVENT TRIGGER audit ON ddl_command_end EXECUTE FUNCTION audit()".to_owned(),
+            "CREATE SCHEMA billing AUTHORIZATION billing_owner".to_owned(),
+            "ALTER FUNCTION public.audit() OWNER TO admin".to_owned(),
+            "ALTER SCHEMA app OWNER TO CURRENT_USER".to_owned(),
+        ];
+        let requirements = required_privileges(&plan).unwrap();
+        assert_eq!(
+            requirements,
+            vec![
+                (0, Requirement::CreateSchema),
+                (1, Requirement::Create("app".to_owned())),
+                (2, Requirement::Owner("public.users".to_owned())),
+                (3, Requirement::Owner("public.users".to_owned())),
+                (3, Requirement::Member("admin".to_owned())),
+                (4, Requirement::Owner("public.users_idx".to_owned())),
+                (5, Requirement::Superuser("event trigger")),
+                (6, Requirement::CreateSchema),
+                (6, Requirement::Member("billing_owner".to_owned())),
+                (7, Requirement::Member("admin".to_owned())),
+            ]
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use crate::{
+    parser::{utils::node_to_string, SchemaId},
+    DatabaseSchema, NodeItem,
+};
+use pg_query::{protobuf::FuncCall, NodeEnum, NodeRef};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+/// An object of the dependency graph, e.g. `view public.active_users`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct DepNode {
+    pub type_name: &'static str,
+    pub id: String,
+}
+
+/// The objects of a schema and the objects each of them references, built from their sql: the
+/// relations a view selects from, the tables a foreign key references, the function a trigger
+/// executes, the types of the columns, the sequences of the column defaults. The indexes,
+/// constraints, triggers and policies of a table are its children, they depend on it. The bodies
+/// of the functions aren't parsed, so what they reference isn't in the graph.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DependencyGraph {
+    /// the objects referenced by name, keyed by their qualified name
+    names: BTreeMap<String, DepNode>,
+    /// the objects each object references, children excluded
+    edges: BTreeMap<DepNode, BTreeSet<DepNode>>,
+    /// the indexes, constraints, triggers and policies of each table
+    children: BTreeMap<DepNode, BTreeSet<DepNode>>,
+}
+
+/// A node of the tree printed by `schema deps`, with the objects it depends on (or the ones
+/// depending on it, reversed) below it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DepTree {
+    pub node: DepNode,
+    pub children: Vec<DepTree>,
+    /// already printed by an ancestor, i.e. a cycle, so its children are not repeated
+    pub cycle: bool,
+    /// already printed elsewhere in the tree along with its children, which are not repeated
+    pub repeated: bool,
+}
+
+impl DependencyGraph {
+    /// Build the graph of the schema. Unqualified names are in `default_schema`.
+    pub fn new(schema: &DatabaseSchema, default_schema: &str) -> Self {
+        let mut graph = Self::default();
+        let mut sources = Vec::new();
+        graph.add_named(&schema.composite_types, &mut sources);
+        graph.add_named(&schema.enum_types, &mut sources);
+        graph.add_named(&schema.sequences, &mut sources);
+        graph.add_named(&schema.tables, &mut sources);
+        graph.add_named(&schema.views, &mut sources);
+        graph.add_named(&schema.mviews, &mut sources);
+        graph.add_named(&schema.functions, &mut sources);
+        graph.add_children(&schema.table_sequences, &mut sources);
+        graph.add_children(&schema.table_constraints, &mut sources);
+        graph.add_children(&schema.table_indexes, &mut sources);
+        graph.add_children(&schema.table_policies, &mut sources);
+        graph.add_children(&schema.table_triggers, &mut sources);
+
+        for (node, owner, sql) in sources {
+            let targets = references(&sql, default_schema)
+                .into_iter()
+                .filter_map(|name| graph.names.get(&name).cloned())
+                .filter(|target| target != &node && Some(target) != owner.as_ref())
+                .collect();
+            graph.edges.insert(node, targets);
+        }
+        graph
+    }
+
+    /// the object with the qualified name, e.g. `public.users`
+    pub fn find(&self, name: &str) -> Option<&DepNode> {
+        self.names.get(name)
+    }
+
+    /// whether the object is in the graph, e.g. an index or a constraint
+    pub fn contains(&self, node: &DepNode) -> bool {
+        self.edges.contains_key(node)
+    }
+
+    /// The objects the node depends on, recursively. With `reverse`, the objects depending on
+    /// it instead, i.e. what breaks if it's dropped or changed. Each object is expanded once,
+    /// later occurrences are marked as repeated, so that shared dependencies don't blow it up.
+    pub fn tree(&self, node: &DepNode, reverse: bool) -> DepTree {
+        self.subtree(node, reverse, &mut Vec::new(), &mut BTreeSet::new())
+    }
+
+    fn subtree(
+        &self,
+        node: &DepNode,
+        reverse: bool,
+        ancestors: &mut Vec<DepNode>,
+        expanded: &mut BTreeSet<DepNode>,
+    ) -> DepTree {
+        let cycle = ancestors.contains(node);
+        if cycle || expanded.contains(node) {
+            return DepTree {
+                node: node.clone(),
+                children: vec![],
+                cycle,
+                repeated: !cycle,
+            };
+        }
+        expanded.insert(node.clone());
+        ancestors.push(node.clone());
+        let children = self
+            .next(node, reverse)
+            .iter()
+            .map(|next| self.subtree(next, reverse, ancestors, expanded))
+            .collect();
+        ancestors.pop();
+        DepTree {
+            node: node.clone(),
+            children,
+            cycle: false,
+            repeated: false,
+        }
+    }
+
+    /// The objects depending on the node are its children and the objects referencing it. The
+    /// ones it depends on are the ones it or its children reference: the children themselves
+    /// depend on it, not the other way around.
+    fn next(&self, node: &DepNode, reverse: bool) -> BTreeSet<DepNode> {
+        let children = self.children.get(node).cloned().unwrap_or_default();
+        if reverse {
+            let mut result = children;
+            result.extend(
+                self.edges
+                    .iter()
+                    .filter(|(_, targets)| targets.contains(node))
+                    .map(|(source, _)| source.clone()),
+            );
+            result
+        } else {
+            std::iter::once(node)
+                .chain(children.iter())
+                .filter_map(|source| self.edges.get(source))
+                .flatten()
+                .filter(|target| *target != node && !children.contains(*target))
+                .cloned()
+                .collect()
+        }
+    }
+
+    fn add_named<T: NodeItem>(
+        &mut self,
+        source: &BTreeMap<String, BTreeMap<String, T>>,
+        sources: &mut Vec<(DepNode, Option<DepNode>, String)>,
+    ) {
+        for (schema, items) in source {
+            for (name, item) in items {
+                let node = DepNode::of(item);
+                self.names
+                    .insert(SchemaId::new(schema, name).to_string(), node.clone());
+                sources.push((node, None, item.to_string()));
+            }
+        }
+    }
+
+    fn add_children<T: NodeItem>(
+        &mut self,
+        source: &BTreeMap<SchemaId, BTreeMap<String, T>>,
+        sources: &mut Vec<(DepNode, Option<DepNode>, String)>,
+    ) {
+        for (owner, items) in source {
+            let owner = self.names.get(&owner.to_string()).cloned();
+            for item in items.values() {
+                let node = DepNode::of(item);
+                if let Some(owner) = &owner {
+                    self.children
+                        .entry(owner.clone())
+                        .or_default()
+                        .insert(node.clone());
+                }
+                sources.push((node, owner.clone(), item.to_string()));
+            }
+        }
+    }
+}
+
+impl DepNode {
+    fn of<T: NodeItem>(item: &T) -> Self {
+        Self {
+            type_name: item.type_name(),
+            id: item.id(),
+        }
+    }
+}
+
+impl fmt::Display for DepNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.type_name, self.id)
+    }
+}
+
+impl fmt::Display for DepTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_tree(tree: &DepTree, depth: usize, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:indent$}{}", "", tree.node, indent = depth * 2)?;
+            if tree.cycle {
+                write!(f, " (cycle)")?;
+            }
+            if tree.repeated {
+                write!(f, " (see above)")?;
+            }
+            writeln!(f)?;
+            for child in &tree.children {
+                write_tree(child, depth + 1, f)?;
+            }
+            Ok(())
+        }
+        write_tree(self, 0, f)
+    }
+}
+
+/// the qualified names of the relations, types, functions and sequences the sql references
+fn references(sql: &str, default_schema: &str) -> BTreeSet<String> {
+    let parsed = match pg_query::parse(sql) {
+        Ok(parsed) => parsed,
+        Err(_) => return BTreeSet::new(),
+    };
+    let qualify = |names: &[String]| match names {
+        [] => None,
+        [name] => Some(SchemaId::new(default_schema, name).to_string()),
+        [.., schema, name] => Some(SchemaId::new(schema, name).to_string()),
+    };
+    let names = |nodes: &[pg_query::Node]| -> Vec<String> {
+        nodes.iter().filter_map(node_to_string).collect()
+    };
+
+    let mut result = BTreeSet::new();
+    for (node, _, _) in parsed.protobuf.nodes() {
+        let name = match node {
+            NodeRef::RangeVar(v) if v.schemaname.is_empty() => {
+                Some(SchemaId::new(default_schema, &v.relname).to_string())
+            }
+            NodeRef::RangeVar(v) => Some(SchemaId::new(&v.schemaname, &v.relname).to_string()),
+            NodeRef::TypeName(t) => qualify(&names(&t.names)),
+            NodeRef::CreateTrigStmt(t) => qualify(&names(&t.funcname)),
+            NodeRef::FuncCall(call) => {
+                if let Some(sequence) = nextval_sequence(call) {
+                    let parts: Vec<_> = sequence.split('.').map(str::to_owned).collect();
+                    result.extend(qualify(&parts));
+                }
+                qualify(&names(&call.funcname))
+            }
+            _ => None,
+        };
+        result.extend(name);
+    }
+    result
+}
+
+/// the sequence of `nextval('public.todos_id_seq'::regclass)`
+fn nextval_sequence(call: &FuncCall) -> Option<String> {
+    let name = call.funcname.last().and_then(node_to_string)?;
+    if name != "nextval" {
+        return None;
+    }
+    let arg = match call.args.first()?.node.as_ref()? {
+        NodeEnum::TypeCast(cast) => cast.arg.as_ref()?.node.as_ref()?,
+        arg => arg,
+    };
+    match arg {
+        NodeEnum::AConst(c) => match c.val.as_ref()?.node.as_ref()? {
+            NodeEnum::String(s) => Some(s.str.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SqlLoader;
+    use anyhow::Result;
+
+    #[test]
+    fn dependencies_should_be_found_both_ways() -> Result<()> {
+        let schema = SqlLoader::new(
+            r#"
+            CREATE TYPE public.role AS ENUM ('admin', 'member');
+            CREATE TABLE public.users (id int PRIMARY KEY, role public.role);
+            CREATE TABLE public.todos (id int PRIMARY KEY, owner int);
+            ALTER TABLE ONLY public.todos ADD CONSTRAINT todos_owner_fkey FOREIGN KEY (owner) REFERENCES public.users(id);
+            CREATE INDEX todos_owner_idx ON public.todos (owner);
+            CREATE VIEW public.admins AS SELECT id FROM public.users WHERE role = 'admin';
+            "#,
+        )
+        .parse()?;
+        let graph = DependencyGraph::new(&schema, "public");
+        let todos = graph.find("public.todos").unwrap();
+        assert_eq!(
+            graph.tree(todos, false).to_string(),
+            "table public.todos\n  table public.users\n    enum public.role\n"
+        );
+
+        let users = graph.find("public.users").unwrap();
+        assert_eq!(
+            graph.tree(users, true).to_string(),
+            "table public.users\n  constraint todos_owner_fkey\n  view public.admins\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn shared_dependencies_should_be_expanded_once() -> Result<()> {
+        let schema = SqlLoader::new(
+            r#"
+            CREATE TYPE public.role AS ENUM ('admin', 'member');
+            CREATE TABLE public.users (id int PRIMARY KEY, role public.role);
+            CREATE VIEW public.admins AS SELECT id FROM public.users WHERE role = 'admin';
+            CREATE VIEW public.members AS SELECT id FROM public.users WHERE role = 'member';
+            CREATE VIEW public.everyone AS SELECT id FROM public.admins UNION SELECT id FROM public.members;
+            "#,
+        )
+        .parse()?;
+        let graph = DependencyGraph::new(&schema, "public");
+        let everyone = graph.find("public.everyone").unwrap();
+        assert_eq!(
+            graph.tree(everyone, false).to_string(),
+            "view public.everyone\n  view public.admins\n    table public.users\n      enum public.role\n  view public.members\n    table public.users (see above)\n"
+        );
+        Ok(())
+    }
+}
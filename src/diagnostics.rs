@@ -0,0 +1,70 @@
+use crate::{Diagnostic, DiagnosticKind};
+use std::fmt;
+
+impl Diagnostic {
+    pub(crate) fn new(kind: DiagnosticKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            source: None,
+            message: message.into(),
+            statement: None,
+        }
+    }
+
+    pub(crate) fn source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    pub(crate) fn statement(mut self, statement: impl Into<String>) -> Self {
+        self.statement = Some(statement.into());
+        self
+    }
+}
+
+/// The diagnostics of the statements of `source` skipped while parsing, with their line, see
+/// `SqlLoader::skipped_statements`. pg_dump sets the owner of every object, so the ignored owners
+/// are reported once for the whole source.
+pub(crate) fn skipped_diagnostics(
+    source: &str,
+    skipped: Vec<(usize, DiagnosticKind, String)>,
+) -> Vec<Diagnostic> {
+    let mut result = Vec::new();
+    let mut owners = Vec::new();
+    for (line, kind, statement) in skipped {
+        match kind {
+            DiagnosticKind::OwnershipIgnored => owners.push((line, statement)),
+            _ => result.push(
+                Diagnostic::new(kind, "not supported by renovate, skipped")
+                    .source(format!("{}:{}", source, line))
+                    .statement(statement),
+            ),
+        }
+    }
+    if let Some((line, statement)) = owners.first() {
+        let message = format!(
+            "{} owner(s) ignored, only the owners of the tables and sequences are tracked",
+            owners.len()
+        );
+        result.push(
+            Diagnostic::new(DiagnosticKind::OwnershipIgnored, message)
+                .source(format!("{}:{}", source, line))
+                .statement(statement.as_str()),
+        );
+    }
+    result
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "warning: ")?;
+        if let Some(source) = &self.source {
+            write!(f, "{}: ", source)?;
+        }
+        write!(f, "{}", self.message)?;
+        if let Some(statement) = &self.statement {
+            write!(f, "\n  {}", statement)?;
+        }
+        Ok(())
+    }
+}
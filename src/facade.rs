@@ -0,0 +1,1141 @@
+#[cfg(feature = "console")]
+use crate::ConsoleObserver;
+#[cfg(feature = "db")]
+use crate::{
+    compat::{check_dialect, check_pooler, check_server_version},
+    plan::{dropped_data, is_explainable},
+    repo::save_sync_state,
+    ApplyFailed, ApplyReport, CatalogFingerprint, Checkpoint, DatabaseRepo, Dialect, DmlEstimate,
+    DroppedData, DryRunStatement, SavedPlan,
+};
+#[cfg(feature = "cli")]
+use crate::{
+    deps::{DepNode, DepTree, DependencyGraph},
+    plan::extract_data_migrations,
+    ChangeKind,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{
+    plan::{
+        affected_services, extract_service_annotations, ignored_statements,
+        interleave_data_migrations, qualify_sql, DataMigration, IGNORE_FILE, ORDER_FILE,
+    },
+    repo::{
+        add_tag, check_strict, load_data_migrations, load_stats, load_sync_states, load_tags,
+        store_diagnostics, visible_files, SchemaCache, TAGS_FILE,
+    },
+    DiagnosticKind, LocalRepo, MemoryStore, OverlayStore, SchemaFiles, SchemaLoader, SchemaTag,
+    SqlLoader, SyncState, TableStats,
+};
+use crate::{
+    plan::{apply_order, OrderRule},
+    schema::PlanOptions,
+    DatabaseSchema, Diagnostic, NoopObserver, ObjectKind, Observer, RenovateConfig, Vcs,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use anyhow::Context;
+use anyhow::Result;
+use derivative::Derivative;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+#[cfg(feature = "cli")]
+use std::path::{Component, PathBuf};
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+};
+
+/// The entry point for using renovate as a library.
+///
+/// ```no_run
+/// use renovate::{Renovate, RenovateConfig};
+///
+/// # async fn run() -> anyhow::Result<()> {
+/// let renovate = Renovate::new(RenovateConfig::load("renovate.yml").await?);
+/// let plan = renovate.plan(false).await?;
+/// if !plan.is_empty() {
+///     renovate.apply(plan, false).await?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct Renovate {
+    config: RenovateConfig,
+    #[derivative(Debug = "ignore")]
+    observer: Arc<dyn Observer>,
+    cache: bool,
+    /// the kinds of objects to plan, all of them if None
+    kinds: Option<BTreeSet<ObjectKind>>,
+    /// the schemas to plan, all of them if None
+    schemas: Option<BTreeSet<String>>,
+    /// asked whether a table which looks renamed is renamed, see `detect_renames`
+    #[derivative(Debug = "ignore")]
+    renames: Option<Arc<RenameConfirm>>,
+    /// the warnings collected so far, shared by the clones, see `diagnostics`
+    diagnostics: Arc<Mutex<Vec<Diagnostic>>>,
+}
+
+/// `confirm(old, new)` of `Renovate::detect_renames`
+type RenameConfirm = dyn Fn(&str, &str) -> bool + Send + Sync;
+
+impl Renovate {
+    pub fn new(config: RenovateConfig) -> Self {
+        Self {
+            config,
+            observer: Arc::new(NoopObserver),
+            cache: false,
+            kinds: None,
+            schemas: None,
+            renames: None,
+            diagnostics: Default::default(),
+        }
+    }
+
+    /// Load the configuration file, with the environment overrides and credentials resolved.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn open(path: impl AsRef<Path>, env: Option<&str>) -> Result<Self> {
+        let mut config = RenovateConfig::load(path).await?;
+        if let Some(env) = env {
+            config = config.for_env(env)?;
+        }
+        config.resolve_credentials().await?;
+        #[cfg(feature = "db")]
+        config.resolve_search_path().await;
+        Ok(Self::new(config))
+    }
+
+    /// Print the diff of every changed object and the apply progress to the terminal.
+    #[cfg(feature = "console")]
+    pub fn verbose(self, verbose: bool) -> Self {
+        if verbose {
+            self.observer(ConsoleObserver)
+        } else {
+            self.observer(NoopObserver)
+        }
+    }
+
+    /// Report the progress of fetch, plan and apply to the given observer.
+    pub fn observer(mut self, observer: impl Observer + 'static) -> Self {
+        self.observer = Arc::new(observer);
+        self
+    }
+
+    /// Cache the parsed local schema under `.renovate/cache` of the local repo, so that it is only
+    /// parsed (and normalized) again when the sql files change. Disabled by default.
+    pub fn cache(mut self, enabled: bool) -> Self {
+        self.cache = enabled;
+        self
+    }
+
+    /// Plan only the objects of the `only` kinds (every kind if empty) except the `skip` ones, e.g.
+    /// to roll out the privileges separately from the structural changes. The schemas are still
+    /// created, but never dropped then.
+    pub fn kinds(mut self, only: &[ObjectKind], skip: &[ObjectKind]) -> Self {
+        let only = if only.is_empty() {
+            &ObjectKind::ALL[..]
+        } else {
+            only
+        };
+        self.kinds = Some(
+            only.iter()
+                .filter(|kind| !skip.contains(kind))
+                .copied()
+                .collect(),
+        );
+        self
+    }
+
+    /// Plan only the objects in these schemas, e.g. for the teams owning different schemas of a
+    /// shared database to deploy independently.
+    pub fn schemas(mut self, schemas: &[String]) -> Self {
+        self.schemas = Some(schemas.iter().cloned().collect());
+        self
+    }
+
+    /// Plan a table dropped while one with the same definition is created in the same schema
+    /// under another name as `ALTER TABLE ... RENAME TO` if `confirm(old, new)` returns true,
+    /// instead of dropping it along with its data.
+    pub fn detect_renames(
+        mut self,
+        confirm: impl Fn(&str, &str) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.renames = Some(Arc::new(confirm));
+        self
+    }
+
+    pub fn config(&self) -> &RenovateConfig {
+        &self.config
+    }
+
+    /// The warnings collected so far by the loads, fetches and plans of this instance and its
+    /// clones, e.g. the statements skipped since renovate doesn't support them. Each is also
+    /// reported to the observer as it's collected.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.lock().unwrap().clone()
+    }
+
+    /// Collect the warnings, and report them to the observer. The ones already collected, e.g.
+    /// as the local repo is loaded again, are not repeated.
+    fn report(&self, diagnostics: Vec<Diagnostic>) {
+        let mut collected = self.diagnostics.lock().unwrap();
+        for diagnostic in diagnostics {
+            if !collected.contains(&diagnostic) {
+                self.observer.on_diagnostic(&diagnostic);
+                collected.push(diagnostic);
+            }
+        }
+    }
+
+    /// Parse the sql files in the local repo without normalizing them.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn load_local(&self) -> Result<DatabaseSchema> {
+        let sql = self.local_sql().await?;
+        let cache = self.schema_cache("local", &sql, &[]);
+        if let Some(schema) = cache.get().await {
+            return Ok(schema);
+        }
+        let schema = SqlLoader::new(&sql).parse()?;
+        cache.put(&schema).await;
+        Ok(schema)
+    }
+
+    /// Load the local repo and normalize it via a temp database on the local server.
+    #[cfg(feature = "db")]
+    pub async fn normalize_local(&self) -> Result<DatabaseSchema> {
+        let sql = self.local_sql().await?;
+        // the normalized result depends on the server it is normalized on
+        let cache = self.schema_cache("normalized", &sql, &[&self.config.url]);
+        if let Some(schema) = cache.get().await {
+            return Ok(schema);
+        }
+        let schema = self.database().normalize(&sql).await?;
+        cache.put(&schema).await;
+        Ok(schema)
+    }
+
+    /// Retrieve the schema of the local database, or the remote one if `remote` is true.
+    #[cfg(feature = "db")]
+    pub async fn load_database(&self, remote: bool) -> Result<DatabaseSchema> {
+        let (schema, diagnostics) = self.database().load_with_diagnostics(remote).await?;
+        self.report(diagnostics);
+        schema.notify_fetched(self.observer.as_ref());
+        Ok(schema)
+    }
+
+    /// Retrieve the schema of the local database and save it to the local repo.
+    #[cfg(feature = "db")]
+    pub async fn fetch(&self) -> Result<DatabaseSchema> {
+        let db_repo = self.database();
+        // unset, it's taken from the search_path already, see `resolve_search_path`
+        let default_schema = self.config.diff.default_schema();
+        if let Some(schema) = db_repo.current_schema(false).await? {
+            if schema != default_schema {
+                let message = format!(
+                    "the search_path of the database starts with {}, but the unqualified names in the local files are in `default_schema` {}",
+                    schema, default_schema
+                );
+                self.report(vec![Diagnostic::new(DiagnosticKind::SearchPath, message)]);
+            }
+        }
+        let (schema, diagnostics) = db_repo.fetch_with_diagnostics().await?;
+        self.report(diagnostics);
+        schema.notify_fetched(self.observer.as_ref());
+        Ok(schema)
+    }
+
+    /// Generate the migration sqls to transit `remote` to `local`. The objects annotated with
+    /// `-- renovate:ignore` in the local repo are left out of both.
+    pub fn diff(&self, local: &DatabaseSchema, remote: &DatabaseSchema) -> Result<Vec<String>> {
+        self.diff_ignoring(local, remote, self.ignored_schema()?)
+    }
+
+    /// same as `diff`, with the objects of `ignored` left out of both, e.g. the ones ignored by
+    /// the store the local schema is loaded from rather than by the working tree
+    fn diff_ignoring(
+        &self,
+        local: &DatabaseSchema,
+        remote: &DatabaseSchema,
+        ignored: Option<DatabaseSchema>,
+    ) -> Result<Vec<String>> {
+        let unignored;
+        let (local, remote) = match ignored {
+            Some(ignored) => {
+                unignored = (local.without(&ignored), remote.without(&ignored));
+                (&unignored.0, &unignored.1)
+            }
+            None => (local, remote),
+        };
+        let options = PlanOptions {
+            kinds: self.kinds.as_ref(),
+            schemas: self.schemas.as_ref(),
+            renames: self.renames.as_deref(),
+        };
+        let (plan, diagnostics) =
+            local.plan_with_options(remote, &self.config.diff, options, self.observer.as_ref())?;
+        self.report(diagnostics);
+        Ok(apply_order(plan, &self.order_rules()?))
+    }
+
+    /// Make a migration plan. If `remote` is false, the local repo is diffed against the local
+    /// database; otherwise the local database is diffed against the remote database. Fail if the
+    /// plan uses features the target server (or the configured dialect) doesn't support.
+    #[cfg(feature = "db")]
+    pub async fn plan(&self, remote: bool) -> Result<Vec<String>> {
+        Ok(self.saved_plan(remote).await?.statements)
+    }
+
+    /// Same as `plan`, with the fingerprint of the target database so that the plan could be
+    /// saved and applied later, see `verify_plan`.
+    #[cfg(feature = "db")]
+    pub async fn saved_plan(&self, remote: bool) -> Result<SavedPlan> {
+        let local = if !remote {
+            self.normalize_local().await?
+        } else {
+            self.load_database(false).await?
+        };
+        let other = self.load_database(remote).await?;
+        let plan = self.diff(&local, &other)?;
+        let plan = interleave_data_migrations(plan, &self.local_data_migrations().await?)?;
+        self.check_plan(&plan, remote).await?;
+        Ok(SavedPlan {
+            fingerprint: other.fingerprint(),
+            remote,
+            statements: plan,
+        })
+    }
+
+    /// Fail if the plan uses features the target server, the dialect or the pooler doesn't
+    /// support. The `server_version_num` of cockroach is the postgres version it's compatible
+    /// with, not what it supports, so only the dialect is checked there.
+    #[cfg(feature = "db")]
+    async fn check_plan(&self, plan: &[String], remote: bool) -> Result<()> {
+        if self.config.dialect == Dialect::Postgres {
+            let version = self.database().server_version(remote).await?;
+            check_server_version(plan, version)?;
+        }
+        check_dialect(plan, self.config.dialect)?;
+        check_pooler(plan, self.config.connection.pooler)
+    }
+
+    /// Whether the target database of the saved plan is still in the state the plan was computed
+    /// against. A plan computed against a stale state shouldn't be applied.
+    #[cfg(feature = "db")]
+    pub async fn verify_plan(&self, plan: &SavedPlan) -> Result<bool> {
+        let current = self.load_database(plan.remote).await?;
+        Ok(current.fingerprint() == plan.fingerprint)
+    }
+
+    /// Make a migration plan offline: the local repo is diffed against a schema-only pg_dump
+    /// file instead of a database. The local repo is parsed as is since there's no database to
+    /// normalize it.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn plan_against(&self, dump: impl AsRef<Path>) -> Result<Vec<String>> {
+        let dump = dump.as_ref();
+        let sql = tokio::fs::read_to_string(dump)
+            .await
+            .with_context(|| format!("Failed to read {}", dump.display()))?;
+        let local = self.load_local().await?;
+        let (other, diagnostics) =
+            SqlLoader::new(sql).parse_with_diagnostics(&dump.display().to_string())?;
+        if self.config.diff.strict {
+            check_strict(&diagnostics)?;
+        }
+        self.report(diagnostics);
+        let plan = self.diff(&local, &other)?;
+        interleave_data_migrations(plan, &self.local_data_migrations().await?)
+    }
+
+    /// Make a migration plan from the schema in the `old` directory to the one in `new`, without
+    /// any database, e.g. in CI runners with no postgres. Both are parsed as is like
+    /// `plan_against`, and `output.base` doesn't apply.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn plan_between(
+        &self,
+        old: impl AsRef<Path>,
+        new: impl AsRef<Path>,
+    ) -> Result<Vec<String>> {
+        let mut schemas = Vec::with_capacity(2);
+        for dir in [old.as_ref(), new.as_ref()] {
+            if !dir.is_dir() {
+                anyhow::bail!("{} is not a directory", dir.display());
+            }
+            let sql = self.qualify_local(LocalRepo::new(dir).load_sql().await?)?;
+            schemas.push(SqlLoader::new(sql).parse()?);
+        }
+        let ignored = self.ignored_schema_of(local_ignored_statements(new.as_ref())?)?;
+        let plan = self.diff_ignoring(&schemas[1], &schemas[0], ignored)?;
+        let schema = self.config.diff.default_schema();
+        let migrations = load_data_migrations(&LocalRepo::new(new.as_ref()))
+            .await?
+            .into_iter()
+            .map(|migration| migration.qualify(schema))
+            .collect::<Result<Vec<_>>>()?;
+        interleave_data_migrations(plan, &migrations)
+    }
+
+    /// The services to redeploy along with the plan, each with its tables or columns the plan
+    /// changes, from the `-- renovate:services` annotations in the sql files of the local repo,
+    /// including the ones under `_meta` which `fetch` never rewrites.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn affected_services(
+        &self,
+        plan: &[String],
+    ) -> Result<std::collections::BTreeMap<String, BTreeSet<String>>> {
+        let pattern = self.config.output.path.join("**/*.sql");
+        let files = glob::glob_with(&pattern.to_string_lossy(), visible_files())?;
+        let mut annotations = Vec::new();
+        for file in files.filter_map(Result::ok) {
+            let content = tokio::fs::read_to_string(&file)
+                .await
+                .with_context(|| format!("Failed to read {}", file.display()))?;
+            annotations.extend(
+                extract_service_annotations(&content, self.config.diff.default_schema())
+                    .with_context(|| format!("Failed to load {}", file.display()))?,
+            );
+        }
+        Ok(affected_services(plan, &annotations))
+    }
+
+    /// Record the local schema as of the current commit of the local repo under `name`, see
+    /// `plan_to_tag`. The local repo must be clean so that the commit has the tagged schema; the
+    /// tags file is committed afterwards.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn tag(&self, name: &str, vcs: &dyn Vcs) -> Result<SchemaTag> {
+        if vcs.is_dirty()? {
+            anyhow::bail!(
+                "The local repo has uncommitted changes. Commit them before tagging the schema."
+            );
+        }
+        let commit = match vcs.head()? {
+            Some(commit) => commit,
+            None => anyhow::bail!("The local repo has no commit to tag, tags need git."),
+        };
+        let tag = SchemaTag {
+            name: name.to_owned(),
+            fingerprint: self.load_local().await?.fingerprint(),
+            commit,
+            created_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_secs(),
+        };
+        add_tag(&self.config.output.path, tag.clone())?;
+        vcs.commit(&format!("tag the schema as {}", name))?;
+        Ok(tag)
+    }
+
+    /// Make a migration plan from the local database back to the schema tagged as `name`. The
+    /// tagged schema is read from the tagged commit, layered on the current `output.base`, and
+    /// the working tree is untouched. The data migrations are not replayed.
+    #[cfg(feature = "db")]
+    pub async fn plan_to_tag(&self, name: &str, vcs: &dyn Vcs) -> Result<Vec<String>> {
+        let tags = load_tags(&self.config.output.path)?;
+        let tag = match tags.into_iter().find(|t| t.name == name) {
+            Some(tag) => tag,
+            None => anyhow::bail!("Tag {} is not found in {}", name, TAGS_FILE),
+        };
+        let files = vcs.files_at(&tag.commit)?;
+        let mut ignored = ignored_statements(&files)?;
+        if let Some(base) = &self.config.output.base {
+            ignored.extend(local_ignored_statements(base)?);
+        }
+        let sql = self.store_sql(MemoryStore::from(files)).await?;
+        if SqlLoader::new(&sql).parse()?.fingerprint() != tag.fingerprint {
+            let message = format!(
+                "the schema at commit {} doesn't match the fingerprint of the tag, it may be tagged by another renovate build",
+                tag.commit
+            );
+            self.report(vec![
+                Diagnostic::new(DiagnosticKind::TagMismatch, message).source(name)
+            ]);
+        }
+
+        let local = self.database().normalize(&sql).await?;
+        let other = self.load_database(false).await?;
+        let plan = self.diff_ignoring(&local, &other, self.ignored_schema_of(ignored)?)?;
+        self.check_plan(&plan, false).await?;
+        Ok(plan)
+    }
+
+    /// The objects changed by the local sql `file` since the commit `since` of the local repo,
+    /// each with the tree of the objects depending on it, i.e. what the change may affect. The
+    /// dependents of the removed objects are found in the schema at `since`.
+    #[cfg(feature = "cli")]
+    pub(crate) async fn impact(
+        &self,
+        file: &Path,
+        since: &str,
+        vcs: &dyn Vcs,
+    ) -> Result<Vec<(ChangeKind, DepTree)>> {
+        let without_cur_dir = |path: &Path| -> PathBuf {
+            path.components()
+                .filter(|c| !matches!(c, Component::CurDir))
+                .collect()
+        };
+        let root = without_cur_dir(&self.config.output.path);
+        let relative = without_cur_dir(file)
+            .strip_prefix(&root)
+            .map(Path::to_path_buf)
+            .with_context(|| {
+                format!(
+                    "{} is not in the local repo {}",
+                    file.display(),
+                    root.display()
+                )
+            })?;
+        let old_files = vcs.files_at(since)?;
+        let old = old_files.get(&relative).cloned().unwrap_or_default();
+        let new = match tokio::fs::read_to_string(file).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", file.display())),
+        };
+        let parse = |content: &str| -> Result<DatabaseSchema> {
+            let (sql, _) = extract_data_migrations(content)
+                .with_context(|| format!("Failed to load {}", file.display()))?;
+            SqlLoader::new(self.qualify_local(sql)?).parse()
+        };
+        let changes = parse(&new)?.changed_objects(&parse(&old)?);
+        if changes.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let schema = self.config.diff.default_schema();
+        let current = DependencyGraph::new(&self.load_local().await?, schema);
+        let previous = if changes
+            .iter()
+            .any(|(kind, ..)| *kind == ChangeKind::Removed)
+        {
+            let sql = self.store_sql(MemoryStore::from(old_files)).await?;
+            DependencyGraph::new(&SqlLoader::new(sql).parse()?, schema)
+        } else {
+            DependencyGraph::default()
+        };
+        Ok(changes
+            .into_iter()
+            .map(|(kind, type_name, id)| {
+                let node = DepNode { type_name, id };
+                let graph = match kind {
+                    ChangeKind::Removed => &previous,
+                    _ => &current,
+                };
+                let tree = match graph.contains(&node) {
+                    true => graph.tree(&node, true),
+                    false => DepTree {
+                        node,
+                        children: vec![],
+                        cycle: false,
+                        repeated: false,
+                    },
+                };
+                (kind, tree)
+            })
+            .collect())
+    }
+
+    /// Hash of the catalog of the local database, or the remote one if `remote` is true, see
+    /// `DatabaseRepo::catalog_fingerprint`. Much cheaper than loading the schema.
+    #[cfg(feature = "db")]
+    pub async fn catalog_fingerprint(&self, remote: bool) -> Result<CatalogFingerprint> {
+        self.database().catalog_fingerprint(remote).await
+    }
+
+    /// Record the sync of the local repo with the local database, or the remote one if `remote`
+    /// is true, as the last one of `env`, e.g. after a fetch or an apply. The current commit is
+    /// recorded if the local repo is clean, so that the next fetch merges from it.
+    #[cfg(feature = "db")]
+    pub async fn record_sync(&self, env: &str, remote: bool, vcs: &dyn Vcs) -> Result<SyncState> {
+        let fingerprint = match self.config.dialect {
+            Dialect::Cockroach => None,
+            Dialect::Postgres => Some(self.catalog_fingerprint(remote).await?.overall),
+        };
+        let commit = match vcs.is_dirty()? {
+            true => None,
+            false => vcs.head()?,
+        };
+        let state = SyncState {
+            fingerprint,
+            remote,
+            commit,
+            synced_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_secs(),
+        };
+        save_sync_state(&self.config.output.path, env, state.clone())?;
+        Ok(state)
+    }
+
+    /// The last sync of `env` recorded in the local repo, if any, see `record_sync`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn last_sync(&self, env: &str) -> Result<Option<SyncState>> {
+        Ok(load_sync_states(&self.config.output.path)?.remove(env))
+    }
+
+    /// Record the contract phase deferred by `apply --stop-after expand` in the target database
+    #[cfg(feature = "db")]
+    pub async fn save_checkpoint(&self, remote: bool, statements: &[String]) -> Result<()> {
+        self.database().save_checkpoint(remote, statements).await
+    }
+
+    /// The contract phase waiting to be resumed in the target database, if any
+    #[cfg(feature = "db")]
+    pub async fn checkpoint(&self, remote: bool) -> Result<Option<Checkpoint>> {
+        self.database().checkpoint(remote).await
+    }
+
+    /// Remove the checkpoint of the target database once the contract phase is applied
+    #[cfg(feature = "db")]
+    pub async fn clear_checkpoint(&self, remote: bool) -> Result<()> {
+        self.database().clear_checkpoint(remote).await
+    }
+
+    /// The tables and columns dropped by the plan whose table has more rows than
+    /// `apply.data_loss_threshold`, with the row count.
+    #[cfg(feature = "db")]
+    pub async fn data_at_risk(
+        &self,
+        plan: &[String],
+        remote: bool,
+    ) -> Result<Vec<(DroppedData, u64)>> {
+        let dropped = dropped_data(plan);
+        if dropped.is_empty() {
+            return Ok(Vec::new());
+        }
+        let counts = self
+            .database()
+            .row_counts(dropped.iter().map(|d| d.table.as_str()), remote)
+            .await?;
+        Ok(dropped
+            .into_iter()
+            .filter_map(|d| counts.get(&d.table).map(|rows| (d, *rows)))
+            .filter(|(_, rows)| *rows > self.config.apply.data_loss_threshold)
+            .collect())
+    }
+
+    /// Apply the migration plan to a throwaway clone of the target database, see
+    /// `DatabaseRepo::dry_run`. The target database itself is untouched.
+    #[cfg(feature = "db")]
+    pub async fn dry_run(&self, plan: &[String], remote: bool) -> Result<Vec<DryRunStatement>> {
+        self.database().dry_run(plan, remote).await
+    }
+
+    /// Run the migration plan against the target database and roll it back, see
+    /// `DatabaseRepo::validate`.
+    #[cfg(feature = "db")]
+    pub async fn validate(&self, plan: &[String], remote: bool) -> Result<Vec<DryRunStatement>> {
+        self.database().validate(plan, remote).await
+    }
+
+    /// The planner estimates of the data migration statements of the plan against the target
+    /// database, see `DatabaseRepo::explain`.
+    #[cfg(feature = "db")]
+    pub async fn explain_data_migrations(
+        &self,
+        plan: &[String],
+        remote: bool,
+    ) -> Result<Vec<DmlEstimate>> {
+        let migrations: BTreeSet<String> = self
+            .local_data_migrations()
+            .await?
+            .into_iter()
+            .flat_map(|migration| migration.statements)
+            .collect();
+        let statements: Vec<String> = plan
+            .iter()
+            .filter(|sql| migrations.contains(*sql) && is_explainable(sql))
+            .cloned()
+            .collect();
+        self.database().explain(&statements, remote).await
+    }
+
+    /// Apply the whole local schema, without any data, to the empty database at `url`, e.g. the
+    /// database of a review app. The configured databases and the local repo are untouched.
+    #[cfg(feature = "db")]
+    pub async fn clone_to(&self, local: &DatabaseSchema, url: &str) -> Result<ApplyReport> {
+        let config = RenovateConfig {
+            url: url.to_owned(),
+            remote_url: url.to_owned(),
+            ..self.config.clone()
+        };
+        let target = DatabaseRepo::new(&config);
+        let current = target.load().await?;
+        if !current.missing_in(&DatabaseSchema::default()).is_empty() {
+            anyhow::bail!(
+                "The target database is not empty. Only an empty database could be cloned to."
+            );
+        }
+        let plan = self.diff(local, &current)?;
+        let result = target.apply_to(&plan, url, self.observer.as_ref()).await;
+        self.finish_report(result)
+    }
+
+    /// Apply the migration plan to the local database, or the remote one if `remote` is true.
+    /// Return what was run.
+    #[cfg(feature = "db")]
+    pub async fn apply(&self, plan: Vec<String>, remote: bool) -> Result<ApplyReport> {
+        let result = self
+            .database()
+            .apply_with(plan, remote, self.observer.as_ref())
+            .await;
+        self.finish_report(result)
+    }
+
+    /// Same as `apply` for the expand phase of a plan, without fetching the local repo
+    /// afterwards, see `DatabaseRepo::apply_expand`
+    #[cfg(feature = "db")]
+    pub async fn apply_expand(&self, plan: Vec<String>, remote: bool) -> Result<ApplyReport> {
+        let result = self
+            .database()
+            .apply_expand(plan, remote, self.observer.as_ref())
+            .await;
+        self.finish_report(result)
+    }
+
+    /// mark the slow statements of the report, or of the partial one of an `ApplyFailed`
+    #[cfg(feature = "db")]
+    fn finish_report(&self, result: Result<ApplyReport>) -> Result<ApplyReport> {
+        match result {
+            Ok(report) => Ok(self.mark_slow_statements(report)),
+            Err(e) => match e.downcast::<ApplyFailed>() {
+                Ok(failed) => Err(ApplyFailed {
+                    report: self.mark_slow_statements(failed.report),
+                    source: failed.source,
+                }
+                .into()),
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    /// flag the statements of the report which took longer than `apply.slow_statement_ms`
+    #[cfg(feature = "db")]
+    fn mark_slow_statements(&self, mut report: ApplyReport) -> ApplyReport {
+        let threshold = self.config.apply.slow_statement_ms;
+        report.slow_statement_ms = threshold;
+        for statement in &mut report.statements {
+            statement.slow = statement.elapsed_ms > threshold;
+        }
+        report
+    }
+
+    /// The vcs of the local repo at `output.path`, as configured by `vcs` in the config, the same
+    /// one the CLI checks and commits. Renovate itself never commits on behalf of library users;
+    /// use `VcsBackend::None` to make this a no-op as well.
+    pub fn vcs(&self) -> Box<dyn Vcs> {
+        self.config.vcs.open(&self.config.output.path)
+    }
+
+    /// sql of the local repo, layered on the `output.base` directory if it is set, with the
+    /// unqualified names qualified by `diff.default_schema`
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn local_sql(&self) -> Result<String> {
+        let local = LocalRepo::new(&self.config.output.path);
+        let (sql, diagnostics) = match &self.config.output.base {
+            Some(base) => {
+                let store = OverlayStore::new(LocalRepo::new(base), local);
+                (store.load_sql().await?, store_diagnostics(&store).await?)
+            }
+            None => (local.load_sql().await?, store_diagnostics(&local).await?),
+        };
+        if self.config.diff.strict {
+            check_strict(&diagnostics)?;
+        }
+        self.report(diagnostics);
+        self.qualify_local(sql)
+    }
+
+    /// the sql of the local schema in the store instead of the local repo, e.g. at a commit, see
+    /// `local_sql`
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn store_sql(&self, store: MemoryStore) -> Result<String> {
+        let sql = match &self.config.output.base {
+            Some(base) => {
+                OverlayStore::new(LocalRepo::new(base), store)
+                    .load_sql()
+                    .await?
+            }
+            None => store.load_sql().await?,
+        };
+        self.qualify_local(sql)
+    }
+
+    /// qualify the unqualified names of the local sql with `diff.default_schema`
+    #[cfg(not(target_arch = "wasm32"))]
+    fn qualify_local(&self, sql: String) -> Result<String> {
+        // names are keyed by `public` if not qualified
+        match self.config.diff.default_schema.as_deref() {
+            Some(schema) if schema != "public" => {
+                let message = format!(
+                    "the unqualified names of the local repo are qualified by `diff.default_schema` {}",
+                    schema
+                );
+                self.report(vec![Diagnostic::new(DiagnosticKind::Normalized, message)]);
+                qualify_sql(&sql, schema)
+            }
+            _ => Ok(sql),
+        }
+    }
+
+    /// the data migration blocks of the local repo, qualified by `diff.default_schema`
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn local_data_migrations(&self) -> Result<Vec<DataMigration>> {
+        let local = LocalRepo::new(&self.config.output.path);
+        let migrations = match &self.config.output.base {
+            Some(base) => {
+                load_data_migrations(&OverlayStore::new(LocalRepo::new(base), local)).await?
+            }
+            None => load_data_migrations(&local).await?,
+        };
+        let schema = self.config.diff.default_schema();
+        migrations
+            .into_iter()
+            .map(|migration| migration.qualify(schema))
+            .collect()
+    }
+
+    /// the rules of the `plan.order` file at the root of the local repo, if it exists
+    #[cfg(not(target_arch = "wasm32"))]
+    fn order_rules(&self) -> Result<Vec<OrderRule>> {
+        let path = self.config.output.path.join(ORDER_FILE);
+        match std::fs::read_to_string(&path) {
+            Ok(content) => OrderRule::parse(&content, self.config.diff.default_schema()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn order_rules(&self) -> Result<Vec<OrderRule>> {
+        Ok(Vec::new())
+    }
+
+    /// The estimated rows and size of the tables as of the last fetch with `output.stats`, keyed
+    /// by their qualified name, e.g. to show the size of the tables a plan rewrites offline
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn table_stats(&self) -> Result<std::collections::BTreeMap<String, TableStats>> {
+        load_stats(&self.config.output.path)
+    }
+
+    /// The objects annotated with `-- renovate:ignore` in the local repo, which are never diffed
+    /// nor planned, e.g. to list them along with the plan
+    pub fn suppressed_objects(&self) -> Result<Vec<String>> {
+        Ok(self
+            .ignored_schema()?
+            .map(|schema| schema.object_names())
+            .unwrap_or_default())
+    }
+
+    /// the statements ignored by the working tree of the local repo and `output.base`, see
+    /// `ignored_statements`, None if there's none
+    #[cfg(not(target_arch = "wasm32"))]
+    fn ignored_schema(&self) -> Result<Option<DatabaseSchema>> {
+        let mut statements = local_ignored_statements(&self.config.output.path)?;
+        if let Some(base) = &self.config.output.base {
+            statements.extend(local_ignored_statements(base)?);
+        }
+        self.ignored_schema_of(statements)
+    }
+
+    /// the schema of the ignored statements, qualified like the local repo, None if there's none
+    #[cfg(not(target_arch = "wasm32"))]
+    fn ignored_schema_of(&self, statements: Vec<String>) -> Result<Option<DatabaseSchema>> {
+        if statements.is_empty() {
+            return Ok(None);
+        }
+        let sql = statements
+            .into_iter()
+            .map(|statement| format!("{};\n", statement))
+            .collect();
+        Ok(Some(SqlLoader::new(self.qualify_local(sql)?).parse()?))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn ignored_schema(&self) -> Result<Option<DatabaseSchema>> {
+        Ok(None)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn schema_cache(&self, name: &str, sql: &str, extra: &[&str]) -> SchemaCache {
+        if self.cache {
+            SchemaCache::new(&self.config.output.path, name, sql, extra)
+        } else {
+            SchemaCache::disabled()
+        }
+    }
+
+    #[cfg(feature = "db")]
+    fn database(&self) -> DatabaseRepo {
+        DatabaseRepo::new(&self.config)
+    }
+}
+
+/// the statements ignored by the local repo at `dir`, see `ignored_statements`
+#[cfg(not(target_arch = "wasm32"))]
+fn local_ignored_statements(dir: &Path) -> Result<Vec<String>> {
+    let mut files = SchemaFiles::new();
+    for file in LocalRepo::new(dir).files()? {
+        let content = std::fs::read_to_string(&file)
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        files.insert(file.strip_prefix(dir)?.to_owned(), content);
+    }
+    match std::fs::read_to_string(dir.join(IGNORE_FILE)) {
+        Ok(content) => {
+            files.insert(IGNORE_FILE.into(), content);
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    ignored_statements(&files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use url::Url;
+
+    /// a renovate for the local repo at `path`, with a database which is never connected to
+    fn renovate_at(path: &Path) -> Renovate {
+        let mut config = RenovateConfig::new(Url::parse("postgres://localhost:5432/test").unwrap());
+        config.output.path = path.to_owned();
+        Renovate::new(config)
+    }
+
+    #[cfg(feature = "git")]
+    #[test]
+    fn vcs_should_be_the_one_of_the_local_repo() -> Result<()> {
+        let dir = tempdir()?;
+        std::fs::write(dir.path().join("all.sql"), "CREATE TABLE foo (id int);")?;
+        let git = crate::VcsBackend::Git.open(dir.path());
+        git.commit("init")?;
+
+        let mut renovate = renovate_at(dir.path());
+        renovate.config.vcs = crate::VcsBackend::Git;
+        let vcs = renovate.vcs();
+        assert_eq!(vcs.head()?, git.head()?);
+        assert!(!vcs.is_dirty()?);
+
+        std::fs::write(dir.path().join("all.sql"), "CREATE TABLE foo (id bigint);")?;
+        assert!(vcs.is_dirty()?);
+        Ok(())
+    }
+
+    #[test]
+    fn diff_should_plan_the_kinds_and_schemas_only() -> Result<()> {
+        let dir = tempdir()?;
+        let remote = SqlLoader::new(
+            "CREATE TABLE billing.invoices (id int); CREATE TABLE public.legacy (id int);",
+        )
+        .parse()?;
+        let local = SqlLoader::new(
+            r#"
+            CREATE TABLE billing.invoices (id int, total numeric);
+            GRANT SELECT ON billing.invoices TO accountant;
+            CREATE TABLE public.todos (id int);
+            "#,
+        )
+        .parse()?;
+        let renovate = renovate_at(dir.path());
+        assert_eq!(renovate.diff(&local, &remote)?.len(), 4);
+
+        let privileges = renovate.clone().kinds(&[ObjectKind::Privileges], &[]);
+        assert_eq!(
+            privileges.diff(&local, &remote)?,
+            vec!["GRANT select ON billing.invoices TO accountant"]
+        );
+        let billing = renovate.clone().schemas(&["billing".to_owned()]);
+        assert_eq!(
+            billing.diff(&local, &remote)?,
+            vec![
+                "ALTER TABLE ONLY billing.invoices ADD COLUMN total numeric",
+                "GRANT select ON billing.invoices TO accountant",
+            ]
+        );
+        let structure = billing.kinds(&[], &[ObjectKind::Privileges]);
+        assert_eq!(
+            structure.diff(&local, &remote)?,
+            vec!["ALTER TABLE ONLY billing.invoices ADD COLUMN total numeric"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn diagnostics_should_be_reported_once_across_clones() -> Result<()> {
+        #[derive(Default, Clone)]
+        struct Recorder(Arc<Mutex<Vec<Diagnostic>>>);
+
+        impl Observer for Recorder {
+            fn on_diagnostic(&self, diagnostic: &Diagnostic) {
+                self.0.lock().unwrap().push(diagnostic.clone());
+            }
+        }
+
+        let dir = tempdir()?;
+        std::fs::write(
+            dir.path().join("all.sql"),
+            "CREATE TABLE public.todos (id int);\nCREATE DOMAIN public.email AS text;\n",
+        )?;
+        let recorder = Recorder::default();
+        let renovate = renovate_at(dir.path()).observer(recorder.clone());
+        renovate.load_local().await?;
+        renovate.clone().load_local().await?;
+
+        let diagnostics = renovate.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::SkippedStatement);
+        assert_eq!(*recorder.0.lock().unwrap(), diagnostics);
+        Ok(())
+    }
+
+    #[test]
+    fn suppressed_objects_should_be_left_out_of_the_diff() -> Result<()> {
+        let dir = tempdir()?;
+        std::fs::write(
+            dir.path().join("all.sql"),
+            r#"
+            CREATE TABLE public.todos (id int);
+            -- renovate:ignore
+            CREATE FUNCTION public.audit() RETURNS int AS $$ SELECT 1 $$ LANGUAGE sql;
+            "#,
+        )?;
+        std::fs::create_dir(dir.path().join("_meta"))?;
+        std::fs::write(
+            dir.path().join(IGNORE_FILE),
+            "CREATE TABLE public.legacy (id int);\n",
+        )?;
+        let renovate = renovate_at(dir.path());
+        let mut suppressed = renovate.suppressed_objects()?;
+        suppressed.sort();
+        assert_eq!(
+            suppressed,
+            vec!["function public.audit", "table public.legacy"]
+        );
+
+        let local = SqlLoader::new(
+            "CREATE TABLE public.todos (id int); CREATE FUNCTION public.audit() RETURNS int AS $$ SELECT 1 $$ LANGUAGE sql;",
+        )
+        .parse()?;
+        let remote = SqlLoader::new(
+            "CREATE TABLE public.legacy (id int); CREATE FUNCTION public.audit() RETURNS int AS $$ SELECT 2 $$ LANGUAGE sql;",
+        )
+        .parse()?;
+        let plan = renovate.diff(&local, &remote)?;
+        assert_eq!(plan.len(), 1);
+        assert!(plan[0].starts_with("CREATE TABLE public.todos"));
+        Ok(())
+    }
+
+    #[test]
+    fn order_rules_should_reorder_the_diff() -> Result<()> {
+        let dir = tempdir()?;
+        let remote = DatabaseSchema::default();
+        let local =
+            SqlLoader::new("CREATE TABLE public.audit (id int); CREATE TABLE public.log (id int);")
+                .parse()?;
+        let renovate = renovate_at(dir.path());
+        let plan = renovate.diff(&local, &remote)?;
+        assert_eq!(plan.len(), 2);
+        assert!(plan[0].starts_with("CREATE TABLE public.audit"));
+
+        std::fs::write(
+            dir.path().join(ORDER_FILE),
+            "# audit is only written to by the triggers of log\naudit after public.log\n",
+        )?;
+        assert_eq!(
+            renovate.diff(&local, &remote)?,
+            vec![plan[1].clone(), plan[0].clone()]
+        );
+
+        std::fs::write(dir.path().join(ORDER_FILE), "audit depends on log\n")?;
+        assert!(renovate.diff(&local, &remote).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn load_local_should_be_cached_until_the_sql_changes() -> Result<()> {
+        let dir = tempdir()?;
+        let file = dir.path().join("all.sql");
+        std::fs::write(&file, "CREATE TABLE public.todos (id int);")?;
+        let renovate = renovate_at(dir.path()).cache(true);
+        let schema = renovate.load_local().await?;
+        assert_eq!(schema.object_names(), vec!["table public.todos"]);
+
+        // served from the cache as long as the hash of the sql matches
+        let entry = dir.path().join(".renovate/cache/local.json");
+        let mut cached: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&entry)?)?;
+        cached["schema"] =
+            serde_json::to_value(SqlLoader::new("CREATE TABLE public.cached (id int);").parse()?)?;
+        std::fs::write(&entry, cached.to_string())?;
+        let schema = renovate.load_local().await?;
+        assert_eq!(schema.object_names(), vec!["table public.cached"]);
+        // not used when disabled
+        let schema = renovate.clone().cache(false).load_local().await?;
+        assert_eq!(schema.object_names(), vec!["table public.todos"]);
+
+        std::fs::write(&file, "CREATE TABLE public.tags (id int);")?;
+        let schema = renovate.load_local().await?;
+        assert_eq!(schema.object_names(), vec!["table public.tags"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn plan_between_should_diff_two_directories() -> Result<()> {
+        let dir = tempdir()?;
+        let (old, new, repo, base) = (
+            dir.path().join("old"),
+            dir.path().join("new"),
+            dir.path().join("repo"),
+            dir.path().join("base"),
+        );
+        for path in [&old, &new, &repo, &base] {
+            std::fs::create_dir_all(path.join("_meta"))?;
+        }
+        std::fs::write(
+            old.join("all.sql"),
+            r#"
+            CREATE TABLE public.todos (id int);
+            -- renovate:data-migration for public.todos
+            DELETE FROM todos;
+            -- renovate:end
+            "#,
+        )?;
+        std::fs::write(
+            new.join("all.sql"),
+            r#"
+            CREATE TABLE public.todos (id int, title text);
+            -- renovate:ignore
+            CREATE FUNCTION public.audit() RETURNS int AS $$ SELECT 1 $$ LANGUAGE sql;
+            -- renovate:data-migration for public.todos
+            UPDATE todos SET title = 'untitled' WHERE title IS NULL;
+            -- renovate:end
+            "#,
+        )?;
+        // only the ignores of the new directory apply
+        let ignore_todos = "CREATE TABLE public.todos (id int);\n";
+        std::fs::write(old.join(IGNORE_FILE), ignore_todos)?;
+        std::fs::write(repo.join(IGNORE_FILE), ignore_todos)?;
+        std::fs::write(
+            base.join("base.sql"),
+            "CREATE TABLE public.shared (id int);",
+        )?;
+
+        let mut renovate = renovate_at(&repo);
+        renovate.config.output.base = Some(base);
+        assert_eq!(
+            renovate.plan_between(&old, &new).await?,
+            vec![
+                "ALTER TABLE ONLY public.todos ADD COLUMN title text",
+                "UPDATE public.todos SET title = 'untitled' WHERE title IS NULL",
+            ]
+        );
+
+        let e = renovate
+            .plan_between(old.join("all.sql"), &new)
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("is not a directory"));
+        Ok(())
+    }
+}
@@ -1,22 +1,47 @@
 #[cfg(feature = "cli")]
 pub mod commands;
+#[cfg(feature = "db")]
+mod compat;
 mod config;
+#[cfg(feature = "cli")]
+mod deps;
+mod diagnostics;
+mod facade;
 mod macros;
+mod observer;
 mod parser;
+mod plan;
 mod repo;
 mod schema;
+pub mod testing;
 mod types;
 mod utils;
+#[cfg(feature = "wasm")]
+mod wasm;
 
 use anyhow::Result;
 use async_trait::async_trait;
-use config::RenovateOutputConfig;
 use pg_query::NodeEnum;
-use std::{collections::BTreeSet, path::PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
-pub use config::RenovateConfig;
-pub use parser::DatabaseSchema;
+pub use config::{
+    Dialect, DiffAlgorithm, DiffFormat, Layout, Pooler, Preset, RenovateApplyConfig,
+    RenovateConfig, RenovateDatabaseConfig, RenovateDiffConfig, RenovateDiffRenderConfig,
+    RenovateEnvConfig, RenovateGitConfig, RenovateOutputConfig, RenovateRoleConfig, Unmanaged,
+    VcsBackend,
+};
+pub use facade::Renovate;
+pub use parser::{clear_handlers, register_handler, DatabaseSchema};
+pub use plan::{diff_sql, DroppedData};
+#[cfg(feature = "git")]
 pub use repo::git::{BumpVersion, GitRepo};
+#[cfg(feature = "db")]
+pub use repo::NOTICE_TARGET;
 
 #[async_trait]
 pub trait SchemaLoader {
@@ -31,6 +56,367 @@ pub trait SqlSaver {
     async fn save(&self, config: &RenovateOutputConfig) -> Result<()>;
 }
 
+/// Storage backend for the sql files of a schema. Paths are relative to the root of the store.
+#[async_trait]
+pub trait SchemaStore: Send + Sync {
+    /// list the managed sql files in the store, sorted by path
+    async fn list(&self) -> Result<Vec<PathBuf>>;
+    /// read the content of a sql file
+    async fn read(&self, path: &Path) -> Result<String>;
+    /// create or overwrite a sql file
+    async fn write(&self, path: &Path, content: &str) -> Result<()>;
+    /// remove a sql file
+    async fn remove(&self, path: &Path) -> Result<()>;
+}
+
+/// Version control used to snapshot the schema files before and after the changes
+pub trait Vcs: Send + Sync {
+    /// whether the working tree has uncommitted changes
+    fn is_dirty(&self) -> Result<bool>;
+    /// commit all the changes in the working tree, do nothing if it is clean
+    fn commit(&self, message: &str) -> Result<()>;
+    /// make sure `branch` would succeed, so that a command fails before writing any file
+    fn check_branch(&self, name: &str) -> Result<()> {
+        anyhow::bail!(
+            "cannot create branch {}: the repo is not managed by git",
+            name
+        )
+    }
+    /// create a branch at the current commit and switch to it, keeping the working tree as is
+    fn branch(&self, name: &str) -> Result<()> {
+        self.check_branch(name)
+    }
+    /// the id of the current commit, None if there's no commit yet
+    fn head(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+    /// the files of the repo at the given commit, keyed by their path relative to the repo
+    fn files_at(&self, commit: &str) -> Result<SchemaFiles> {
+        anyhow::bail!(
+            "cannot read the files at {}: the vcs has no history",
+            commit
+        )
+    }
+}
+
+/// How a schema object is changed in the diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// Kind of the schema objects, to plan only some of them, see `Renovate::kinds`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ObjectKind {
+    Types,
+    Enums,
+    Sequences,
+    /// the tables and their columns, and the sequences owned by the columns
+    Tables,
+    Constraints,
+    Indexes,
+    /// the row level security policies, and whether rls is enabled on the tables
+    Policies,
+    Owners,
+    Hypertables,
+    Views,
+    Mviews,
+    Functions,
+    Triggers,
+    Privileges,
+    /// the objects handled by the registered handlers
+    Custom,
+}
+
+impl ObjectKind {
+    pub const ALL: [ObjectKind; 15] = [
+        ObjectKind::Types,
+        ObjectKind::Enums,
+        ObjectKind::Sequences,
+        ObjectKind::Tables,
+        ObjectKind::Constraints,
+        ObjectKind::Indexes,
+        ObjectKind::Policies,
+        ObjectKind::Owners,
+        ObjectKind::Hypertables,
+        ObjectKind::Views,
+        ObjectKind::Mviews,
+        ObjectKind::Functions,
+        ObjectKind::Triggers,
+        ObjectKind::Privileges,
+        ObjectKind::Custom,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectKind::Types => "types",
+            ObjectKind::Enums => "enums",
+            ObjectKind::Sequences => "sequences",
+            ObjectKind::Tables => "tables",
+            ObjectKind::Constraints => "constraints",
+            ObjectKind::Indexes => "indexes",
+            ObjectKind::Policies => "policies",
+            ObjectKind::Owners => "owners",
+            ObjectKind::Hypertables => "hypertables",
+            ObjectKind::Views => "views",
+            ObjectKind::Mviews => "mviews",
+            ObjectKind::Functions => "functions",
+            ObjectKind::Triggers => "triggers",
+            ObjectKind::Privileges => "privileges",
+            ObjectKind::Custom => "custom",
+        }
+    }
+}
+
+impl std::fmt::Display for ObjectKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::str::FromStr for ObjectKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == s)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Unknown object kind {}, expected one of: {}",
+                    s,
+                    Self::ALL.map(|kind| kind.as_str()).join(", ")
+                )
+            })
+    }
+}
+
+/// Callbacks for the progress of fetch, plan and apply. All of them do nothing by default.
+pub trait Observer: Send + Sync {
+    /// an object is loaded from the database
+    fn on_object_fetched(&self, _type_name: &str, _id: &str) {}
+    /// the diff of an object is computed while planning
+    fn on_diff_computed(&self, _type_name: &str, _id: &str, _kind: ChangeKind, _diff: &str) {}
+    /// the hunks of the diff just reported by `on_diff_computed`
+    fn on_diff_hunks(&self, _type_name: &str, _id: &str, _hunks: &[DiffHunk]) {}
+    /// a statement of the migration plan is applied, `index` starts from 1
+    fn on_statement_applied(&self, _sql: &str, _index: usize, _total: usize) {}
+    /// the server sent a NOTICE / WARNING while applying the statement, e.g.
+    /// `NOTICE: identifier ... will be truncated to ...`
+    fn on_notice(&self, _sql: &str, _notice: &str) {}
+    /// a warning is collected while loading, fetching, diffing or planning, see
+    /// `Renovate::diagnostics`
+    fn on_diagnostic(&self, _diagnostic: &Diagnostic) {}
+}
+
+/// A warning collected while loading, fetching, diffing or planning, e.g. a statement skipped
+/// since renovate doesn't support it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    /// where it's found, e.g. `public/types.sql:3`, or `pg_dump:120` for the dump of a database
+    pub source: Option<String>,
+    pub message: String,
+    /// the statement it's about, if any
+    pub statement: Option<String>,
+}
+
+/// What a diagnostic is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticKind {
+    /// a statement no NodeItem handles, thus never planned, see `diff.strict`
+    SkippedStatement,
+    /// the owners set by `ALTER ... OWNER TO` on anything but the tables and sequences
+    OwnershipIgnored,
+    /// the local sql is rewritten before it's diffed, e.g. qualified by `diff.default_schema`
+    Normalized,
+    /// the search_path of the database doesn't start with `diff.default_schema`
+    SearchPath,
+    /// the schema at the commit of a tag doesn't match the fingerprint of the tag
+    TagMismatch,
+    /// a remote object the local repo doesn't define, left untouched with `diff.unmanaged: warn`
+    Unmanaged,
+    /// an object dropped and created again since it can't be altered, e.g. an enum type losing
+    /// a value
+    Recreated,
+    /// a fetched object which doesn't load back from its sql, kept as it is in the local repo
+    Unparsable,
+}
+
+/// Migration plan between two sets of sql, generated by `diff_sql`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct Plan {
+    /// the sql statements to migrate from the old schema to the new one
+    pub statements: Vec<String>,
+    /// the objects changed, in the order they were planned
+    pub changes: Vec<PlanChange>,
+    /// the statements which rewrite the whole table, thus hold an exclusive lock for long
+    pub rewrites: Vec<TableRewrite>,
+    /// the planner estimates of the data migrations, see `Renovate::explain_data_migrations`
+    pub estimates: Vec<DmlEstimate>,
+    /// the services to redeploy with their changed objects, see `Renovate::affected_services`
+    pub services: BTreeMap<String, BTreeSet<String>>,
+    /// the objects annotated with `-- renovate:ignore`, see `Renovate::suppressed_objects`
+    pub suppressed: Vec<String>,
+}
+
+/// A statement of the plan which forces a full table rewrite
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct TableRewrite {
+    pub statement: String,
+    /// what causes the rewrite, e.g. `column type change`
+    pub reason: String,
+    /// the size of the table as of the last fetch, see `Plan::with_stats`
+    pub size: Option<TableStats>,
+}
+
+/// The estimated rows and size of a table, saved to `_meta/stats.json` of the local repo on fetch
+/// with `output.stats`, so that they are known offline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct TableStats {
+    /// the planner estimate, 0 if the table was never analyzed
+    pub rows: u64,
+    /// the size of the table with its indexes and toast
+    pub bytes: u64,
+}
+
+/// The estimates of the query planner of the target database for a data migration statement
+/// of the plan, e.g. to spot a backfill which updates the whole table
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DmlEstimate {
+    pub statement: String,
+    /// total cost of the statement, in the units of the planner
+    pub cost: u64,
+    /// the rows the statement is estimated to touch
+    pub rows: u64,
+    /// whether a table is scanned without any filter, i.e. all of its rows are touched
+    pub full_scan: bool,
+    /// why the statement couldn't be explained, the estimates are 0 then
+    pub error: Option<String>,
+}
+
+/// A migration plan saved by `renovate schema plan --out`, pinned to the target database state
+/// it was computed against
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedPlan {
+    /// `DatabaseSchema::fingerprint` of the target database at plan time
+    pub fingerprint: String,
+    /// whether the plan targets the remote database instead of the local one
+    pub remote: bool,
+    pub statements: Vec<String>,
+}
+
+/// A named snapshot of the local schema recorded by `renovate schema tag`, to plan the migration
+/// back to it later
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaTag {
+    pub name: String,
+    /// `DatabaseSchema::fingerprint` of the local schema when it was tagged
+    pub fingerprint: String,
+    /// the commit of the local repo which has the tagged schema
+    pub commit: String,
+    /// seconds since the unix epoch
+    pub created_at: u64,
+}
+
+/// The last successful fetch or apply of an environment, recorded in `.renovate/state.json` of
+/// the local repo
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncState {
+    /// `CatalogFingerprint::overall` of the synced database, none on cockroach
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    /// whether the remote database is synced rather than the local one
+    pub remote: bool,
+    /// the commit of the local repo which has the synced schema, none if it isn't committed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// seconds since the unix epoch
+    pub synced_at: u64,
+}
+
+/// A changed schema object in the plan
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlanChange {
+    pub type_name: String,
+    pub id: String,
+    pub kind: ChangeKind,
+    /// unified diff of the sql of the object
+    pub diff: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hunks: Vec<DiffHunk>,
+}
+
+/// A group of changed lines of the diff of an object, with the unchanged lines around them.
+/// Line numbers start from 1.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiffHunk {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiffLine {
+    pub kind: DiffLineKind,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffLineKind {
+    Equal,
+    Removed,
+    Added,
+}
+
+/// Handler for the statements renovate doesn't understand, e.g. `SELECT create_hypertable(...)`.
+/// Register it with `register_handler` before loading the schema, and the matched statements
+/// will be diffed and planned along with the other objects.
+///
+/// Note pg_dump may not emit the statements (e.g. function calls), in that case they're planned
+/// as added on every run, so the generated sql should be idempotent.
+pub trait NodeHandler: Send + Sync {
+    /// database type name for the objects handled, e.g. `hypertable`
+    fn type_name(&self) -> &'static str;
+    /// unique id of the object if the top level statement is handled by this handler
+    fn id(&self, node: &NodeEnum) -> Option<String>;
+    /// generate sql to create the object, default to the statement itself
+    fn create(&self, node: &NodeEnum) -> Result<Vec<String>> {
+        Ok(vec![node.deparse()?])
+    }
+    /// generate sql to drop the object
+    fn drop(&self, node: &NodeEnum) -> Result<Vec<String>>;
+    /// generate sql to alter the object. Return empty to drop and recreate it (default)
+    fn alter(&self, _old: &NodeEnum, _new: &NodeEnum) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
+}
+
+/// A sql file `DatabaseSchema::save_to` would create, modify or delete in a store
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+    /// content in the store, empty if the file is added
+    pub old: String,
+    /// rendered content, empty if the file is removed
+    pub new: String,
+}
+
+/// Rendered sql files, keyed by the path relative to the root of the store
+pub type SchemaFiles = BTreeMap<PathBuf, String>;
+
 /// Object for Differ<T> must satisfy NodeItem trait
 pub trait NodeItem: ToString {
     type Inner;
@@ -132,11 +518,170 @@ pub struct LocalRepo {
     pub path: PathBuf,
 }
 
+/// In-memory schema store, mostly for testing
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore(Arc<Mutex<SchemaFiles>>);
+
+/// Schema store layering the files of `overlay` on the `base` store, e.g. the tables and policies
+/// of a tenant on the schema shared by all the tenants. A file of the overlay replaces the base
+/// file of the same path; writes and removals go to the overlay only.
+pub struct OverlayStore {
+    base: Box<dyn SchemaStore>,
+    overlay: Box<dyn SchemaStore>,
+}
+
+/// Schema store backed by an object storage bucket, e.g. `s3://bucket/prefix` or
+/// `gs://bucket/prefix`. It uses the `aws` or `gsutil` cli so that the credentials are
+/// resolved the same way as the user's shell.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone)]
+pub struct ObjectStore {
+    url: url::Url,
+}
+
+/// Git backend for Vcs. The repo is discovered from (or initialized at) the given path.
+#[cfg(feature = "git")]
+#[derive(Debug, Clone)]
+pub struct GitVcs {
+    path: PathBuf,
+}
+
+/// Vcs backend which does nothing, for repos managed by other tools
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopVcs;
+
+/// Observer which ignores all the events
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopObserver;
+
+/// Observer used by the cli: print the diffs and the progress of apply to the terminal
+#[cfg(feature = "console")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConsoleObserver;
+
 /// Remote repository
+#[cfg(feature = "db")]
 #[derive(Debug, Clone)]
 pub struct DatabaseRepo {
     url: String,
     remote_url: String,
+    output: RenovateOutputConfig,
+    /// max number of concurrent pg_dump processes when loading the schema
+    jobs: usize,
+    dialect: Dialect,
+    pooler: Pooler,
+    /// max number of statements applied in a single round trip
+    batch_size: usize,
+    /// fail the fetch on the statements of the dump no NodeItem handles, see `diff.strict`
+    strict: bool,
+}
+
+/// What `apply` ran, written to `apply.report` so that deployment pipelines could archive it
+#[cfg(feature = "db")]
+#[derive(Debug, Clone, Default, Serialize)]
+#[non_exhaustive]
+pub struct ApplyReport {
+    /// url of the database applied to, without the password
+    pub target: String,
+    /// unix timestamp in seconds
+    pub started_at: u64,
+    pub elapsed_ms: u64,
+    /// sha256 of the plan, the same as the `Renovate-Plan-Checksum` trailer of the commit made
+    /// after the apply
+    pub plan_checksum: String,
+    /// `apply.slow_statement_ms` at the time of the apply
+    pub slow_statement_ms: u64,
+    pub statements: Vec<AppliedStatement>,
+    /// the batches the statements were sent in, see `apply.batch_size`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub batches: Vec<AppliedBatch>,
+    /// why the apply failed, the statements rolled back with the failure are left out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The error of a failed `apply`, with the report of the statements committed before the
+/// failure, so that it could be archived as well
+#[cfg(feature = "db")]
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ApplyFailed {
+    pub report: ApplyReport,
+    pub source: anyhow::Error,
+}
+
+#[cfg(feature = "db")]
+impl std::fmt::Display for ApplyFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:#}", self.source)
+    }
+}
+
+#[cfg(feature = "db")]
+impl std::error::Error for ApplyFailed {}
+
+/// A statement of the plan applied by `apply`
+#[cfg(feature = "db")]
+#[derive(Debug, Clone, Serialize)]
+#[non_exhaustive]
+pub struct AppliedStatement {
+    pub sql: String,
+    pub elapsed_ms: u64,
+    /// whether the statement took longer than `apply.slow_statement_ms`
+    pub slow: bool,
+    /// rows inserted, updated or deleted by the statement, 0 for DDL
+    pub rows_affected: u64,
+    /// NOTICE / WARNING messages sent by the server for the statement
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub notices: Vec<String>,
+    /// the batch the statement was sent in (from 1), see `apply.batch_size`. The server doesn't
+    /// time the statements of a batch one by one, so the elapsed time is the statement's even
+    /// share of the batch's, and the rows and notices are on the batch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch: Option<usize>,
+}
+
+/// Statements of the plan sent to the server in a single round trip, see `apply.batch_size`
+#[cfg(feature = "db")]
+#[derive(Debug, Clone, Serialize)]
+#[non_exhaustive]
+pub struct AppliedBatch {
+    /// from 1, in the order they're sent
+    pub number: usize,
+    pub statements: usize,
+    pub elapsed_ms: u64,
+    /// rows inserted, updated or deleted by the statements of the batch
+    pub rows_affected: u64,
+    /// NOTICE / WARNING messages sent by the server for the statements of the batch
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub notices: Vec<String>,
+}
+
+/// A statement of the plan applied to the dry run clone of the target database
+#[cfg(feature = "db")]
+#[derive(Debug, Clone)]
+pub struct DryRunStatement {
+    pub sql: String,
+    pub elapsed: std::time::Duration,
+    /// the error returned by the server, the statements after it are not applied
+    pub error: Option<String>,
+}
+
+/// md5 of the catalog entries of a database, overall and per schema, see
+/// `DatabaseRepo::catalog_fingerprint`
+#[cfg(feature = "db")]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CatalogFingerprint {
+    pub overall: String,
+    pub schemas: BTreeMap<String, String>,
+}
+
+/// the contract phase deferred by `apply --stop-after expand`, recorded in the target database
+#[cfg(feature = "db")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub statements: Vec<String>,
+    pub created_at: String,
 }
 
 /// intermediate representation for local and remote repo
@@ -4,13 +4,20 @@ use renovate::commands::{Args, CommandExecutor};
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    args.init_tracing();
     let action = &args.action;
     action.execute(&args).await?;
 
     #[cfg(feature = "cli-test")]
     if args.drop_on_exit {
         use renovate::{DatabaseRepo, RenovateConfig};
-        let config = RenovateConfig::load("renovate.yml").await?;
+        let mut config = RenovateConfig::load("renovate.yml").await?;
+        if let Some(db) = args.db.as_deref() {
+            config = config.for_db(db)?;
+        }
+        if let Some(env) = args.env.as_deref() {
+            config = config.for_env(env)?;
+        }
         let repo = DatabaseRepo::new(&config);
         repo.drop_database().await.ok();
     }
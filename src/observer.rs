@@ -0,0 +1,115 @@
+#[cfg(feature = "console")]
+use crate::ConsoleObserver;
+use crate::{ChangeKind, Diagnostic, DiffHunk, NoopObserver, Observer};
+#[cfg(feature = "console")]
+use console::Term;
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Mutex;
+
+/// Buffer the diffs computed on a worker thread, so that they could be replayed to the real
+/// observer in a stable order.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Default)]
+pub(crate) struct DiffBuffer(Mutex<Vec<(String, String, DiffEvent)>>);
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug)]
+enum DiffEvent {
+    Computed(ChangeKind, String),
+    Hunks(Vec<DiffHunk>),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl DiffBuffer {
+    pub fn replay(self, observer: &dyn Observer) {
+        for (type_name, id, event) in self.0.into_inner().unwrap() {
+            match event {
+                DiffEvent::Computed(kind, diff) => {
+                    observer.on_diff_computed(&type_name, &id, kind, &diff)
+                }
+                DiffEvent::Hunks(hunks) => observer.on_diff_hunks(&type_name, &id, &hunks),
+            }
+        }
+    }
+
+    fn push(&self, type_name: &str, id: &str, event: DiffEvent) {
+        self.0
+            .lock()
+            .unwrap()
+            .push((type_name.to_owned(), id.to_owned(), event));
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Observer for DiffBuffer {
+    fn on_diff_computed(&self, type_name: &str, id: &str, kind: ChangeKind, diff: &str) {
+        self.push(type_name, id, DiffEvent::Computed(kind, diff.to_owned()));
+    }
+
+    fn on_diff_hunks(&self, type_name: &str, id: &str, hunks: &[DiffHunk]) {
+        self.push(type_name, id, DiffEvent::Hunks(hunks.to_vec()));
+    }
+}
+
+impl Observer for NoopObserver {}
+
+/// share an observer with the caller, e.g. to read what it collected after planning
+impl<T: Observer> Observer for Arc<T> {
+    fn on_object_fetched(&self, type_name: &str, id: &str) {
+        self.as_ref().on_object_fetched(type_name, id)
+    }
+
+    fn on_diff_computed(&self, type_name: &str, id: &str, kind: ChangeKind, diff: &str) {
+        self.as_ref().on_diff_computed(type_name, id, kind, diff)
+    }
+
+    fn on_diff_hunks(&self, type_name: &str, id: &str, hunks: &[DiffHunk]) {
+        self.as_ref().on_diff_hunks(type_name, id, hunks)
+    }
+
+    fn on_statement_applied(&self, sql: &str, index: usize, total: usize) {
+        self.as_ref().on_statement_applied(sql, index, total)
+    }
+
+    fn on_notice(&self, sql: &str, notice: &str) {
+        self.as_ref().on_notice(sql, notice)
+    }
+
+    fn on_diagnostic(&self, diagnostic: &Diagnostic) {
+        self.as_ref().on_diagnostic(diagnostic)
+    }
+}
+
+#[cfg(feature = "console")]
+impl Observer for ConsoleObserver {
+    fn on_diff_computed(&self, type_name: &str, id: &str, kind: ChangeKind, diff: &str) {
+        if !atty::is(atty::Stream::Stdout) {
+            return;
+        }
+        let action = match kind {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Changed => "changed",
+        };
+        println!("{} {} is {}:\n\n{}", type_name, id, action, diff);
+    }
+
+    fn on_statement_applied(&self, _sql: &str, index: usize, total: usize) {
+        if !atty::is(atty::Stream::Stderr) {
+            return;
+        }
+        let term = Term::stderr();
+        let _ = term.clear_line();
+        let _ = term.write_str(&format!("Applying migration [{}/{}]", index, total));
+        if index == total {
+            let _ = term.write_line("");
+        }
+    }
+
+    fn on_notice(&self, sql: &str, notice: &str) {
+        let term = Term::stderr();
+        let _ = term.clear_line();
+        let _ = term.write_line(&format!("{}\n  while applying: {}", notice, sql));
+    }
+}
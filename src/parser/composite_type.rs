@@ -1,6 +1,7 @@
 use super::CompositeType;
 use crate::NodeItem;
 use pg_query::{protobuf::CompositeTypeStmt, NodeEnum, NodeRef};
+use std::sync::Arc;
 
 impl NodeItem for CompositeType {
     type Inner = CompositeTypeStmt;
@@ -17,7 +18,7 @@ impl NodeItem for CompositeType {
     }
 
     fn inner(&self) -> anyhow::Result<&Self::Inner> {
-        match &self.node {
+        match self.node.as_ref() {
             NodeEnum::CompositeTypeStmt(stmt) => Ok(stmt),
             _ => anyhow::bail!("not a create view statement"),
         }
@@ -38,7 +39,7 @@ impl TryFrom<&CompositeTypeStmt> for CompositeType {
     type Error = anyhow::Error;
     fn try_from(stmt: &CompositeTypeStmt) -> Result<Self, Self::Error> {
         let id = stmt.typevar.as_ref().into();
-        let node = NodeEnum::CompositeTypeStmt(stmt.clone());
+        let node = Arc::new(NodeEnum::CompositeTypeStmt(stmt.clone()));
         Ok(Self { id, node })
     }
 }
@@ -0,0 +1,258 @@
+use super::CustomNode;
+use crate::{MigrationPlanner, MigrationResult, NodeDiff, NodeHandler, NodeItem};
+use anyhow::Context;
+use pg_query::NodeEnum;
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+static HANDLERS: RwLock<Vec<Arc<dyn NodeHandler>>> = RwLock::new(Vec::new());
+
+/// Register a handler for the statements renovate doesn't understand. Handlers registered
+/// earlier take precedence; one registered again for the same type name replaces the previous
+/// one in place.
+pub fn register_handler(handler: impl NodeHandler + 'static) {
+    let mut handlers = HANDLERS.write().unwrap();
+    let handler: Arc<dyn NodeHandler> = Arc::new(handler);
+    match handlers
+        .iter_mut()
+        .find(|h| h.type_name() == handler.type_name())
+    {
+        Some(existing) => *existing = handler,
+        None => handlers.push(handler),
+    }
+}
+
+/// Unregister every handler, e.g. at the end of a test so that the next ones don't see them.
+pub fn clear_handlers() {
+    HANDLERS.write().unwrap().clear();
+}
+
+impl CustomNode {
+    /// find the first registered handler for the top level statement
+    pub fn from_node(node: &NodeEnum) -> Option<Self> {
+        let handlers = HANDLERS.read().unwrap();
+        handlers.iter().find_map(|handler| {
+            handler.id(node).map(|id| Self {
+                id,
+                type_name: handler.type_name(),
+                node: Arc::new(node.clone()),
+                handler: handler.clone(),
+            })
+        })
+    }
+}
+
+impl NodeItem for CustomNode {
+    type Inner = NodeEnum;
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    fn node(&self) -> &NodeEnum {
+        &self.node
+    }
+
+    fn inner(&self) -> anyhow::Result<&Self::Inner> {
+        Ok(self.node.as_ref())
+    }
+
+    fn revert(&self) -> anyhow::Result<NodeEnum> {
+        anyhow::bail!("{} {} can't be reverted", self.type_name, self.id)
+    }
+}
+
+impl MigrationPlanner for NodeDiff<CustomNode> {
+    type Migration = String;
+
+    fn drop(&self) -> MigrationResult<Self::Migration> {
+        match &self.old {
+            Some(old) => old.handler.drop(&old.node),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn create(&self) -> MigrationResult<Self::Migration> {
+        match &self.new {
+            Some(new) => new.handler.create(&new.node),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn alter(&self) -> MigrationResult<Self::Migration> {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => new.handler.alter(&old.node, &new.node),
+            _ => Ok(vec![]),
+        }
+    }
+}
+
+impl fmt::Display for CustomNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sql = self.node.deparse().map_err(|_| fmt::Error)?;
+        write!(f, "{}", sql)
+    }
+}
+
+impl FromStr for CustomNode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let parsed = pg_query::parse(s).with_context(|| format!("Failed to parse: {}", s))?;
+        let node = match parsed.protobuf.nodes().first() {
+            Some((node, _, _)) => node.to_enum(),
+            None => anyhow::bail!("no statement in: {:?}", s),
+        };
+        Self::from_node(&node).with_context(|| format!("no handler registered for: {}", s))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for CustomNode {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        struct SqlRepr {
+            sql: String,
+        }
+
+        let repr = SqlRepr::deserialize(deserializer)?;
+        repr.sql.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SchemaLoader, SqlLoader};
+
+    struct CronJob;
+
+    impl NodeHandler for CronJob {
+        fn type_name(&self) -> &'static str {
+            "cron_job"
+        }
+
+        fn id(&self, node: &NodeEnum) -> Option<String> {
+            let sql = node.deparse().ok()?;
+            let args = sql.strip_prefix("SELECT cron.schedule('")?;
+            args.split('\'').next().map(|s| s.to_owned())
+        }
+
+        fn drop(&self, node: &NodeEnum) -> anyhow::Result<Vec<String>> {
+            let id = self.id(node).context("not a cron job")?;
+            Ok(vec![format!("SELECT cron.unschedule('{}')", id)])
+        }
+    }
+
+    /// unregisters the handlers when the test ends, even if it fails
+    struct Registered;
+
+    impl Drop for Registered {
+        fn drop(&mut self) {
+            clear_handlers();
+        }
+    }
+
+    #[tokio::test]
+    async fn registered_handler_should_participate_in_plan() -> anyhow::Result<()> {
+        register_handler(CronJob);
+        let _registered = Registered;
+
+        let remote = SqlLoader::new("CREATE TABLE public.metrics (ts timestamptz, value int);")
+            .load()
+            .await?;
+        let local = SqlLoader::new(
+            "CREATE TABLE public.metrics (ts timestamptz, value int); SELECT cron.schedule('nightly-vacuum', '0 3 * * *', 'VACUUM');",
+        )
+        .load()
+        .await?;
+        assert!(local.custom_nodes["cron_job"].contains_key("nightly-vacuum"));
+
+        let plan = local.plan(&remote, false)?;
+        assert_eq!(
+            plan,
+            vec!["SELECT cron.schedule('nightly-vacuum', '0 3 * * *', 'VACUUM')"]
+        );
+        assert_eq!(
+            remote.plan(&local, false)?,
+            vec!["SELECT cron.unschedule('nightly-vacuum')"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn preset_should_ignore_platform_custom_nodes() -> anyhow::Result<()> {
+        // built by hand rather than registered, so that the other tests don't see the handler
+        let node = |id: &str, sql: &str| CustomNode {
+            id: id.to_owned(),
+            type_name: "cron_job",
+            node: Arc::new(
+                pg_query::parse(sql).unwrap().protobuf.nodes()[0]
+                    .0
+                    .to_enum(),
+            ),
+            handler: Arc::new(CronJob),
+        };
+        let local = SqlLoader::new("CREATE TABLE public.todos (id uuid);")
+            .load()
+            .await?;
+        let mut remote = local.clone();
+        remote
+            .custom_nodes
+            .entry("cron_job".to_owned())
+            .or_default()
+            .extend([
+                (
+                    "auth.cleanup".to_owned(),
+                    node(
+                        "auth.cleanup",
+                        "SELECT cron.schedule('auth.cleanup', '0 3 * * *', 'VACUUM')",
+                    ),
+                ),
+                (
+                    "purge".to_owned(),
+                    node(
+                        "purge",
+                        "SELECT cron.schedule('purge', '0 3 * * *', 'VACUUM') FROM storage.buckets",
+                    ),
+                ),
+            ]);
+
+        let config = crate::RenovateDiffConfig {
+            preset: Some(crate::Preset::Supabase),
+            ..Default::default()
+        };
+        let mut with_user_job = remote.clone();
+        with_user_job
+            .custom_nodes
+            .get_mut("cron_job")
+            .unwrap()
+            .insert(
+                "nightly-vacuum".to_owned(),
+                node(
+                    "nightly-vacuum",
+                    "SELECT cron.schedule('nightly-vacuum', '0 3 * * *', 'VACUUM')",
+                ),
+            );
+        assert!(local
+            .plan_with(&remote, &config, &crate::NoopObserver)?
+            .is_empty());
+        assert_eq!(
+            local.plan_with(&with_user_job, &config, &crate::NoopObserver)?,
+            vec!["SELECT cron.unschedule('nightly-vacuum')"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn empty_sql_should_not_be_a_custom_node() {
+        assert!("".parse::<CustomNode>().is_err());
+        assert!("-- nothing".parse::<CustomNode>().is_err());
+    }
+}
@@ -1,7 +1,7 @@
-use super::{utils::node_to_string, EnumType};
+use super::{utils::node_to_string, EnumType, Literal, SchemaId};
 use crate::{MigrationPlanner, MigrationResult, NodeDiff, NodeItem};
-use itertools::Itertools;
 use pg_query::{protobuf::CreateEnumStmt, NodeEnum, NodeRef};
+use std::sync::Arc;
 
 impl NodeItem for EnumType {
     type Inner = CreateEnumStmt;
@@ -18,7 +18,7 @@ impl NodeItem for EnumType {
     }
 
     fn inner(&self) -> anyhow::Result<&Self::Inner> {
-        match &self.node {
+        match self.node.as_ref() {
             NodeEnum::CreateEnumStmt(stmt) => Ok(stmt),
             _ => anyhow::bail!("not a create view statement"),
         }
@@ -35,16 +35,22 @@ impl NodeItem for EnumType {
     }
 }
 
+impl EnumType {
+    /// Whether the type can't be altered into `new`, thus is dropped and created again, which
+    /// fails if the type is used elsewhere: values are removed, other than by a single rename.
+    pub(crate) fn recreated_as(&self, new: &EnumType) -> bool {
+        let added = new.items.difference(&self.items).count();
+        let removed = self.items.difference(&new.items).count();
+        removed > 0 && !(removed == 1 && added == 1)
+    }
+}
+
 impl TryFrom<&CreateEnumStmt> for EnumType {
     type Error = anyhow::Error;
     fn try_from(stmt: &CreateEnumStmt) -> Result<Self, Self::Error> {
-        let id = stmt
-            .type_name
-            .iter()
-            .filter_map(node_to_string)
-            .join(".")
-            .parse()?;
-        let node = NodeEnum::CreateEnumStmt(stmt.clone());
+        let names: Vec<_> = stmt.type_name.iter().filter_map(node_to_string).collect();
+        let id = SchemaId::new_with(&names.iter().map(|v| v.as_str()).collect::<Vec<_>>());
+        let node = Arc::new(NodeEnum::CreateEnumStmt(stmt.clone()));
         let items = stmt.vals.iter().filter_map(node_to_string).collect();
         Ok(Self { id, items, node })
     }
@@ -79,24 +85,22 @@ impl MigrationPlanner for NodeDiff<EnumType> {
                 if removed.is_empty() {
                     let migrations = added
                         .iter()
-                        .map(|s| format!("ALTER TYPE {} ADD VALUE '{}'", old.id, s))
+                        .map(|s| format!("ALTER TYPE {} ADD VALUE {}", old.id, Literal(s)))
                         .collect();
                     return Ok(migrations);
                 }
 
                 if removed.len() == added.len() && removed.len() == 1 {
                     let sql = format!(
-                        "ALTER TYPE {} RENAME VALUE '{}' TO '{}'",
+                        "ALTER TYPE {} RENAME VALUE {} TO {}",
                         old.id,
-                        removed.get(0).unwrap(),
-                        added.get(0).unwrap()
+                        Literal(removed[0]),
+                        Literal(added[0])
                     );
                     return Ok(vec![sql]);
                 }
 
-                if atty::is(atty::Stream::Stdout) {
-                    println!("WARNING: recreate enum type {} because of incompatible changes. Be CAUTIOUS this migration might fail if you referenced the type in other places.", old.id);
-                }
+                // dropped and created again, see `recreated_as`
                 Ok(vec![])
             }
             _ => Ok(vec![]),
@@ -0,0 +1,39 @@
+use super::{utils::node_to_string, Extension, SchemaId};
+use pg_query::{protobuf::CreateExtensionStmt, NodeEnum};
+use std::sync::Arc;
+
+/// the extensions are neither planned nor saved, they're only recorded so that the objects they
+/// create could be told apart from the user's, see `remove_extension_objects`
+impl TryFrom<&CreateExtensionStmt> for Extension {
+    type Error = anyhow::Error;
+    fn try_from(stmt: &CreateExtensionStmt) -> Result<Self, Self::Error> {
+        let schema = stmt
+            .options
+            .iter()
+            .find_map(|option| match &option.node {
+                Some(NodeEnum::DefElem(elem)) if elem.defname == "schema" => {
+                    elem.arg.as_deref().and_then(node_to_string)
+                }
+                _ => None,
+            })
+            .unwrap_or_else(|| "public".to_owned());
+        let id = SchemaId::new(schema, &stmt.extname);
+        let node = Arc::new(NodeEnum::CreateExtensionStmt(stmt.clone()));
+        Ok(Self { id, node })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extension_should_parse() {
+        let sql = "CREATE EXTENSION IF NOT EXISTS timescaledb WITH SCHEMA extensions;";
+        let parsed: Extension = sql.parse().unwrap();
+        assert_eq!(parsed.id, SchemaId::new("extensions", "timescaledb"));
+
+        let parsed: Extension = "CREATE EXTENSION postgis".parse().unwrap();
+        assert_eq!(parsed.id, SchemaId::new("public", "postgis"));
+    }
+}
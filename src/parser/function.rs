@@ -1,10 +1,14 @@
 use super::{
-    utils::{node_to_string, type_name_to_string},
-    Function, FunctionArg, SchemaId,
+    utils::{node_enum_to_string, node_to_string, type_name_to_string},
+    Function, FunctionArg, Ident, SchemaId,
 };
 use crate::{MigrationPlanner, MigrationResult, NodeDiff, NodeItem};
 use itertools::Itertools;
-use pg_query::{protobuf::CreateFunctionStmt, Node, NodeEnum, NodeRef};
+use pg_query::{
+    protobuf::{CreateFunctionStmt, DefElem, VariableSetKind},
+    Node, NodeEnum, NodeRef,
+};
+use std::{collections::BTreeMap, sync::Arc};
 
 impl NodeItem for Function {
     type Inner = CreateFunctionStmt;
@@ -22,7 +26,7 @@ impl NodeItem for Function {
     }
 
     fn inner(&self) -> anyhow::Result<&Self::Inner> {
-        match &self.node {
+        match self.node.as_ref() {
             NodeEnum::CreateFunctionStmt(stmt) => Ok(stmt),
             _ => anyhow::bail!("not a create function statement"),
         }
@@ -44,16 +48,12 @@ impl TryFrom<&CreateFunctionStmt> for Function {
     fn try_from(stmt: &CreateFunctionStmt) -> Result<Self, Self::Error> {
         let args = parse_args(&stmt.parameters);
 
-        let id = stmt
-            .funcname
-            .iter()
-            .filter_map(node_to_string)
-            .join(".")
-            .parse()?;
+        let names: Vec<_> = stmt.funcname.iter().filter_map(node_to_string).collect();
+        let id = SchemaId::new_with(&names.iter().map(|v| v.as_str()).collect::<Vec<_>>());
 
         let returns = type_name_to_string(stmt.return_type.as_ref().unwrap());
 
-        let node = NodeEnum::CreateFunctionStmt(stmt.clone());
+        let node = Arc::new(NodeEnum::CreateFunctionStmt(stmt.clone()));
         Ok(Self {
             id,
             args,
@@ -92,6 +92,10 @@ impl MigrationPlanner for NodeDiff<Function> {
                     return Ok(vec![]);
                 }
 
+                if let Some(sql) = alter_attributes(old, new)? {
+                    return Ok(vec![sql]);
+                }
+
                 let sql = new.node.deparse()?;
                 let sql = sql.replace("CREATE FUNCTION", "CREATE OR REPLACE FUNCTION");
                 Ok(vec![sql])
@@ -121,6 +125,128 @@ fn parse_id(nodes: &[Node], args: &[FunctionArg]) -> SchemaId {
     SchemaId::new_with(&names.iter().map(|v| v.as_str()).collect::<Vec<_>>())
 }
 
+/// `ALTER FUNCTION` with the changed attributes (e.g. volatility, `SECURITY DEFINER`,
+/// `SET search_path`), if nothing else of the function is changed.
+fn alter_attributes(old: &Function, new: &Function) -> anyhow::Result<Option<String>> {
+    let (old_stmt, new_stmt) = (old.inner()?, new.inner()?);
+    if without_attributes(old_stmt)? != without_attributes(new_stmt)? {
+        return Ok(None);
+    }
+
+    let (old_attrs, new_attrs) = (attributes(old_stmt), attributes(new_stmt));
+    let mut actions: Vec<String> = new_attrs
+        .iter()
+        .filter(|(k, v)| old_attrs.get(*k) != Some(*v))
+        .map(|(_, v)| v.clone())
+        .collect();
+    for name in old_attrs.keys().filter(|k| !new_attrs.contains_key(*k)) {
+        match reset_attribute(name) {
+            Some(action) => actions.push(action),
+            // e.g. COST, whose default depends on the language
+            None => return Ok(None),
+        }
+    }
+    if actions.is_empty() {
+        return Ok(None);
+    }
+
+    let kind = if new_stmt.is_procedure {
+        "PROCEDURE"
+    } else {
+        "FUNCTION"
+    };
+    Ok(Some(format!(
+        "ALTER {} {} {}",
+        kind,
+        old.signature(),
+        actions.join(" ")
+    )))
+}
+
+/// the function without the attributes `ALTER FUNCTION` could change
+fn without_attributes(stmt: &CreateFunctionStmt) -> anyhow::Result<String> {
+    let mut stmt = stmt.clone();
+    stmt.options.retain(|option| match option.node.as_ref() {
+        Some(NodeEnum::DefElem(d)) => attribute(d).is_none(),
+        _ => true,
+    });
+    Ok(NodeEnum::CreateFunctionStmt(stmt).deparse()?)
+}
+
+/// the alterable attributes by name, with their `ALTER FUNCTION` action
+fn attributes(stmt: &CreateFunctionStmt) -> BTreeMap<String, String> {
+    stmt.options
+        .iter()
+        .filter_map(|option| match option.node.as_ref() {
+            Some(NodeEnum::DefElem(d)) => attribute(d),
+            _ => None,
+        })
+        .collect()
+}
+
+fn attribute(d: &DefElem) -> Option<(String, String)> {
+    let arg = d.arg.as_deref().and_then(|arg| arg.node.as_ref());
+    let value = match arg {
+        Some(NodeEnum::Float(f)) => f.str.clone(),
+        Some(n) => node_enum_to_string(n).unwrap_or_default(),
+        None => String::new(),
+    };
+    let on = value == "1" || value == "true";
+    let action = match d.defname.as_str() {
+        "volatility" => value.to_uppercase(),
+        "security" if on => "SECURITY DEFINER".to_owned(),
+        "security" => "SECURITY INVOKER".to_owned(),
+        "strict" if on => "STRICT".to_owned(),
+        "strict" => "CALLED ON NULL INPUT".to_owned(),
+        "leakproof" if on => "LEAKPROOF".to_owned(),
+        "leakproof" => "NOT LEAKPROOF".to_owned(),
+        "parallel" => format!("PARALLEL {}", value.to_uppercase()),
+        "cost" => format!("COST {}", value),
+        "rows" => format!("ROWS {}", value),
+        "set" => match arg {
+            Some(NodeEnum::VariableSetStmt(v)) => {
+                let action = match v.kind() {
+                    VariableSetKind::VarSetValue => format!(
+                        "SET {} = {}",
+                        setting_name(&v.name),
+                        v.args.iter().filter_map(node_to_string).join(", ")
+                    ),
+                    VariableSetKind::VarSetCurrent => {
+                        format!("SET {} FROM CURRENT", setting_name(&v.name))
+                    }
+                    _ => return None,
+                };
+                return Some((format!("set {}", v.name), action));
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some((d.defname.clone(), action))
+}
+
+/// the action to restore the default of the attribute, if it's the same for all languages
+fn reset_attribute(name: &str) -> Option<String> {
+    let action = match name {
+        "volatility" => "VOLATILE",
+        "security" => "SECURITY INVOKER",
+        "strict" => "CALLED ON NULL INPUT",
+        "leakproof" => "NOT LEAKPROOF",
+        "parallel" => "PARALLEL UNSAFE",
+        _ => {
+            return name
+                .strip_prefix("set ")
+                .map(|v| format!("RESET {}", setting_name(v)))
+        }
+    };
+    Some(action.to_owned())
+}
+
+/// the name of the setting, e.g. `search_path` or `"my.setting"`, quoted part by part
+fn setting_name(name: &str) -> String {
+    name.split('.').map(Ident).join(".")
+}
+
 fn parse_args(args: &[Node]) -> Vec<FunctionArg> {
     args.iter()
         .map(|n| match n.node.as_ref() {
@@ -207,4 +333,23 @@ mod tests {
         assert_eq!(plan.len(), 1);
         assert_eq!(plan[0], "CREATE OR REPLACE FUNCTION test(name2 text) RETURNS text LANGUAGE sql IMMUTABLE AS $$ select name2 $$");
     }
+
+    #[test]
+    fn function_attribute_change_should_be_altered() {
+        let f1 = "CREATE FUNCTION test(name1 text) RETURNS text LANGUAGE sql STABLE AS $$ select name1 $$";
+        let f2 = "CREATE FUNCTION test(name1 text) RETURNS text LANGUAGE sql IMMUTABLE SECURITY DEFINER SET search_path = public AS $$ select name1 $$";
+        let old: Function = f1.parse().unwrap();
+        let new: Function = f2.parse().unwrap();
+        let plan = old.diff(&new).unwrap().unwrap().plan().unwrap();
+        assert_eq!(
+            plan,
+            vec!["ALTER FUNCTION public.test(text) SECURITY DEFINER SET search_path = 'public' IMMUTABLE"]
+        );
+
+        let plan = new.diff(&old).unwrap().unwrap().plan().unwrap();
+        assert_eq!(
+            plan,
+            vec!["ALTER FUNCTION public.test(text) STABLE SECURITY INVOKER RESET search_path"]
+        );
+    }
 }
@@ -1,11 +1,13 @@
 mod composite_type;
+mod custom;
 mod enum_type;
+mod extension;
 mod function;
 mod mview;
 mod privilege;
 mod sequence;
 mod table;
-mod utils;
+pub(crate) mod utils;
 mod view;
 
 use derivative::Derivative;
@@ -14,7 +16,24 @@ use pg_query::{
     protobuf::{ConstrType, GrantTargetType, ObjectType},
     NodeEnum,
 };
-use std::collections::{BTreeMap, BTreeSet};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+pub use custom::{clear_handlers, register_handler};
+
+/// A postgres identifier that displays double quoted when postgres would otherwise fold it to
+/// lower case or parse it as a keyword, e.g. `"User"` or `"order"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ident<'a>(pub &'a str);
+
+/// A postgres string literal, single quoted with the quotes in it doubled, e.g. `'it''s'`. The
+/// values interpolated in the generated statements are displayed with it, so that they can't end
+/// the literal early.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Literal<'a>(pub &'a str);
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct SchemaId {
@@ -28,7 +47,7 @@ pub struct RelationId {
 }
 
 /// All the parsed information about a database
-#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DatabaseSchema {
     pub schemas: BTreeSet<String>,
 
@@ -53,8 +72,13 @@ pub struct DatabaseSchema {
     pub table_policies: BTreeMap<SchemaId, BTreeMap<String, TablePolicy>>,
     pub table_rls: BTreeMap<SchemaId, TableRls>,
     pub table_owners: BTreeMap<SchemaId, TableOwner>,
+    pub table_hypertables: BTreeMap<SchemaId, Hypertable>,
+
+    // objects handled by the registered NodeHandlers, keyed by type name
+    pub custom_nodes: BTreeMap<String, BTreeMap<String, CustomNode>>,
 
     // internal data structures
+    #[serde(skip)]
     _table_sequences: BTreeMap<SchemaId, SequenceInfo>,
 }
 
@@ -69,35 +93,38 @@ pub struct Schema {
 }
 
 /// Trigger defined in the database
-#[derive(Derivative, Clone)]
+#[derive(Derivative, Clone, Serialize)]
 #[derivative(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Trigger {
     pub id: RelationId,
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    #[serde(rename = "sql", serialize_with = "utils::serialize_node")]
+    pub node: Arc<NodeEnum>,
 }
 
 /// Composite type defined in the schema
-#[derive(Derivative, Debug, Clone)]
+#[derive(Derivative, Debug, Clone, Serialize)]
 #[derivative(PartialEq, Eq, PartialOrd, Ord)]
 pub struct CompositeType {
     pub id: SchemaId,
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    #[serde(rename = "sql", serialize_with = "utils::serialize_node")]
+    pub node: Arc<NodeEnum>,
 }
 
 /// Enum type defined in the schema
-#[derive(Derivative, Debug, Clone)]
+#[derive(Derivative, Debug, Clone, Serialize)]
 #[derivative(PartialEq, Eq, PartialOrd, Ord)]
 pub struct EnumType {
     pub id: SchemaId,
     pub items: BTreeSet<String>,
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    #[serde(rename = "sql", serialize_with = "utils::serialize_node")]
+    pub node: Arc<NodeEnum>,
 }
 
 /// Table defined in the schema
-#[derive(Derivative, Debug, Clone)]
+#[derive(Derivative, Debug, Clone, Serialize)]
 #[derivative(PartialEq, Eq)]
 pub struct Table {
     pub id: SchemaId,
@@ -105,40 +132,44 @@ pub struct Table {
     pub constraints: IndexMap<String, ConstraintInfo>,
 
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    #[serde(rename = "sql", serialize_with = "utils::serialize_node")]
+    pub node: Arc<NodeEnum>,
 }
 
 /// View defined in the schema
-#[derive(Derivative, Debug, Clone)]
+#[derive(Derivative, Debug, Clone, Serialize)]
 #[derivative(PartialEq, Eq, PartialOrd, Ord)]
 pub struct View {
     pub id: SchemaId,
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    #[serde(rename = "sql", serialize_with = "utils::serialize_node")]
+    pub node: Arc<NodeEnum>,
 }
 
 /// Materialized View defined in the schema
-#[derive(Derivative, Debug, Clone)]
+#[derive(Derivative, Debug, Clone, Serialize)]
 #[derivative(PartialEq, Eq, PartialOrd, Ord)]
 pub struct MatView {
     pub id: SchemaId,
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    #[serde(rename = "sql", serialize_with = "utils::serialize_node")]
+    pub node: Arc<NodeEnum>,
 }
 
 /// Function defined in the schema
-#[derive(Derivative, Debug, Clone)]
+#[derive(Derivative, Debug, Clone, Serialize)]
 #[derivative(PartialEq, Eq, PartialOrd, Ord)]
 pub struct Function {
     pub id: SchemaId,
     pub args: Vec<FunctionArg>,
     pub returns: String,
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    #[serde(rename = "sql", serialize_with = "utils::serialize_node")]
+    pub node: Arc<NodeEnum>,
 }
 
 /// Function defined in the schema
-#[derive(Derivative, Debug, Clone, PartialOrd, Ord)]
+#[derive(Derivative, Debug, Clone, PartialOrd, Ord, Serialize)]
 #[derivative(PartialEq, Eq)]
 pub struct FunctionArg {
     #[derivative(PartialEq = "ignore")]
@@ -146,7 +177,7 @@ pub struct FunctionArg {
     pub data_type: String,
 }
 
-#[derive(Derivative, Debug, Clone)]
+#[derive(Derivative, Debug, Clone, Serialize)]
 #[derivative(PartialEq, Eq, PartialOrd, Ord)]
 pub struct Column {
     pub id: RelationId,
@@ -155,23 +186,26 @@ pub struct Column {
     pub default: Option<ConstraintInfo>,
     pub constraints: BTreeSet<ConstraintInfo>,
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    #[serde(skip)]
+    pub node: Arc<NodeEnum>,
 }
 
-#[derive(Derivative, Debug, Clone)]
+#[derive(Derivative, Debug, Clone, Serialize)]
 #[derivative(PartialEq, Eq, PartialOrd, Ord)]
 pub struct Sequence {
     pub id: SchemaId,
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    #[serde(rename = "sql", serialize_with = "utils::serialize_node")]
+    pub node: Arc<NodeEnum>,
 }
 
-#[derive(Derivative, Debug, Clone)]
+#[derive(Derivative, Debug, Clone, Serialize)]
 #[derivative(PartialEq, Eq, PartialOrd, Ord)]
 pub struct TableSequence {
     pub id: RelationId,
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    #[serde(rename = "sql", serialize_with = "utils::serialize_node")]
+    pub node: Arc<NodeEnum>,
 }
 
 #[derive(Derivative, Debug, Clone)]
@@ -179,7 +213,7 @@ pub struct TableSequence {
 pub struct SequenceInfo {
     pub column: String,
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    pub node: Arc<NodeEnum>,
 }
 
 #[derive(Derivative, Debug, Clone)]
@@ -188,60 +222,68 @@ pub struct ConstraintInfo {
     pub name: String,
     pub con_type: ConstrType,
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    pub node: Arc<NodeEnum>,
 }
 
-#[derive(Derivative, Debug, Clone)]
+#[derive(Derivative, Debug, Clone, Serialize)]
 #[derivative(PartialEq, Eq, PartialOrd, Ord)]
 pub struct TableConstraint {
     pub id: RelationId,
     pub info: ConstraintInfo,
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    #[serde(rename = "sql", serialize_with = "utils::serialize_node")]
+    pub node: Arc<NodeEnum>,
 }
 
-#[derive(Derivative, Debug, Clone)]
+#[derive(Derivative, Debug, Clone, Serialize)]
 #[derivative(PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Privilege {
     pub id: String,
+    #[serde(serialize_with = "utils::serialize_debug")]
     pub target_type: GrantTargetType,
+    #[serde(serialize_with = "utils::serialize_debug")]
     pub object_type: ObjectType,
     pub privileges: BTreeMap<String, SinglePriv>,
     pub grantee: String,
     pub grant: bool,
+    /// `WITH GRANT OPTION`
+    pub grant_option: bool,
     #[derivative(
         Debug = "ignore",
         PartialOrd = "ignore",
         Ord = "ignore",
         Hash = "ignore"
     )]
-    pub node: NodeEnum,
+    #[serde(rename = "sql", serialize_with = "utils::serialize_node")]
+    pub node: Arc<NodeEnum>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct SinglePriv {
     pub name: String,
     pub cols: BTreeSet<String>,
 }
 
 /// Index for table or material view
-#[derive(Derivative, Debug, Clone)]
+#[derive(Derivative, Debug, Clone, Serialize)]
 #[derivative(PartialEq, Eq, PartialOrd, Ord)]
 pub struct TableIndex {
     pub id: RelationId,
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    #[serde(rename = "sql", serialize_with = "utils::serialize_node")]
+    pub node: Arc<NodeEnum>,
 }
 
-#[derive(Derivative, Debug, Clone)]
+#[derive(Derivative, Debug, Clone, Serialize)]
 #[derivative(PartialEq, Eq, PartialOrd, Ord)]
 pub struct Extension {
     pub id: SchemaId,
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    #[serde(rename = "sql", serialize_with = "utils::serialize_node")]
+    pub node: Arc<NodeEnum>,
 }
 
-#[derive(Derivative, Debug, Clone)]
+#[derive(Derivative, Debug, Clone, Serialize)]
 #[derivative(PartialEq, Eq, PartialOrd, Ord)]
 pub struct TablePolicy {
     pub id: RelationId,
@@ -251,7 +293,8 @@ pub struct TablePolicy {
     pub qual: Option<String>,
     pub with_check: Option<String>,
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    #[serde(rename = "sql", serialize_with = "utils::serialize_node")]
+    pub node: Arc<NodeEnum>,
 }
 
 /// Struct to capture all alter table statements
@@ -262,7 +305,7 @@ pub struct AlterTable {
     // for sql from pg_dump, only one action is used
     pub action: AlterTableAction,
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    pub node: Arc<NodeEnum>,
 }
 
 /// Supported alter table actions
@@ -276,20 +319,52 @@ pub enum AlterTableAction {
 }
 
 /// Struct to capture `ALTER TABLE ENABLE ROW LEVEL SECURITY;`
-#[derive(Derivative, Debug, Clone)]
+#[derive(Derivative, Debug, Clone, Serialize)]
 #[derivative(PartialEq, Eq, PartialOrd, Ord)]
 pub struct TableRls {
     pub id: SchemaId,
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    #[serde(rename = "sql", serialize_with = "utils::serialize_node")]
+    pub node: Arc<NodeEnum>,
 }
 
 /// Struct to capture `ALTER TABLE OWNER TO new_owner;`
-#[derive(Derivative, Debug, Clone)]
+#[derive(Derivative, Debug, Clone, Serialize)]
 #[derivative(PartialEq, Eq, PartialOrd, Ord)]
 pub struct TableOwner {
     pub id: SchemaId,
     pub owner: String,
     #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
-    pub node: NodeEnum,
+    #[serde(rename = "sql", serialize_with = "utils::serialize_node")]
+    pub node: Arc<NodeEnum>,
+}
+
+/// Struct to capture `SELECT create_hypertable('table', 'time_column');` of timescaledb
+#[derive(Derivative, Debug, Clone, Serialize)]
+#[derivative(PartialEq, Eq, PartialOrd, Ord)]
+pub struct Hypertable {
+    pub id: SchemaId,
+    pub time_column: String,
+    #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
+    #[serde(rename = "sql", serialize_with = "utils::serialize_node")]
+    pub node: Arc<NodeEnum>,
+}
+
+/// Statement handled by a registered `NodeHandler`
+#[derive(Derivative, Clone, Serialize)]
+#[derivative(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CustomNode {
+    pub id: String,
+    pub type_name: &'static str,
+    #[derivative(Debug = "ignore", PartialOrd = "ignore", Ord = "ignore")]
+    #[serde(rename = "sql", serialize_with = "utils::serialize_node")]
+    pub node: Arc<NodeEnum>,
+    #[derivative(
+        Debug = "ignore",
+        PartialEq = "ignore",
+        PartialOrd = "ignore",
+        Ord = "ignore"
+    )]
+    #[serde(skip)]
+    handler: Arc<dyn crate::NodeHandler>,
 }
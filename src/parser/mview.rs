@@ -1,6 +1,7 @@
 use super::{MatView, SchemaId};
 use crate::NodeItem;
 use pg_query::{protobuf::CreateTableAsStmt, NodeEnum, NodeRef};
+use std::sync::Arc;
 
 impl NodeItem for MatView {
     type Inner = CreateTableAsStmt;
@@ -18,7 +19,7 @@ impl NodeItem for MatView {
     }
 
     fn inner(&self) -> anyhow::Result<&Self::Inner> {
-        match &self.node {
+        match self.node.as_ref() {
             NodeEnum::CreateTableAsStmt(stmt) => Ok(stmt),
             _ => anyhow::bail!("not a create materialized view statement"),
         }
@@ -39,7 +40,7 @@ impl TryFrom<&CreateTableAsStmt> for MatView {
     type Error = anyhow::Error;
     fn try_from(stmt: &CreateTableAsStmt) -> Result<Self, Self::Error> {
         let id = get_mview_id(stmt);
-        let node = NodeEnum::CreateTableAsStmt(Box::new(stmt.clone()));
+        let node = Arc::new(NodeEnum::CreateTableAsStmt(Box::new(stmt.clone())));
         Ok(Self { id, node })
     }
 }
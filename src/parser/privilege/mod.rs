@@ -1,12 +1,16 @@
 mod single_priv;
 
 use super::{Privilege, SinglePriv};
-use crate::{parser::SchemaId, MigrationPlanner, MigrationResult, NodeDelta, NodeDiff, NodeItem};
+use crate::{
+    parser::{utils::node_to_string, SchemaId},
+    MigrationPlanner, MigrationResult, NodeDelta, NodeDiff, NodeItem,
+};
+use itertools::Itertools;
 use pg_query::{
-    protobuf::{GrantStmt, GrantTargetType, ObjectType},
-    NodeEnum,
+    protobuf::{GrantStmt, GrantTargetType, ObjectType, RoleSpecType},
+    Node, NodeEnum,
 };
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, sync::Arc};
 
 impl NodeItem for Privilege {
     type Inner = GrantStmt;
@@ -24,7 +28,7 @@ impl NodeItem for Privilege {
     }
 
     fn inner(&self) -> anyhow::Result<&Self::Inner> {
-        match &self.node {
+        match self.node.as_ref() {
             NodeEnum::GrantStmt(stmt) => Ok(stmt),
             _ => anyhow::bail!("not a grant statement"),
         }
@@ -33,10 +37,63 @@ impl NodeItem for Privilege {
     fn revert(&self) -> anyhow::Result<NodeEnum> {
         let mut stmt = self.inner()?.clone();
         stmt.is_grant = !stmt.is_grant;
+        // a revoke with grant_option set only revokes the grant option
+        stmt.grant_option = stmt.is_grant && self.grant_option;
         Ok(NodeEnum::GrantStmt(stmt))
     }
 }
 
+impl Privilege {
+    /// One privilege per grantee of the statement, e.g. `GRANT SELECT ON t TO a, b` is split to
+    /// the grants to `a` and to `b`, so that a changed role list is planned role by role.
+    pub fn from_grant(stmt: &GrantStmt) -> anyhow::Result<Vec<Self>> {
+        if stmt.grantees.len() <= 1 {
+            return Ok(vec![stmt.try_into()?]);
+        }
+        stmt.grantees
+            .iter()
+            .map(|grantee| {
+                let mut stmt = stmt.clone();
+                stmt.grantees = vec![grantee.clone()];
+                Self::try_from(&stmt)
+            })
+            .collect()
+    }
+
+    /// Whether the other grant can be merged to this one: same object, grantee and options.
+    pub fn can_merge(&self, other: &Self) -> bool {
+        self.id() == other.id()
+            && self.grant == other.grant
+            && self.grant_option == other.grant_option
+            && self.target_type == other.target_type
+    }
+
+    /// Merge the privileges of the other grant, e.g. `GRANT SELECT(a) ON t TO r` and
+    /// `GRANT SELECT(b) ON t TO r` (pg_dump emits the column level grants column by column)
+    /// become `GRANT SELECT(a, b) ON t TO r`. A table level privilege covers all the columns.
+    pub fn merge(&self, other: &Self) -> anyhow::Result<Self> {
+        let mut privileges = BTreeMap::new();
+        if !self.privileges.is_empty() && !other.privileges.is_empty() {
+            privileges = self.privileges.clone();
+            for (name, p) in &other.privileges {
+                privileges
+                    .entry(name.clone())
+                    .and_modify(|v: &mut SinglePriv| {
+                        if v.cols.is_empty() || p.cols.is_empty() {
+                            v.cols.clear();
+                        } else {
+                            v.cols.extend(p.cols.iter().cloned());
+                        }
+                    })
+                    .or_insert_with(|| p.clone());
+            }
+        }
+        let mut stmt = self.inner()?.clone();
+        stmt.privileges = privileges.into_values().map(Node::from).collect();
+        Self::try_from(&stmt)
+    }
+}
+
 impl TryFrom<&GrantStmt> for Privilege {
     type Error = anyhow::Error;
 
@@ -44,9 +101,13 @@ impl TryFrom<&GrantStmt> for Privilege {
         let target_type = get_target_type(stmt);
         let object_type = get_object_type(stmt)?;
         let id = get_id(stmt)?;
-        let privileges = get_privileges(stmt);
+        let mut privileges = get_privileges(stmt);
+        if object_type == ObjectType::ObjectFunction && is_function_all(&privileges) {
+            // EXECUTE is the only privilege of a function, so it's the same as ALL
+            privileges.clear();
+        }
         let grantee = get_grantee(stmt);
-        let node = NodeEnum::GrantStmt(stmt.clone());
+        let node = Arc::new(NodeEnum::GrantStmt(stmt.clone()));
         Ok(Self {
             target_type,
             object_type,
@@ -55,6 +116,7 @@ impl TryFrom<&GrantStmt> for Privilege {
             grantee,
             node,
             grant: stmt.is_grant,
+            grant_option: stmt.is_grant && stmt.grant_option,
         })
     }
 }
@@ -96,13 +158,28 @@ impl MigrationPlanner for NodeDiff<Privilege> {
                     old.privileges.iter().collect(),
                     new.privileges.iter().collect(),
                 );
-                delta.plan(old)
+                let mut migrations = delta.plan(old)?;
+                if old.grant_option != new.grant_option {
+                    migrations.push(grant_option_change(new)?);
+                }
+                Ok(migrations)
             }
             _ => Ok(vec![]),
         }
     }
 }
 
+/// `GRANT ... WITH GRANT OPTION` if the new privilege has the grant option, otherwise
+/// `REVOKE GRANT OPTION FOR ...`, for all the privileges of the new one
+fn grant_option_change(new: &Privilege) -> anyhow::Result<String> {
+    let mut stmt = new.inner()?.clone();
+    if !new.grant_option {
+        stmt.is_grant = false;
+        stmt.grant_option = true;
+    }
+    Ok(NodeEnum::GrantStmt(stmt).deparse()?)
+}
+
 fn get_target_type(stmt: &GrantStmt) -> GrantTargetType {
     let target_type = GrantTargetType::from_i32(stmt.targtype);
     assert!(target_type.is_some());
@@ -115,6 +192,7 @@ fn get_object_type(stmt: &GrantStmt) -> anyhow::Result<ObjectType> {
     match object_type.unwrap() {
         ObjectType::ObjectTable => Ok(ObjectType::ObjectTable),
         ObjectType::ObjectSchema => Ok(ObjectType::ObjectSchema),
+        ObjectType::ObjectFunction => Ok(ObjectType::ObjectFunction),
         v => anyhow::bail!("unsupported grant/revoke object type: {:?}", v),
     }
 }
@@ -127,6 +205,16 @@ fn get_id(stmt: &GrantStmt) -> anyhow::Result<String> {
     let name = match node.as_ref().unwrap() {
         NodeEnum::String(s) => s.str.clone(),
         NodeEnum::RangeVar(v) => SchemaId::from(v).to_string(),
+        // e.g. `public.add(pg_catalog.int4, pg_catalog.int4)`, int and integer are the same type
+        NodeEnum::ObjectWithArgs(f) => {
+            let names: Vec<_> = f.objname.iter().filter_map(node_to_string).collect();
+            let id = SchemaId::new_with(&names.iter().map(|v| v.as_str()).collect::<Vec<_>>());
+            format!(
+                "{}({})",
+                id,
+                f.objargs.iter().filter_map(node_to_string).join(", ")
+            )
+        }
         _ => anyhow::bail!("unsupported grant/revoke object name: {:?}", node),
     };
 
@@ -148,8 +236,18 @@ fn get_privileges(stmt: &GrantStmt) -> BTreeMap<String, SinglePriv> {
         .collect()
 }
 
+fn is_function_all(privileges: &BTreeMap<String, SinglePriv>) -> bool {
+    privileges.len() == 1
+        && privileges
+            .get("execute")
+            .map_or(false, |p| p.cols.is_empty())
+}
+
 fn get_grantee(stmt: &GrantStmt) -> String {
     let name = stmt.grantees.first().and_then(|n| match n.node.as_ref() {
+        Some(NodeEnum::RoleSpec(r)) if r.roletype() == RoleSpecType::RolespecPublic => {
+            Some("PUBLIC".to_owned())
+        }
         Some(NodeEnum::RoleSpec(r)) => Some(r.rolename.clone()),
         _ => None,
     });
@@ -195,6 +293,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn function_revoke_should_parse() {
+        let s = "REVOKE EXECUTE ON FUNCTION public.add(int, int) FROM PUBLIC";
+        let p: Privilege = s.parse().unwrap();
+        assert!(!p.grant);
+        assert_eq!(p.object_type, ObjectType::ObjectFunction);
+        assert_eq!(p.id, "public.add(pg_catalog.int4, pg_catalog.int4)");
+        assert_eq!(p.grantee, "PUBLIC");
+
+        // what pg_dump generates for the same revoke
+        let s = "REVOKE ALL ON FUNCTION public.add(integer, integer) FROM PUBLIC";
+        let dumped: Privilege = s.parse().unwrap();
+        assert!(p.diff(&dumped).unwrap().is_none());
+    }
+
     #[test]
     fn grand_diff_change_to_all_should_work() {
         let s1 = "GRANT SELECT(id, name) ON TABLE public.test TO test";
@@ -246,6 +359,31 @@ mod tests {
         assert_eq!(plan[0], "REVOKE delete (name) ON public.test FROM test");
     }
 
+    #[test]
+    fn grant_option_change_should_work() {
+        let s1 = "GRANT SELECT ON TABLE public.test TO test";
+        let s2 = "GRANT SELECT ON TABLE public.test TO test WITH GRANT OPTION";
+        let p1: Privilege = s1.parse().unwrap();
+        let p2: Privilege = s2.parse().unwrap();
+        assert!(p2.grant_option);
+
+        let plan = p1.diff(&p2).unwrap().unwrap().plan().unwrap();
+        assert_eq!(
+            plan,
+            vec!["GRANT select ON public.test TO test WITH GRANT OPTION"]
+        );
+        let plan = p2.diff(&p1).unwrap().unwrap().plan().unwrap();
+        assert_eq!(
+            plan,
+            vec!["REVOKE GRANT OPTION FOR select ON public.test FROM test"]
+        );
+        // dropping the grant revokes the privilege itself
+        assert_eq!(
+            p2.revert().unwrap().deparse().unwrap(),
+            "REVOKE select ON public.test FROM test"
+        );
+    }
+
     #[test]
     fn grant_diff_alter_should_work() {
         let s1 = "GRANT SELECT(id, name), DELETE(name) ON TABLE public.test TO test";
@@ -257,7 +395,35 @@ mod tests {
         assert_eq!(plan.len(), 4);
         assert_eq!(plan[0], "REVOKE delete (name) ON public.test FROM test");
         assert_eq!(plan[1], "GRANT update (name) ON public.test TO test");
-        assert_eq!(plan[2], "REVOKE select (id, name) ON public.test FROM test");
-        assert_eq!(plan[3], "GRANT select (id, temp) ON public.test TO test");
+        assert_eq!(plan[2], "REVOKE select (name) ON public.test FROM test");
+        assert_eq!(plan[3], "GRANT select (temp) ON public.test TO test");
+    }
+
+    #[test]
+    fn column_grants_should_merge() {
+        let p1: Privilege = "GRANT SELECT(id) ON TABLE public.test TO test"
+            .parse()
+            .unwrap();
+        let p2: Privilege = "GRANT SELECT(name), UPDATE(name) ON TABLE public.test TO test"
+            .parse()
+            .unwrap();
+        assert!(p1.can_merge(&p2));
+        let p = p1.merge(&p2).unwrap();
+        assert_eq!(
+            p.to_string(),
+            "GRANT select (id, name), update (name) ON public.test TO test"
+        );
+
+        // the table level privilege covers the columns
+        let p3: Privilege = "GRANT SELECT ON TABLE public.test TO test".parse().unwrap();
+        assert_eq!(
+            p.merge(&p3).unwrap().to_string(),
+            "GRANT select, update (name) ON public.test TO test"
+        );
+
+        let p4: Privilege = "GRANT SELECT(id) ON TABLE public.test TO test WITH GRANT OPTION"
+            .parse()
+            .unwrap();
+        assert!(!p1.can_merge(&p4));
     }
 }
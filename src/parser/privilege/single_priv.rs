@@ -36,6 +36,8 @@ impl SinglePriv {
     fn generate_change(self, item: &Privilege, is_grant: bool) -> anyhow::Result<NodeEnum> {
         let mut stmt = item.inner()?.clone();
         stmt.is_grant = is_grant;
+        // a revoke with grant_option set only revokes the grant option
+        stmt.grant_option = is_grant && item.grant_option;
         stmt.privileges = vec![self.into()];
         Ok(NodeEnum::GrantStmt(stmt))
     }
@@ -59,6 +61,31 @@ impl DeltaItem for SinglePriv {
 
     fn alter(self, item: &Self::SqlNode, new: Self) -> anyhow::Result<Vec<String>> {
         let mut migrations = vec![];
+        if !self.cols.is_empty() && !new.cols.is_empty() {
+            // only revoke / grant the columns changed
+            let removed: BTreeSet<_> = self.cols.difference(&new.cols).cloned().collect();
+            let added: BTreeSet<_> = new.cols.difference(&self.cols).cloned().collect();
+            if !removed.is_empty() {
+                let name = self.name.clone();
+                migrations.extend(
+                    Self {
+                        name,
+                        cols: removed,
+                    }
+                    .drop(item)?,
+                );
+            }
+            if !added.is_empty() {
+                migrations.extend(
+                    Self {
+                        name: new.name,
+                        cols: added,
+                    }
+                    .create(item)?,
+                );
+            }
+            return Ok(migrations);
+        }
         let sql = self.drop(item)?;
         migrations.extend(sql);
         let sql = new.create(item)?;
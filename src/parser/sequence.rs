@@ -1,6 +1,7 @@
 use super::{SchemaId, Sequence};
 use crate::NodeItem;
 use pg_query::{protobuf::CreateSeqStmt, NodeEnum, NodeRef};
+use std::sync::Arc;
 
 impl NodeItem for Sequence {
     type Inner = CreateSeqStmt;
@@ -18,7 +19,7 @@ impl NodeItem for Sequence {
     }
 
     fn inner(&self) -> anyhow::Result<&Self::Inner> {
-        match &self.node {
+        match self.node.as_ref() {
             NodeEnum::CreateSeqStmt(stmt) => Ok(stmt),
             _ => anyhow::bail!("not a create sequence statement"),
         }
@@ -39,7 +40,7 @@ impl TryFrom<&CreateSeqStmt> for Sequence {
     type Error = anyhow::Error;
     fn try_from(stmt: &CreateSeqStmt) -> Result<Self, Self::Error> {
         let id = SchemaId::from(stmt.sequence.as_ref());
-        let node = NodeEnum::CreateSeqStmt(stmt.clone());
+        let node = Arc::new(NodeEnum::CreateSeqStmt(stmt.clone()));
         Ok(Self { id, node })
     }
 }
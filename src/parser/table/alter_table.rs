@@ -5,7 +5,8 @@ use pg_query::{
     protobuf::{AlterTableCmd, AlterTableStmt, AlterTableType},
     NodeEnum,
 };
-use tracing::warn;
+use std::sync::Arc;
+use tracing::info;
 
 impl TryFrom<&AlterTableStmt> for AlterTable {
     type Error = anyhow::Error;
@@ -23,7 +24,7 @@ impl TryFrom<&AlterTableStmt> for AlterTable {
             _ => anyhow::bail!("not an alter table command"),
         };
 
-        let node = NodeEnum::AlterTableStmt(alter.clone());
+        let node = Arc::new(NodeEnum::AlterTableStmt(alter.clone()));
 
         Ok(Self { id, action, node })
     }
@@ -51,12 +52,13 @@ impl TryFrom<&AlterTableCmd> for AlterTableAction {
             (AlterTableType::AtColumnDefault, Some(n)) => {
                 let info = SequenceInfo {
                     column: cmd.name.clone(),
-                    node: n.clone(),
+                    node: Arc::new(n.clone()),
                 };
                 Ok(Self::Sequence(Box::new(info)))
             }
             (ty, node) => {
-                warn!("unhandled alter table action: {:?} {:?}", ty, node);
+                // reported as a skipped statement by the loader
+                info!("unhandled alter table action: {:?} {:?}", ty, node);
                 Ok(Self::Unsupported)
             }
         }
@@ -1,8 +1,9 @@
 use crate::{
-    parser::{utils::node_to_string, ConstraintInfo, Table},
+    parser::{utils::node_to_string, ConstraintInfo, Ident, Table},
     DeltaItem,
 };
 use pg_query::{protobuf::ConstrType, NodeEnum};
+use serde::{ser::SerializeStruct, Serialize, Serializer};
 use std::fmt;
 
 impl ConstraintInfo {}
@@ -10,7 +11,11 @@ impl ConstraintInfo {}
 impl DeltaItem for ConstraintInfo {
     type SqlNode = Table;
     fn drop(self, item: &Self::SqlNode) -> anyhow::Result<Vec<String>> {
-        let sql = format!("ALTER TABLE ONLY {} DROP CONSTRAINT {}", item.id, self.name);
+        let sql = format!(
+            "ALTER TABLE ONLY {} DROP CONSTRAINT {}",
+            item.id,
+            Ident(&self.name)
+        );
 
         Ok(vec![sql])
     }
@@ -26,7 +31,9 @@ impl DeltaItem for ConstraintInfo {
         if sql1 == sql2 {
             return Ok(vec![format!(
                 "ALTER TABLE ONLY {} RENAME CONSTRAINT {} TO {}",
-                item.id, self.name, new.name
+                item.id,
+                Ident(&self.name),
+                Ident(&new.name)
             )]);
         }
         Ok(vec![])
@@ -44,7 +51,7 @@ impl DeltaItem for ConstraintInfo {
 
 impl fmt::Display for ConstraintInfo {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let s = match self.node {
+        let s = match *self.node {
             NodeEnum::Constraint(ref constraint)
                 if constraint.contype() == ConstrType::ConstrDefault =>
             {
@@ -72,6 +79,16 @@ impl fmt::Display for ConstraintInfo {
     }
 }
 
+impl Serialize for ConstraintInfo {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("ConstraintInfo", 3)?;
+        state.serialize_field("name", &self.name)?;
+        state.serialize_field("con_type", &format!("{:?}", self.con_type))?;
+        state.serialize_field("sql", &self.to_string())?;
+        state.end()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{parser::Table, Differ, MigrationPlanner};
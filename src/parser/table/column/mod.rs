@@ -3,7 +3,7 @@ mod constraint_info;
 use crate::{
     parser::{
         utils::{node_to_embed_constraint, type_name_to_string},
-        Column, RelationId, SchemaId, Table,
+        Column, Ident, RelationId, SchemaId, Table,
     },
     DeltaItem,
 };
@@ -11,7 +11,7 @@ use pg_query::{
     protobuf::{ColumnDef, ConstrType},
     NodeEnum,
 };
-use std::{collections::BTreeSet, fmt};
+use std::{collections::BTreeSet, fmt, sync::Arc};
 
 impl TryFrom<(SchemaId, ColumnDef)> for Column {
     type Error = anyhow::Error;
@@ -50,7 +50,7 @@ impl TryFrom<(SchemaId, ColumnDef)> for Column {
             nullable,
             constraints,
             default,
-            node: NodeEnum::ColumnDef(Box::new(column)),
+            node: Arc::new(NodeEnum::ColumnDef(Box::new(column))),
         })
     }
 }
@@ -69,7 +69,11 @@ impl Column {
 impl DeltaItem for Column {
     type SqlNode = Table;
     fn drop(self, item: &Self::SqlNode) -> anyhow::Result<Vec<String>> {
-        let sql = format!("ALTER TABLE {} DROP COLUMN {}", item.id, self.id.name);
+        let sql = format!(
+            "ALTER TABLE {} DROP COLUMN {}",
+            item.id,
+            Ident(&self.id.name)
+        );
 
         Ok(vec![sql])
     }
@@ -87,7 +91,9 @@ impl DeltaItem for Column {
         {
             return Ok(vec![format!(
                 "ALTER TABLE ONLY {} RENAME COLUMN {} TO {}",
-                item.id, self.id.name, new.id.name
+                item.id,
+                Ident(&self.id.name),
+                Ident(&new.id.name)
             )]);
         }
         Ok(vec![])
@@ -101,14 +107,15 @@ impl DeltaItem for Column {
         if self.type_name != new.type_name {
             commands.push(format!(
                 "ALTER COLUMN {} TYPE {}",
-                new.id.name, new.type_name
+                Ident(&new.id.name),
+                new.type_name
             ));
         }
 
         if self.nullable != new.nullable {
             let nullable = format!(
                 "ALTER COLUMN {} {}",
-                new.id.name,
+                Ident(&new.id.name),
                 if new.nullable {
                     "DROP NOT NULL"
                 } else {
@@ -121,7 +128,7 @@ impl DeltaItem for Column {
         if self.default != new.default {
             let default = format!(
                 "ALTER COLUMN {} {}",
-                new.id.name,
+                Ident(&new.id.name),
                 if let Some(v) = new.default {
                     format!("SET {}", v)
                 } else {
@@ -142,7 +149,7 @@ impl DeltaItem for Column {
 
 impl fmt::Display for Column {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut fragments = vec![self.id.name.clone(), self.type_name.clone()];
+        let mut fragments = vec![Ident(&self.id.name).to_string(), self.type_name.clone()];
         if !self.nullable {
             fragments.push("NOT NULL".to_owned());
         }
@@ -208,6 +215,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn table_change_column_typmods_should_work() {
+        let s1 = "CREATE TABLE places (geom public.geometry(Point,4326), price numeric(10,2))";
+        let s2 = "CREATE TABLE places (geom public.geometry(Point, 3857), price numeric(10, 2))";
+        let old: Table = s1.parse().unwrap();
+        let new: Table = s2.parse().unwrap();
+        let diff = old.diff(&new).unwrap().unwrap();
+        let plan = diff.plan().unwrap();
+        assert_eq!(
+            plan,
+            vec!["ALTER TABLE public.places ALTER COLUMN geom TYPE public.geometry(point, 3857)"]
+        );
+    }
+
+    #[test]
+    fn mixed_case_and_keyword_identifiers_should_be_quoted() {
+        let s1 = r#"CREATE TABLE "User" ("order" int)"#;
+        let s2 = r#"CREATE TABLE "User" ("order" bigint)"#;
+        let old: Table = s1.parse().unwrap();
+        let new: Table = s2.parse().unwrap();
+        let diff = old.diff(&new).unwrap().unwrap();
+        let plan = diff.plan().unwrap();
+        assert_eq!(
+            plan,
+            vec![r#"ALTER TABLE public."User" ALTER COLUMN "order" TYPE pg_catalog.int8"#]
+        );
+
+        let s3 = r#"CREATE TABLE "User" ("order" int, "firstName" text)"#;
+        let new: Table = s3.parse().unwrap();
+        let diff = old.diff(&new).unwrap().unwrap();
+        let plan = diff.plan().unwrap();
+        assert_eq!(
+            plan,
+            vec![r#"ALTER TABLE ONLY public."User" ADD COLUMN "firstName" text"#]
+        );
+    }
+
     #[test]
     fn table_change_column_array_type_should_work() {
         let s1 = "CREATE TABLE foo (name text[][4])";
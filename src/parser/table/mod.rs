@@ -1,6 +1,7 @@
 mod alter_table;
 mod column;
 mod table_constraint;
+mod table_hypertable;
 mod table_index;
 mod table_owner;
 mod table_policy;
@@ -12,6 +13,7 @@ use super::{Column, ConstraintInfo, SchemaId, Table};
 use crate::{MigrationPlanner, MigrationResult, NodeDelta, NodeDiff, NodeItem};
 use indexmap::IndexMap;
 use pg_query::{protobuf::CreateStmt, NodeEnum, NodeRef};
+use std::sync::Arc;
 
 impl NodeItem for Table {
     type Inner = CreateStmt;
@@ -29,7 +31,7 @@ impl NodeItem for Table {
     }
 
     fn inner(&self) -> anyhow::Result<&Self::Inner> {
-        match &self.node {
+        match self.node.as_ref() {
             NodeEnum::CreateStmt(stmt) => Ok(stmt),
             _ => anyhow::bail!("not a create table statement"),
         }
@@ -51,7 +53,7 @@ impl TryFrom<&CreateStmt> for Table {
     fn try_from(stmt: &CreateStmt) -> Result<Self, Self::Error> {
         let id = SchemaId::from(stmt.relation.as_ref());
         let (columns, constraints) = parse_nodes(id.clone(), stmt)?;
-        let node = NodeEnum::CreateStmt(stmt.clone());
+        let node = Arc::new(NodeEnum::CreateStmt(stmt.clone()));
         Ok(Self {
             id,
             columns,
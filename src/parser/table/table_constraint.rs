@@ -1,11 +1,14 @@
 use crate::{
-    parser::{AlterTable, AlterTableAction, ConstraintInfo, RelationId, SchemaId, TableConstraint},
+    parser::{
+        AlterTable, AlterTableAction, ConstraintInfo, Ident, RelationId, SchemaId, TableConstraint,
+    },
     NodeItem,
 };
 use pg_query::{
     protobuf::{AlterTableStmt, Constraint as PgConstraint},
     NodeEnum, NodeRef,
 };
+use std::sync::Arc;
 
 impl NodeItem for TableConstraint {
     type Inner = AlterTableStmt;
@@ -23,7 +26,7 @@ impl NodeItem for TableConstraint {
     }
 
     fn inner(&self) -> anyhow::Result<&Self::Inner> {
-        match &self.node {
+        match self.node.as_ref() {
             NodeEnum::AlterTableStmt(stmt) => Ok(stmt),
             _ => anyhow::bail!("not a alter table statement"),
         }
@@ -32,7 +35,8 @@ impl NodeItem for TableConstraint {
     fn revert(&self) -> anyhow::Result<NodeEnum> {
         let sql = format!(
             "ALTER TABLE ONLY {} DROP CONSTRAINT {}",
-            self.id.schema_id, self.id.name
+            self.id.schema_id,
+            Ident(&self.id.name)
         );
         let parsed = pg_query::parse(&sql)?;
         let node = parsed.protobuf.nodes()[0].0;
@@ -54,7 +58,7 @@ impl TryFrom<AlterTable> for TableConstraint {
 }
 
 impl TableConstraint {
-    fn new(id: SchemaId, info: ConstraintInfo, node: NodeEnum) -> Self {
+    fn new(id: SchemaId, info: ConstraintInfo, node: Arc<NodeEnum>) -> Self {
         let id = RelationId::new_with(id, info.name.clone());
         Self { id, info, node }
     }
@@ -64,7 +68,7 @@ impl TryFrom<&PgConstraint> for ConstraintInfo {
     type Error = anyhow::Error;
     fn try_from(constraint: &PgConstraint) -> Result<Self, Self::Error> {
         let con_type = constraint.contype();
-        let node = NodeEnum::Constraint(Box::new(constraint.clone()));
+        let node = Arc::new(NodeEnum::Constraint(Box::new(constraint.clone())));
         let name = constraint.conname.clone();
         Ok(Self {
             name,
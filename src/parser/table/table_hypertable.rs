@@ -0,0 +1,159 @@
+use crate::{
+    parser::{utils::node_to_string, Hypertable, SchemaId},
+    types::split_literal,
+    MigrationPlanner, MigrationResult, NodeDiff, NodeItem,
+};
+use pg_query::{protobuf::SelectStmt, NodeEnum};
+use std::{fmt, sync::Arc};
+
+impl NodeItem for Hypertable {
+    type Inner = SelectStmt;
+
+    fn id(&self) -> String {
+        self.id.to_string()
+    }
+
+    fn type_name(&self) -> &'static str {
+        "hypertable"
+    }
+
+    fn node(&self) -> &NodeEnum {
+        &self.node
+    }
+
+    fn inner(&self) -> anyhow::Result<&Self::Inner> {
+        match self.node.as_ref() {
+            NodeEnum::SelectStmt(stmt) => Ok(stmt),
+            _ => anyhow::bail!("not a select statement"),
+        }
+    }
+
+    /// timescale can't convert a hypertable back to a regular table
+    fn revert(&self) -> anyhow::Result<NodeEnum> {
+        anyhow::bail!(
+            "hypertable {} can't be converted back to a regular table, please recreate the table",
+            self.id
+        )
+    }
+}
+
+impl MigrationPlanner for NodeDiff<Hypertable> {
+    type Migration = String;
+
+    fn drop(&self) -> MigrationResult<Self::Migration> {
+        match &self.old {
+            Some(old) => Ok(vec![old.revert()?.deparse()?]),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn create(&self) -> MigrationResult<Self::Migration> {
+        match &self.new {
+            Some(new) => Ok(vec![new.node.deparse()?]),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn alter(&self) -> MigrationResult<Self::Migration> {
+        Ok(vec![])
+    }
+}
+
+/// annotated so that readers of the table file know where the statement comes from
+impl fmt::Display for Hypertable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sql = self.node.deparse().map_err(|_| fmt::Error)?;
+        write!(
+            f,
+            "-- timescaledb hypertable, partitioned by {}\n{}",
+            self.time_column, sql
+        )
+    }
+}
+
+impl TryFrom<&SelectStmt> for Hypertable {
+    type Error = anyhow::Error;
+    fn try_from(stmt: &SelectStmt) -> Result<Self, Self::Error> {
+        let args = create_hypertable_args(stmt)
+            .ok_or_else(|| anyhow::anyhow!("not a create_hypertable call"))?;
+        if args.len() < 2 {
+            anyhow::bail!("create_hypertable requires the table and the time column");
+        }
+        let table = args[0].split("::").next().unwrap_or_default();
+        let id: SchemaId = unquote(table).parse()?;
+        let time_column = unquote(&args[1]);
+        let node = Arc::new(NodeEnum::SelectStmt(Box::new(stmt.clone())));
+        Ok(Self {
+            id,
+            time_column,
+            node,
+        })
+    }
+}
+
+impl Hypertable {
+    /// whether the statement is a `SELECT create_hypertable(...)` call
+    pub fn is_hypertable(stmt: &SelectStmt) -> bool {
+        create_hypertable_args(stmt).is_some()
+    }
+}
+
+fn create_hypertable_args(stmt: &SelectStmt) -> Option<Vec<String>> {
+    if stmt.target_list.len() != 1 {
+        return None;
+    }
+    let val = match &stmt.target_list[0].node {
+        Some(NodeEnum::ResTarget(target)) => target.val.as_deref()?,
+        _ => return None,
+    };
+    match &val.node {
+        Some(NodeEnum::FuncCall(f))
+            if f.funcname.last().and_then(node_to_string).as_deref()
+                == Some("create_hypertable") =>
+        {
+            Some(f.args.iter().filter_map(node_to_string).collect())
+        }
+        _ => None,
+    }
+}
+
+/// the value of a string literal argument, or the argument as is if it's not one
+fn unquote(s: &str) -> String {
+    match split_literal(s) {
+        Some((value, _)) => value,
+        None => s.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Differ;
+
+    #[test]
+    fn hypertable_should_parse() {
+        let sql = "SELECT create_hypertable('metrics', 'ts')";
+        let parsed: Hypertable = sql.parse().unwrap();
+        assert_eq!(parsed.id, SchemaId::new("public", "metrics"));
+        assert_eq!(parsed.time_column, "ts");
+
+        let sql = "SELECT public.create_hypertable('app.events'::regclass, 'created_at', chunk_time_interval => interval '1 day')";
+        let parsed: Hypertable = sql.parse().unwrap();
+        assert_eq!(parsed.id, SchemaId::new("app", "events"));
+        assert_eq!(parsed.time_column, "created_at");
+
+        let sql = r#"SELECT create_hypertable('"App"."it''s"', 'ts')"#;
+        let parsed: Hypertable = sql.parse().unwrap();
+        assert_eq!(parsed.id, SchemaId::new("App", "it's"));
+    }
+
+    #[test]
+    fn hypertable_should_not_be_dropped() {
+        let old: Hypertable = "SELECT create_hypertable('metrics', 'ts')".parse().unwrap();
+        let new: Hypertable = "SELECT create_hypertable('metrics', 'time')"
+            .parse()
+            .unwrap();
+        let diff = old.diff(&new).unwrap().unwrap();
+        assert!(diff.plan().is_err());
+    }
+}
@@ -1,8 +1,9 @@
 use crate::{
-    parser::{RelationId, TableIndex},
+    parser::{Ident, RelationId, TableIndex},
     NodeItem,
 };
 use pg_query::{protobuf::IndexStmt, NodeEnum, NodeRef};
+use std::sync::Arc;
 
 impl NodeItem for TableIndex {
     type Inner = IndexStmt;
@@ -19,14 +20,18 @@ impl NodeItem for TableIndex {
     }
 
     fn inner(&self) -> anyhow::Result<&Self::Inner> {
-        match &self.node {
+        match self.node.as_ref() {
             NodeEnum::IndexStmt(stmt) => Ok(stmt),
             _ => anyhow::bail!("not a create index statement"),
         }
     }
 
     fn revert(&self) -> anyhow::Result<NodeEnum> {
-        let sql = format!("DROP INDEX {}", self.id.name);
+        let sql = format!(
+            "DROP INDEX {}.{}",
+            Ident(&self.id.schema_id.schema),
+            Ident(&self.id.name)
+        );
         let parsed = pg_query::parse(&sql)?;
         let node = parsed.protobuf.nodes()[0].0;
         match node {
@@ -40,7 +45,7 @@ impl TryFrom<&IndexStmt> for TableIndex {
     type Error = anyhow::Error;
     fn try_from(stmt: &IndexStmt) -> Result<Self, Self::Error> {
         let id = get_id(stmt);
-        let node = pg_query::NodeEnum::IndexStmt(Box::new(stmt.clone()));
+        let node = Arc::new(pg_query::NodeEnum::IndexStmt(Box::new(stmt.clone())));
         Ok(Self { id, node })
     }
 }
@@ -84,7 +89,7 @@ mod tests {
         let new: TableIndex = sql2.parse().unwrap();
         let diff = old.diff(&new).unwrap().unwrap();
         let migrations = diff.plan().unwrap();
-        assert_eq!(migrations[0], "DROP INDEX foo");
+        assert_eq!(migrations[0], "DROP INDEX public.foo");
         assert_eq!(migrations[1], "CREATE INDEX foo ON bar USING btree (ooo)");
     }
 }
@@ -1,8 +1,9 @@
 use crate::{
-    parser::{utils::node_to_string, RelationId, TablePolicy},
+    parser::{utils::node_to_string, Ident, RelationId, TablePolicy},
     NodeItem,
 };
 use pg_query::{protobuf::CreatePolicyStmt, NodeEnum, NodeRef};
+use std::sync::Arc;
 
 impl NodeItem for TablePolicy {
     type Inner = CreatePolicyStmt;
@@ -19,14 +20,18 @@ impl NodeItem for TablePolicy {
     }
 
     fn inner(&self) -> anyhow::Result<&Self::Inner> {
-        match &self.node {
+        match self.node.as_ref() {
             NodeEnum::CreatePolicyStmt(stmt) => Ok(stmt),
             _ => anyhow::bail!("not a create policy statement"),
         }
     }
 
     fn revert(&self) -> anyhow::Result<NodeEnum> {
-        let sql = format!("DROP POLICY {} On {}", self.id.name, self.id.schema_id);
+        let sql = format!(
+            "DROP POLICY {} On {}",
+            Ident(&self.id.name),
+            self.id.schema_id
+        );
         let parsed = pg_query::parse(&sql)?;
         let node = parsed.protobuf.nodes()[0].0;
         match node {
@@ -45,7 +50,7 @@ impl TryFrom<&CreatePolicyStmt> for TablePolicy {
         let roles = stmt.roles.iter().filter_map(node_to_string).collect();
         let qual = stmt.qual.as_deref().and_then(node_to_string);
         let with_check = stmt.with_check.as_deref().and_then(node_to_string);
-        let node = NodeEnum::CreatePolicyStmt(Box::new(stmt.clone()));
+        let node = Arc::new(NodeEnum::CreatePolicyStmt(Box::new(stmt.clone())));
         Ok(Self {
             id,
             cmd_name,
@@ -3,6 +3,7 @@ use crate::{
     NodeItem,
 };
 use pg_query::{protobuf::AlterTableStmt, NodeEnum, NodeRef};
+use std::sync::Arc;
 
 impl NodeItem for TableRls {
     type Inner = AlterTableStmt;
@@ -20,7 +21,7 @@ impl NodeItem for TableRls {
     }
 
     fn inner(&self) -> anyhow::Result<&Self::Inner> {
-        match &self.node {
+        match self.node.as_ref() {
             NodeEnum::AlterTableStmt(stmt) => Ok(stmt),
             _ => anyhow::bail!("not a alter table statement"),
         }
@@ -49,7 +50,7 @@ impl TryFrom<AlterTable> for TableRls {
 }
 
 impl TableRls {
-    fn new(id: SchemaId, node: NodeEnum) -> Self {
+    fn new(id: SchemaId, node: Arc<NodeEnum>) -> Self {
         Self { id, node }
     }
 }
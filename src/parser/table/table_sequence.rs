@@ -1,8 +1,11 @@
 use crate::{
-    parser::{AlterTable, AlterTableAction, RelationId, SchemaId, SequenceInfo, TableSequence},
+    parser::{
+        AlterTable, AlterTableAction, Ident, RelationId, SchemaId, SequenceInfo, TableSequence,
+    },
     NodeItem,
 };
 use pg_query::{protobuf::AlterTableStmt, NodeEnum, NodeRef};
+use std::sync::Arc;
 
 impl NodeItem for TableSequence {
     type Inner = AlterTableStmt;
@@ -28,7 +31,8 @@ impl NodeItem for TableSequence {
     fn revert(&self) -> anyhow::Result<NodeEnum> {
         let sql = format!(
             "ALTER TABLE {} ALTER COLUMN {} DROP DEFAULT",
-            self.id.schema_id, self.id.name
+            self.id.schema_id,
+            Ident(&self.id.name)
         );
         let parsed = pg_query::parse(&sql)?;
         let node = parsed.protobuf.nodes()[0].0;
@@ -50,7 +54,7 @@ impl TryFrom<AlterTable> for TableSequence {
 }
 
 impl TableSequence {
-    fn new(id: SchemaId, info: SequenceInfo, node: NodeEnum) -> Self {
+    fn new(id: SchemaId, info: SequenceInfo, node: Arc<NodeEnum>) -> Self {
         let id = RelationId::new_with(id, info.column);
         Self { id, node }
     }
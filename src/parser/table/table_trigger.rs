@@ -1,8 +1,9 @@
 use crate::{
-    parser::{RelationId, Trigger},
+    parser::{utils::node_to_string, Ident, RelationId, SchemaId, Trigger},
     NodeItem,
 };
 use pg_query::{protobuf::CreateTrigStmt, NodeEnum, NodeRef};
+use std::sync::Arc;
 
 impl NodeItem for Trigger {
     type Inner = CreateTrigStmt;
@@ -20,14 +21,18 @@ impl NodeItem for Trigger {
     }
 
     fn inner(&self) -> anyhow::Result<&Self::Inner> {
-        match &self.node {
+        match self.node.as_ref() {
             NodeEnum::CreateTrigStmt(stmt) => Ok(stmt),
             _ => anyhow::bail!("not a create trigger statement"),
         }
     }
 
     fn revert(&self) -> anyhow::Result<NodeEnum> {
-        let sql = format!("DROP TRIGGER {} on {}", self.id.name, self.id.schema_id);
+        let sql = format!(
+            "DROP TRIGGER {} on {}",
+            Ident(&self.id.name),
+            self.id.schema_id
+        );
         let parsed = pg_query::parse(&sql)?;
         let node = parsed.protobuf.nodes()[0].0;
         match node {
@@ -37,13 +42,28 @@ impl NodeItem for Trigger {
     }
 }
 
+impl Trigger {
+    /// id of the function executed by the trigger, e.g. `public.set_updated_at()`, in the
+    /// form of the privilege ids
+    pub fn function(&self) -> anyhow::Result<String> {
+        let names: Vec<_> = self
+            .inner()?
+            .funcname
+            .iter()
+            .filter_map(node_to_string)
+            .collect();
+        let names: Vec<_> = names.iter().map(|n| n.as_str()).collect();
+        Ok(format!("{}()", SchemaId::new_with(&names)))
+    }
+}
+
 impl TryFrom<&CreateTrigStmt> for Trigger {
     type Error = anyhow::Error;
     fn try_from(stmt: &CreateTrigStmt) -> Result<Self, Self::Error> {
         let name = stmt.trigname.clone();
         let schema_id = stmt.relation.as_ref().into();
         let id = RelationId::new_with(schema_id, name);
-        let node = NodeEnum::CreateTrigStmt(Box::new(stmt.clone()));
+        let node = Arc::new(NodeEnum::CreateTrigStmt(Box::new(stmt.clone())));
         Ok(Self { id, node })
     }
 }
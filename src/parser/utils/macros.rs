@@ -1,8 +1,8 @@
 use crate::{
     parser::{
-        AlterTable, CompositeType, EnumType, Function, MatView, Privilege, Sequence, Table,
-        TableConstraint, TableIndex, TableOwner, TablePolicy, TableRls, TableSequence, Trigger,
-        View,
+        AlterTable, CompositeType, EnumType, Extension, Function, Hypertable, MatView, Privilege,
+        Sequence, Table, TableConstraint, TableIndex, TableOwner, TablePolicy, TableRls,
+        TableSequence, Trigger, View,
     },
     MigrationPlanner, MigrationResult, NodeDiff, NodeItem,
 };
@@ -89,6 +89,23 @@ macro_rules! def_from_str {
     };
 }
 
+// node based types are serialized as `{ "sql": ..., ... }`, only the sql is needed to restore them
+macro_rules! def_deserialize {
+    ($($name:ident),*) => {
+        $(impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                #[derive(serde::Deserialize)]
+                struct SqlRepr {
+                    sql: String,
+                }
+
+                let repr = SqlRepr::deserialize(deserializer)?;
+                repr.sql.parse().map_err(serde::de::Error::custom)
+            }
+        })*
+    };
+}
+
 def_display!(
     CompositeType,
     EnumType,
@@ -117,13 +134,14 @@ def_simple_planner!(
     TablePolicy,
     TableRls,
     TableSequence,
-    Trigger,
-    View
+    Trigger
 );
 
 def_from_str!(CompositeType, CompositeTypeStmt);
 def_from_str!(EnumType, CreateEnumStmt);
+def_from_str!(Extension, CreateExtensionStmt);
 def_from_str!(Function, CreateFunctionStmt);
+def_from_str!(Hypertable, SelectStmt);
 def_from_str!(MatView, CreateTableAsStmt);
 def_from_str!(Sequence, CreateSeqStmt);
 def_from_str!(Table, CreateStmt);
@@ -136,3 +154,23 @@ def_from_str!(TableSequence);
 def_from_str!(Trigger, CreateTrigStmt);
 def_from_str!(Privilege, GrantStmt);
 def_from_str!(View, ViewStmt);
+
+def_deserialize!(
+    CompositeType,
+    EnumType,
+    Extension,
+    Function,
+    Hypertable,
+    MatView,
+    Privilege,
+    Sequence,
+    Table,
+    TableConstraint,
+    TableIndex,
+    TableOwner,
+    TablePolicy,
+    TableRls,
+    TableSequence,
+    Trigger,
+    View
+);
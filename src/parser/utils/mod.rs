@@ -6,6 +6,24 @@ pub use node::{
     node_enum_to_string, node_to_embed_constraint, node_to_string, type_name_to_string,
 };
 
+use pg_query::NodeEnum;
+use serde::Serializer;
+use std::fmt;
+
+/// Serialize a parsed node back into its sql representation.
+pub fn serialize_node<S: Serializer>(node: &NodeEnum, serializer: S) -> Result<S::Ok, S::Error> {
+    let sql = node.deparse().map_err(serde::ser::Error::custom)?;
+    serializer.serialize_str(&sql)
+}
+
+/// Serialize the protobuf enums (e.g. `ObjectType`) by their variant name.
+pub fn serialize_debug<T: fmt::Debug, S: Serializer>(
+    v: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&format_args!("{:?}", v))
+}
+
 #[allow(dead_code)]
 pub fn drain_where<T, Pred: Fn(&T) -> bool>(source: Vec<T>, pred: Pred) -> (Vec<T>, Vec<T>) {
     let mut orig: Vec<T> = Vec::with_capacity(source.len());
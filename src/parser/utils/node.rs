@@ -1,4 +1,4 @@
-use crate::parser::ConstraintInfo;
+use crate::parser::{ConstraintInfo, Ident, Literal};
 use itertools::Itertools;
 use pg_query::{
     protobuf::{AExprKind, RoleSpecType, SqlValueFunctionOp, TypeName},
@@ -13,8 +13,13 @@ pub fn node_to_embed_constraint(node: &Node) -> Option<ConstraintInfo> {
 }
 
 pub fn type_name_to_string(n: &TypeName) -> String {
-    let typname = n.names.iter().filter_map(node_to_string).join(".");
-    let typmod = n.typmods.iter().filter_map(node_to_string).join("");
+    let typname = n
+        .names
+        .iter()
+        .filter_map(node_to_string)
+        .map(|name| Ident(&name).to_string())
+        .join(".");
+    let typmod = n.typmods.iter().filter_map(node_to_string).join(", ");
     let array_bounds = array_bounds_to_string(&n.array_bounds);
 
     match (typmod.as_str(), array_bounds.as_str()) {
@@ -51,7 +56,7 @@ pub fn node_enum_to_string(node: &NodeEnum) -> Option<String> {
         NodeEnum::String(s) => Some(s.str.clone()),
         NodeEnum::Integer(i) => Some(i.ival.to_string()),
         NodeEnum::AConst(a) => a.val.as_ref().and_then(|v| match &v.node {
-            Some(NodeEnum::String(s)) => Some(format!("'{}'", s.str)),
+            Some(NodeEnum::String(s)) => Some(Literal(&s.str).to_string()),
             Some(NodeEnum::Integer(i)) => Some(i.ival.to_string()),
             _ => None,
         }),
@@ -1,6 +1,11 @@
-use super::{SchemaId, View};
-use crate::NodeItem;
-use pg_query::{protobuf::ViewStmt, NodeEnum, NodeRef};
+use super::{utils::node_to_string, Ident, Literal, SchemaId, View};
+use crate::{types::split_literal, MigrationPlanner, MigrationResult, NodeDiff, NodeItem};
+use itertools::Itertools;
+use pg_query::{
+    protobuf::{ViewCheckOption, ViewStmt},
+    NodeEnum, NodeRef,
+};
+use std::{collections::BTreeMap, sync::Arc};
 
 impl NodeItem for View {
     type Inner = ViewStmt;
@@ -17,7 +22,7 @@ impl NodeItem for View {
     }
 
     fn inner(&self) -> anyhow::Result<&Self::Inner> {
-        match &self.node {
+        match self.node.as_ref() {
             NodeEnum::ViewStmt(stmt) => Ok(stmt),
             _ => anyhow::bail!("not a create view statement"),
         }
@@ -38,11 +43,170 @@ impl TryFrom<&ViewStmt> for View {
     type Error = anyhow::Error;
     fn try_from(stmt: &ViewStmt) -> Result<Self, Self::Error> {
         let id = get_view_id(stmt);
-        let node = NodeEnum::ViewStmt(Box::new(stmt.clone()));
+        let node = Arc::new(NodeEnum::ViewStmt(Box::new(normalize(stmt)?)));
         Ok(Self { id, node })
     }
 }
 
+/// Normalize the view so that it's the same whether it's written by users or dumped by
+/// pg_dump. `CREATE RECURSIVE VIEW v (n) AS ...` is parsed as `CREATE VIEW v (n) AS WITH
+/// RECURSIVE v(n) AS (...) SELECT n FROM v`, whose column aliases are redundant and not dumped.
+/// The query is then deparsed and parsed again, so that the CTEs are in the form the deparser
+/// generates, and the rendered file is stable across round trips.
+fn normalize(stmt: &ViewStmt) -> anyhow::Result<ViewStmt> {
+    let mut stmt = stmt.clone();
+    if is_recursive_view(&stmt) {
+        stmt.aliases.clear();
+    }
+    let sql = NodeEnum::ViewStmt(Box::new(stmt)).deparse()?;
+    let parsed = pg_query::parse(&sql)?;
+    match parsed.protobuf.nodes()[0].0 {
+        NodeRef::ViewStmt(stmt) => Ok(stmt.clone()),
+        _ => anyhow::bail!("not a create view statement: {}", sql),
+    }
+}
+
+/// whether the query is the recursive CTE of the view itself, with the view column aliases
+fn is_recursive_view(stmt: &ViewStmt) -> bool {
+    let name = stmt.view.as_ref().map(|v| v.relname.as_str());
+    let select = match stmt.query.as_ref().and_then(|q| q.node.as_ref()) {
+        Some(NodeEnum::SelectStmt(select)) => select,
+        _ => return false,
+    };
+    match select.with_clause.as_ref() {
+        Some(with) if with.recursive && with.ctes.len() == 1 => match with.ctes[0].node.as_ref() {
+            Some(NodeEnum::CommonTableExpr(cte)) => {
+                Some(cte.ctename.as_str()) == name && cte.aliascolnames == stmt.aliases
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+impl MigrationPlanner for NodeDiff<View> {
+    type Migration = String;
+
+    fn drop(&self) -> MigrationResult<Self::Migration> {
+        if let Some(old) = &self.old {
+            let sql = old.revert()?.deparse()?;
+            Ok(vec![sql])
+        } else {
+            Ok(vec![])
+        }
+    }
+
+    fn create(&self) -> MigrationResult<Self::Migration> {
+        if let Some(new) = &self.new {
+            let sql = new.to_string();
+            Ok(vec![sql])
+        } else {
+            Ok(vec![])
+        }
+    }
+
+    /// If the query is the same, the changed options and column aliases are altered in place,
+    /// so that the grants and the objects depending on the view are kept.
+    fn alter(&self) -> MigrationResult<Self::Migration> {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => {
+                let (old_stmt, new_stmt) = (old.inner()?, new.inner()?);
+                if old_stmt.aliases.len() != new_stmt.aliases.len()
+                    || view_body(old_stmt)? != view_body(new_stmt)?
+                {
+                    return Ok(vec![]);
+                }
+
+                let mut migrations = vec![];
+                let aliases = old_stmt.aliases.iter().zip(&new_stmt.aliases);
+                for (from, to) in
+                    aliases.filter_map(|(a, b)| node_to_string(a).zip(node_to_string(b)))
+                {
+                    if from != to {
+                        migrations.push(format!(
+                            "ALTER VIEW {} RENAME COLUMN {} TO {}",
+                            old.id,
+                            Ident(&from),
+                            Ident(&to)
+                        ));
+                    }
+                }
+
+                let (old_options, new_options) = (view_options(old_stmt), view_options(new_stmt));
+                let changed = new_options
+                    .iter()
+                    .filter(|(k, v)| old_options.get(*k) != Some(*v))
+                    .map(|(k, v)| format!("{} = {}", Ident(k), option_value(v)))
+                    .join(", ");
+                if !changed.is_empty() {
+                    migrations.push(format!("ALTER VIEW {} SET ({})", old.id, changed));
+                }
+                let removed = old_options
+                    .keys()
+                    .filter(|k| !new_options.contains_key(*k))
+                    .map(|k| Ident(k.as_str()))
+                    .join(", ");
+                if !removed.is_empty() {
+                    migrations.push(format!("ALTER VIEW {} RESET ({})", old.id, removed));
+                }
+                Ok(migrations)
+            }
+            _ => Ok(vec![]),
+        }
+    }
+}
+
+/// the view definition without the options and column aliases
+fn view_body(stmt: &ViewStmt) -> anyhow::Result<String> {
+    let mut stmt = stmt.clone();
+    stmt.options.clear();
+    stmt.aliases.clear();
+    stmt.with_check_option = ViewCheckOption::NoCheckOption as i32;
+    stmt.replace = false;
+    Ok(NodeEnum::ViewStmt(Box::new(stmt)).deparse()?)
+}
+
+/// the value as is if it's a word or a quoted literal, quoted otherwise
+fn option_value(value: &str) -> String {
+    let word = value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    let literal = matches!(split_literal(value), Some((_, rest)) if rest.is_empty());
+    if (word && !value.is_empty()) || literal {
+        value.to_owned()
+    } else {
+        Literal(value).to_string()
+    }
+}
+
+/// the options of the view, e.g. `security_barrier`, with `WITH CHECK OPTION` as `check_option`
+fn view_options(stmt: &ViewStmt) -> BTreeMap<String, String> {
+    let mut options: BTreeMap<String, String> = stmt
+        .options
+        .iter()
+        .filter_map(|n| match n.node.as_ref() {
+            Some(NodeEnum::DefElem(d)) => {
+                let value = d.arg.as_deref().and_then(node_to_string);
+                Some((
+                    d.defname.clone(),
+                    value.unwrap_or_else(|| "true".to_owned()),
+                ))
+            }
+            _ => None,
+        })
+        .collect();
+    match ViewCheckOption::from_i32(stmt.with_check_option) {
+        Some(ViewCheckOption::LocalCheckOption) => {
+            options.insert("check_option".to_owned(), "local".to_owned());
+        }
+        Some(ViewCheckOption::CascadedCheckOption) => {
+            options.insert("check_option".to_owned(), "cascaded".to_owned());
+        }
+        _ => {}
+    }
+    options
+}
+
 fn get_view_id(stmt: &ViewStmt) -> SchemaId {
     assert!(stmt.view.is_some());
     stmt.view.as_ref().unwrap().into()
@@ -72,4 +236,43 @@ mod tests {
         assert_eq!(migrations[0], "DROP VIEW public.foo");
         assert_eq!(migrations[1], "CREATE VIEW foo AS SELECT 2");
     }
+
+    #[test]
+    fn recursive_view_should_be_normalized() {
+        let sql1 = "CREATE RECURSIVE VIEW nums (n) AS VALUES (1) UNION ALL SELECT n + 1 FROM nums WHERE n < 10";
+        let sql2 = "CREATE VIEW nums AS WITH RECURSIVE nums(n) AS (VALUES (1) UNION ALL SELECT n + 1 FROM nums WHERE n < 10) SELECT n FROM nums";
+        let written: View = sql1.parse().unwrap();
+        let dumped: View = sql2.parse().unwrap();
+        assert!(written.diff(&dumped).unwrap().is_none());
+
+        // rendered sql parses back to the same view
+        let rendered: View = written.to_string().parse().unwrap();
+        assert_eq!(rendered.to_string(), written.to_string());
+    }
+
+    #[test]
+    fn view_option_change_should_be_altered() {
+        let sql1 = "CREATE VIEW foo (a) AS SELECT id FROM todos";
+        let sql2 =
+            "CREATE VIEW foo (b) WITH (security_barrier = true) AS SELECT id FROM todos WITH LOCAL CHECK OPTION";
+        let old: View = sql1.parse().unwrap();
+        let new: View = sql2.parse().unwrap();
+        let migrations = old.diff(&new).unwrap().unwrap().plan().unwrap();
+        assert_eq!(
+            migrations,
+            vec![
+                "ALTER VIEW public.foo RENAME COLUMN a TO b",
+                "ALTER VIEW public.foo SET (check_option = local, security_barrier = true)",
+            ]
+        );
+
+        let migrations = new.diff(&old).unwrap().unwrap().plan().unwrap();
+        assert_eq!(
+            migrations,
+            vec![
+                "ALTER VIEW public.foo RENAME COLUMN b TO a",
+                "ALTER VIEW public.foo RESET (check_option, security_barrier)",
+            ]
+        );
+    }
 }
@@ -0,0 +1,1891 @@
+use crate::{
+    parser::{utils::node_to_string, Ident, Literal, Privilege, SchemaId},
+    utils::ignore_file,
+    ChangeKind, DiffHunk, DmlEstimate, Observer, Plan, PlanChange, RenovateDiffConfig, SchemaFiles,
+    SqlLoader, TableRewrite, TableStats,
+};
+use anyhow::{bail, Context, Result};
+use itertools::Itertools;
+use pg_query::{
+    protobuf::{AlterTableType, ConstrType, ObjectType},
+    NodeEnum, NodeRef,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+/// Diff two sets of sql and generate the migration plan from `old` to `new`.
+///
+/// It only parses and diffs the sql without touching any io, so it is also available on wasm.
+pub fn diff_sql(old: &str, new: &str) -> Result<Plan> {
+    let old = SqlLoader::new(old).parse()?;
+    let new = SqlLoader::new(new).parse()?;
+    let collector = ChangeCollector::default();
+    let statements = new.plan_with(&old, &RenovateDiffConfig::default(), &collector)?;
+    Ok(Plan::new(statements, collector.take()))
+}
+
+impl Plan {
+    /// Build the plan from the planned statements and the changes reported while planning
+    pub fn new(statements: Vec<String>, changes: Vec<PlanChange>) -> Self {
+        let rewrites = statements
+            .iter()
+            .filter_map(|sql| {
+                table_rewrite(sql).map(|reason| TableRewrite {
+                    statement: sql.clone(),
+                    reason: reason.to_owned(),
+                    size: None,
+                })
+            })
+            .collect();
+        Self {
+            statements,
+            changes,
+            rewrites,
+            estimates: Vec::new(),
+            services: BTreeMap::new(),
+            suppressed: Vec::new(),
+        }
+    }
+
+    /// Attach the size of the rewritten tables from the stats of the last fetch, see
+    /// `Renovate::table_stats`, shown by `to_markdown`
+    pub fn with_stats(mut self, stats: &BTreeMap<String, TableStats>) -> Self {
+        for rewrite in &mut self.rewrites {
+            rewrite.size = table_size(&rewrite.statement, stats);
+        }
+        self
+    }
+
+    /// Attach the objects left out of the plan by `-- renovate:ignore`, shown by `to_markdown`
+    pub fn with_suppressed(mut self, suppressed: Vec<String>) -> Self {
+        self.suppressed = suppressed;
+        self
+    }
+
+    /// Attach the services to redeploy along with the plan, shown by `to_markdown`
+    pub fn with_services(mut self, services: BTreeMap<String, BTreeSet<String>>) -> Self {
+        self.services = services;
+        self
+    }
+
+    /// Attach the planner estimates of the data migrations, shown by `to_markdown`
+    pub fn with_estimates(mut self, estimates: Vec<DmlEstimate>) -> Self {
+        self.estimates = estimates;
+        self
+    }
+
+    /// the statements which may lose data, e.g. dropping a table or changing a column type
+    pub fn destructive_statements(&self) -> Vec<&str> {
+        self.statements
+            .iter()
+            .filter(|sql| is_destructive(sql))
+            .map(|sql| sql.as_str())
+            .collect()
+    }
+
+    /// Render the plan as a markdown document (summary table, destructive warnings and the sql
+    /// in a collapsed section), e.g. to post it as a pull request comment in CI.
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::from("### Renovate migration plan\n\n");
+        if self.statements.is_empty() {
+            md.push_str("No changes detected.\n");
+            return md;
+        }
+
+        let mut summary: BTreeMap<&str, [usize; 3]> = BTreeMap::new();
+        for change in &self.changes {
+            let counts = summary.entry(change.type_name.as_str()).or_default();
+            match change.kind {
+                ChangeKind::Added => counts[0] += 1,
+                ChangeKind::Changed => counts[1] += 1,
+                ChangeKind::Removed => counts[2] += 1,
+            }
+        }
+        if !summary.is_empty() {
+            md.push_str("| Object | Added | Changed | Removed |\n");
+            md.push_str("| --- | ---: | ---: | ---: |\n");
+            for (type_name, [added, changed, removed]) in summary {
+                let _ = writeln!(
+                    md,
+                    "| {} | {} | {} | {} |",
+                    type_name, added, changed, removed
+                );
+            }
+            md.push('\n');
+        }
+
+        let destructive = self.destructive_statements();
+        if !destructive.is_empty() {
+            let _ = writeln!(
+                md,
+                "> **Warning**\n> The plan contains {} destructive statement(s) which may lose data:\n>",
+                destructive.len()
+            );
+            for sql in destructive {
+                let _ = writeln!(md, "> - `{}`", sql);
+            }
+            md.push('\n');
+        }
+
+        if !self.rewrites.is_empty() {
+            let _ = writeln!(
+                md,
+                "> **Note**\n> The plan contains {} statement(s) rewriting the whole table, which lock it until done:\n>",
+                self.rewrites.len()
+            );
+            for rewrite in &self.rewrites {
+                let size = rewrite.size.map(|s| format!(", {}", s)).unwrap_or_default();
+                let _ = writeln!(
+                    md,
+                    "> - `{}` ({}{})",
+                    rewrite.statement, rewrite.reason, size
+                );
+            }
+            md.push('\n');
+        }
+
+        if !self.services.is_empty() {
+            let _ = writeln!(
+                md,
+                "> **Note**\n> The plan changes the objects of {} service(s), redeploy them along with it:\n>",
+                self.services.len()
+            );
+            for (service, objects) in &self.services {
+                let _ = writeln!(md, "> - {}: {}", service, objects.iter().join(", "));
+            }
+            md.push('\n');
+        }
+
+        if !self.suppressed.is_empty() {
+            let _ = writeln!(
+                md,
+                "> **Note**\n> {} object(s) annotated with `-- renovate:ignore` are left out of the plan:\n>",
+                self.suppressed.len()
+            );
+            for name in &self.suppressed {
+                let _ = writeln!(md, "> - {}", name);
+            }
+            md.push('\n');
+        }
+
+        if !self.estimates.is_empty() {
+            let _ = writeln!(
+                md,
+                "> **Note**\n> The estimates of the data migrations against the target database:\n>"
+            );
+            for estimate in &self.estimates {
+                let _ = writeln!(md, "> - `{}`: {}", estimate.statement, estimate);
+            }
+            md.push('\n');
+        }
+
+        let _ = writeln!(
+            md,
+            "<details>\n<summary>{} SQL statement(s)</summary>\n\n```sql",
+            self.statements.len()
+        );
+        for sql in &self.statements {
+            let _ = writeln!(md, "{};", sql);
+        }
+        md.push_str("```\n\n</details>\n");
+        md
+    }
+}
+
+impl fmt::Display for DmlEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(error) = &self.error {
+            return write!(f, "no estimate ({})", error);
+        }
+        write!(f, "~{} row(s), cost {}", self.rows, self.cost)?;
+        if self.full_scan {
+            write!(f, ", full table scan")?;
+        }
+        Ok(())
+    }
+}
+
+/// The tables with more rows, or bytes, are marked as large, e.g. in the DBML
+const LARGE_TABLE_ROWS: u64 = 1_000_000;
+const LARGE_TABLE_BYTES: u64 = 1 << 30;
+
+impl TableStats {
+    /// whether the table is big enough that rewriting or scanning it takes a while
+    pub fn is_large(&self) -> bool {
+        self.rows >= LARGE_TABLE_ROWS || self.bytes >= LARGE_TABLE_BYTES
+    }
+}
+
+/// e.g. `~1.2M rows, 340.5 MB`
+impl fmt::Display for TableStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = match self.rows {
+            n if n < 1000 => n.to_string(),
+            n if n < 1_000_000 => format!("{:.1}k", n as f64 / 1e3),
+            n if n < 1_000_000_000 => format!("{:.1}M", n as f64 / 1e6),
+            n => format!("{:.1}B", n as f64 / 1e9),
+        };
+        let mut bytes = self.bytes as f64;
+        let mut unit = 0;
+        while bytes >= 1024.0 && unit < 4 {
+            bytes /= 1024.0;
+            unit += 1;
+        }
+        let units = ["bytes", "kB", "MB", "GB", "TB"];
+        match unit {
+            0 => write!(f, "~{} rows, {} bytes", rows, self.bytes),
+            _ => write!(f, "~{} rows, {:.1} {}", rows, bytes, units[unit]),
+        }
+    }
+}
+
+/// the size of the table the statement rewrites or alters, from the stats of the last fetch
+pub(crate) fn table_size(sql: &str, stats: &BTreeMap<String, TableStats>) -> Option<TableStats> {
+    statement_targets(sql)
+        .iter()
+        .find_map(|table| stats.get(table).copied())
+}
+
+/// Rewrite the statement with `IF EXISTS` / `IF NOT EXISTS` / `OR REPLACE` so that a partially
+/// applied plan could be re-run. Statements without such a clause, e.g. `CREATE TYPE`, `CREATE
+/// POLICY` or `ADD CONSTRAINT`, are returned as is.
+pub(crate) fn make_idempotent(sql: &str) -> Result<String> {
+    let parsed = pg_query::parse(sql)?;
+    let nodes = parsed.protobuf.nodes();
+    let mut statements = Vec::new();
+    for (node, depth, _) in nodes {
+        if depth != 0 {
+            continue;
+        }
+        let mut node = node.to_enum();
+        match &mut node {
+            NodeEnum::DropStmt(stmt) => stmt.missing_ok = true,
+            NodeEnum::CreateStmt(stmt) => stmt.if_not_exists = true,
+            NodeEnum::IndexStmt(stmt) => stmt.if_not_exists = true,
+            NodeEnum::CreateSeqStmt(stmt) => stmt.if_not_exists = true,
+            NodeEnum::CreateSchemaStmt(stmt) => stmt.if_not_exists = true,
+            NodeEnum::CreateExtensionStmt(stmt) => stmt.if_not_exists = true,
+            NodeEnum::RenameStmt(stmt) => stmt.missing_ok = true,
+            NodeEnum::CreateTableAsStmt(stmt) => stmt.if_not_exists = true,
+            NodeEnum::AlterSeqStmt(stmt) => stmt.missing_ok = true,
+            NodeEnum::ViewStmt(stmt) => stmt.replace = true,
+            NodeEnum::CreateFunctionStmt(stmt) => stmt.replace = true,
+            NodeEnum::AlterEnumStmt(stmt)
+                if !stmt.new_val.is_empty() && stmt.old_val.is_empty() =>
+            {
+                stmt.skip_if_new_val_exists = true
+            }
+            // the other objects have no `IF EXISTS` in `SET SCHEMA`
+            NodeEnum::AlterObjectSchemaStmt(stmt)
+                if matches!(
+                    stmt.object_type(),
+                    ObjectType::ObjectTable
+                        | ObjectType::ObjectSequence
+                        | ObjectType::ObjectView
+                        | ObjectType::ObjectMatview
+                        | ObjectType::ObjectForeignTable
+                ) =>
+            {
+                stmt.missing_ok = true
+            }
+            NodeEnum::AlterTableStmt(stmt) => {
+                for cmd in stmt.cmds.iter_mut() {
+                    if let Some(NodeEnum::AlterTableCmd(cmd)) = cmd.node.as_mut() {
+                        if matches!(
+                            cmd.subtype(),
+                            AlterTableType::AtAddColumn
+                                | AlterTableType::AtDropColumn
+                                | AlterTableType::AtDropConstraint
+                        ) {
+                            cmd.missing_ok = true;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        statements.push(node.deparse()?);
+    }
+    Ok(statements.join(";\n"))
+}
+
+/// Placeholder of the current sequence value in the `setval()` planned by
+/// `preserve_sequence_values`. It's resolved against the target database right before the plan
+/// is applied, while the sequence is not dropped yet.
+pub(crate) const SEQUENCE_VALUE: &str = "__renovate_sequence_value__";
+
+/// Restore the value of every sequence the plan drops and recreates (e.g. for a type change),
+/// so that the ids don't restart from the start value, e.g. `SELECT setval('public.todos_id_seq',
+/// __renovate_sequence_value__)` right after the `CREATE SEQUENCE`.
+pub(crate) fn preserve_sequence_values(plan: Vec<String>) -> Result<Vec<String>> {
+    let mut dropped = Vec::new();
+    let mut migrations = Vec::with_capacity(plan.len());
+    for sql in plan {
+        let parsed = pg_query::parse(&sql)?;
+        let mut created = None;
+        for (node, depth, _) in parsed.protobuf.nodes() {
+            match node {
+                NodeRef::DropStmt(stmt)
+                    if depth == 0 && stmt.remove_type() == ObjectType::ObjectSequence =>
+                {
+                    for object in &stmt.objects {
+                        if let Some(NodeEnum::List(list)) = object.node.as_ref() {
+                            let names: Vec<_> =
+                                list.items.iter().filter_map(node_to_string).collect();
+                            let names: Vec<_> = names.iter().map(|n| n.as_str()).collect();
+                            dropped.push(SchemaId::new_with(&names).to_string());
+                        }
+                    }
+                }
+                NodeRef::CreateSeqStmt(stmt) if depth == 0 => {
+                    created = Some(SchemaId::from(stmt.sequence.as_ref()).to_string());
+                }
+                _ => {}
+            }
+        }
+        migrations.push(sql);
+        if let Some(id) = created.filter(|id| dropped.contains(id)) {
+            migrations.push(format!(
+                "SELECT setval({}, {})",
+                Literal(&id),
+                SEQUENCE_VALUE
+            ));
+        }
+    }
+    Ok(migrations)
+}
+
+/// Whether the statement can't run in the transaction of the plan: a `CALL`, since the procedure
+/// may commit or roll back, and the creation of a procedure whose body does so, which is
+/// committed on its own before it's called; the transaction control statements themselves; and
+/// the ones postgres refuses in a transaction block, e.g. `VACUUM` or `CREATE INDEX
+/// CONCURRENTLY`.
+pub(crate) fn requires_autocommit(sql: &str) -> bool {
+    let parsed = match pg_query::parse(sql) {
+        Ok(parsed) => parsed,
+        Err(_) => return false,
+    };
+    let nodes = parsed.protobuf.nodes();
+    nodes.into_iter().any(|(node, depth, _)| match node {
+        _ if depth != 0 => false,
+        NodeRef::CallStmt(_)
+        | NodeRef::TransactionStmt(_)
+        | NodeRef::CreatedbStmt(_)
+        | NodeRef::DropdbStmt(_)
+        | NodeRef::AlterSystemStmt(_)
+        | NodeRef::CreateTableSpaceStmt(_)
+        | NodeRef::DropTableSpaceStmt(_) => true,
+        NodeRef::VacuumStmt(stmt) => stmt.is_vacuumcmd,
+        NodeRef::IndexStmt(stmt) => stmt.concurrent,
+        NodeRef::DropStmt(stmt) => stmt.concurrent,
+        NodeRef::ReindexStmt(stmt) => stmt.concurrent,
+        NodeRef::CreateFunctionStmt(stmt) if stmt.is_procedure => {
+            let plpgsql = stmt
+                .options
+                .iter()
+                .any(|option| match option.node.as_ref() {
+                    Some(NodeEnum::DefElem(d)) if d.defname == "language" => {
+                        d.arg.as_deref().and_then(node_to_string).as_deref() == Some("plpgsql")
+                    }
+                    _ => false,
+                });
+            plpgsql && controls_transaction(sql)
+        }
+        _ => false,
+    })
+}
+
+/// whether the body of the plpgsql function or procedure created by the sql has a `COMMIT` or
+/// a `ROLLBACK` statement, as opposed to the words in a comment or a string
+fn controls_transaction(sql: &str) -> bool {
+    fn walk(value: &serde_json::Value) -> bool {
+        match value {
+            serde_json::Value::Object(map) => map.iter().any(|(key, value)| {
+                key == "PLpgSQL_stmt_commit" || key == "PLpgSQL_stmt_rollback" || walk(value)
+            }),
+            serde_json::Value::Array(items) => items.iter().any(walk),
+            _ => false,
+        }
+    }
+    pg_query::parse_plpgsql(sql).map_or(false, |body| walk(&body))
+}
+
+/// The ids of the tables, views, sequences and functions the plan drops, in the form of the
+/// privilege ids, e.g. `public.todos` or `public.add(pg_catalog.int4)`. Their grants are gone
+/// with them.
+pub(crate) fn dropped_objects(plan: &[String]) -> BTreeSet<String> {
+    let mut dropped = BTreeSet::new();
+    for sql in plan {
+        let parsed = match pg_query::parse(sql) {
+            Ok(parsed) => parsed,
+            Err(_) => continue,
+        };
+        for (node, depth, _) in parsed.protobuf.nodes() {
+            let stmt = match node {
+                NodeRef::DropStmt(stmt) if depth == 0 => stmt,
+                _ => continue,
+            };
+            let relation = matches!(
+                stmt.remove_type(),
+                ObjectType::ObjectTable
+                    | ObjectType::ObjectView
+                    | ObjectType::ObjectMatview
+                    | ObjectType::ObjectSequence
+            );
+            for object in &stmt.objects {
+                match object.node.as_ref() {
+                    Some(NodeEnum::List(list)) if relation => {
+                        let names: Vec<_> = list.items.iter().filter_map(node_to_string).collect();
+                        let names: Vec<_> = names.iter().map(|n| n.as_str()).collect();
+                        dropped.insert(SchemaId::new_with(&names).to_string());
+                    }
+                    Some(NodeEnum::ObjectWithArgs(f))
+                        if stmt.remove_type() == ObjectType::ObjectFunction =>
+                    {
+                        let names: Vec<_> = f.objname.iter().filter_map(node_to_string).collect();
+                        let names: Vec<_> = names.iter().map(|n| n.as_str()).collect();
+                        dropped.insert(format!(
+                            "{}({})",
+                            SchemaId::new_with(&names),
+                            f.objargs.iter().filter_map(node_to_string).join(", ")
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    dropped
+}
+
+/// Qualify the relations referenced by the statement, the sequences passed to `nextval()` and
+/// the like, and the function or enum it creates, with the default schema, so that the plan
+/// doesn't depend on the search_path of the role applying it. CTE names are left as is, and
+/// function bodies are strings, hence untouched. The types and functions it references can't be
+/// told apart from the built-in ones here, so they're left to the search_path as well.
+pub(crate) fn qualify_references(sql: &str, schema: &str) -> Result<String> {
+    let mut sql = qualify_created_name(sql, schema)?;
+    let parsed = pg_query::parse(&sql)?;
+    let nodes = parsed.protobuf.nodes();
+    let ctes: BTreeSet<&str> = nodes
+        .iter()
+        .filter_map(|(node, _, _)| match node {
+            NodeRef::CommonTableExpr(cte) => Some(cte.ctename.as_str()),
+            _ => None,
+        })
+        .collect();
+    let prefix = format!("{}.", Ident(schema));
+    // within a string literal, e.g. `nextval('todos_id_seq')`, after the opening quote
+    let literal_prefix = prefix.replace('\'', "''");
+    let mut locations: Vec<(usize, &str)> = nodes
+        .iter()
+        .filter_map(|(node, _, _)| match node {
+            NodeRef::RangeVar(v)
+                if v.schemaname.is_empty()
+                    && v.location >= 0
+                    && !ctes.contains(v.relname.as_str()) =>
+            {
+                Some((v.location as usize, prefix.as_str()))
+            }
+            NodeRef::FuncCall(call) => match sequence_argument(call) {
+                Some((name, location))
+                    if !name.contains('.') && sql[location..].starts_with('\'') =>
+                {
+                    Some((location + 1, literal_prefix.as_str()))
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    locations.sort_unstable();
+    locations.dedup();
+    // from the end, so that the locations before stay valid
+    for (location, prefix) in locations.into_iter().rev() {
+        sql.insert_str(location, prefix);
+    }
+    Ok(sql)
+}
+
+/// functions which take the sequence by name, as a regclass literal
+const SEQUENCE_FUNCTIONS: &[&str] = &["nextval", "currval", "setval"];
+
+/// the sequence name passed to one of `SEQUENCE_FUNCTIONS` as a literal, and its location
+fn sequence_argument(call: &pg_query::protobuf::FuncCall) -> Option<(&str, usize)> {
+    let name = call.funcname.last().and_then(node_to_string)?;
+    if !SEQUENCE_FUNCTIONS.contains(&name.as_str()) {
+        return None;
+    }
+    let arg = match call.args.first()?.node.as_ref()? {
+        NodeEnum::TypeCast(cast) => cast.arg.as_ref()?.node.as_ref()?,
+        arg => arg,
+    };
+    match arg {
+        NodeEnum::AConst(c) if c.location >= 0 => match c.val.as_ref()?.node.as_ref()? {
+            NodeEnum::String(s) => Some((s.str.as_str(), c.location as usize)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Qualify the unqualified names of every statement of the sql, see `qualify_references`.
+pub(crate) fn qualify_sql(sql: &str, schema: &str) -> Result<String> {
+    let mut result = String::new();
+    for statement in split_statements(sql)? {
+        result.push_str(&qualify_references(&statement, schema)?);
+        result.push_str(";\n");
+    }
+    Ok(result)
+}
+
+/// the source of each statement of the sql, without the trailing semicolon
+fn split_statements(sql: &str) -> Result<Vec<String>> {
+    let parsed = pg_query::parse(sql)?;
+    let mut statements = Vec::with_capacity(parsed.protobuf.stmts.len());
+    for stmt in &parsed.protobuf.stmts {
+        let start = stmt.stmt_location as usize;
+        let end = match stmt.stmt_len {
+            0 => sql.len(),
+            len => start + len as usize,
+        };
+        let statement = sql[start..end].trim();
+        if !statement.is_empty() {
+            statements.push(statement.to_owned());
+        }
+    }
+    Ok(statements)
+}
+
+fn qualify_created_name(sql: &str, schema: &str) -> Result<String> {
+    let parsed = pg_query::parse(sql)?;
+    let nodes = parsed.protobuf.nodes();
+    let mut node = match nodes.first() {
+        Some((node, 0, _)) if parsed.protobuf.stmts.len() == 1 => node.to_enum(),
+        _ => return Ok(sql.to_owned()),
+    };
+    let names = match &mut node {
+        NodeEnum::CreateFunctionStmt(stmt) => &mut stmt.funcname,
+        NodeEnum::CreateEnumStmt(stmt) => &mut stmt.type_name,
+        _ => return Ok(sql.to_owned()),
+    };
+    if names.len() != 1 {
+        return Ok(sql.to_owned());
+    }
+    let schema = NodeEnum::String(pg_query::protobuf::String {
+        str: schema.to_owned(),
+    });
+    names.insert(0, pg_query::Node { node: Some(schema) });
+    Ok(node.deparse()?)
+}
+
+/// functions which make a column default volatile, hence evaluated for every existing row
+const VOLATILE_FUNCTIONS: &[&str] = &[
+    "clock_timestamp",
+    "gen_random_uuid",
+    "nextval",
+    "random",
+    "timeofday",
+    "uuid_generate_v1",
+    "uuid_generate_v4",
+];
+
+/// Why the statement rewrites the whole table, if it does. Adding a column with a constant (or
+/// stable, like `now()`) default doesn't since PostgreSQL 11.
+pub(crate) fn table_rewrite(sql: &str) -> Option<&'static str> {
+    let parsed = pg_query::parse(sql).ok()?;
+    let nodes = parsed.protobuf.nodes();
+    nodes.into_iter().find_map(|(node, _, _)| match node {
+        NodeRef::AlterTableCmd(cmd) => match (cmd.subtype(), cmd.def.as_ref()) {
+            (AlterTableType::AtAlterColumnType, _) => Some("column type change"),
+            (AlterTableType::AtSetLogged, _) => Some("SET LOGGED"),
+            (AlterTableType::AtSetUnLogged, _) => Some("SET UNLOGGED"),
+            (AlterTableType::AtAddColumn, Some(def)) => match def.node.as_ref() {
+                Some(NodeEnum::ColumnDef(column)) => column_rewrite(&column.constraints),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+fn column_rewrite(constraints: &[pg_query::Node]) -> Option<&'static str> {
+    constraints.iter().find_map(|c| match c.node.as_ref() {
+        Some(NodeEnum::Constraint(c)) => match c.contype() {
+            ConstrType::ConstrGenerated => Some("stored generated column"),
+            ConstrType::ConstrDefault => match c.raw_expr.as_ref().and_then(|e| e.node.as_ref()) {
+                Some(NodeEnum::FuncCall(f))
+                    if f.funcname
+                        .last()
+                        .and_then(node_to_string)
+                        .map_or(false, |name| VOLATILE_FUNCTIONS.contains(&name.as_str())) =>
+                {
+                    Some("column with a volatile default")
+                }
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+/// Name of the file at the root of the local repo which pins the relative order of the
+/// statements of some objects, for the dependencies renovate can't see, e.g. a function only
+/// referenced in dynamic sql.
+pub(crate) const ORDER_FILE: &str = "plan.order";
+
+/// A rule of the order file, e.g. `public.audit after public.todos`: the statements of `object`
+/// are moved after (or before) the statements of `anchor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct OrderRule {
+    object: String,
+    anchor: String,
+    after: bool,
+}
+
+impl OrderRule {
+    /// Parse the rules of the order file, one per line. `#` starts a comment, and unqualified
+    /// names are in the default schema.
+    pub fn parse(content: &str, schema: &str) -> Result<Vec<Self>> {
+        content
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| {
+                let line = line.split('#').next().unwrap_or_default().trim();
+                if line.is_empty() {
+                    return None;
+                }
+                Some(Self::parse_line(line, schema).with_context(|| {
+                    format!("Invalid rule at line {} of {}: {}", i + 1, ORDER_FILE, line)
+                }))
+            })
+            .collect()
+    }
+
+    fn parse_line(line: &str, schema: &str) -> Result<Self> {
+        let words: Vec<_> = line.split_whitespace().collect();
+        let after = match words.as_slice() {
+            [_, "after", _] => true,
+            [_, "before", _] => false,
+            _ => bail!("expected `<object> after <object>` or `<object> before <object>`"),
+        };
+        let qualify = |name: &str| match name.contains('.') {
+            true => name.to_owned(),
+            false => format!("{}.{}", schema, name),
+        };
+        Ok(Self {
+            object: qualify(words[0]),
+            anchor: qualify(words[2]),
+            after,
+        })
+    }
+}
+
+/// Move the statements of the object of each rule right after the last statement (or before the
+/// first one) of its anchor, keeping their relative order. A rule already satisfied, or whose
+/// objects are not in the plan, changes nothing.
+pub(crate) fn apply_order(plan: Vec<String>, rules: &[OrderRule]) -> Vec<String> {
+    if rules.is_empty() {
+        return plan;
+    }
+    let mut plan: Vec<_> = plan
+        .into_iter()
+        .map(|sql| {
+            let targets = statement_targets(&sql);
+            (sql, targets)
+        })
+        .collect();
+    for rule in rules {
+        let is_object = |targets: &BTreeSet<String>| {
+            targets.contains(&rule.object) && !targets.contains(&rule.anchor)
+        };
+        let anchors: Vec<_> = plan
+            .iter()
+            .positions(|(_, targets)| targets.contains(&rule.anchor))
+            .collect();
+        let objects: Vec<_> = plan
+            .iter()
+            .positions(|(_, targets)| is_object(targets))
+            .collect();
+        let satisfied = match (anchors.first(), anchors.last()) {
+            (Some(_), Some(last)) if rule.after => objects.iter().all(|i| i > last),
+            (Some(first), _) => objects.iter().all(|i| i < first),
+            _ => true,
+        };
+        if satisfied {
+            continue;
+        }
+
+        let (moved, mut rest): (Vec<_>, Vec<_>) = plan
+            .into_iter()
+            .partition(|(_, targets)| is_object(targets));
+        let pos = if rule.after {
+            rest.iter()
+                .rposition(|(_, targets)| targets.contains(&rule.anchor))
+                .map_or(rest.len(), |i| i + 1)
+        } else {
+            rest.iter()
+                .position(|(_, targets)| targets.contains(&rule.anchor))
+                .unwrap_or_default()
+        };
+        rest.splice(pos..pos, moved);
+        plan = rest;
+    }
+    plan.into_iter().map(|(sql, _)| sql).collect()
+}
+
+/// the tables, views, sequences and functions the statement creates, alters or drops
+fn statement_targets(sql: &str) -> BTreeSet<String> {
+    match pg_query::parse(sql) {
+        Ok(parsed) => parsed
+            .ddl_tables()
+            .into_iter()
+            .chain(parsed.ddl_functions())
+            .collect(),
+        Err(_) => BTreeSet::new(),
+    }
+}
+
+/// Annotation starting a data migration block in the local sql files, followed by the table it
+/// migrates, e.g. `-- renovate:data-migration for public.users`. The block ends at
+/// `-- renovate:end`.
+const DATA_MIGRATION: &str = "-- renovate:data-migration for ";
+const DATA_MIGRATION_END: &str = "-- renovate:end";
+
+/// The statements of a data migration block, e.g. the UPDATE backfilling a new column
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DataMigration {
+    pub table: String,
+    pub statements: Vec<String>,
+}
+
+impl DataMigration {
+    /// qualify the table and the statements with the default schema, see `qualify_references`
+    pub fn qualify(self, schema: &str) -> Result<Self> {
+        let table = match self.table.contains('.') {
+            true => self.table,
+            false => format!("{}.{}", schema, self.table),
+        };
+        let statements = self
+            .statements
+            .iter()
+            .map(|sql| qualify_references(sql, schema))
+            .collect::<Result<_>>()?;
+        Ok(Self { table, statements })
+    }
+}
+
+/// Whether the planner can estimate the statement with `EXPLAIN`, i.e. it's a single SELECT,
+/// INSERT, UPDATE or DELETE
+pub(crate) fn is_explainable(sql: &str) -> bool {
+    match pg_query::parse(sql) {
+        Ok(parsed) if parsed.protobuf.stmts.len() == 1 => matches!(
+            parsed.protobuf.stmts[0]
+                .stmt
+                .as_ref()
+                .and_then(|n| n.node.as_ref()),
+            Some(
+                NodeEnum::SelectStmt(_)
+                    | NodeEnum::InsertStmt(_)
+                    | NodeEnum::UpdateStmt(_)
+                    | NodeEnum::DeleteStmt(_)
+            )
+        ),
+        _ => false,
+    }
+}
+
+/// A SELECT scanning the rows the UPDATE or DELETE touches, without the columns it sets, so that
+/// it could be estimated before the plan adds them, e.g. the backfill of a new column. None for
+/// the other statements.
+pub(crate) fn scan_probe(sql: &str) -> Option<String> {
+    let parsed = pg_query::parse(sql).ok()?;
+    if parsed.protobuf.stmts.len() != 1 {
+        return None;
+    }
+    let (relation, from, where_clause, with_clause) =
+        match parsed.protobuf.stmts[0].stmt.as_ref()?.node.as_ref()? {
+            NodeEnum::UpdateStmt(stmt) => (
+                stmt.relation.clone()?,
+                stmt.from_clause.clone(),
+                stmt.where_clause.clone(),
+                stmt.with_clause.clone(),
+            ),
+            NodeEnum::DeleteStmt(stmt) => (
+                stmt.relation.clone()?,
+                stmt.using_clause.clone(),
+                stmt.where_clause.clone(),
+                stmt.with_clause.clone(),
+            ),
+            _ => return None,
+        };
+
+    let probe = pg_query::parse("SELECT 1").ok()?;
+    let mut select = match probe.protobuf.stmts[0].stmt.as_ref()?.node.as_ref()? {
+        NodeEnum::SelectStmt(stmt) => stmt.clone(),
+        _ => return None,
+    };
+    select.from_clause = std::iter::once(pg_query::Node {
+        node: Some(NodeEnum::RangeVar(relation)),
+    })
+    .chain(from)
+    .collect();
+    select.where_clause = where_clause;
+    select.with_clause = with_clause;
+    NodeEnum::SelectStmt(select).deparse().ok()
+}
+
+/// Annotation leaving the statement right after it out of the diff and the plan, e.g. for an
+/// object documented in the local repo but managed elsewhere
+const IGNORE: &str = "-- renovate:ignore";
+
+/// The statements annotated with `-- renovate:ignore` in the content of a sql file
+pub(crate) fn extract_ignored_statements(content: &str) -> Result<Vec<String>> {
+    let mut statements = Vec::new();
+    let mut offset = 0;
+    for line in content.split_inclusive('\n') {
+        offset += line.len();
+        if line.trim() != IGNORE {
+            continue;
+        }
+        match split_statements(&content[offset..])?.into_iter().next() {
+            Some(sql) => statements.push(sql),
+            None => bail!("`{}` is not followed by any statement", IGNORE),
+        }
+    }
+    Ok(statements)
+}
+
+/// File under `_meta` every statement of which is ignored like an annotated one. `fetch` moves
+/// the annotated statements there when it rewrites their files, since it renders no comments.
+pub(crate) const IGNORE_FILE: &str = "_meta/ignored.sql";
+
+/// The ignored statements of the files of a local repo, keyed by their path under it: the ones
+/// annotated with `-- renovate:ignore` in the sql files, and every one of `IGNORE_FILE`
+pub(crate) fn ignored_statements(files: &SchemaFiles) -> Result<Vec<String>> {
+    let mut statements = Vec::new();
+    for (path, content) in files {
+        let found = if path == Path::new(IGNORE_FILE) {
+            split_statements(content)
+        } else if path.extension().map(|ext| ext == "sql").unwrap_or(false)
+            && ignore_file(path, "_")
+            && ignore_file(path, ".")
+        {
+            extract_ignored_statements(content)
+        } else {
+            continue;
+        };
+        statements.extend(found.with_context(|| format!("Failed to load {}", path.display()))?);
+    }
+    Ok(statements)
+}
+
+/// Annotation of the services owning a table, or some of its columns, in the local sql files,
+/// e.g. `-- renovate:services billing-api, auth-api for public.users (email)`
+const SERVICES: &str = "-- renovate:services ";
+
+/// The services to redeploy when a table (or the given columns of it) changes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ServiceAnnotation {
+    pub services: Vec<String>,
+    pub table: String,
+    /// empty for the whole table
+    pub columns: Vec<String>,
+}
+
+/// The service annotations in the content of a sql file, with the unqualified tables in
+/// `schema`
+pub(crate) fn extract_service_annotations(
+    content: &str,
+    schema: &str,
+) -> Result<Vec<ServiceAnnotation>> {
+    let mut annotations = Vec::new();
+    for line in content.lines() {
+        let directive = match line.trim().strip_prefix(SERVICES) {
+            Some(directive) => directive,
+            None => continue,
+        };
+        let (services, target) = match directive.split_once(" for ") {
+            Some(parts) => parts,
+            None => bail!(
+                "Invalid service annotation `{}`, expected `{}<services> for <table>`",
+                line.trim(),
+                SERVICES
+            ),
+        };
+        let (table, columns) = match target.split_once('(') {
+            Some((table, columns)) => (table.trim(), columns.trim_end().trim_end_matches(')')),
+            None => (target.trim(), ""),
+        };
+        let split = |list: &str| -> Vec<String> {
+            list.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_owned)
+                .collect()
+        };
+        annotations.push(ServiceAnnotation {
+            services: split(services),
+            table: match table.contains('.') {
+                true => table.to_owned(),
+                false => format!("{}.{}", schema, table),
+            },
+            columns: split(columns),
+        });
+    }
+    Ok(annotations)
+}
+
+/// The services whose annotated tables or columns the plan changes, each with the objects
+/// changed, e.g. `billing-api` => `public.users (email)`. A column is changed by the `ALTER
+/// TABLE` commands naming it, and by any other statement on its table, e.g. `DROP TABLE`.
+pub(crate) fn affected_services(
+    plan: &[String],
+    annotations: &[ServiceAnnotation],
+) -> BTreeMap<String, BTreeSet<String>> {
+    let mut result: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for sql in plan {
+        let targets = statement_targets(sql);
+        let columns = altered_columns(sql);
+        for annotation in annotations {
+            if !targets.contains(&annotation.table) {
+                continue;
+            }
+            let object = match &columns {
+                _ if annotation.columns.is_empty() => annotation.table.clone(),
+                Some(columns) => {
+                    let changed: Vec<_> = annotation
+                        .columns
+                        .iter()
+                        .filter(|c| columns.contains(c.as_str()))
+                        .map(|c| c.as_str())
+                        .collect();
+                    if changed.is_empty() {
+                        continue;
+                    }
+                    format!("{} ({})", annotation.table, changed.join(", "))
+                }
+                None => annotation.table.clone(),
+            };
+            for service in &annotation.services {
+                result
+                    .entry(service.clone())
+                    .or_default()
+                    .insert(object.clone());
+            }
+        }
+    }
+    result
+}
+
+/// the columns named by the commands of an `ALTER TABLE` or the column renamed, None for the
+/// other statements
+fn altered_columns(sql: &str) -> Option<BTreeSet<String>> {
+    let parsed = pg_query::parse(sql).ok()?;
+    let columns = match parsed
+        .protobuf
+        .stmts
+        .first()?
+        .stmt
+        .as_ref()?
+        .node
+        .as_ref()?
+    {
+        NodeEnum::AlterTableStmt(stmt) => stmt
+            .cmds
+            .iter()
+            .filter_map(|cmd| match cmd.node.as_ref() {
+                Some(NodeEnum::AlterTableCmd(cmd)) => {
+                    match cmd.def.as_ref().and_then(|d| d.node.as_ref()) {
+                        Some(NodeEnum::ColumnDef(def)) => Some(def.colname.clone()),
+                        _ if !cmd.name.is_empty() => Some(cmd.name.clone()),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            })
+            .collect(),
+        NodeEnum::RenameStmt(stmt) if stmt.rename_type() == ObjectType::ObjectColumn => {
+            [stmt.subname.clone()].into_iter().collect()
+        }
+        _ => return None,
+    };
+    Some(columns)
+}
+
+/// Split the data migration blocks out of the content of a sql file, so that the rest is the
+/// schema only.
+pub(crate) fn extract_data_migrations(content: &str) -> Result<(String, Vec<DataMigration>)> {
+    let mut schema = String::with_capacity(content.len());
+    let mut migrations = Vec::new();
+    let mut block: Option<(String, String)> = None;
+    for line in content.lines() {
+        let trimmed = line.trim();
+        match block.take() {
+            None => match trimmed.strip_prefix(DATA_MIGRATION) {
+                Some(table) => block = Some((table.trim().to_owned(), String::new())),
+                None => {
+                    schema.push_str(line);
+                    schema.push('\n');
+                }
+            },
+            Some((table, body)) if trimmed == DATA_MIGRATION_END => {
+                let statements = split_statements(&body)
+                    .with_context(|| format!("Invalid data migration for {}", table))?;
+                migrations.push(DataMigration { table, statements });
+            }
+            Some((table, mut body)) => {
+                body.push_str(line);
+                body.push('\n');
+                block = Some((table, body));
+            }
+        }
+    }
+    if let Some((table, _)) = block {
+        bail!(
+            "The data migration for {} has no `{}`",
+            table,
+            DATA_MIGRATION_END
+        );
+    }
+    Ok((schema, migrations))
+}
+
+/// Insert each data migration right after the last statement of the plan changing its table,
+/// e.g. the `ADD COLUMN`. The `NOT NULL` of the columns the plan adds or alters is set after the
+/// data migration instead, so that it could backfill them. The data migrations of the tables the
+/// plan doesn't change, or drops, are skipped: they already ran when the table last changed.
+pub(crate) fn interleave_data_migrations(
+    plan: Vec<String>,
+    migrations: &[DataMigration],
+) -> Result<Vec<String>> {
+    let mut plan = plan;
+    for migration in migrations {
+        let last = plan
+            .iter()
+            .rposition(|sql| statement_targets(sql).contains(&migration.table));
+        match last {
+            Some(i) if !dropped_objects(&plan[i..=i]).contains(&migration.table) => {}
+            _ => continue,
+        }
+
+        // the statements left empty are kept as None until the data migration is inserted
+        let mut not_null = Vec::new();
+        let mut stripped = Vec::with_capacity(plan.len());
+        for sql in plan {
+            let (sql, columns) = without_not_null(sql, &migration.table)?;
+            not_null.extend(columns);
+            stripped.push(sql);
+        }
+        let pos = stripped
+            .iter()
+            .rposition(|sql| {
+                sql.as_ref().map_or(true, |sql| {
+                    statement_targets(sql).contains(&migration.table)
+                })
+            })
+            .map_or(0, |i| i + 1);
+
+        let mut inserted: Vec<_> = migration.statements.iter().cloned().map(Some).collect();
+        if !not_null.is_empty() {
+            inserted.push(Some(format!(
+                "ALTER TABLE {} {}",
+                migration.table,
+                not_null
+                    .iter()
+                    .map(|column| format!("ALTER COLUMN {} SET NOT NULL", Ident(column)))
+                    .join(", ")
+            )));
+        }
+        stripped.splice(pos..pos, inserted);
+        plan = stripped.into_iter().flatten().collect();
+    }
+    Ok(plan)
+}
+
+/// Take the `NOT NULL` of the added columns, and the `SET NOT NULL` commands, out of an
+/// `ALTER TABLE` of the table. Return the statement left (None if no command is left) and the
+/// columns.
+fn without_not_null(sql: String, table: &str) -> Result<(Option<String>, Vec<String>)> {
+    let parsed = pg_query::parse(&sql)?;
+    let node = parsed
+        .protobuf
+        .stmts
+        .first()
+        .and_then(|s| s.stmt.as_ref())
+        .and_then(|n| n.node.as_ref());
+    let mut stmt = match node {
+        Some(NodeEnum::AlterTableStmt(stmt)) if parsed.protobuf.stmts.len() == 1 => stmt.clone(),
+        _ => return Ok((Some(sql), vec![])),
+    };
+    match stmt.relation.as_ref() {
+        Some(rv) if SchemaId::from(rv).to_string() == table => {}
+        _ => return Ok((Some(sql), vec![])),
+    }
+
+    let mut columns = Vec::new();
+    stmt.cmds.retain_mut(|cmd| {
+        let cmd = match cmd.node.as_mut() {
+            Some(NodeEnum::AlterTableCmd(cmd)) => cmd,
+            _ => return true,
+        };
+        match cmd.subtype() {
+            AlterTableType::AtSetNotNull => {
+                columns.push(cmd.name.clone());
+                false
+            }
+            AlterTableType::AtAddColumn => {
+                if let Some(NodeEnum::ColumnDef(def)) =
+                    cmd.def.as_mut().and_then(|def| def.node.as_mut())
+                {
+                    let before = def.constraints.len();
+                    def.constraints.retain(|c| match c.node.as_ref() {
+                        Some(NodeEnum::Constraint(c)) => c.contype() != ConstrType::ConstrNotnull,
+                        _ => true,
+                    });
+                    if def.constraints.len() != before {
+                        columns.push(def.colname.clone());
+                    }
+                }
+                true
+            }
+            _ => true,
+        }
+    });
+    if columns.is_empty() {
+        return Ok((Some(sql), columns));
+    }
+    if stmt.cmds.is_empty() {
+        return Ok((None, columns));
+    }
+    Ok((Some(NodeEnum::AlterTableStmt(stmt).deparse()?), columns))
+}
+
+/// A table or column whose data is lost by the plan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DroppedData {
+    pub table: String,
+    pub column: Option<String>,
+}
+
+/// the tables and columns dropped by the plan, so that their row count could be checked
+pub(crate) fn dropped_data(plan: &[String]) -> Vec<DroppedData> {
+    let mut dropped = Vec::new();
+    for sql in plan {
+        let parsed = match pg_query::parse(sql) {
+            Ok(parsed) => parsed,
+            Err(_) => continue,
+        };
+        for (node, _, _) in parsed.protobuf.nodes() {
+            match node {
+                NodeRef::DropStmt(stmt) if stmt.remove_type() == ObjectType::ObjectTable => {
+                    for object in &stmt.objects {
+                        if let Some(NodeEnum::List(list)) = object.node.as_ref() {
+                            let names: Vec<_> =
+                                list.items.iter().filter_map(node_to_string).collect();
+                            let names: Vec<_> = names.iter().map(|n| n.as_str()).collect();
+                            dropped.push(DroppedData {
+                                table: SchemaId::new_with(&names).to_string(),
+                                column: None,
+                            });
+                        }
+                    }
+                }
+                NodeRef::AlterTableStmt(stmt) => {
+                    let table = match stmt.relation.as_ref() {
+                        Some(rv) => SchemaId::from(rv).to_string(),
+                        None => continue,
+                    };
+                    for cmd in &stmt.cmds {
+                        if let Some(NodeEnum::AlterTableCmd(cmd)) = cmd.node.as_ref() {
+                            if cmd.subtype() == AlterTableType::AtDropColumn {
+                                dropped.push(DroppedData {
+                                    table: table.clone(),
+                                    column: Some(cmd.name.clone()),
+                                });
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    dropped
+}
+
+impl fmt::Display for DroppedData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.column {
+            Some(column) => write!(f, "column {} of {}", Ident(column), self.table),
+            None => write!(f, "table {}", self.table),
+        }
+    }
+}
+
+fn is_destructive(sql: &str) -> bool {
+    let parsed = match pg_query::parse(sql) {
+        Ok(parsed) => parsed,
+        Err(_) => return false,
+    };
+    let nodes = parsed.protobuf.nodes();
+    nodes.iter().any(|(node, _, _)| match node {
+        NodeRef::DropStmt(_) | NodeRef::TruncateStmt(_) => true,
+        NodeRef::AlterTableCmd(cmd) => matches!(
+            cmd.subtype(),
+            AlterTableType::AtDropColumn | AlterTableType::AtAlterColumnType
+        ),
+        _ => false,
+    })
+}
+
+/// Split the plan into the expand phase, which the running application is compatible with, and
+/// the contract phase: the objects and columns dropped for good, and the revoked privileges. The
+/// contract phase is meant to be applied once the application no longer uses them. Both phases
+/// keep the order of the plan.
+pub(crate) fn split_phases(plan: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut expand = Vec::new();
+    let mut contract = Vec::new();
+    for (i, sql) in plan.iter().enumerate() {
+        if is_contract(sql, &plan[i + 1..]) {
+            contract.push(sql.clone());
+        } else {
+            expand.push(sql.clone());
+        }
+    }
+    (expand, contract)
+}
+
+/// whether the statement only takes away something the later statements don't bring back, e.g.
+/// a view dropped to be created again with a new definition belongs to the expand phase
+fn is_contract(sql: &str, rest: &[String]) -> bool {
+    let parsed = match pg_query::parse(sql) {
+        Ok(parsed) => parsed,
+        Err(_) => return false,
+    };
+    let node = parsed
+        .protobuf
+        .stmts
+        .first()
+        .and_then(|s| s.stmt.as_ref())
+        .and_then(|n| n.node.as_ref());
+    match node {
+        Some(NodeEnum::DropStmt(_)) => {
+            let dropped: BTreeSet<String> = dropped_objects(&[sql.to_owned()])
+                .into_iter()
+                .map(|id| id.split('(').next().unwrap_or_default().to_owned())
+                .collect();
+            !dropped.is_empty()
+                && !rest
+                    .iter()
+                    .any(|later| !statement_targets(later).is_disjoint(&dropped))
+        }
+        Some(NodeEnum::AlterTableStmt(stmt)) => {
+            let dropped = altered_columns(sql, AlterTableType::AtDropColumn);
+            !stmt.cmds.is_empty()
+                && stmt.cmds.iter().all(|cmd| match cmd.node.as_ref() {
+                    Some(NodeEnum::AlterTableCmd(cmd)) => {
+                        cmd.subtype() == AlterTableType::AtDropColumn
+                    }
+                    _ => false,
+                })
+                && !rest.iter().any(|later| {
+                    !altered_columns(later, AlterTableType::AtAddColumn).is_disjoint(&dropped)
+                })
+        }
+        Some(NodeEnum::GrantStmt(stmt)) if !stmt.is_grant => {
+            let revoked = grant_targets(stmt);
+            !revoked.is_empty()
+                && !rest.iter().any(|later| {
+                    let parsed = match pg_query::parse(later) {
+                        Ok(parsed) => parsed,
+                        Err(_) => return false,
+                    };
+                    parsed
+                        .protobuf
+                        .nodes()
+                        .into_iter()
+                        .any(|(node, _, _)| match node {
+                            NodeRef::GrantStmt(stmt) if stmt.is_grant => {
+                                !grant_targets(stmt).is_disjoint(&revoked)
+                            }
+                            _ => false,
+                        })
+                })
+        }
+        _ => false,
+    }
+}
+
+/// the (table, column) pairs of the columns the ALTER TABLE statements of `sql` add or drop, as
+/// given by `subtype`
+fn altered_columns(sql: &str, subtype: AlterTableType) -> BTreeSet<(String, String)> {
+    let parsed = match pg_query::parse(sql) {
+        Ok(parsed) => parsed,
+        Err(_) => return BTreeSet::new(),
+    };
+    let mut columns = BTreeSet::new();
+    for (node, _, _) in parsed.protobuf.nodes() {
+        let stmt = match node {
+            NodeRef::AlterTableStmt(stmt) => stmt,
+            _ => continue,
+        };
+        let table = SchemaId::from(stmt.relation.as_ref()).to_string();
+        for cmd in &stmt.cmds {
+            let cmd = match cmd.node.as_ref() {
+                Some(NodeEnum::AlterTableCmd(cmd)) if cmd.subtype() == subtype => cmd,
+                _ => continue,
+            };
+            let name = match cmd.def.as_ref().and_then(|def| def.node.as_ref()) {
+                Some(NodeEnum::ColumnDef(def)) => def.colname.clone(),
+                _ => cmd.name.clone(),
+            };
+            columns.insert((table.clone(), name));
+        }
+    }
+    columns
+}
+
+/// the objects and grantees of a GRANT or REVOKE statement
+fn grant_targets(stmt: &pg_query::protobuf::GrantStmt) -> BTreeSet<(String, String)> {
+    Privilege::from_grant(stmt)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|p| (p.id, p.grantee))
+        .collect()
+}
+
+/// Collect the diffs computed while planning, so that they could be reported with the plan.
+#[derive(Debug, Default)]
+pub(crate) struct ChangeCollector(Mutex<Vec<PlanChange>>);
+
+impl ChangeCollector {
+    pub fn take(&self) -> Vec<PlanChange> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Observer for ChangeCollector {
+    fn on_diff_computed(&self, type_name: &str, id: &str, kind: ChangeKind, diff: &str) {
+        self.0.lock().unwrap().push(PlanChange {
+            type_name: type_name.to_owned(),
+            id: id.to_owned(),
+            kind,
+            diff: diff.to_owned(),
+            hunks: Vec::new(),
+        });
+    }
+
+    fn on_diff_hunks(&self, _type_name: &str, id: &str, hunks: &[DiffHunk]) {
+        if let Some(change) = self.0.lock().unwrap().last_mut() {
+            if change.id == id {
+                change.hunks = hunks.to_vec();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_tables_and_columns_should_be_found() {
+        let plan = vec![
+            "DROP TABLE public.legacy".to_owned(),
+            "ALTER TABLE public.todos DROP COLUMN title, ALTER COLUMN id SET NOT NULL".to_owned(),
+            "DROP INDEX public.todos_idx".to_owned(),
+        ];
+        let dropped: Vec<_> = dropped_data(&plan).iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            dropped,
+            vec!["table public.legacy", "column title of public.todos"]
+        );
+    }
+
+    #[test]
+    fn table_rewrites_should_be_flagged() {
+        let cases = [
+            (
+                "ALTER TABLE public.todos ALTER COLUMN title TYPE pg_catalog.varchar(256)",
+                Some("column type change"),
+            ),
+            (
+                "ALTER TABLE ONLY public.todos ADD COLUMN id uuid DEFAULT gen_random_uuid()",
+                Some("column with a volatile default"),
+            ),
+            (
+                "ALTER TABLE ONLY public.todos ADD COLUMN created_at timestamptz DEFAULT now()",
+                None,
+            ),
+            ("ALTER TABLE public.todos SET LOGGED", Some("SET LOGGED")),
+            (
+                "ALTER TABLE public.todos ALTER COLUMN title SET NOT NULL",
+                None,
+            ),
+        ];
+        for (sql, expected) in cases {
+            assert_eq!(table_rewrite(sql), expected, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn rewritten_tables_should_have_their_size() {
+        let stats = [(
+            "public.todos".to_owned(),
+            TableStats {
+                rows: 1_234_567,
+                bytes: 357_040_128,
+            },
+        )]
+        .into_iter()
+        .collect();
+        let plan = Plan::new(
+            vec![
+                "ALTER TABLE public.todos ALTER COLUMN title TYPE pg_catalog.varchar(256)"
+                    .to_owned(),
+            ],
+            vec![],
+        )
+        .with_stats(&stats);
+        let size = plan.rewrites[0].size.unwrap();
+        assert!(size.is_large());
+        assert_eq!(size.to_string(), "~1.2M rows, 340.5 MB");
+        assert!(plan
+            .to_markdown()
+            .contains("(column type change, ~1.2M rows, 340.5 MB)"));
+    }
+
+    #[test]
+    fn recreated_sequence_should_keep_its_value() {
+        let plan = vec![
+            "DROP SEQUENCE public.todos_id_seq".to_owned(),
+            "CREATE SEQUENCE public.todos_id_seq AS bigint".to_owned(),
+            "CREATE SEQUENCE public.new_seq".to_owned(),
+        ];
+        assert_eq!(
+            preserve_sequence_values(plan).unwrap(),
+            vec![
+                "DROP SEQUENCE public.todos_id_seq",
+                "CREATE SEQUENCE public.todos_id_seq AS bigint",
+                "SELECT setval('public.todos_id_seq', __renovate_sequence_value__)",
+                "CREATE SEQUENCE public.new_seq",
+            ]
+        );
+    }
+
+    #[test]
+    fn transaction_control_should_require_autocommit() {
+        let procedure = "CREATE PROCEDURE public.archive() LANGUAGE plpgsql AS $$ BEGIN DELETE FROM todos; COMMIT; END $$";
+        assert!(requires_autocommit(procedure));
+        assert!(requires_autocommit("CALL public.archive()"));
+        assert!(!requires_autocommit(
+            "CREATE PROCEDURE public.cleanup() LANGUAGE sql AS $$ DELETE FROM todos $$"
+        ));
+        assert!(!requires_autocommit("CREATE TABLE public.todos (id uuid)"));
+
+        // the words alone don't control the transaction
+        let procedure = "CREATE PROCEDURE public.log() LANGUAGE plpgsql AS $$ BEGIN -- commit later\n INSERT INTO logs VALUES ('rollback'); END $$";
+        assert!(!requires_autocommit(procedure));
+
+        assert!(requires_autocommit(
+            "CREATE INDEX CONCURRENTLY todos_title_idx ON public.todos (title)"
+        ));
+        assert!(requires_autocommit(
+            "DROP INDEX CONCURRENTLY public.todos_title_idx"
+        ));
+        assert!(requires_autocommit("VACUUM public.todos"));
+        assert!(requires_autocommit("COMMIT"));
+        assert!(!requires_autocommit("ANALYZE public.todos"));
+        assert!(!requires_autocommit(
+            "CREATE INDEX todos_title_idx ON public.todos (title)"
+        ));
+    }
+
+    #[test]
+    fn drops_and_revokes_should_be_deferred_to_contract_phase() {
+        let plan: Vec<String> = [
+            "DROP VIEW public.active",
+            "DROP TABLE public.legacy",
+            "ALTER TABLE public.todos ADD COLUMN title text",
+            "ALTER TABLE public.todos DROP COLUMN name",
+            "ALTER TABLE public.todos DROP COLUMN done",
+            "REVOKE select ON public.todos FROM readonly",
+            "ALTER TABLE public.todos ADD COLUMN done int",
+            "CREATE VIEW public.active AS SELECT * FROM public.todos",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let (expand, contract) = split_phases(&plan);
+        assert_eq!(
+            expand,
+            vec![
+                "DROP VIEW public.active",
+                "ALTER TABLE public.todos ADD COLUMN title text",
+                // a column type change planned as a drop and an add stays in one phase
+                "ALTER TABLE public.todos DROP COLUMN done",
+                "ALTER TABLE public.todos ADD COLUMN done int",
+                "CREATE VIEW public.active AS SELECT * FROM public.todos",
+            ]
+        );
+        assert_eq!(
+            contract,
+            vec![
+                "DROP TABLE public.legacy",
+                "ALTER TABLE public.todos DROP COLUMN name",
+                "REVOKE select ON public.todos FROM readonly",
+            ]
+        );
+    }
+
+    #[test]
+    fn dropped_objects_should_be_collected() {
+        let plan = vec![
+            "DROP TABLE public.todos".to_owned(),
+            "DROP FUNCTION public.add(int, int)".to_owned(),
+            "DROP INDEX public.todos_idx".to_owned(),
+        ];
+        assert_eq!(
+            dropped_objects(&plan).into_iter().collect::<Vec<_>>(),
+            vec![
+                "public.add(pg_catalog.int4, pg_catalog.int4)",
+                "public.todos"
+            ]
+        );
+    }
+
+    #[test]
+    fn references_should_be_qualified() {
+        let cases = [
+            (
+                "CREATE TABLE todos (id int REFERENCES users (id))",
+                "CREATE TABLE public.todos (id int REFERENCES public.users (id))",
+            ),
+            (
+                "CREATE VIEW active AS WITH recent AS (SELECT * FROM todos) SELECT * FROM recent",
+                "CREATE VIEW public.active AS WITH recent AS (SELECT * FROM public.todos) SELECT * FROM recent",
+            ),
+            (
+                "CREATE FUNCTION touch() RETURNS trigger LANGUAGE plpgsql AS $$ BEGIN UPDATE todos SET n = 1; RETURN NEW; END $$",
+                "CREATE FUNCTION public.touch() RETURNS trigger LANGUAGE plpgsql AS $$ BEGIN UPDATE todos SET n = 1; RETURN NEW; END $$",
+            ),
+            (
+                "ALTER TABLE ONLY other.todos ADD CONSTRAINT todos_pkey PRIMARY KEY (id)",
+                "ALTER TABLE ONLY other.todos ADD CONSTRAINT todos_pkey PRIMARY KEY (id)",
+            ),
+            (
+                "ALTER TABLE todos ALTER COLUMN id SET DEFAULT nextval('todos_id_seq'::regclass)",
+                "ALTER TABLE public.todos ALTER COLUMN id SET DEFAULT nextval('public.todos_id_seq'::regclass)",
+            ),
+            (
+                "SELECT setval('other.seq', 42), currval('\"Seq\"')",
+                "SELECT setval('other.seq', 42), currval('public.\"Seq\"')",
+            ),
+        ];
+        for (sql, expected) in cases {
+            assert_eq!(qualify_references(sql, "public").unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn sql_should_be_qualified_with_default_schema() {
+        let sql = "CREATE TABLE todos (id int);\n-- the active ones\nCREATE VIEW active AS SELECT * FROM todos";
+        assert_eq!(
+            qualify_sql(sql, "app").unwrap(),
+            "CREATE TABLE app.todos (id int);\n-- the active ones\nCREATE VIEW app.active AS SELECT * FROM app.todos;\n"
+        );
+    }
+
+    #[test]
+    fn statements_should_be_made_idempotent() {
+        let cases = [
+            (
+                "DROP INDEX public.todos_idx",
+                "DROP INDEX IF EXISTS public.todos_idx",
+            ),
+            (
+                "CREATE TABLE public.todos (id int)",
+                "CREATE TABLE IF NOT EXISTS public.todos (id int)",
+            ),
+            (
+                "ALTER TABLE public.todos DROP COLUMN title",
+                "ALTER TABLE public.todos DROP COLUMN IF EXISTS title",
+            ),
+            (
+                "ALTER TABLE ONLY public.todos ADD COLUMN title text",
+                "ALTER TABLE ONLY public.todos ADD COLUMN IF NOT EXISTS title text",
+            ),
+            (
+                "ALTER TABLE public.todos ALTER COLUMN title SET NOT NULL",
+                "ALTER TABLE public.todos ALTER COLUMN title SET NOT NULL",
+            ),
+            (
+                "ALTER TABLE public.todos SET SCHEMA archive",
+                "ALTER TABLE IF EXISTS public.todos SET SCHEMA archive",
+            ),
+            (
+                "ALTER TYPE public.status SET SCHEMA archive",
+                "ALTER TYPE public.status SET SCHEMA archive",
+            ),
+            (
+                "ALTER TYPE public.status ADD VALUE 'archived'",
+                "ALTER TYPE public.status ADD VALUE IF NOT EXISTS 'archived'",
+            ),
+            (
+                "CREATE VIEW public.open_todos AS SELECT id FROM public.todos",
+                "CREATE OR REPLACE VIEW public.open_todos AS SELECT id FROM public.todos",
+            ),
+            (
+                "CREATE MATERIALIZED VIEW public.stats AS SELECT count(*) FROM public.todos",
+                "CREATE MATERIALIZED VIEW IF NOT EXISTS public.stats AS SELECT count(*) FROM public.todos",
+            ),
+            (
+                "ALTER SEQUENCE public.todos_id_seq OWNED BY public.todos.id",
+                "ALTER SEQUENCE IF EXISTS public.todos_id_seq OWNED BY public.todos.id",
+            ),
+        ];
+        for (sql, expected) in cases {
+            assert_eq!(make_idempotent(sql).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn diff_sql_should_generate_plan_and_changes() -> Result<()> {
+        let old = "CREATE TABLE public.todos (id uuid); CREATE TABLE public.old (id uuid);";
+        let new = "CREATE TABLE public.todos (id uuid, title text);";
+        let plan = diff_sql(old, new)?;
+        assert_eq!(
+            plan.statements,
+            vec![
+                "DROP TABLE public.old",
+                "ALTER TABLE ONLY public.todos ADD COLUMN title text",
+            ]
+        );
+        let changes: Vec<_> = plan
+            .changes
+            .iter()
+            .map(|c| (c.id.as_str(), c.kind))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("public.old", ChangeKind::Removed),
+                ("public.todos", ChangeKind::Changed),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn plan_changes_should_have_diff_hunks() -> Result<()> {
+        let plan = diff_sql(
+            "CREATE TABLE public.todos (id uuid)",
+            "CREATE TABLE public.todos (id uuid, title text)",
+        )?;
+        let hunks = &plan.changes[0].hunks;
+        assert_eq!(hunks.len(), 1);
+        assert_eq!((hunks[0].old_start, hunks[0].new_start), (1, 1));
+        assert!(hunks[0]
+            .lines
+            .iter()
+            .any(|l| l.kind == crate::DiffLineKind::Added && l.text.contains("title")));
+        assert!(hunks[0]
+            .lines
+            .iter()
+            .any(|l| l.kind == crate::DiffLineKind::Removed));
+        Ok(())
+    }
+
+    #[test]
+    fn plan_should_render_markdown() -> Result<()> {
+        let old = "CREATE TABLE public.todos (id uuid); CREATE TABLE public.old (id uuid);";
+        let new = "CREATE TABLE public.todos (id uuid, title text);";
+        let md = diff_sql(old, new)?.to_markdown();
+        assert!(md.contains("| table | 0 | 1 | 1 |"));
+        assert!(md.contains("> - `DROP TABLE public.old`"));
+        assert!(!md.contains("> - `ALTER TABLE"));
+        assert!(md.contains("<summary>2 SQL statement(s)</summary>"));
+        assert!(md.contains("ALTER TABLE ONLY public.todos ADD COLUMN title text;\n"));
+
+        let md = diff_sql(new, new)?.to_markdown();
+        assert!(md.ends_with("No changes detected.\n"));
+
+        let new = "CREATE TABLE public.todos (id bigint);";
+        let md = diff_sql(old, new)?.to_markdown();
+        assert!(md.contains("> - `ALTER TABLE public.todos ALTER COLUMN id TYPE pg_catalog.int8` (column type change)"));
+        Ok(())
+    }
+
+    #[test]
+    fn order_rules_should_move_statements() -> Result<()> {
+        let rules = OrderRule::parse(
+            "# audit() is only called via dynamic sql in log()\naudit after public.log\n",
+            "public",
+        )?;
+        let plan = vec![
+            "CREATE FUNCTION public.audit() RETURNS void LANGUAGE sql AS $$ SELECT 1 $$".to_owned(),
+            "CREATE TABLE public.todos (id int)".to_owned(),
+            "CREATE FUNCTION public.log() RETURNS void LANGUAGE sql AS $$ SELECT 2 $$".to_owned(),
+        ];
+        let ordered = apply_order(plan.clone(), &rules);
+        assert_eq!(
+            ordered,
+            vec![plan[1].clone(), plan[2].clone(), plan[0].clone()]
+        );
+        // already satisfied
+        assert_eq!(apply_order(ordered.clone(), &rules), ordered);
+
+        assert!(OrderRule::parse("audit depends on log", "public").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn scan_probe_should_select_the_rows_touched() {
+        assert_eq!(
+            scan_probe("UPDATE public.users SET name_lower = lower(name) WHERE id > 10").as_deref(),
+            Some("SELECT 1 FROM public.users WHERE id > 10")
+        );
+        assert_eq!(
+            scan_probe("DELETE FROM public.todos t USING public.users u WHERE t.owner = u.id")
+                .as_deref(),
+            Some("SELECT 1 FROM public.todos t, public.users u WHERE t.owner = u.id")
+        );
+        assert_eq!(scan_probe("INSERT INTO public.users (id) VALUES (1)"), None);
+        assert!(is_explainable("UPDATE public.users SET name = 'x'"));
+        assert!(!is_explainable(
+            "ALTER TABLE public.users ADD COLUMN age int"
+        ));
+    }
+
+    #[test]
+    fn ignored_statements_should_be_extracted() -> Result<()> {
+        let statements = extract_ignored_statements(
+            r#"
+            CREATE TABLE public.todos (id int);
+            -- renovate:ignore
+            CREATE FUNCTION public.audit() RETURNS trigger AS $$
+            BEGIN
+              RETURN NEW;
+            END;
+            $$ LANGUAGE plpgsql;
+            CREATE INDEX todos_id_idx ON public.todos (id);
+            "#,
+        )?;
+        assert_eq!(statements.len(), 1);
+        assert!(statements[0].starts_with("CREATE FUNCTION public.audit()"));
+        assert!(statements[0].ends_with("LANGUAGE plpgsql"));
+        assert!(
+            extract_ignored_statements("CREATE TABLE t (id int);\n-- renovate:ignore\n").is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ignored_statements_should_include_the_ignore_file() -> Result<()> {
+        let files = SchemaFiles::from([
+            (
+                "public/tables.sql".into(),
+                "-- renovate:ignore\nCREATE TABLE public.audit (id int);\nCREATE TABLE public.todos (id int);\n"
+                    .to_owned(),
+            ),
+            (
+                IGNORE_FILE.into(),
+                "CREATE FUNCTION public.f() RETURNS int AS 'SELECT 1' LANGUAGE sql;\n".to_owned(),
+            ),
+            (
+                "_meta/services.sql".into(),
+                "-- renovate:ignore\nCREATE TABLE public.users (id int);\n".to_owned(),
+            ),
+        ]);
+        assert_eq!(
+            ignored_statements(&files)?,
+            vec![
+                "CREATE FUNCTION public.f() RETURNS int AS 'SELECT 1' LANGUAGE sql",
+                "CREATE TABLE public.audit (id int)",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn services_of_the_changed_columns_should_be_listed() -> Result<()> {
+        let annotations = extract_service_annotations(
+            r#"
+            -- renovate:services billing-api, auth-api for users (email)
+            -- renovate:services search for public.todos
+            CREATE TABLE users (id int, email text, name text);
+            "#,
+            "public",
+        )?;
+        assert_eq!(annotations[0].table, "public.users");
+        assert_eq!(annotations[0].columns, vec!["email"]);
+
+        let plan = vec![
+            "ALTER TABLE public.users ADD COLUMN age int".to_owned(),
+            "DROP TABLE public.todos".to_owned(),
+        ];
+        let affected = affected_services(&plan, &annotations);
+        assert_eq!(affected.keys().collect::<Vec<_>>(), vec!["search"]);
+
+        let plan = vec!["ALTER TABLE public.users ALTER COLUMN email TYPE varchar(256)".to_owned()];
+        let affected = affected_services(&plan, &annotations);
+        assert_eq!(
+            affected
+                .get("billing-api")
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>(),
+            vec!["public.users (email)"]
+        );
+        assert!(extract_service_annotations("-- renovate:services billing-api", "public").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn data_migrations_should_be_interleaved() -> Result<()> {
+        let content = "CREATE TABLE users (id int, email text NOT NULL);\n\
+            -- renovate:data-migration for users\n\
+            UPDATE users SET email = id || '@example.com' WHERE email IS NULL;\n\
+            -- renovate:end\n";
+        let (schema, migrations) = extract_data_migrations(content)?;
+        assert_eq!(
+            schema,
+            "CREATE TABLE users (id int, email text NOT NULL);\n"
+        );
+        let migrations: Vec<_> = migrations
+            .into_iter()
+            .map(|m| m.qualify("public"))
+            .collect::<Result<_>>()?;
+
+        let plan = vec![
+            "ALTER TABLE ONLY public.users ADD COLUMN email text NOT NULL".to_owned(),
+            "CREATE INDEX users_email_idx ON public.users USING btree (email)".to_owned(),
+            "CREATE TABLE public.todos (id int)".to_owned(),
+        ];
+        let plan = interleave_data_migrations(plan, &migrations)?;
+        assert_eq!(
+            plan,
+            vec![
+                "ALTER TABLE ONLY public.users ADD COLUMN email text",
+                "CREATE INDEX users_email_idx ON public.users USING btree (email)",
+                "UPDATE public.users SET email = id || '@example.com' WHERE email IS NULL",
+                "ALTER TABLE public.users ALTER COLUMN email SET NOT NULL",
+                "CREATE TABLE public.todos (id int)",
+            ]
+        );
+
+        // the table is not changed by the plan
+        let plan = vec!["CREATE TABLE public.todos (id int)".to_owned()];
+        assert_eq!(interleave_data_migrations(plan.clone(), &migrations)?, plan);
+
+        assert!(extract_data_migrations("-- renovate:data-migration for users\n").is_err());
+        Ok(())
+    }
+}
@@ -1,56 +1,528 @@
-use std::thread;
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    thread,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
-use crate::{utils::load_config, DatabaseRepo, DatabaseSchema, SchemaLoader, SqlSaver};
-use anyhow::{bail, Result};
-use sqlx::{Connection, Executor, PgConnection};
-use tokio::runtime::Runtime;
+use super::{cockroach, notice::NoticeCapture};
+use crate::{
+    compat::{required_privileges, Requirement},
+    parser::{Ident, Literal, SchemaId},
+    plan::{requires_autocommit, scan_probe, SEQUENCE_VALUE},
+    repo::{check_strict, file_changes, overlay_files, save_files_to_dir, save_stats},
+    schema::{Merge, LEDGER_SCHEMA},
+    types::split_literal,
+    utils::plan_checksum,
+    AppliedBatch, AppliedStatement, ApplyFailed, ApplyReport, CatalogFingerprint, Checkpoint,
+    DatabaseRepo, DatabaseSchema, Diagnostic, DiagnosticKind, Dialect, DmlEstimate,
+    DryRunStatement, FileChange, LocalRepo, NoopObserver, ObjectStore, Observer, OverlayStore,
+    Pooler, SchemaFiles, SchemaLoader, SchemaStore, SqlLoader, TableStats,
+};
+use anyhow::{bail, Context, Result};
+use sqlx::{postgres::PgConnectOptions, ConnectOptions, Connection, Executor, PgConnection};
+use tokio::{runtime::Runtime, task::JoinSet};
+use tracing::{debug, info, instrument, instrument::WithSubscriber, warn};
 use url::Url;
 use uuid::Uuid;
 
 impl DatabaseRepo {
+    #[instrument(skip(self))]
     pub async fn load_sql_string(&self, remote: bool) -> Result<String> {
         let url = if remote { &self.remote_url } else { &self.url };
+        let start = Instant::now();
 
-        let output = async_process::Command::new("pg_dump")
-            .arg("-s")
-            .arg(url)
-            .output()
-            .await?;
-
-        if !output.status.success() {
-            bail!("{}", String::from_utf8(output.stderr)?);
+        if self.dialect == Dialect::Postgres && self.pooler == Pooler::Pgbouncer {
+            warn!("pg_dump sets its search_path before its transaction, which transaction pooling may run on another server connection; point the url at the server or a session pooling port to dump");
         }
-
-        let sql = String::from_utf8(output.stdout)?;
+        let sql = match self.dialect {
+            Dialect::Cockroach => cockroach::show_create_all(url).await?,
+            Dialect::Postgres if self.jobs > 1 => {
+                pg_dump_parallel(url, self.jobs, self.pooler).await?
+            }
+            Dialect::Postgres => pg_dump(url, &[]).await?,
+        };
+        let sql = match self.dialect {
+            Dialect::Postgres => {
+                sql + &hypertables(url, self.pooler).await?
+                    + &column_grants(url, self.pooler).await?
+            }
+            Dialect::Cockroach => sql,
+        };
+        debug!(
+            bytes = sql.len(),
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            "schema dumped"
+        );
         Ok(sql)
     }
+
+    #[instrument(skip_all)]
     pub async fn normalize(&self, sql: &str) -> Result<DatabaseSchema> {
-        let tdb = TmpDb::new(self.server_url()?, sql).await?;
+        let tdb = TmpDb::new(self.server_url()?, sql, self.pooler).await?;
         let repo = DatabaseRepo::new_with(tdb.url());
         repo.load().await
     }
 
     /// Apply the migration plan to the remote database server.
     pub async fn apply(&self, plan: Vec<String>, remote: bool) -> Result<()> {
+        self.apply_with(plan, remote, &NoopObserver).await?;
+        Ok(())
+    }
+
+    /// Apply the migration plan, reporting each applied statement to the observer. Return what
+    /// was run; the report is empty if nothing is applied.
+    #[instrument(skip(self, plan, observer), fields(statements = plan.len()))]
+    pub async fn apply_with(
+        &self,
+        plan: Vec<String>,
+        remote: bool,
+        observer: &dyn Observer,
+    ) -> Result<ApplyReport> {
+        match self.target_url(remote) {
+            Some(url) => self.do_apply(&plan, url, observer).await,
+            None => self.unapplied_report(&plan),
+        }
+    }
+
+    /// Same as `apply_with` for the expand phase of a plan, see `split_phases`. The local repo
+    /// isn't fetched afterwards, since the database still has the objects the deferred contract
+    /// phase drops, which would come back to the local files.
+    pub async fn apply_expand(
+        &self,
+        plan: Vec<String>,
+        remote: bool,
+        observer: &dyn Observer,
+    ) -> Result<ApplyReport> {
+        match self.target_url(remote) {
+            Some(url) => self.apply_to(&plan, url, observer).await,
+            None => self.unapplied_report(&plan),
+        }
+    }
+
+    /// the report of a plan which isn't applied since the remote database is the local one
+    fn unapplied_report(&self, plan: &[String]) -> Result<ApplyReport> {
+        Ok(ApplyReport {
+            target: without_password(&self.url)?,
+            plan_checksum: plan_checksum(plan),
+            ..Default::default()
+        })
+    }
+
+    /// the database to apply to, None if the remote one is the local one
+    fn target_url(&self, remote: bool) -> Option<&str> {
         if !remote {
-            self.do_apply(&plan, &self.url).await?;
+            Some(&self.url)
         } else if self.url != self.remote_url {
-            self.do_apply(&plan, &self.remote_url).await?;
+            Some(&self.remote_url)
+        } else {
+            None
         }
-        Ok(())
     }
 
-    /// Fetch the most recent schema from the remote database server.
+    /// Fetch the most recent schema from the remote database server. The fetched objects which
+    /// can't be loaded back from their sql are kept as they are in the local repo.
+    #[instrument(skip(self))]
     pub async fn fetch(&self) -> Result<DatabaseSchema> {
-        let schema = self.load().await?;
-        let config = load_config().await?;
-        schema.save(&config.output).await?;
+        Ok(self.fetch_with_diagnostics().await?.0)
+    }
+
+    /// Same as `fetch`, with the warnings about the dump, see `load_with_diagnostics`, and about
+    /// the objects kept as they are in the local repo
+    pub async fn fetch_with_diagnostics(&self) -> Result<(DatabaseSchema, Vec<Diagnostic>)> {
+        let (schema, mut diagnostics) = self.load_with_diagnostics(false).await?;
+        let (schema, kept) = self.keep_local_unparsable(schema).await?;
+        diagnostics.extend(kept);
+        self.save_schema(&schema).await?;
+        Ok((schema, diagnostics))
+    }
+
+    /// The fetched schema with the objects which can't be loaded back from their sql as they are
+    /// in the local repo, see `DatabaseSchema::unparsable_objects`, along with the warnings
+    /// about them.
+    pub(crate) async fn keep_local_unparsable(
+        &self,
+        schema: DatabaseSchema,
+    ) -> Result<(DatabaseSchema, Vec<Diagnostic>)> {
+        let unparsable = schema.unparsable_objects();
+        if unparsable.is_empty() {
+            return Ok((schema, Vec::new()));
+        }
+        let local = self.load_local().await?;
+        let schema = schema.with_local_unparsable(&local, &unparsable)?;
+        let diagnostics = unparsable
+            .iter()
+            .map(|o| {
+                let message = format!(
+                    "{} {} can't be loaded back from its sql, the local version is kept: {}",
+                    o.type_name, o.id, o.reason
+                );
+                Diagnostic::new(DiagnosticKind::Unparsable, message).source("pg_dump")
+            })
+            .collect();
+        Ok((schema, diagnostics))
+    }
+
+    /// Load the schema of the local database, or the remote one if `remote` is true, with the
+    /// warnings about the dump, e.g. the statements skipped since renovate doesn't support them.
+    /// Fail on those statements instead if `diff.strict` is set.
+    pub async fn load_with_diagnostics(
+        &self,
+        remote: bool,
+    ) -> Result<(DatabaseSchema, Vec<Diagnostic>)> {
+        let sql = if remote {
+            self.load_sql_string(true).await?
+        } else {
+            self.load_sql().await?
+        };
+        let (schema, diagnostics) = SqlLoader::new(sql).parse_with_diagnostics("pg_dump")?;
+        if self.strict {
+            check_strict(&diagnostics)?;
+        }
+        Ok((schema, diagnostics))
+    }
+
+    /// Save the schema to the local repo, and publish it if configured. Return the number of
+    /// local files touched.
+    pub async fn save_schema(&self, schema: &DatabaseSchema) -> Result<usize> {
+        // query the stats first, so that the files aren't touched if it fails
+        let stats = match self.output.stats {
+            true => Some(self.table_stats(false).await?),
+            false => None,
+        };
+        let files = self.output_files(schema.render(&self.output)).await?;
+        let touched = save_files_to_dir(&self.output.path, files).await?;
+        info!(touched, "schema saved");
+        if let Some(stats) = stats {
+            save_stats(&self.output.path, &stats)?;
+        }
+        if let Some(url) = &self.output.publish {
+            let store = ObjectStore::new(url)?;
+            schema.save_to(&store, &self.output).await?;
+        }
+        Ok(touched)
+    }
+
+    /// The files `save_schema` would create, modify or delete in the local repo. Nothing is
+    /// written.
+    pub(crate) async fn preview_save(&self, schema: &DatabaseSchema) -> Result<Vec<FileChange>> {
+        let files = self.output_files(schema.render(&self.output)).await?;
+        file_changes(&LocalRepo::new(&self.output.path), files).await
+    }
+
+    /// the local repo, layered on `output.base` if it's set
+    pub(crate) async fn load_local(&self) -> Result<DatabaseSchema> {
+        match &self.output.base {
+            Some(base) => {
+                OverlayStore::new(LocalRepo::new(base), LocalRepo::new(&self.output.path))
+                    .load()
+                    .await
+            }
+            None => LocalRepo::new(&self.output.path).load().await,
+        }
+    }
+
+    /// the files to save to `output.path` for the local repo to have the given ones. With
+    /// `output.base`, only the files which differ from the base are saved, see `overlay_files`.
+    async fn output_files(&self, files: SchemaFiles) -> Result<SchemaFiles> {
+        let base = match &self.output.base {
+            Some(base) => LocalRepo::new(base),
+            None => return Ok(files),
+        };
+        let mut base_files = SchemaFiles::new();
+        for path in base.list().await? {
+            let content = base.read(&path).await?;
+            base_files.insert(path, content);
+        }
+        Ok(overlay_files(files, &base_files))
+    }
+
+    /// Save the three-way merge of the local schema and the fetched one like `save_schema`, with
+    /// the conflicting objects between conflict markers. Nothing is published until the
+    /// conflicts are resolved.
+    pub(crate) async fn save_merge(&self, merge: &Merge) -> Result<usize> {
+        if merge.conflicts.is_empty() {
+            return self.save_schema(&merge.schema).await;
+        }
+        let stats = match self.output.stats {
+            true => Some(self.table_stats(false).await?),
+            false => None,
+        };
+        let files = merge.schema.render_merge(&merge.conflicts, &self.output)?;
+        let touched = save_files_to_dir(&self.output.path, self.output_files(files).await?).await?;
+        info!(
+            touched,
+            conflicts = merge.conflicts.len(),
+            "merged schema saved"
+        );
+        if let Some(stats) = stats {
+            save_stats(&self.output.path, &stats)?;
+        }
+        Ok(touched)
+    }
+
+    /// Apply the plan to `<dbname>_renovate_dryrun`, a clone of the target database created via
+    /// `CREATE DATABASE ... TEMPLATE`, then drop the clone. It stops at the first failed
+    /// statement since the rest of the plan likely depends on it.
+    #[instrument(skip(self, plan), fields(statements = plan.len()))]
+    pub async fn dry_run(&self, plan: &[String], remote: bool) -> Result<Vec<DryRunStatement>> {
+        let url = if remote { &self.remote_url } else { &self.url };
+        let mut url = Url::parse(url)?;
+        let dbname = url.path().trim_start_matches('/').to_owned();
+        let clone = format!("{}_renovate_dryrun", dbname);
+        url.set_path("");
+        let server_url = url.to_string();
+
+        drop_database(&server_url, &clone, self.pooler).await?;
+        let mut conn = connect(&server_url, self.pooler).await?;
+        conn.execute(format!("CREATE DATABASE {} TEMPLATE {}", Ident(&clone), Ident(&dbname)).as_str())
+            .await
+            .with_context(|| format!("Failed to clone {} (the role needs CREATEDB, and the database must have no other connections)", dbname))?;
+
+        let clone_url = database_url(&server_url, &clone)?;
+        let result = match connect(&clone_url, self.pooler).await {
+            Ok(mut conn) => match resolve_sequence_values(&mut conn, plan).await {
+                Ok(plan) => apply_statements(&clone_url, &plan, self.pooler).await,
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        drop_database(&server_url, &clone, self.pooler).await?;
+        result
+    }
+
+    /// Run the plan against the target database in a transaction which is always rolled back,
+    /// so that syntax and feature errors show up at plan time. It stops at the first failed
+    /// statement. The statements which can't run in a transaction block are skipped, and the
+    /// locks the migration takes are held until the rollback.
+    #[instrument(skip(self, plan), fields(statements = plan.len()))]
+    pub async fn validate(&self, plan: &[String], remote: bool) -> Result<Vec<DryRunStatement>> {
+        let url = if remote { &self.remote_url } else { &self.url };
+        let mut conn = connect(url, self.pooler).await?;
+        let plan = resolve_sequence_values(&mut conn, plan).await?;
+        let mut tx = conn.begin().await?;
+        let mut report = Vec::with_capacity(plan.len());
+        for sql in plan.iter().filter(|sql| !requires_autocommit(sql)) {
+            let t = Instant::now();
+            let error = (&mut *tx)
+                .execute(sql.as_str())
+                .await
+                .err()
+                .map(|e| e.to_string());
+            let failed = error.is_some();
+            report.push(DryRunStatement {
+                sql: sql.clone(),
+                elapsed: t.elapsed(),
+                error,
+            });
+            if failed {
+                break;
+            }
+        }
+        tx.rollback().await?;
+        Ok(report)
+    }
+
+    /// EXPLAIN the statements against the target database in a read only transaction, without
+    /// running them. A statement which can't be planned yet, e.g. the backfill of a column the
+    /// migration adds, is estimated by the scan of the rows it touches, see `scan_probe`. Nothing
+    /// is estimated on cockroach.
+    #[instrument(skip(self, statements), fields(statements = statements.len()))]
+    pub async fn explain(&self, statements: &[String], remote: bool) -> Result<Vec<DmlEstimate>> {
+        if matches!(self.dialect, Dialect::Cockroach) || statements.is_empty() {
+            return Ok(Vec::new());
+        }
+        let url = if remote { &self.remote_url } else { &self.url };
+        let mut conn = connect(url, self.pooler).await?;
+        let mut tx = conn.begin().await?;
+        tx.execute("SET TRANSACTION READ ONLY").await?;
+        let mut estimates = Vec::with_capacity(statements.len());
+        for sql in statements {
+            let mut explained = explain_statement(&mut tx, sql).await;
+            if explained.is_err() {
+                if let Some(probe) = scan_probe(sql) {
+                    if let Ok(plan) = explain_statement(&mut tx, &probe).await {
+                        explained = Ok(plan);
+                    }
+                }
+            }
+            estimates.push(match explained {
+                Ok(plan) => dml_estimate(sql, &plan),
+                Err(e) => DmlEstimate {
+                    statement: sql.clone(),
+                    cost: 0,
+                    rows: 0,
+                    full_scan: false,
+                    error: Some(e.to_string()),
+                },
+            });
+        }
+        tx.rollback().await?;
+        Ok(estimates)
+    }
+
+    /// `server_version_num` of the local database server, or the remote one if `remote` is true
+    pub async fn server_version(&self, remote: bool) -> Result<u32> {
+        let url = if remote { &self.remote_url } else { &self.url };
+        let mut conn = connect(url, self.pooler).await?;
+        let (version,): (String,) = sqlx::query_as("SHOW server_version_num")
+            .fetch_one(&mut conn)
+            .await?;
+        Ok(version.parse()?)
+    }
+
+    /// The first existing schema in the search_path of the local database, or the remote one if
+    /// `remote` is true
+    pub async fn current_schema(&self, remote: bool) -> Result<Option<String>> {
+        let url = if remote { &self.remote_url } else { &self.url };
+        let mut conn = connect(url, self.pooler).await?;
+        let (schema,): (Option<String>,) = sqlx::query_as("SELECT current_schema()::text")
+            .fetch_one(&mut conn)
+            .await?;
         Ok(schema)
     }
 
+    /// md5 of the catalog entries of each user schema of the local database, or the remote one if
+    /// `remote` is true: relations, columns, constraints, indexes, functions, triggers, policies,
+    /// types, sequences, view definitions, extensions and comments, with the owners and ACLs of
+    /// the relations, functions and schemas. It's a few catalog queries instead of
+    /// a pg_dump, so that drift could be checked cheaply. Only comparable between the same
+    /// server versions.
+    #[instrument(skip(self))]
+    pub async fn catalog_fingerprint(&self, remote: bool) -> Result<CatalogFingerprint> {
+        if matches!(self.dialect, Dialect::Cockroach) {
+            bail!("The catalog fingerprint is only available on postgres");
+        }
+        let url = if remote { &self.remote_url } else { &self.url };
+        let mut conn = connect(url, self.pooler).await?;
+        let schemas: Vec<(String, String)> = sqlx::query_as(CATALOG_FINGERPRINT)
+            .fetch_all(&mut conn)
+            .await?;
+        let all = schemas
+            .iter()
+            .map(|(schema, hash)| format!("{} {}\n", schema, hash))
+            .collect::<String>();
+        let (overall,): (String,) = sqlx::query_as("SELECT md5($1)")
+            .bind(all)
+            .fetch_one(&mut conn)
+            .await?;
+        Ok(CatalogFingerprint {
+            overall,
+            schemas: schemas.into_iter().collect(),
+        })
+    }
+
+    /// Record the contract phase deferred by a two-phase apply in the ledger of the target
+    /// database, replacing the previous checkpoint if any.
+    pub async fn save_checkpoint(&self, remote: bool, statements: &[String]) -> Result<()> {
+        let url = if remote { &self.remote_url } else { &self.url };
+        let mut conn = connect(url, self.pooler).await?;
+        conn.execute(
+            format!(
+                "CREATE SCHEMA IF NOT EXISTS {0}; CREATE TABLE IF NOT EXISTS {0}.checkpoints (phase text PRIMARY KEY, statements text[] NOT NULL, created_at timestamptz NOT NULL DEFAULT now())",
+                LEDGER_SCHEMA
+            )
+            .as_str(),
+        )
+        .await?;
+        sqlx::query(&format!(
+            "INSERT INTO {}.checkpoints (phase, statements) VALUES ('contract', $1) ON CONFLICT (phase) DO UPDATE SET statements = EXCLUDED.statements, created_at = now()",
+            LEDGER_SCHEMA
+        ))
+        .bind(statements)
+        .execute(&mut conn)
+        .await?;
+        Ok(())
+    }
+
+    /// The contract phase waiting to be resumed in the target database, if any
+    pub async fn checkpoint(&self, remote: bool) -> Result<Option<Checkpoint>> {
+        let url = if remote { &self.remote_url } else { &self.url };
+        let mut conn = connect(url, self.pooler).await?;
+        if !has_checkpoints(&mut conn).await? {
+            return Ok(None);
+        }
+        let row: Option<(Vec<String>, String)> = sqlx::query_as(&format!(
+            "SELECT statements, created_at::text FROM {}.checkpoints WHERE phase = 'contract'",
+            LEDGER_SCHEMA
+        ))
+        .fetch_optional(&mut conn)
+        .await?;
+        Ok(row.map(|(statements, created_at)| Checkpoint {
+            statements,
+            created_at,
+        }))
+    }
+
+    /// Remove the checkpoint once the contract phase is applied
+    pub async fn clear_checkpoint(&self, remote: bool) -> Result<()> {
+        let url = if remote { &self.remote_url } else { &self.url };
+        let mut conn = connect(url, self.pooler).await?;
+        if has_checkpoints(&mut conn).await? {
+            conn.execute(
+                format!(
+                    "DELETE FROM {}.checkpoints WHERE phase = 'contract'",
+                    LEDGER_SCHEMA
+                )
+                .as_str(),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// The estimated rows and size of every table and materialized view, keyed by their qualified
+    /// name. Empty on cockroach which has no relation sizes.
+    pub async fn table_stats(&self, remote: bool) -> Result<BTreeMap<String, TableStats>> {
+        if matches!(self.dialect, Dialect::Cockroach) {
+            return Ok(BTreeMap::new());
+        }
+        let url = if remote { &self.remote_url } else { &self.url };
+        let mut conn = connect(url, self.pooler).await?;
+        let rows: Vec<(String, String, f32, i64)> =
+            sqlx::query_as(TABLE_STATS).fetch_all(&mut conn).await?;
+        Ok(rows
+            .into_iter()
+            .map(|(schema, name, rows, bytes)| {
+                let stats = TableStats {
+                    // -1 until the first vacuum / analyze
+                    rows: rows.max(0.0) as u64,
+                    bytes: bytes.max(0) as u64,
+                };
+                (SchemaId::new(&schema, &name).to_string(), stats)
+            })
+            .collect())
+    }
+
+    /// Estimated number of rows of the table from the planner statistics, or the exact count if
+    /// the table was never analyzed. None if the table doesn't exist.
+    pub async fn row_count(&self, table: &str, remote: bool) -> Result<Option<u64>> {
+        let url = if remote { &self.remote_url } else { &self.url };
+        let mut conn = connect(url, self.pooler).await?;
+        row_count(&mut conn, table, self.dialect).await
+    }
+
+    /// Same as `row_count` for each of the tables, over a single connection. The tables which
+    /// don't exist are left out.
+    pub async fn row_counts(
+        &self,
+        tables: impl IntoIterator<Item = &str>,
+        remote: bool,
+    ) -> Result<BTreeMap<String, u64>> {
+        let url = if remote { &self.remote_url } else { &self.url };
+        let mut conn = connect(url, self.pooler).await?;
+        let mut counts = BTreeMap::new();
+        for table in tables {
+            if let Some(rows) = row_count(&mut conn, table, self.dialect).await? {
+                counts.insert(table.to_owned(), rows);
+            }
+        }
+        Ok(counts)
+    }
+
     /// create & init local database if not exists
     pub async fn init_local_database(&self) -> Result<()> {
-        let ret = PgConnection::connect(&self.url).await;
+        let ret = connect(&self.url, self.pooler).await;
         match ret {
             Ok(_) => Ok(()),
             Err(_) => {
@@ -60,7 +532,7 @@ impl DatabaseRepo {
                 } else {
                     "".to_owned()
                 };
-                init_database(&server_url, &self.db_name()?, &sql).await?;
+                init_database(&server_url, &self.db_name()?, &sql, self.pooler).await?;
 
                 Ok(())
             }
@@ -69,19 +541,113 @@ impl DatabaseRepo {
 
     /// drop database
     pub async fn drop_database(&self) -> Result<()> {
-        drop_database(&self.server_url()?, &self.db_name()?).await
+        drop_database(&self.server_url()?, &self.db_name()?, self.pooler).await
     }
 
-    async fn do_apply(&self, plan: &[String], url: &str) -> Result<()> {
-        let mut conn = PgConnection::connect(url).await?;
-        let mut tx = conn.begin().await?;
+    async fn do_apply(
+        &self,
+        plan: &[String],
+        url: &str,
+        observer: &dyn Observer,
+    ) -> Result<ApplyReport> {
+        let mut report = self.apply_to(plan, url, observer).await?;
+        match self.fetch_with_diagnostics().await {
+            Ok((_, diagnostics)) => {
+                for diagnostic in diagnostics {
+                    warn!("{}", diagnostic);
+                }
+                Ok(report)
+            }
+            // the plan is committed by then, which the report should still tell
+            Err(source) => {
+                report.error = Some(format!(
+                    "applied, but failed to fetch the schema: {:#}",
+                    source
+                ));
+                Err(ApplyFailed { report, source }.into())
+            }
+        }
+    }
 
-        for sql in plan {
-            tx.execute(sql.as_str()).await?;
+    /// Apply the plan to the database at `url`, which isn't necessarily the local or remote one.
+    /// The local repo is left untouched. If the apply fails, the error is an `ApplyFailed` with
+    /// the report of what was committed until then.
+    pub async fn apply_to(
+        &self,
+        plan: &[String],
+        url: &str,
+        observer: &dyn Observer,
+    ) -> Result<ApplyReport> {
+        let mut report = ApplyReport {
+            target: without_password(url)?,
+            // the checksum of the plan as given, which the commit of the apply is made with
+            plan_checksum: plan_checksum(plan),
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let result = self.run_plan(plan, url, observer, &mut report).await;
+        report.elapsed_ms = start.elapsed().as_millis() as u64;
+        match result {
+            Ok(()) => {
+                info!(
+                    statements = report.statements.len(),
+                    elapsed_ms = report.elapsed_ms,
+                    "migration committed"
+                );
+                Ok(report)
+            }
+            Err(source) => {
+                report.error = Some(format!("{:#}", source));
+                Err(ApplyFailed { report, source }.into())
+            }
         }
-        tx.commit().await?;
+    }
+
+    /// run the plan on the database at `url`, recording the statements committed in `report`
+    async fn run_plan(
+        &self,
+        plan: &[String],
+        url: &str,
+        observer: &dyn Observer,
+        report: &mut ApplyReport,
+    ) -> Result<()> {
+        let mut conn = connect(url, self.pooler).await?;
+        let plan = &resolve_sequence_values(&mut conn, plan).await?;
+        check_privileges(&mut conn, plan).await?;
 
-        self.fetch().await?;
+        let capture = NoticeCapture::new();
+        // the statements which can't run in a transaction block are run on their own, which
+        // commits the statements before them
+        for (autocommit, group) in transaction_groups(plan) {
+            if autocommit {
+                debug!(statements = group.len(), "applied outside of a transaction");
+                apply_group(&mut conn, &group, 1, plan.len(), &capture, observer, report).await?;
+            } else {
+                let mut tx = conn.begin().await?;
+                let committed = (report.statements.len(), report.batches.len());
+                let applied = apply_group(
+                    &mut tx,
+                    &group,
+                    self.batch_size,
+                    plan.len(),
+                    &capture,
+                    observer,
+                    report,
+                )
+                .await;
+                if let Err(e) = applied {
+                    // rolled back along with the transaction
+                    report.statements.truncate(committed.0);
+                    report.batches.truncate(committed.1);
+                    return Err(e);
+                }
+                tx.commit().await?;
+            }
+        }
         Ok(())
     }
 
@@ -103,13 +669,18 @@ impl DatabaseRepo {
 pub struct TmpDb {
     pub server_url: String,
     pub dbname: String,
+    pub pooler: Pooler,
 }
 
 impl TmpDb {
-    pub async fn new(server_url: String, sql: &str) -> Result<Self> {
+    pub async fn new(server_url: String, sql: &str, pooler: Pooler) -> Result<Self> {
         let dbname = format!("tmpdb_{}", Uuid::new_v4());
-        init_database(&server_url, &dbname, sql).await?;
-        Ok(Self { server_url, dbname })
+        init_database(&server_url, &dbname, sql, pooler).await?;
+        Ok(Self {
+            server_url,
+            dbname,
+            pooler,
+        })
     }
 
     pub fn server_url(&self) -> String {
@@ -117,7 +688,7 @@ impl TmpDb {
     }
 
     pub fn url(&self) -> String {
-        format!("{}/{}", self.server_url, self.dbname)
+        database_url(&self.server_url, &self.dbname).expect("the server url is checked by new")
     }
 }
 
@@ -125,10 +696,11 @@ impl Drop for TmpDb {
     fn drop(&mut self) {
         let server_url = self.server_url();
         let dbname = self.dbname.clone();
+        let pooler = self.pooler;
         thread::spawn(move || {
             let rt = Runtime::new().unwrap();
             rt.block_on(async move {
-                drop_database(&server_url, &dbname).await.unwrap();
+                drop_database(&server_url, &dbname, pooler).await.unwrap();
             });
         })
         .join()
@@ -136,31 +708,721 @@ impl Drop for TmpDb {
     }
 }
 
-async fn init_database(server_url: &str, dbname: &str, sql: &str) -> Result<()> {
+/// the savepoint a batch of statements is rolled back to if it fails, see `apply_group`
+const BATCH_SAVEPOINT: &str = "renovate_batch";
+
+/// the url to report, without the password
+fn without_password(url: &str) -> Result<String> {
+    let mut url = Url::parse(url)?;
+    let _ = url.set_password(None);
+    Ok(url.to_string())
+}
+
+/// Connect to the database at `url`. Behind pgbouncer in transaction pooling mode, the queries
+/// are never cached as prepared statements on the connection, since the next transaction may run
+/// on another server connection which doesn't have them.
+async fn connect(url: &str, pooler: Pooler) -> Result<PgConnection> {
+    let options = PgConnectOptions::from_str(url)?;
+    let options = match pooler {
+        Pooler::None => options,
+        Pooler::Pgbouncer => options.statement_cache_capacity(0),
+    };
+    Ok(options.connect().await?)
+}
+
+/// split the plan into runs of statements which are all in a transaction, or all not
+fn transaction_groups(plan: &[String]) -> Vec<(bool, Vec<String>)> {
+    let mut groups: Vec<(bool, Vec<String>)> = Vec::new();
+    for sql in plan {
+        let autocommit = requires_autocommit(sql);
+        match groups.last_mut() {
+            Some((a, group)) if *a == autocommit => group.push(sql.clone()),
+            _ => groups.push((autocommit, vec![sql.clone()])),
+        }
+    }
+    groups
+}
+
+/// whether the ledger of the database has the checkpoints table
+async fn has_checkpoints(conn: &mut PgConnection) -> Result<bool> {
+    let (exists,): (bool,) = sqlx::query_as("SELECT to_regclass($1) IS NOT NULL")
+        .bind(format!("{}.checkpoints", LEDGER_SCHEMA))
+        .fetch_one(conn)
+        .await?;
+    Ok(exists)
+}
+
+/// Execute the statements in batches of up to `batch_size`, reporting each of them to the
+/// observer. A batch is sent as a single multi-statement query behind a savepoint, so it has to
+/// be in a transaction; if it fails, it's rolled back to the savepoint and replayed statement by
+/// statement, so that the error is reported for the statement which failed.
+async fn apply_group(
+    conn: &mut PgConnection,
+    group: &[String],
+    batch_size: usize,
+    total: usize,
+    capture: &NoticeCapture,
+    observer: &dyn Observer,
+    report: &mut ApplyReport,
+) -> Result<()> {
+    for batch in group.chunks(batch_size.max(1)) {
+        if batch.len() == 1 {
+            apply_statement(conn, &batch[0], total, capture, observer, report).await?;
+            continue;
+        }
+
+        // a statement may end with a comment, so the separators are on their own lines
+        let sql = format!(
+            "SAVEPOINT {0};\n{1}\n;\nRELEASE SAVEPOINT {0}",
+            BATCH_SAVEPOINT,
+            batch.join("\n;\n")
+        );
+        let t = Instant::now();
+        let result = match (&mut *conn)
+            .execute(sql.as_str())
+            .with_subscriber(capture.clone())
+            .await
+        {
+            Ok(result) => result,
+            Err(e) => {
+                capture.take();
+                debug!(error = %e, statements = batch.len(), "batch failed, replayed one by one");
+                (&mut *conn)
+                    .execute(format!("ROLLBACK TO SAVEPOINT {}", BATCH_SAVEPOINT).as_str())
+                    .await?;
+                for sql in batch {
+                    apply_statement(conn, sql, total, capture, observer, report).await?;
+                }
+                continue;
+            }
+        };
+        let elapsed_ms = t.elapsed().as_millis() as u64;
+        debug!(statements = batch.len(), elapsed_ms, "batch applied");
+
+        let number = report.batches.len() + 1;
+        let notices = capture.take();
+        for notice in &notices {
+            observer.on_notice(&batch[0], notice);
+        }
+        for statement in batch_statements(batch, number, elapsed_ms) {
+            observer.on_statement_applied(&statement.sql, report.statements.len() + 1, total);
+            report.statements.push(statement);
+        }
+        report.batches.push(AppliedBatch {
+            number,
+            statements: batch.len(),
+            elapsed_ms,
+            rows_affected: result.rows_affected(),
+            notices,
+        });
+    }
+    Ok(())
+}
+
+/// the statements of a batch, with the elapsed time of the batch split evenly between them
+fn batch_statements(batch: &[String], number: usize, elapsed_ms: u64) -> Vec<AppliedStatement> {
+    let count = batch.len().max(1) as u64;
+    batch
+        .iter()
+        .enumerate()
+        .map(|(i, sql)| AppliedStatement {
+            sql: sql.clone(),
+            // the remainder goes to the first statements, so that the shares add up
+            elapsed_ms: elapsed_ms / count + u64::from((i as u64) < elapsed_ms % count),
+            slow: false,
+            rows_affected: 0,
+            notices: Vec::new(),
+            batch: Some(number),
+        })
+        .collect()
+}
+
+/// `EXPLAIN (FORMAT JSON)` of the statement, in a savepoint since a failed one aborts the
+/// transaction
+async fn explain_statement(conn: &mut PgConnection, sql: &str) -> Result<serde_json::Value> {
+    conn.execute("SAVEPOINT renovate_explain").await?;
+    let result: Result<(serde_json::Value,), _> =
+        sqlx::query_as(&format!("EXPLAIN (FORMAT JSON) {}", sql))
+            .fetch_one(&mut *conn)
+            .await;
+    match result {
+        Ok((plan,)) => {
+            conn.execute("RELEASE SAVEPOINT renovate_explain").await?;
+            Ok(plan)
+        }
+        Err(e) => {
+            conn.execute("ROLLBACK TO SAVEPOINT renovate_explain")
+                .await?;
+            Err(e.into())
+        }
+    }
+}
+
+/// the estimates of the statement from its `EXPLAIN (FORMAT JSON)` output
+fn dml_estimate(sql: &str, explained: &serde_json::Value) -> DmlEstimate {
+    let plan = &explained[0]["Plan"];
+    // the node modifying the table returns no rows, the rows it touches come from the scan
+    let scan = match plan["Node Type"].as_str() {
+        Some("ModifyTable") => &plan["Plans"][0],
+        _ => plan,
+    };
+    DmlEstimate {
+        statement: sql.to_owned(),
+        cost: plan["Total Cost"].as_f64().unwrap_or_default().round() as u64,
+        rows: scan["Plan Rows"].as_f64().unwrap_or_default() as u64,
+        full_scan: has_unfiltered_scan(plan),
+        error: None,
+    }
+}
+
+fn has_unfiltered_scan(node: &serde_json::Value) -> bool {
+    (node["Node Type"] == "Seq Scan" && node.get("Filter").is_none())
+        || node["Plans"]
+            .as_array()
+            .map_or(false, |plans| plans.iter().any(has_unfiltered_scan))
+}
+
+/// execute a single statement, reporting it to the observer
+async fn apply_statement(
+    conn: &mut PgConnection,
+    sql: &str,
+    total: usize,
+    capture: &NoticeCapture,
+    observer: &dyn Observer,
+    report: &mut ApplyReport,
+) -> Result<()> {
+    let t = Instant::now();
+    let result = (&mut *conn)
+        .execute(sql)
+        .with_subscriber(capture.clone())
+        .await?;
+    let elapsed_ms = t.elapsed().as_millis() as u64;
+    debug!(statement = sql, elapsed_ms, "statement applied");
+    let notices = capture.take();
+    for notice in &notices {
+        observer.on_notice(sql, notice);
+    }
+    observer.on_statement_applied(sql, report.statements.len() + 1, total);
+    report.statements.push(AppliedStatement {
+        sql: sql.to_owned(),
+        elapsed_ms,
+        slow: false,
+        rows_affected: result.rows_affected(),
+        notices,
+        batch: None,
+    });
+    Ok(())
+}
+
+async fn apply_statements(
+    url: &str,
+    plan: &[String],
+    pooler: Pooler,
+) -> Result<Vec<DryRunStatement>> {
+    let mut conn = connect(url, pooler).await?;
+    let mut report = Vec::with_capacity(plan.len());
+    for sql in plan {
+        let t = Instant::now();
+        let error = conn
+            .execute(sql.as_str())
+            .await
+            .err()
+            .map(|e| e.to_string());
+        let failed = error.is_some();
+        report.push(DryRunStatement {
+            sql: sql.clone(),
+            elapsed: t.elapsed(),
+            error,
+        });
+        if failed {
+            break;
+        }
+    }
+    Ok(report)
+}
+
+/// Replace the sequence value placeholders of the plan with the current values of the
+/// sequences, e.g. `SELECT setval('public.todos_id_seq', 42, true)`.
+async fn resolve_sequence_values(conn: &mut PgConnection, plan: &[String]) -> Result<Vec<String>> {
+    let mut resolved = Vec::with_capacity(plan.len());
+    for sql in plan {
+        if !sql.contains(SEQUENCE_VALUE) {
+            resolved.push(sql.clone());
+            continue;
+        }
+        let id = sql
+            .split_once("setval(")
+            .and_then(|(_, rest)| split_literal(rest))
+            .map(|(id, _)| id)
+            .with_context(|| format!("Invalid sequence value placeholder: {}", sql))?;
+        // the id is a quoted SchemaId generated by the planner
+        let (value, called): (i64, bool) =
+            sqlx::query_as(&format!("SELECT last_value, is_called FROM {}", id))
+                .fetch_one(&mut *conn)
+                .await
+                .with_context(|| format!("Failed to get the current value of {}", id))?;
+        resolved.push(format!(
+            "SELECT setval({}, {}, {})",
+            Literal(&id),
+            value,
+            called
+        ));
+    }
+    Ok(resolved)
+}
+
+/// Make sure the connected role could execute every statement of the plan. All the missing
+/// privileges are reported at once. Objects not in the database yet are created by the plan
+/// itself, hence skipped.
+async fn check_privileges(conn: &mut PgConnection, plan: &[String]) -> Result<()> {
+    let (user, superuser): (String, bool) = sqlx::query_as(
+        "SELECT current_user::text, rolsuper FROM pg_roles WHERE rolname = current_user",
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if superuser {
+        return Ok(());
+    }
+
+    let mut errors = Vec::new();
+    for (i, requirement) in required_privileges(plan)? {
+        let granted: Option<(bool,)> = match &requirement {
+            // the owner checks of the server accept the roles whose privileges are inherited
+            Requirement::Owner(name) => {
+                sqlx::query_as("SELECT pg_has_role(current_user, relowner, 'USAGE') FROM pg_class WHERE oid = to_regclass($1)")
+                    .bind(name)
+                    .fetch_optional(&mut *conn)
+                    .await?
+            }
+            Requirement::Create(schema) => {
+                sqlx::query_as("SELECT has_schema_privilege(oid, 'CREATE') FROM pg_namespace WHERE nspname = $1")
+                    .bind(schema)
+                    .fetch_optional(&mut *conn)
+                    .await?
+            }
+            Requirement::CreateSchema => {
+                sqlx::query_as("SELECT has_database_privilege(current_database(), 'CREATE')")
+                    .fetch_optional(&mut *conn)
+                    .await?
+            }
+            // while handing an object over takes a membership, inherited or not
+            Requirement::Member(role) => {
+                sqlx::query_as("SELECT pg_has_role(current_user, oid, 'MEMBER') FROM pg_roles WHERE rolname = $1")
+                    .bind(role)
+                    .fetch_optional(&mut *conn)
+                    .await?
+            }
+            Requirement::Superuser(_) => Some((false,)),
+        };
+        if let Some((false,)) = granted {
+            errors.push(format!("  {}: {}", plan[i], requirement));
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!(
+            "Role {} lacks the privileges to apply the migration plan:\n\n{}",
+            user,
+            errors.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// dump the schema only sql, limited to the given schemas if not empty
+async fn pg_dump(url: &str, schemas: &[String]) -> Result<String> {
+    let mut cmd = async_process::Command::new("pg_dump");
+    cmd.arg("-s");
+    for schema in schemas {
+        cmd.arg("-n").arg(schema);
+    }
+    let output = cmd.arg(url).output().await?;
+
+    if !output.status.success() {
+        bail!("{}", String::from_utf8(output.stderr)?);
+    }
+
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// split the user schemas into `jobs` groups and dump them concurrently
+async fn pg_dump_parallel(url: &str, jobs: usize, pooler: Pooler) -> Result<String> {
+    let mut conn = connect(url, pooler).await?;
+    let schemas: Vec<(String,)> = sqlx::query_as(
+        "SELECT nspname::text FROM pg_namespace WHERE nspname !~ '^pg_' AND nspname <> 'information_schema' ORDER BY nspname",
+    )
+    .fetch_all(&mut conn)
+    .await?;
+    let schemas: Vec<String> = schemas.into_iter().map(|(name,)| name).collect();
+    if schemas.is_empty() {
+        return Ok(String::new());
+    }
+
+    let size = (schemas.len() + jobs - 1) / jobs;
+    let mut tasks = JoinSet::new();
+    for (i, group) in schemas.chunks(size).enumerate() {
+        let (url, group) = (url.to_owned(), group.to_vec());
+        tasks.spawn(async move { pg_dump(&url, &group).await.map(|sql| (i, sql)) });
+    }
+
+    // merge in the order of the groups to keep the output stable
+    let mut dumps = Vec::new();
+    while let Some(ret) = tasks.join_next().await {
+        dumps.push(ret??);
+    }
+    dumps.sort_by_key(|(i, _)| *i);
+    debug!(schemas = schemas.len(), jobs, "schemas dumped in parallel");
+    Ok(dumps.into_iter().map(|(_, sql)| sql).collect())
+}
+
+/// pg_dump doesn't keep the `create_hypertable()` calls, so recover them from the timescaledb
+/// catalog. The first dimension of a hypertable is its time column. The extension itself is
+/// declared first, since the dumps of the schemas one by one don't have it, and the chunks and
+/// the other objects of timescaledb are only told apart when it's installed.
+async fn hypertables(url: &str, pooler: Pooler) -> Result<String> {
+    let mut conn = connect(url, pooler).await?;
+    let schema: Option<(String,)> = sqlx::query_as(
+        "SELECT n.nspname::text FROM pg_extension e JOIN pg_namespace n ON n.oid = e.extnamespace WHERE e.extname = 'timescaledb'",
+    )
+    .fetch_optional(&mut conn)
+    .await?;
+    let extension = match schema {
+        Some((schema,)) => format!(
+            "CREATE EXTENSION IF NOT EXISTS timescaledb WITH SCHEMA {};\n",
+            Ident(&schema)
+        ),
+        None => return Ok(String::new()),
+    };
+
+    let rows: Vec<(String, String, String)> = sqlx::query_as(
+        r#"SELECT h.schema_name::text, h.table_name::text, d.column_name::text
+        FROM _timescaledb_catalog.hypertable h
+        JOIN _timescaledb_catalog.dimension d ON d.hypertable_id = h.id
+        WHERE d.id = (SELECT min(id) FROM _timescaledb_catalog.dimension WHERE hypertable_id = h.id)
+            AND h.schema_name <> '_timescaledb_internal'
+        ORDER BY 1, 2"#,
+    )
+    .fetch_all(&mut conn)
+    .await?;
+    debug!(hypertables = rows.len(), "timescaledb hypertables found");
+    Ok(extension
+        + &rows
+            .into_iter()
+            .map(|(schema, table, column)| {
+                format!(
+                    "SELECT create_hypertable({}, {});\n",
+                    Literal(&SchemaId::new(schema, table).to_string()),
+                    Literal(&column)
+                )
+            })
+            .collect::<String>())
+}
+
+/// see `DatabaseRepo::row_count`. The table name is a quoted SchemaId.
+async fn row_count(conn: &mut PgConnection, table: &str, dialect: Dialect) -> Result<Option<u64>> {
+    if dialect == Dialect::Cockroach {
+        // neither estimates nor relation sizes there
+        return exact_row_count(conn, table).await;
+    }
+    let estimate: Option<(f32, bool)> = sqlx::query_as(
+        "SELECT reltuples, relpages > 0 OR pg_relation_size(oid) > 0 FROM pg_class WHERE oid = to_regclass($1)",
+    )
+    .bind(table)
+    .fetch_optional(&mut *conn)
+    .await?;
+    match estimate {
+        None => Ok(None),
+        // -1 until the first vacuum / analyze, or 0 with some pages before postgres 14
+        Some((rows, has_pages)) if rows < 0.0 || (rows == 0.0 && has_pages) => {
+            exact_row_count(conn, table).await
+        }
+        Some((rows, _)) => Ok(Some(rows as u64)),
+    }
+}
+
+/// the rows of the table counted one by one, None if the table doesn't exist
+async fn exact_row_count(conn: &mut PgConnection, table: &str) -> Result<Option<u64>> {
+    let (exists,): (bool,) = sqlx::query_as("SELECT to_regclass($1) IS NOT NULL")
+        .bind(table)
+        .fetch_one(&mut *conn)
+        .await?;
+    if !exists {
+        return Ok(None);
+    }
+    let (rows,): (i64,) = sqlx::query_as(&format!("SELECT count(*) FROM {}", table))
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok(Some(rows as u64))
+}
+
+/// the tables, partitioned tables and materialized views of the user schemas, see `table_stats`
+const TABLE_STATS: &str = r#"SELECT n.nspname::text, c.relname::text, c.reltuples,
+    pg_total_relation_size(c.oid)
+FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+WHERE c.relkind IN ('r', 'p', 'm')
+    AND n.nspname NOT IN ('pg_catalog', 'information_schema', '_renovate')
+    AND n.nspname NOT LIKE 'pg_toast%'"#;
+
+/// one line per catalog entry of the user schemas, hashed per schema. Entries are rendered by the
+/// catalog functions, e.g. `pg_get_indexdef`, so that they don't depend on the oids.
+const CATALOG_FINGERPRINT: &str = r#"WITH entries AS (
+    SELECT n.nspname, format('relation %s %s %s %s', c.relname, c.relkind,
+        pg_get_userbyid(c.relowner), c.relacl) AS entry
+    FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+    UNION ALL
+    SELECT n.nspname, format('column %s.%s %s %s %s %s', c.relname, a.attname,
+        format_type(a.atttypid, a.atttypmod), a.attnotnull, pg_get_expr(d.adbin, d.adrelid), a.attacl)
+    FROM pg_attribute a
+    JOIN pg_class c ON c.oid = a.attrelid
+    JOIN pg_namespace n ON n.oid = c.relnamespace
+    LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+    WHERE a.attnum > 0 AND NOT a.attisdropped
+    UNION ALL
+    SELECT n.nspname, format('constraint %s %s %s', co.conrelid::regclass, co.conname,
+        pg_get_constraintdef(co.oid))
+    FROM pg_constraint co JOIN pg_namespace n ON n.oid = co.connamespace
+    UNION ALL
+    SELECT n.nspname, format('index %s', pg_get_indexdef(i.indexrelid))
+    FROM pg_index i JOIN pg_class c ON c.oid = i.indexrelid JOIN pg_namespace n ON n.oid = c.relnamespace
+    UNION ALL
+    SELECT n.nspname, format('function %s %s %s %s %s %s %s', p.oid::regprocedure,
+        pg_get_function_result(p.oid), md5(p.prosrc), p.provolatile, p.prosecdef, p.proconfig,
+        p.proacl)
+    FROM pg_proc p JOIN pg_namespace n ON n.oid = p.pronamespace
+    UNION ALL
+    SELECT n.nspname, format('trigger %s', pg_get_triggerdef(t.oid))
+    FROM pg_trigger t JOIN pg_class c ON c.oid = t.tgrelid JOIN pg_namespace n ON n.oid = c.relnamespace
+    WHERE NOT t.tgisinternal
+    UNION ALL
+    SELECT n.nspname, format('policy %s %s %s %s %s %s', c.relname, p.polname, p.polcmd,
+        p.polroles, pg_get_expr(p.polqual, p.polrelid), pg_get_expr(p.polwithcheck, p.polrelid))
+    FROM pg_policy p JOIN pg_class c ON c.oid = p.polrelid JOIN pg_namespace n ON n.oid = c.relnamespace
+    UNION ALL
+    SELECT n.nspname, format('type %s %s %s', t.typname, t.typtype,
+        (SELECT string_agg(e.enumlabel, ',' ORDER BY e.enumsortorder) FROM pg_enum e WHERE e.enumtypid = t.oid))
+    FROM pg_type t JOIN pg_namespace n ON n.oid = t.typnamespace
+    WHERE t.typtype IN ('c', 'd', 'e')
+    UNION ALL
+    SELECT n.nspname, format('sequence %s %s %s %s %s %s %s', c.relname, s.seqtypid::regtype,
+        s.seqstart, s.seqincrement, s.seqmin, s.seqmax, s.seqcycle)
+    FROM pg_sequence s JOIN pg_class c ON c.oid = s.seqrelid JOIN pg_namespace n ON n.oid = c.relnamespace
+    UNION ALL
+    SELECT n.nspname, format('view %s %s', c.relname, pg_get_viewdef(c.oid))
+    FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+    WHERE c.relkind IN ('v', 'm')
+    UNION ALL
+    SELECT n.nspname, format('extension %s %s', e.extname, e.extversion)
+    FROM pg_extension e JOIN pg_namespace n ON n.oid = e.extnamespace
+    UNION ALL
+    SELECT n.nspname, format('schema %s %s', pg_get_userbyid(n.nspowner), n.nspacl)
+    FROM pg_namespace n
+    UNION ALL
+    SELECT coalesce(o.schema, CASE WHEN d.classoid = 'pg_namespace'::regclass THEN o.identity END)::name,
+        format('comment %s %s %s', o.type, o.identity, d.description)
+    FROM pg_description d CROSS JOIN LATERAL pg_identify_object(d.classoid, d.objoid, d.objsubid) o
+    WHERE d.objoid >= 16384
+)
+SELECT nspname::text, md5(string_agg(entry, E'\n' ORDER BY entry))
+FROM entries
+WHERE nspname !~ '^pg_' AND nspname NOT IN ('information_schema', '_renovate')
+GROUP BY nspname
+ORDER BY nspname"#;
+
+/// Column level grants from the pg_attribute ACLs, one statement per privilege and grantee with
+/// all its columns. pg_dump emits them column by column, the loader merges both into one
+/// privilege per grantee.
+async fn column_grants(url: &str, pooler: Pooler) -> Result<String> {
+    let mut conn = connect(url, pooler).await?;
+    let rows: Vec<(String, String, String, String, Option<String>, bool)> = sqlx::query_as(
+        r#"SELECT n.nspname::text, c.relname::text, a.attname::text, acl.privilege_type::text,
+            r.rolname::text, acl.is_grantable
+        FROM pg_attribute a
+        JOIN pg_class c ON c.oid = a.attrelid
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        CROSS JOIN LATERAL aclexplode(a.attacl) acl
+        LEFT JOIN pg_roles r ON r.oid = acl.grantee
+        WHERE a.attacl IS NOT NULL AND a.attnum > 0 AND NOT a.attisdropped
+            AND n.nspname !~ '^pg_' AND n.nspname <> 'information_schema'
+        ORDER BY 1, 2, 5, 4, 3"#,
+    )
+    .fetch_all(&mut conn)
+    .await?;
+    debug!(columns = rows.len(), "column level grants found");
+
+    let mut grants: BTreeMap<_, Vec<String>> = BTreeMap::new();
+    for (schema, table, column, privilege, grantee, grantable) in rows {
+        // grantee 0 is PUBLIC, which isn't in pg_roles
+        let grantee = grantee.map_or_else(|| "PUBLIC".to_owned(), |r| Ident(&r).to_string());
+        grants
+            .entry((schema, table, grantee, privilege, grantable))
+            .or_default()
+            .push(Ident(&column).to_string());
+    }
+    Ok(grants
+        .into_iter()
+        .map(
+            |((schema, table, grantee, privilege, grantable), columns)| {
+                format!(
+                    "GRANT {}({}) ON TABLE {}.{} TO {}{};\n",
+                    privilege,
+                    columns.join(", "),
+                    Ident(&schema),
+                    Ident(&table),
+                    grantee,
+                    if grantable { " WITH GRANT OPTION" } else { "" }
+                )
+            },
+        )
+        .collect())
+}
+
+/// the url of the database `dbname` on the server, keeping the parameters of the server url such
+/// as `sslmode`
+fn database_url(server_url: &str, dbname: &str) -> Result<String> {
+    let mut url = Url::parse(server_url)?;
+    url.set_path(&format!("/{}", dbname));
+    Ok(url.to_string())
+}
+
+async fn init_database(server_url: &str, dbname: &str, sql: &str, pooler: Pooler) -> Result<()> {
     // create database dbname
     // use server url to create database
-    let mut conn = PgConnection::connect(server_url).await?;
-    conn.execute(format!(r#"CREATE DATABASE "{}""#, dbname).as_str())
+    let mut conn = connect(server_url, pooler).await?;
+    conn.execute(format!("CREATE DATABASE {}", Ident(dbname)).as_str())
         .await?;
 
     // now connect to test database for migration
-    let url = format!("{}/{}", server_url, dbname);
-    let mut conn = PgConnection::connect(&url).await?;
-    let mut tx = conn.begin().await?;
-    tx.execute(sql).await?;
-    tx.commit().await?;
-    Ok(())
+    let url = database_url(server_url, dbname)?;
+    let result: Result<()> = async {
+        let mut conn = connect(&url, pooler).await?;
+        let mut tx = conn.begin().await?;
+        tx.execute(sql).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+    .await;
+    // the caller has nothing to drop yet, e.g. the TmpDb of a schema which doesn't load
+    if result.is_err() {
+        if let Err(e) = drop_database(server_url, dbname, pooler).await {
+            warn!(dbname, "failed to drop the database: {:#}", e);
+        }
+    }
+    result
 }
 
-async fn drop_database(server_url: &str, dbname: &str) -> Result<()> {
-    let mut conn = PgConnection::connect(server_url).await?;
+async fn drop_database(server_url: &str, dbname: &str, pooler: Pooler) -> Result<()> {
+    let mut conn = connect(server_url, pooler).await?;
     // terminate existing connections
-    sqlx::query(&format!(r#"SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE pid <> pg_backend_pid() AND datname = '{}'"#, dbname))
-                    .execute( &mut conn)
-                    .await
-                    .expect("Terminate all other connections");
-    conn.execute(format!(r#"DROP DATABASE "{}""#, dbname).as_str())
+    sqlx::query("SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE pid <> pg_backend_pid() AND datname = $1")
+        .bind(dbname)
+        .execute(&mut conn)
+        .await
+        .context("Failed to terminate the other connections")?;
+    conn.execute(format!("DROP DATABASE IF EXISTS {}", Ident(dbname)).as_str())
         .await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn database_url_should_keep_the_parameters() -> Result<()> {
+        let repo = DatabaseRepo::new_with("postgres://user:pw@db:5432/app?sslmode=require".into());
+        let server_url = repo.server_url()?;
+        assert_eq!(server_url, "postgres://user:pw@db:5432?sslmode=require");
+        assert_eq!(
+            database_url(&server_url, "app_renovate_dryrun")?,
+            "postgres://user:pw@db:5432/app_renovate_dryrun?sslmode=require"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn batch_time_should_be_split_between_its_statements() {
+        let batch = vec![
+            "SELECT 1".to_owned(),
+            "SELECT 2".to_owned(),
+            "SELECT 3".to_owned(),
+        ];
+        let statements = batch_statements(&batch, 2, 10);
+        let elapsed: Vec<_> = statements.iter().map(|s| s.elapsed_ms).collect();
+        assert_eq!(elapsed, vec![4, 3, 3]);
+        assert!(statements.iter().all(|s| s.batch == Some(2)));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a postgres server at RENOVATE_TEST_SERVER_URL"]
+    async fn statements_should_be_applied_in_batches() -> Result<()> {
+        let server_url = std::env::var(crate::testing::TEST_SERVER_URL_ENV)?;
+        let db = TmpDb::new(server_url, "", Pooler::None).await?;
+        let repo = DatabaseRepo {
+            batch_size: 2,
+            ..DatabaseRepo::new_with(db.url())
+        };
+        let plan = vec![
+            "CREATE TABLE public.todos (id int)".to_owned(),
+            "INSERT INTO public.todos VALUES (1), (2)".to_owned(),
+            "INSERT INTO public.todos VALUES (3)".to_owned(),
+        ];
+        let report = repo.apply_expand(plan, false, &NoopObserver).await?;
+
+        let batches: Vec<_> = report.statements.iter().map(|s| s.batch).collect();
+        assert_eq!(batches, vec![Some(1), Some(1), None]);
+        assert_eq!(report.batches.len(), 1);
+        assert_eq!(report.batches[0].statements, 2);
+        assert_eq!(report.batches[0].rows_affected, 2);
+        assert_eq!(report.statements[2].rows_affected, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a postgres server at RENOVATE_TEST_SERVER_URL"]
+    async fn failed_apply_should_report_the_committed_statements() -> Result<()> {
+        let server_url = std::env::var(crate::testing::TEST_SERVER_URL_ENV)?;
+        let db = TmpDb::new(server_url, "", Pooler::None).await?;
+        let repo = DatabaseRepo::new_with(db.url());
+        let plan = vec![
+            "CREATE TABLE public.todos (id int)".to_owned(),
+            "VACUUM public.todos".to_owned(),
+            "INSERT INTO public.todos VALUES (1)".to_owned(),
+            "INSERT INTO public.missing VALUES (1)".to_owned(),
+        ];
+        let e = repo
+            .apply_expand(plan, false, &NoopObserver)
+            .await
+            .unwrap_err();
+        let failed = e.downcast_ref::<ApplyFailed>().unwrap();
+
+        // the insert is rolled back along with the failed one
+        let applied: Vec<_> = failed.report.statements.iter().map(|s| &s.sql).collect();
+        assert_eq!(
+            applied,
+            vec!["CREATE TABLE public.todos (id int)", "VACUUM public.todos"]
+        );
+        assert!(failed.report.error.as_ref().unwrap().contains("missing"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a postgres server at RENOVATE_TEST_SERVER_URL"]
+    async fn database_should_be_dropped_if_the_sql_fails() -> Result<()> {
+        let server_url = std::env::var(crate::testing::TEST_SERVER_URL_ENV)?;
+        let dbname = format!("tmpdb_{}", Uuid::new_v4());
+        let sql = "CREATE TABLE public.todos (id int REFERENCES public.missing (id))";
+        assert!(init_database(&server_url, &dbname, sql, Pooler::None)
+            .await
+            .is_err());
+
+        let mut conn = connect(&server_url, Pooler::None).await?;
+        let (exists,): (bool,) =
+            sqlx::query_as("SELECT EXISTS (SELECT 1 FROM pg_database WHERE datname = $1)")
+                .bind(&dbname)
+                .fetch_one(&mut conn)
+                .await?;
+        assert!(!exists);
+        Ok(())
+    }
+}
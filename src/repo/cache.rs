@@ -0,0 +1,129 @@
+use super::state::state_dir;
+use crate::DatabaseSchema;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+use tokio::fs;
+use tracing::{debug, warn};
+
+/// folder for the cache, relative to the root of the local repo
+pub const CACHE_DIR: &str = ".renovate/cache";
+
+/// Parsed schema cached on disk, keyed by the hash of the sql it is parsed from. Only the latest
+/// entry of each name is kept, so the cache won't grow over time.
+#[derive(Debug, Clone)]
+pub(crate) struct SchemaCache {
+    /// the root of the local repo, and the file of the entry
+    path: Option<(PathBuf, PathBuf)>,
+    hash: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    hash: String,
+    schema: DatabaseSchema,
+}
+
+impl SchemaCache {
+    /// cache entry `name` under `root` for the given sql and any extra inputs affecting the result
+    pub fn new(root: impl AsRef<Path>, name: &str, sql: &str, extra: &[&str]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        sql.hash(&mut hasher);
+        extra.hash(&mut hasher);
+        let root = root.as_ref();
+        Self {
+            path: Some((
+                root.to_owned(),
+                root.join(CACHE_DIR).join(format!("{}.json", name)),
+            )),
+            hash: format!("{:016x}", hasher.finish()),
+        }
+    }
+
+    /// a cache which never hits
+    pub fn disabled() -> Self {
+        Self {
+            path: None,
+            hash: String::new(),
+        }
+    }
+
+    /// get the cached schema if the sql is unchanged since it was cached
+    pub async fn get(&self) -> Option<DatabaseSchema> {
+        let (_, path) = self.path.as_ref()?;
+        let content = fs::read_to_string(path).await.ok()?;
+        match serde_json::from_str::<CacheEntry>(&content) {
+            Ok(entry) if entry.hash == self.hash => {
+                debug!(path = %path.display(), "schema cache hit");
+                Some(entry.schema)
+            }
+            Ok(_) => None,
+            Err(e) => {
+                warn!("ignore broken schema cache {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// cache the schema, failures are only logged since the cache is optional
+    pub async fn put(&self, schema: &DatabaseSchema) {
+        if let Err(e) = self.try_put(schema).await {
+            warn!("failed to write schema cache: {:#}", e);
+        }
+    }
+
+    async fn try_put(&self, schema: &DatabaseSchema) -> Result<()> {
+        let (root, path) = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        // along with the .gitignore which keeps the cache out of git
+        state_dir(root)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let entry = CacheEntry {
+            hash: self.hash.clone(),
+            schema: schema.clone(),
+        };
+        fs::write(path, serde_json::to_string(&entry)?).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SqlLoader;
+
+    #[tokio::test]
+    async fn schema_cache_should_only_hit_for_same_sql() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let sql = "CREATE TABLE public.todos (id uuid, title text);";
+        let schema = SqlLoader::new(sql).parse()?;
+
+        let cache = SchemaCache::new(dir.path(), "local", sql, &[]);
+        assert!(cache.get().await.is_none());
+        cache.put(&schema).await;
+        assert_eq!(
+            cache.get().await.map(|s| s.sql(true)),
+            Some(schema.sql(true))
+        );
+
+        let changed = SchemaCache::new(
+            dir.path(),
+            "local",
+            "CREATE TABLE public.todos (id uuid);",
+            &[],
+        );
+        assert!(changed.get().await.is_none());
+        assert!(SchemaCache::disabled().get().await.is_none());
+        assert!(dir.path().join(".renovate/.gitignore").exists());
+        Ok(())
+    }
+}
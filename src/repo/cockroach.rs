@@ -0,0 +1,381 @@
+use anyhow::{bail, Context, Result};
+use pg_query::{protobuf::String as PgString, Node, NodeEnum};
+use sqlx::{Connection, PgConnection};
+use tracing::warn;
+
+/// Retrieve the schema of a CockroachDB database, converted to the postgres dialect.
+pub(crate) async fn show_create_all(url: &str) -> Result<String> {
+    let mut conn = PgConnection::connect(url).await?;
+    let mut statements = Vec::new();
+    for query in ["SHOW CREATE ALL TYPES", "SHOW CREATE ALL TABLES"] {
+        let rows: Vec<(String,)> = sqlx::query_as(query).fetch_all(&mut conn).await?;
+        statements.extend(rows.into_iter().map(|(stmt,)| stmt));
+    }
+    to_postgres_ddl(&statements)
+}
+
+/// Convert the DDL generated by CockroachDB, one statement each, to what the postgres parser
+/// understands: inline indexes become `CREATE INDEX` statements, column families and hidden
+/// columns (e.g. the implicit `rowid`) are dropped, and the cockroach types are mapped to the
+/// postgres ones. The table is split into its elements by the tokens of the postgres scanner, so
+/// that a comma or a parenthesis in a default or a check doesn't split it, and the options of the
+/// table and the indexes are rewritten on the parse tree. The table options postgres can't
+/// parse, e.g. `LOCALITY`, are left out with a warning.
+pub(crate) fn to_postgres_ddl(statements: &[String]) -> Result<String> {
+    let mut output = String::new();
+    for statement in statements {
+        let statement = statement.trim().trim_end_matches(';');
+        for sql in convert_statement(statement)
+            .with_context(|| format!("Failed to convert the CockroachDB DDL: {}", statement))?
+        {
+            output.push_str(&sql);
+            output.push_str(";\n");
+        }
+    }
+    Ok(output)
+}
+
+/// cockroach types without a postgres name, as (cockroach, postgres, postgres with a length)
+const TYPES: &[(&str, &str, &str)] = &[("string", "text", "varchar"), ("bytes", "bytea", "bytea")];
+
+/// A token of the postgres scanner, which splits the cockroach DDL as well
+struct Token<'a> {
+    start: usize,
+    end: usize,
+    text: &'a str,
+}
+
+impl Token<'_> {
+    fn is(&self, keyword: &str) -> bool {
+        self.text.eq_ignore_ascii_case(keyword)
+    }
+}
+
+fn tokens(sql: &str) -> Result<Vec<Token>> {
+    let scanned = pg_query::scan(sql)?;
+    Ok(scanned
+        .tokens
+        .iter()
+        .map(|t| {
+            let (start, end) = (t.start as usize, t.end as usize);
+            Token {
+                start,
+                end,
+                text: &sql[start..end],
+            }
+        })
+        .collect())
+}
+
+fn convert_statement(sql: &str) -> Result<Vec<String>> {
+    let tokens = tokens(sql)?;
+    if !(tokens.len() > 2 && tokens[0].is("CREATE") && tokens[1].is("TABLE")) {
+        return Ok(vec![sql.to_owned()]);
+    }
+    let open = match tokens.iter().position(|t| t.text == "(") {
+        Some(open) => open,
+        None => return Ok(vec![sql.to_owned()]),
+    };
+    let table = sql[tokens[1].end..tokens[open].start].trim();
+
+    // the elements of the table, split at the commas outside of any parenthesis
+    let mut elements = vec![vec![]];
+    let mut depth = 0;
+    let mut close = None;
+    for (i, token) in tokens.iter().enumerate().skip(open + 1) {
+        match token.text {
+            "(" => depth += 1,
+            ")" if depth == 0 => {
+                close = Some(i);
+                break;
+            }
+            ")" => depth -= 1,
+            "," if depth == 0 => {
+                elements.push(vec![]);
+                continue;
+            }
+            _ => {}
+        }
+        if let Some(element) = elements.last_mut() {
+            element.push(token);
+        }
+    }
+    let close = match close {
+        Some(close) => close,
+        None => bail!("unbalanced parentheses"),
+    };
+
+    let mut columns = Vec::new();
+    let mut indexes = Vec::new();
+    for element in elements.iter().filter(|e| !e.is_empty()) {
+        let text = |tokens: &[&Token]| sql[tokens[0].start..tokens[tokens.len() - 1].end].trim();
+        match (
+            element[0].text.to_ascii_uppercase().as_str(),
+            element.get(1),
+        ) {
+            ("FAMILY", _) => {}
+            ("INDEX", _) => indexes.push(inline_index(sql, table, &element[1..], "", false)?),
+            ("UNIQUE" | "INVERTED", Some(next)) if next.is("INDEX") => {
+                let unique = element[0].is("UNIQUE");
+                let prefix = if unique { "UNIQUE " } else { "" };
+                indexes.push(inline_index(sql, table, &element[2..], prefix, !unique)?);
+            }
+            // the hidden columns, e.g. `rowid`
+            _ if element
+                .windows(2)
+                .any(|w| w[0].is("NOT") && w[1].is("VISIBLE")) => {}
+            _ if element
+                .windows(2)
+                .any(|w| w[0].is("PRIMARY") && w[1].is("KEY")) =>
+            {
+                columns.push(primary_key(element))
+            }
+            _ => columns
+                .push(computed_column(sql, element).unwrap_or_else(|| text(element).to_owned())),
+        }
+    }
+
+    let options = sql[tokens[close].end..].trim();
+    let create = |options: &str| {
+        format!(
+            "CREATE TABLE {} (\n    {}\n) {}",
+            table,
+            columns.join(",\n    "),
+            options
+        )
+    };
+    let mut stmt = match parse_statement(&create(options)) {
+        Ok(stmt) => stmt,
+        Err(_) if !options.is_empty() => {
+            warn!(
+                table,
+                options, "the table options are not supported by postgres, skipped"
+            );
+            parse_statement(&create(""))?
+        }
+        Err(e) => return Err(e),
+    };
+    if let NodeEnum::CreateStmt(create) = &mut stmt {
+        for element in create.table_elts.iter_mut() {
+            if let Some(NodeEnum::ColumnDef(column)) = element.node.as_mut() {
+                if let Some(type_name) = column.type_name.as_mut() {
+                    map_type(type_name);
+                }
+            }
+        }
+    }
+
+    let mut statements = vec![stmt.deparse()?];
+    statements.extend(indexes);
+    Ok(statements)
+}
+
+/// `CREATE INDEX` of an inline index of the table, from the tokens after `INDEX`: the name, the
+/// columns and the options. `STORING` becomes `INCLUDE`, and the visibility and the hash
+/// sharding of cockroach are left out.
+fn inline_index(
+    sql: &str,
+    table: &str,
+    tokens: &[&Token],
+    prefix: &str,
+    inverted: bool,
+) -> Result<String> {
+    let (name, rest) = match tokens.split_first() {
+        Some((name, rest)) if !rest.is_empty() => (name.text, rest),
+        _ => bail!("invalid inline index of {}", table),
+    };
+    let mut definition = Vec::new();
+    let mut depth = 0;
+    let mut skip_depth = None;
+    let mut i = 0;
+    while i < rest.len() {
+        let token = rest[i];
+        match token.text {
+            "(" => depth += 1,
+            ")" => depth -= 1,
+            _ => {}
+        }
+        if let Some(d) = skip_depth {
+            // the `WITH (bucket_count = ...)` of a hash sharded index
+            if depth <= d && token.text == ")" {
+                skip_depth = None;
+            }
+            i += 1;
+            continue;
+        }
+        let next = rest.get(i + 1);
+        if depth == 0 && token.is("USING") && next.map_or(false, |t| t.is("HASH")) {
+            i += 2;
+            if rest.get(i).map_or(false, |t| t.is("WITH")) {
+                skip_depth = Some(depth);
+                i += 1;
+            }
+            continue;
+        }
+        if depth == 0 && token.is("NOT") && next.map_or(false, |t| t.is("VISIBLE")) {
+            i += 2;
+            continue;
+        }
+        if depth == 0 && token.is("VISIBLE") {
+            i += 1;
+            continue;
+        }
+        if depth == 0 && token.is("STORING") {
+            definition.push("INCLUDE");
+        } else {
+            definition.push(&sql[token.start..token.end]);
+        }
+        i += 1;
+    }
+
+    let mut stmt = parse_statement(&format!(
+        "CREATE {}INDEX {} ON {} {}",
+        prefix,
+        name,
+        table,
+        definition.join(" ")
+    ))?;
+    if let NodeEnum::IndexStmt(index) = &mut stmt {
+        if inverted {
+            index.access_method = "gin".to_owned();
+        }
+    }
+    Ok(stmt.deparse()?)
+}
+
+/// The primary key constraint without the sort order of its columns, e.g. `PRIMARY KEY (id ASC)`,
+/// which postgres only has for indexes
+fn primary_key(tokens: &[&Token]) -> String {
+    let mut depth = 0;
+    let mut words = Vec::new();
+    for token in tokens {
+        match token.text {
+            "(" => depth += 1,
+            ")" => depth -= 1,
+            _ if depth > 0 && (token.is("ASC") || token.is("DESC")) => continue,
+            _ => {}
+        }
+        words.push(token.text);
+    }
+    words.join(" ")
+}
+
+/// The computed column `name type AS (expr) STORED` of cockroach in the postgres syntax, None if
+/// the column is not computed
+fn computed_column(sql: &str, tokens: &[&Token]) -> Option<String> {
+    let i = tokens.iter().position(|t| t.is("AS"))?;
+    let generated = i > 0 && tokens[i - 1].is("ALWAYS");
+    if generated || tokens.get(i + 1).map_or(true, |t| t.text != "(") {
+        return None;
+    }
+    let start = tokens[0].start;
+    let end = tokens[tokens.len() - 1].end;
+    let at = tokens[i].start;
+    Some(format!(
+        "{} GENERATED ALWAYS {}",
+        sql[start..at].trim_end(),
+        &sql[at..end]
+    ))
+}
+
+fn map_type(type_name: &mut pg_query::protobuf::TypeName) {
+    let name = match type_name.names.as_slice() {
+        [Node {
+            node: Some(NodeEnum::String(s)),
+        }] => s.str.clone(),
+        _ => return,
+    };
+    if let Some((_, plain, sized)) = TYPES.iter().find(|(crdb, _, _)| *crdb == name) {
+        let postgres = if type_name.typmods.is_empty() {
+            plain
+        } else {
+            sized
+        };
+        type_name.names = vec![Node {
+            node: Some(NodeEnum::String(PgString {
+                str: (*postgres).to_owned(),
+            })),
+        }];
+    }
+}
+
+/// the single statement of the sql
+fn parse_statement(sql: &str) -> Result<NodeEnum> {
+    let parsed = pg_query::parse(sql)?;
+    match parsed.protobuf.nodes().first() {
+        Some((node, 0, _)) if parsed.protobuf.stmts.len() == 1 => Ok(node.to_enum()),
+        _ => bail!("expected a single statement: {}", sql),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SqlLoader;
+
+    #[test]
+    fn cockroach_ddl_should_be_converted_to_postgres() -> Result<()> {
+        let sql = r#"CREATE TABLE public.users (
+	id UUID NOT NULL DEFAULT gen_random_uuid(),
+	name STRING NULL,
+	email STRING NOT NULL,
+	rowid INT8 NOT VISIBLE NOT NULL DEFAULT unique_rowid(),
+	CONSTRAINT users_pkey PRIMARY KEY (id ASC),
+	UNIQUE INDEX users_email_key (email ASC),
+	INDEX users_name_idx (name ASC) STORING (email),
+	FAMILY "primary" (id, name, email, rowid)
+);
+"#;
+        let ddl = to_postgres_ddl(&[sql.to_owned()])?;
+        assert!(ddl.contains("CREATE UNIQUE INDEX users_email_key ON public.users"));
+        assert!(ddl.contains("INCLUDE (email)"));
+        assert!(!ddl.contains("rowid"));
+        assert!(!ddl.contains("FAMILY"));
+
+        let schema = SqlLoader::new(ddl).parse()?;
+        let table = &schema.tables["public"]["users"];
+        assert_eq!(table.columns.len(), 3);
+        assert_eq!(
+            schema
+                .table_indexes
+                .values()
+                .map(|v| v.len())
+                .sum::<usize>(),
+            2
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cockroach_options_should_be_kept() -> Result<()> {
+        let sql = r#"CREATE TABLE public.events (
+	id INT8 NOT NULL,
+	payload JSONB NULL,
+	total DECIMAL(10,2) NULL DEFAULT 0,
+	doubled INT8 NULL AS (id * 2) STORED,
+	CONSTRAINT events_pkey PRIMARY KEY (id ASC),
+	INVERTED INDEX events_payload_idx (payload),
+	INDEX events_total_idx (total ASC) USING HASH WITH (bucket_count = 8) WHERE total > 0,
+	CONSTRAINT check_total CHECK (total >= 0)
+) WITH (ttl = 'on', ttl_expire_after = '1 day')"#;
+        let ddl = to_postgres_ddl(&[sql.to_owned()])?;
+        assert!(ddl.contains("ttl_expire_after"), "{}", ddl);
+        assert!(
+            ddl.contains("GENERATED ALWAYS AS (id * 2) STORED"),
+            "{}",
+            ddl
+        );
+        assert!(ddl.contains("USING gin (payload)"), "{}", ddl);
+        assert!(ddl.contains("WHERE total > 0"), "{}", ddl);
+        assert!(!ddl.contains("bucket_count"), "{}", ddl);
+        let schema = SqlLoader::new(&ddl).parse()?;
+        assert_eq!(schema.tables["public"]["events"].columns.len(), 4);
+
+        // unknown to postgres, skipped rather than failing the whole schema
+        let sql = "CREATE TABLE public.regional (id INT8 NOT NULL, name STRING(32)) LOCALITY REGIONAL BY ROW";
+        let ddl = to_postgres_ddl(&[sql.to_owned()])?;
+        assert!(!ddl.contains("LOCALITY"), "{}", ddl);
+        assert!(ddl.contains("varchar(32)"), "{}", ddl);
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+use crate::{
+    parser::{utils::node_to_string, ConstraintInfo, SchemaId, Table},
+    DatabaseSchema, TableStats,
+};
+use itertools::Itertools;
+use pg_query::{protobuf::ConstrType, NodeEnum};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+impl DatabaseSchema {
+    /// Render the tables, columns, enums and foreign keys as [DBML](https://dbml.dbdiagram.io),
+    /// e.g. to draw the schema in dbdiagram.io.
+    pub fn to_dbml(&self) -> String {
+        self.to_dbml_with(&BTreeMap::new())
+    }
+
+    /// Same as `to_dbml`, with the large tables of the stats highlighted and noted with their
+    /// size, see `Renovate::table_stats`
+    pub fn to_dbml_with(&self, stats: &BTreeMap<String, TableStats>) -> String {
+        let mut keys: BTreeMap<SchemaId, Vec<Key>> = BTreeMap::new();
+        for table in self.tables.values().flat_map(|t| t.values()) {
+            let table_keys = keys.entry(table.id.clone()).or_default();
+            for column in table.columns.values() {
+                for info in &column.constraints {
+                    table_keys.extend(Key::new(info, Some(&column.id.name)));
+                }
+            }
+            for info in table.constraints.values() {
+                table_keys.extend(Key::new(info, None));
+            }
+        }
+        for constraint in self.table_constraints.values().flat_map(|c| c.values()) {
+            if let Some(key) = Key::new(&constraint.info, None) {
+                let id = &constraint.id.schema_id;
+                keys.entry(id.clone()).or_default().push(key);
+            }
+        }
+
+        let mut dbml = String::new();
+        for item in self.enum_types.values().flat_map(|e| e.values()) {
+            let _ = writeln!(dbml, "Enum {} {{", item.id);
+            for value in &item.items {
+                let _ = writeln!(dbml, "  \"{}\"", value);
+            }
+            dbml.push_str("}\n\n");
+        }
+
+        let mut refs = Vec::new();
+        for table in self.tables.values().flat_map(|t| t.values()) {
+            let table_keys = keys.get(&table.id).map(|k| k.as_slice()).unwrap_or(&[]);
+            let size = stats
+                .get(&table.id.to_string())
+                .filter(|size| size.is_large());
+            render_table(table, table_keys, size, &mut dbml);
+            for key in table_keys {
+                if let Key::Foreign(columns, target, target_columns) = key {
+                    // `REFERENCES users` without columns refers to the primary key
+                    let target_columns = if target_columns.is_empty() {
+                        primary_key(&keys, target)
+                    } else {
+                        target_columns.clone()
+                    };
+                    refs.push(format!(
+                        "Ref: {}.{} > {}.{}",
+                        table.id,
+                        column_list(columns),
+                        target,
+                        column_list(&target_columns)
+                    ));
+                }
+            }
+        }
+
+        for r in refs {
+            dbml.push_str(&r);
+            dbml.push('\n');
+        }
+        dbml
+    }
+}
+
+/// the relational constraints of a table, i.e. the ones drawn in the diagram
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Key {
+    Primary(Vec<String>),
+    Unique(Vec<String>),
+    Foreign(Vec<String>, SchemaId, Vec<String>),
+}
+
+impl Key {
+    /// `column` is set for the constraints defined inline in a column
+    fn new(info: &ConstraintInfo, column: Option<&str>) -> Option<Self> {
+        let constraint = match info.node.as_ref() {
+            NodeEnum::Constraint(constraint) => constraint,
+            _ => return None,
+        };
+        let names = |nodes: &[pg_query::Node]| -> Vec<String> {
+            match column {
+                Some(column) if nodes.is_empty() => vec![column.to_owned()],
+                _ => nodes.iter().filter_map(node_to_string).collect(),
+            }
+        };
+        match info.con_type {
+            ConstrType::ConstrPrimary => Some(Self::Primary(names(&constraint.keys))),
+            ConstrType::ConstrUnique => Some(Self::Unique(names(&constraint.keys))),
+            ConstrType::ConstrForeign => {
+                let target: SchemaId = constraint.pktable.as_ref()?.into();
+                let columns = names(&constraint.fk_attrs);
+                let target_columns = constraint.pk_attrs.iter().filter_map(node_to_string);
+                Some(Self::Foreign(columns, target, target_columns.collect()))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn primary_key(keys: &BTreeMap<SchemaId, Vec<Key>>, id: &SchemaId) -> Vec<String> {
+    keys.get(id)
+        .and_then(|keys| {
+            keys.iter().find_map(|k| match k {
+                Key::Primary(columns) => Some(columns.clone()),
+                _ => None,
+            })
+        })
+        .unwrap_or_default()
+}
+
+/// the header color of the large tables, see `TableStats::is_large`
+const LARGE_TABLE_COLOR: &str = "#E8590C";
+
+fn render_table(table: &Table, keys: &[Key], large: Option<&TableStats>, dbml: &mut String) {
+    let pk = single_column_keys(keys, |k| matches!(k, Key::Primary(_)));
+    let unique = single_column_keys(keys, |k| matches!(k, Key::Unique(_)));
+
+    let _ = match large {
+        Some(_) => writeln!(
+            dbml,
+            "Table {} [headercolor: {}] {{",
+            table.id, LARGE_TABLE_COLOR
+        ),
+        None => writeln!(dbml, "Table {} {{", table.id),
+    };
+    for (name, column) in &table.columns {
+        let mut settings = Vec::new();
+        if pk.contains(name.as_str()) {
+            settings.push("pk".to_owned());
+        }
+        if unique.contains(name.as_str()) {
+            settings.push("unique".to_owned());
+        }
+        if !column.nullable {
+            settings.push("not null".to_owned());
+        }
+        if let Some(default) = &column.default {
+            let expr = default.to_string();
+            let expr = expr.strip_prefix("DEFAULT ").unwrap_or(&expr);
+            settings.push(format!("default: `{}`", expr));
+        }
+
+        let _ = write!(dbml, "  {} {}", name, dbml_type(&column.type_name));
+        if !settings.is_empty() {
+            let _ = write!(dbml, " [{}]", settings.join(", "));
+        }
+        dbml.push('\n');
+    }
+
+    let composite: Vec<_> = keys
+        .iter()
+        .filter_map(|k| match k {
+            Key::Primary(columns) if columns.len() > 1 => Some((columns, "pk")),
+            Key::Unique(columns) if columns.len() > 1 => Some((columns, "unique")),
+            _ => None,
+        })
+        .collect();
+    if !composite.is_empty() {
+        dbml.push_str("\n  Indexes {\n");
+        for (columns, setting) in composite {
+            let _ = writeln!(dbml, "    ({}) [{}]", columns.join(", "), setting);
+        }
+        dbml.push_str("  }\n");
+    }
+    if let Some(size) = large {
+        let _ = writeln!(dbml, "\n  Note: 'large table, {}'", size);
+    }
+    dbml.push_str("}\n\n");
+}
+
+fn single_column_keys(keys: &[Key], f: impl Fn(&Key) -> bool) -> BTreeSet<&str> {
+    keys.iter()
+        .filter(|k| f(k))
+        .filter_map(|k| match k {
+            Key::Primary(columns) | Key::Unique(columns) if columns.len() == 1 => {
+                Some(columns[0].as_str())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// drop the `pg_catalog.` prefix for readability, and quote the types dbml can't parse as is
+fn dbml_type(type_name: &str) -> String {
+    let name = type_name.strip_prefix("pg_catalog.").unwrap_or(type_name);
+    if name.contains([' ', '[', '"']) {
+        format!("\"{}\"", name.replace('"', "\\\""))
+    } else {
+        name.to_owned()
+    }
+}
+
+fn column_list(columns: &[String]) -> String {
+    match columns {
+        [column] => column.clone(),
+        _ => format!("({})", columns.iter().join(", ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::SqlLoader;
+
+    #[test]
+    fn schema_should_render_dbml() -> anyhow::Result<()> {
+        let schema = SqlLoader::new(
+            r#"
+            CREATE TYPE public.status AS ENUM ('active', 'archived');
+            CREATE TABLE public.users (id bigint PRIMARY KEY, email text NOT NULL UNIQUE);
+            CREATE TABLE public.todos (
+                id bigint NOT NULL,
+                user_id bigint REFERENCES public.users (id),
+                owner_id bigint REFERENCES public.users,
+                title varchar(256) DEFAULT 'untitled',
+                tags text[],
+                status public.status
+            );
+            ALTER TABLE ONLY public.todos ADD CONSTRAINT todos_pkey PRIMARY KEY (id);
+            "#,
+        )
+        .parse()?;
+        let dbml = schema.to_dbml();
+        assert!(dbml.contains("Enum public.status {\n  \"active\"\n  \"archived\"\n}"));
+        assert!(dbml.contains("  id int8 [pk]\n"));
+        assert!(dbml.contains("  email text [unique, not null]\n"));
+        assert!(dbml.contains("  id int8 [pk, not null]\n"));
+        assert!(dbml.contains("  title varchar(256) [default: `'untitled'`]\n"));
+        assert!(dbml.contains("  tags \"text[]\"\n"));
+        assert!(dbml.contains("Ref: public.todos.user_id > public.users.id\n"));
+        assert!(dbml.contains("Ref: public.todos.owner_id > public.users.id\n"));
+        Ok(())
+    }
+}
@@ -1,6 +1,11 @@
 #![allow(clippy::unwrap_used)]
-use git2::{Error, IndexAddOption, Object, ObjectType, Oid, Repository, Signature};
+use super::state::STATE_DIR;
+use git2::{
+    BranchType, Error, IndexAddOption, Object, ObjectType, Oid, Repository, Signature,
+    StatusOptions, TreeWalkMode, TreeWalkResult,
+};
 use std::{
+    collections::BTreeMap,
     env, fmt, fs,
     path::{Path, PathBuf},
     sync::Arc,
@@ -15,7 +20,7 @@ pub enum BumpVersion {
     Patch,
 }
 
-const IGNORE_RULES: &str = "dist\nnode_modules\n";
+const IGNORE_RULES: &str = "dist\nnode_modules\n.renovate\n";
 
 impl GitRepo {
     pub fn init(path: impl AsRef<Path>) -> Result<Self, Error> {
@@ -64,9 +69,37 @@ impl GitRepo {
         filtered.peek().is_some()
     }
 
+    /// Whether there're changes under `dir`, so that unrelated changes elsewhere in a monorepo
+    /// are ignored
+    pub fn is_dirty_under(&self, dir: impl AsRef<Path>) -> Result<bool, Error> {
+        let mut opts = StatusOptions::new();
+        opts.include_untracked(true);
+        opts.pathspec(self.pathspec(dir.as_ref())?);
+        let statuses = self.0.statuses(Some(&mut opts))?;
+        let dirty = statuses.iter().any(|s| {
+            !s.status().is_ignored() && !s.path().map_or(false, |p| is_state(Path::new(p)))
+        });
+        Ok(dirty)
+    }
+
     pub fn commit(&self, message: impl AsRef<str>) -> Result<Oid, Error> {
+        self.commit_under(message, self.get_root_path())
+    }
+
+    /// Commit the changes under `dir` only, leaving the state directories of renovate out
+    pub fn commit_under(
+        &self,
+        message: impl AsRef<str>,
+        dir: impl AsRef<Path>,
+    ) -> Result<Oid, Error> {
         let mut index = self.0.index()?;
-        index.add_all(["*"].iter(), IndexAddOption::DEFAULT, None)?;
+        let spec = self.pathspec(dir.as_ref())?;
+        let mut skip_state = |path: &Path, _: &[u8]| i32::from(is_state(path));
+        index.add_all(
+            [spec].iter(),
+            IndexAddOption::DEFAULT,
+            Some(&mut skip_state),
+        )?;
         let oid = index.write_tree()?;
         index.write()?;
         let sig = Signature::now("Bot", "bot@renovate.tools")?;
@@ -100,6 +133,51 @@ impl GitRepo {
         Ok(sid)
     }
 
+    /// the full id of HEAD
+    pub fn head_id(&self) -> Result<String, Error> {
+        Ok(self.find_last_commit()?.id().to_string())
+    }
+
+    /// The files under `dir` at the given commit, keyed by their path relative to `dir`. The
+    /// working tree is untouched.
+    pub fn files_under_at(
+        &self,
+        commit: &str,
+        dir: impl AsRef<Path>,
+    ) -> Result<BTreeMap<PathBuf, String>, Error> {
+        let tree = self.0.revparse_single(commit)?.peel_to_commit()?.tree()?;
+        let tree = match self.pathspec(dir.as_ref())?.as_str() {
+            "*" => tree,
+            spec => tree
+                .get_path(Path::new(spec))?
+                .to_object(&self.0)?
+                .peel_to_tree()?,
+        };
+
+        let mut files = BTreeMap::new();
+        tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+            if entry.kind() == Some(ObjectType::Blob) {
+                if let Ok(blob) = entry.to_object(&self.0).and_then(|o| o.peel_to_blob()) {
+                    let path = Path::new(root).join(entry.name().unwrap_or_default());
+                    files.insert(path, String::from_utf8_lossy(blob.content()).into_owned());
+                }
+            }
+            TreeWalkResult::Ok
+        })?;
+        Ok(files)
+    }
+
+    /// Create a branch at HEAD and switch to it. The working tree and the index are untouched.
+    pub fn create_branch(&self, name: &str) -> Result<(), Error> {
+        let head = self.find_last_commit()?.peel_to_commit()?;
+        let branch = self.0.branch(name, &head, false)?;
+        self.0.set_head(branch.get().name().unwrap())
+    }
+
+    pub fn has_branch(&self, name: &str) -> bool {
+        self.0.find_branch(name, BranchType::Local).is_ok()
+    }
+
     pub fn checkout(&self, refname: &str) -> Result<String, Error> {
         let old_ref = self.0.head()?.shorthand().unwrap().to_owned();
         let (object, reference) = self.0.revparse_ext(refname)?;
@@ -115,6 +193,34 @@ impl GitRepo {
         Ok(old_ref)
     }
 
+    /// the pathspec of `dir` relative to the workdir, or `*` if it is the whole repo. A dir which
+    /// can't be resolved within the workdir is an error rather than the whole repo, so that a
+    /// commit never sweeps up the files outside of the schema dir.
+    fn pathspec(&self, dir: &Path) -> Result<String, Error> {
+        let root = self
+            .0
+            .workdir()
+            .ok_or_else(|| Error::from_str("the repo has no working directory"))?;
+        let root = root
+            .canonicalize()
+            .map_err(|e| Error::from_str(&format!("cannot resolve {}: {}", root.display(), e)))?;
+        let resolved = dir
+            .canonicalize()
+            .map_err(|e| Error::from_str(&format!("cannot resolve {}: {}", dir.display(), e)))?;
+        let relative = resolved.strip_prefix(&root).map_err(|_| {
+            Error::from_str(&format!(
+                "{} is outside of the repo at {}",
+                dir.display(),
+                root.display()
+            ))
+        })?;
+        if relative.as_os_str().is_empty() {
+            Ok("*".to_owned())
+        } else {
+            Ok(relative.to_string_lossy().into_owned())
+        }
+    }
+
     pub fn find_last_commit(&self) -> Result<Object, Error> {
         self.0.head()?.resolve()?.peel(ObjectType::Commit)
     }
@@ -153,6 +259,12 @@ impl GitRepo {
     }
 }
 
+/// whether the path is under a state directory of renovate, e.g. `.renovate/cache`, which is
+/// about the checkout and so neither committed nor counted as a change
+fn is_state(path: &Path) -> bool {
+    path.components().any(|c| c.as_os_str() == STATE_DIR)
+}
+
 impl fmt::Debug for GitRepo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "GitRepo({:?})", self.get_root_path())
@@ -183,4 +295,114 @@ mod tests {
         assert_eq!(old_ref, "master");
         repo.checkout(&old_ref).unwrap();
     }
+
+    #[tokio::test]
+    async fn nested_schema_dir_should_ignore_other_changes() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let schema = root.join("services/db/schema");
+        fs::create_dir_all(&schema).await.unwrap();
+        let repo = GitRepo::init(root).unwrap();
+        fs::write(root.join("README.md"), "monorepo").await.unwrap();
+        fs::write(schema.join("all.sql"), "CREATE TABLE foo (id int);")
+            .await
+            .unwrap();
+        assert!(repo.is_dirty_under(&schema).unwrap());
+
+        repo.commit_under("schema", &schema).unwrap();
+        assert!(!repo.is_dirty_under(&schema).unwrap());
+        // the change outside of the schema dir is neither committed nor counted
+        assert!(repo.is_dirty());
+    }
+
+    #[tokio::test]
+    async fn dir_outside_of_the_repo_should_not_be_committed() {
+        let root = tempfile::tempdir().unwrap();
+        let other = tempfile::tempdir().unwrap();
+        let repo = GitRepo::init(root.path()).unwrap();
+        fs::write(root.path().join("README.md"), "unrelated")
+            .await
+            .unwrap();
+
+        assert!(repo.commit_under("schema", other.path()).is_err());
+        assert!(repo
+            .commit_under("schema", root.path().join("missing"))
+            .is_err());
+        assert!(repo.find_last_commit().is_err());
+    }
+
+    #[tokio::test]
+    async fn state_dir_should_not_be_dirty() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let repo = GitRepo::init(root).unwrap();
+        // a repo not initialized by renovate, which doesn't ignore the state dir
+        fs::remove_file(root.join(".gitignore")).await.unwrap();
+        fs::write(root.join("all.sql"), "CREATE TABLE foo (id int);")
+            .await
+            .unwrap();
+        repo.commit_under("schema", root).unwrap();
+
+        // e.g. the cache written before its .gitignore
+        fs::create_dir_all(root.join(".renovate/cache"))
+            .await
+            .unwrap();
+        fs::write(root.join(".renovate/cache/local.json"), "{}")
+            .await
+            .unwrap();
+        assert!(!repo.is_dirty_under(root).unwrap());
+    }
+
+    #[tokio::test]
+    async fn files_at_commit_should_be_read_without_checkout() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let schema = root.join("db");
+        fs::create_dir_all(schema.join("public")).await.unwrap();
+        let repo = GitRepo::init(root).unwrap();
+        fs::write(
+            schema.join("public/04_tables.sql"),
+            "CREATE TABLE foo (id int);",
+        )
+        .await
+        .unwrap();
+        repo.commit("init").unwrap();
+        let commit = repo.head_id().unwrap();
+
+        fs::write(
+            schema.join("public/04_tables.sql"),
+            "CREATE TABLE foo (id bigint);",
+        )
+        .await
+        .unwrap();
+        repo.commit("bigint").unwrap();
+
+        let files = repo.files_under_at(&commit, &schema).unwrap();
+        assert_eq!(
+            files.get(Path::new("public/04_tables.sql")).unwrap(),
+            "CREATE TABLE foo (id int);"
+        );
+        assert_eq!(files.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn branch_should_keep_working_tree_changes() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let repo = GitRepo::init(root).unwrap();
+        fs::write(root.join("all.sql"), "CREATE TABLE foo (id int);")
+            .await
+            .unwrap();
+        repo.commit("init").unwrap();
+
+        fs::write(root.join("all.sql"), "CREATE TABLE foo (id bigint);")
+            .await
+            .unwrap();
+        repo.create_branch("schema-sync/2023-01-01").unwrap();
+        repo.commit("sync").unwrap();
+        let head = repo.checkout("master").unwrap();
+        assert_eq!(head, "schema-sync/2023-01-01");
+        let content = fs::read_to_string(root.join("all.sql")).await.unwrap();
+        assert_eq!(content, "CREATE TABLE foo (id int);");
+    }
 }
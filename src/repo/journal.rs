@@ -0,0 +1,161 @@
+use super::state::{state_dir, STATE_DIR};
+use crate::{ChangeKind, FileChange};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+/// the moves of a save in progress, under the local repo. Once it's written, the save is as good
+/// as done: whoever finds it, the save itself or the next load, moves the staged files in place.
+const JOURNAL_FILE: &str = ".renovate/save.journal";
+
+/// prefix of the temporary workspaces the files are staged in, under the state directory which
+/// the loader skips, and on the same file system as the files so that they could be renamed in
+/// place
+const WORKSPACE_PREFIX: &str = "workspace-";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Journal {
+    /// the name of the workspace under the state directory
+    workspace: String,
+    changes: Vec<JournalEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalEntry {
+    path: PathBuf,
+    removed: bool,
+}
+
+/// A workspace to stage the files of a save in, removed when dropped unless the save is
+/// committed to the journal by then.
+struct Workspace {
+    dir: PathBuf,
+    committed: bool,
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+/// Apply the changes to the files of the local repo at `path`. The new files are staged first,
+/// then the moves are recorded in the journal and carried out, so that a reader, which replays
+/// any journal left behind before listing the files, sees either the old files or the new ones.
+pub(crate) async fn apply_changes(path: &Path, changes: &[FileChange]) -> Result<()> {
+    let name = format!("{}{}", WORKSPACE_PREFIX, uuid::Uuid::new_v4());
+    let mut workspace = Workspace {
+        dir: state_dir(path)?.join(&name),
+        committed: false,
+    };
+    for change in changes.iter().filter(|c| c.kind != ChangeKind::Removed) {
+        let file = workspace.dir.join(&change.path);
+        if let Some(parent) = file.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&file, &change.new)
+            .await
+            .with_context(|| format!("Failed to stage {}", change.path.display()))?;
+    }
+
+    let journal = Journal {
+        workspace: name,
+        changes: changes
+            .iter()
+            .map(|c| JournalEntry {
+                path: c.path.clone(),
+                removed: c.kind == ChangeKind::Removed,
+            })
+            .collect(),
+    };
+    let file = path.join(JOURNAL_FILE);
+    let tmp = file.with_extension("journal.tmp");
+    fs::write(&tmp, serde_json::to_string(&journal)?)?;
+    fs::rename(tmp, file)?;
+    workspace.committed = true;
+
+    replay_journal(path)
+}
+
+/// Carry out the save recorded in the journal of the local repo at `path`, if any. The moves
+/// already done by an interrupted replay are skipped.
+pub(crate) fn replay_journal(path: &Path) -> Result<()> {
+    let file = path.join(JOURNAL_FILE);
+    let journal: Journal = match fs::read_to_string(&file) {
+        Ok(content) => serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", file.display()))?,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let workspace = path.join(STATE_DIR).join(&journal.workspace);
+    for entry in &journal.changes {
+        let target = path.join(&entry.path);
+        if entry.removed {
+            match fs::remove_file(&target) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => continue,
+            }
+        }
+        let staged = workspace.join(&entry.path);
+        if !staged.exists() {
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&staged, &target)
+            .with_context(|| format!("Failed to move {} in place", target.display()))?;
+    }
+    fs::remove_file(&file)?;
+    let _ = fs::remove_dir_all(workspace);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn interrupted_save_should_be_completed_by_the_next_load() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("old.sql"), "CREATE TABLE old (id int);")?;
+
+        // staged and journaled, but killed before any file is moved
+        let workspace = state_dir(dir.path())?.join("workspace-1");
+        fs::create_dir_all(workspace.join("public"))?;
+        fs::write(
+            workspace.join("public/new.sql"),
+            "CREATE TABLE new (id int);",
+        )?;
+        let journal = Journal {
+            workspace: "workspace-1".to_owned(),
+            changes: vec![
+                JournalEntry {
+                    path: "old.sql".into(),
+                    removed: true,
+                },
+                JournalEntry {
+                    path: "public/new.sql".into(),
+                    removed: false,
+                },
+            ],
+        };
+        fs::write(
+            dir.path().join(JOURNAL_FILE),
+            serde_json::to_string(&journal)?,
+        )?;
+
+        let files = crate::LocalRepo::new(dir.path()).files()?;
+        assert_eq!(files, vec![dir.path().join("public/new.sql")]);
+        assert!(!dir.path().join(JOURNAL_FILE).exists());
+        assert!(!workspace.exists());
+        Ok(())
+    }
+}
@@ -1,21 +1,30 @@
+#[cfg(not(target_arch = "wasm32"))]
+use super::journal::replay_journal;
+use super::saver::CONFLICT_START;
 use crate::{
+    diagnostics::skipped_diagnostics,
     map_insert_relation, map_insert_schema,
     parser::{
-        AlterTable, AlterTableAction, CompositeType, EnumType, Function, MatView, Privilege,
-        Sequence, Table, TableConstraint, TableIndex, TableOwner, TablePolicy, TableRls,
-        TableSequence, Trigger, View,
+        AlterTable, AlterTableAction, CompositeType, CustomNode, EnumType, Extension, Function,
+        Hypertable, MatView, Privilege, Sequence, Table, TableConstraint, TableIndex, TableOwner,
+        TablePolicy, TableRls, TableSequence, Trigger, View,
     },
-    utils::ignore_file,
-    DatabaseRepo, DatabaseSchema, LocalRepo, SchemaLoader, SqlLoader,
+    plan::{extract_data_migrations, DataMigration},
+    DatabaseSchema, Diagnostic, DiagnosticKind, MemoryStore, OverlayStore, SchemaLoader,
+    SchemaStore, SqlLoader,
 };
-use anyhow::{Context, Result};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{utils::ignore_file, LocalRepo, ObjectStore};
+use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
-use glob::glob;
-use pg_query::NodeRef;
+#[cfg(not(target_arch = "wasm32"))]
+use glob::{glob_with, MatchOptions};
+use pg_query::{protobuf::ObjectType, NodeEnum, NodeRef};
+#[cfg(not(target_arch = "wasm32"))]
 use std::path::PathBuf;
-use tokio::fs;
 use tracing::info;
 
+#[cfg(not(target_arch = "wasm32"))]
 #[async_trait]
 impl SchemaLoader for LocalRepo {
     async fn load(&self) -> Result<DatabaseSchema> {
@@ -24,31 +33,55 @@ impl SchemaLoader for LocalRepo {
     }
 
     async fn load_sql(&self) -> Result<String> {
-        let files = self.files()?;
-        // concatenate all the sql files into one string
-        let mut sql = String::with_capacity(16 * 1024);
-        for file in files {
-            let content = fs::read_to_string(file.as_path())
-                .await
-                .with_context(|| format!("Failed to read file: {:?}", file))?;
-            sql.push_str(&content);
-        }
+        load_store_sql(self).await
+    }
+}
 
-        // parse the sql to see if the syntax is correct
-        let ret = pg_query::parse(&sql)?;
-        let sql = ret.deparse()?;
-        Ok(sql)
+#[async_trait]
+impl SchemaLoader for MemoryStore {
+    async fn load(&self) -> Result<DatabaseSchema> {
+        let sql = self.load_sql().await?;
+        SqlLoader(sql).load().await
+    }
+
+    async fn load_sql(&self) -> Result<String> {
+        load_store_sql(self).await
     }
 }
 
 #[async_trait]
-impl SchemaLoader for DatabaseRepo {
-    /// run pg_dump us async process and get the output sql
-    async fn load(&self) -> anyhow::Result<crate::DatabaseSchema> {
+impl SchemaLoader for OverlayStore {
+    async fn load(&self) -> Result<DatabaseSchema> {
         let sql = self.load_sql().await?;
         SqlLoader(sql).load().await
     }
 
+    async fn load_sql(&self) -> Result<String> {
+        load_store_sql(self).await
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait]
+impl SchemaLoader for ObjectStore {
+    async fn load(&self) -> Result<DatabaseSchema> {
+        let sql = self.load_sql().await?;
+        SqlLoader(sql).load().await
+    }
+
+    async fn load_sql(&self) -> Result<String> {
+        load_store_sql(self).await
+    }
+}
+
+#[cfg(feature = "db")]
+#[async_trait]
+impl SchemaLoader for crate::DatabaseRepo {
+    /// run pg_dump us async process and get the output sql
+    async fn load(&self) -> anyhow::Result<crate::DatabaseSchema> {
+        Ok(self.load_with_diagnostics(false).await?.0)
+    }
+
     async fn load_sql(&self) -> anyhow::Result<String> {
         #[cfg(feature = "cli-test")]
         self.init_local_database().await?;
@@ -56,14 +89,72 @@ impl SchemaLoader for DatabaseRepo {
     }
 }
 
-#[async_trait]
-impl SchemaLoader for SqlLoader {
-    async fn load(&self) -> Result<DatabaseSchema> {
+impl SqlLoader {
+    /// Parse the sql to a DatabaseSchema without touching any io
+    pub fn parse(&self) -> Result<DatabaseSchema> {
+        self.parse_with(&mut Vec::new())
+    }
+
+    /// Same as `parse`, with the warnings about the statements skipped while parsing, which are
+    /// found in `source`, e.g. `pg_dump`. See `skipped_statements`.
+    pub(crate) fn parse_with_diagnostics(
+        &self,
+        source: &str,
+    ) -> Result<(DatabaseSchema, Vec<Diagnostic>)> {
+        let mut skipped = Vec::new();
+        let schema = self.parse_with(&mut skipped)?;
+        // the lines are only looked up if there is anything to report
+        let diagnostics = match skipped.is_empty() {
+            true => vec![],
+            false => skipped_diagnostics(source, self.skipped_statements()?),
+        };
+        Ok((schema, diagnostics))
+    }
+
+    /// The top level statements of the sql which are skipped while parsing, with their line: the
+    /// ones no NodeItem handles, e.g. `CREATE DOMAIN`, and the owners set on anything but the
+    /// tables and sequences. The session settings of pg_dump, the comments, the schemas and
+    /// extensions, and `ALTER SEQUENCE ... OWNED BY` are skipped silently. See `diff.strict`.
+    pub(crate) fn skipped_statements(&self) -> Result<Vec<(usize, DiagnosticKind, String)>> {
+        let sql = &self.0;
+        let parsed = pg_query::parse(sql).with_context(|| "Failed to parse SQL statements")?;
+        let mut result = Vec::new();
+        for stmt in &parsed.protobuf.stmts {
+            let start = stmt.stmt_location as usize;
+            let end = match stmt.stmt_len {
+                0 => sql.len(),
+                len => start + len as usize,
+            };
+            let mut skipped = Vec::new();
+            SqlLoader::new(&sql[start..end]).parse_with(&mut skipped)?;
+            let kind = match skipped.first() {
+                Some((kind, _)) => *kind,
+                None => continue,
+            };
+            // the statement starts after the blank lines and comments before it
+            let mut line = sql[..start].matches('\n').count() + 1;
+            let mut text = "";
+            for l in sql[start..end].lines() {
+                let trimmed = l.trim();
+                if !trimmed.is_empty() && !trimmed.starts_with("--") {
+                    text = trimmed;
+                    break;
+                }
+                line += 1;
+            }
+            result.push((line, kind, text.to_owned()));
+        }
+        Ok(result)
+    }
+
+    /// Same as `parse`, with the deparsed statements which are skipped collected in `skipped`,
+    /// except the ones skipped silently
+    fn parse_with(&self, skipped: &mut Vec<(DiagnosticKind, String)>) -> Result<DatabaseSchema> {
         let result = pg_query::parse(&self.0).with_context(|| "Failed to parse SQL statements")?;
         let nodes = result.protobuf.nodes();
         let mut data = DatabaseSchema::default();
 
-        for (node, _, _) in nodes {
+        for (node, depth, _) in nodes {
             match node {
                 NodeRef::CompositeTypeStmt(stmt) => {
                     let item: CompositeType = stmt.try_into()?;
@@ -114,6 +205,7 @@ impl SchemaLoader for SqlLoader {
                         }
                         _ => {
                             info!("ignore alter table action: {:?}", item.action);
+                            skipped.extend(unsupported(&node));
                         }
                     }
                 }
@@ -122,17 +214,25 @@ impl SchemaLoader for SqlLoader {
                     map_insert_relation!(data.table_indexes, item);
                 }
                 NodeRef::GrantStmt(grant) => {
-                    let item: Privilege = grant.try_into()?;
-                    data.privileges
-                        .entry(item.id.clone())
-                        .or_default()
-                        .insert(item);
+                    for item in Privilege::from_grant(grant)? {
+                        let privileges = data.privileges.entry(item.id.clone()).or_default();
+                        match privileges.iter().find(|p| p.can_merge(&item)).cloned() {
+                            Some(existing) => {
+                                privileges.remove(&existing);
+                                privileges.insert(existing.merge(&item)?);
+                            }
+                            None => {
+                                privileges.insert(item);
+                            }
+                        }
+                    }
                 }
                 NodeRef::CommentStmt(_comment) => {
                     info!("ignore comment");
                 }
-                NodeRef::CreateExtensionStmt(_ext) => {
-                    info!("TODO: extension");
+                NodeRef::CreateExtensionStmt(ext) => {
+                    let item: Extension = ext.try_into()?;
+                    map_insert_schema!(data.extensions, item);
                 }
                 NodeRef::CreateSchemaStmt(_schema) => {
                     info!("ignore schema creation statement since we already have the schema name");
@@ -143,38 +243,192 @@ impl SchemaLoader for SqlLoader {
                 }
                 NodeRef::CreateForeignTableStmt(_table) => {
                     info!("TODO: foreign table");
+                    skipped.extend(unsupported(&node));
                 }
                 NodeRef::CreateForeignServerStmt(_server) => {
                     info!("TODO: foreign server");
+                    skipped.extend(unsupported(&node));
                 }
                 NodeRef::CreateFdwStmt(_fdw) => {
                     info!("TODO: fwd");
+                    skipped.extend(unsupported(&node));
                 }
                 NodeRef::CreatePolicyStmt(policy) => {
                     let item: TablePolicy = policy.try_into()?;
                     map_insert_relation!(data.table_policies, item);
                 }
+                NodeRef::SelectStmt(stmt) if depth == 0 && Hypertable::is_hypertable(stmt) => {
+                    let item: Hypertable = stmt.try_into()?;
+                    data.table_hypertables.insert(item.id.clone(), item);
+                }
+                _ if depth == 0 => {
+                    if let Some(item) = CustomNode::from_node(&node.to_enum()) {
+                        data.custom_nodes
+                            .entry(item.type_name.to_owned())
+                            .or_default()
+                            .insert(item.id.clone(), item);
+                    } else {
+                        info!("unhandled node: {:?}", node.deparse());
+                        if let Some(kind) = skipped_kind(&node) {
+                            skipped.extend(node.deparse().ok().map(|sql| (kind, sql)));
+                        }
+                    }
+                }
                 _ => {
                     info!("unhandled node: {:?}", node.deparse());
                 }
             }
         }
+        data.remove_extension_objects();
         data.update_schema_names();
         Ok(data)
     }
+}
+
+#[async_trait]
+impl SchemaLoader for SqlLoader {
+    async fn load(&self) -> Result<DatabaseSchema> {
+        self.parse()
+    }
 
     async fn load_sql(&self) -> anyhow::Result<String> {
         Ok(self.0.clone())
     }
 }
 
+/// the deparsed statement no NodeItem handles
+fn unsupported(node: &NodeRef) -> Option<(DiagnosticKind, String)> {
+    let sql = node.deparse().ok()?;
+    Some((DiagnosticKind::SkippedStatement, sql))
+}
+
+/// Why a top level statement no NodeItem handles is skipped, None for the statements of pg_dump,
+/// or about the schemas and sequences, which are not objects to track
+fn skipped_kind(node: &NodeRef) -> Option<DiagnosticKind> {
+    match node {
+        NodeRef::VariableSetStmt(_) => None,
+        // the schemas themselves are not tracked either
+        NodeRef::AlterOwnerStmt(stmt)
+            if ObjectType::from_i32(stmt.object_type) == Some(ObjectType::ObjectSchema) =>
+        {
+            None
+        }
+        NodeRef::AlterOwnerStmt(_) => Some(DiagnosticKind::OwnershipIgnored),
+        NodeRef::AlterSeqStmt(stmt)
+            if stmt.options.iter().all(|option| {
+                matches!(option.node.as_ref(), Some(NodeEnum::DefElem(e)) if e.defname == "owned_by")
+            }) =>
+        {
+            None
+        }
+        // `SELECT pg_catalog.set_config('search_path', '', false)`
+        NodeRef::SelectStmt(_)
+            if node
+                .deparse()
+                .map_or(false, |sql| sql.contains("set_config(")) =>
+        {
+            None
+        }
+        _ => Some(DiagnosticKind::SkippedStatement),
+    }
+}
+
+/// The warnings about the statements of the sql files in the store skipped while parsing, found
+/// at their file and line, see `SqlLoader::skipped_statements`
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn store_diagnostics(store: &dyn SchemaStore) -> Result<Vec<Diagnostic>> {
+    let mut result = Vec::new();
+    for file in store.list().await? {
+        let content = store.read(&file).await?;
+        let (content, _) = extract_data_migrations(&content)
+            .with_context(|| format!("Failed to load {}", file.display()))?;
+        let (_, diagnostics) =
+            SqlLoader::new(content).parse_with_diagnostics(&file.display().to_string())?;
+        result.extend(diagnostics);
+    }
+    Ok(result)
+}
+
+/// Fail on the statements no NodeItem handles, for `diff.strict`, reporting the file, line and
+/// statement of each
+#[cfg(any(feature = "db", not(target_arch = "wasm32")))]
+pub(crate) fn check_strict(diagnostics: &[Diagnostic]) -> Result<()> {
+    let errors: Vec<_> = diagnostics
+        .iter()
+        .filter(|d| d.kind == DiagnosticKind::SkippedStatement)
+        .map(|d| {
+            format!(
+                "  {}: {}",
+                d.source.as_deref().unwrap_or_default(),
+                d.statement.as_deref().unwrap_or_default()
+            )
+        })
+        .collect();
+    if errors.is_empty() {
+        return Ok(());
+    }
+    bail!(
+        "{} statement(s) are not supported by renovate, and would be silently skipped without `diff.strict`:\n{}",
+        errors.len(),
+        errors.join("\n")
+    )
+}
+
+async fn load_store_sql(store: &dyn SchemaStore) -> Result<String> {
+    // concatenate all the sql files into one string
+    let mut sql = String::with_capacity(16 * 1024);
+    for file in store.list().await? {
+        let content = store.read(&file).await?;
+        if content.lines().any(|l| l.starts_with(CONFLICT_START)) {
+            bail!(
+                "{} has unresolved merge conflicts, resolve them and remove the conflict markers",
+                file.display()
+            );
+        }
+        let (content, _) = extract_data_migrations(&content)
+            .with_context(|| format!("Failed to load {}", file.display()))?;
+        sql.push_str(&content);
+    }
+
+    // parse the sql to see if the syntax is correct
+    let ret = pg_query::parse(&sql)?;
+    let sql = ret.deparse()?;
+    Ok(sql)
+}
+
+/// the glob options which skip the hidden files and directories, e.g. the state directory
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn visible_files() -> MatchOptions {
+    MatchOptions {
+        require_literal_leading_dot: true,
+        ..Default::default()
+    }
+}
+
+/// the data migration blocks of the sql files in the store, in the order of the files
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn load_data_migrations(store: &dyn SchemaStore) -> Result<Vec<DataMigration>> {
+    let mut migrations = Vec::new();
+    for file in store.list().await? {
+        let content = store.read(&file).await?;
+        let (_, items) = extract_data_migrations(&content)
+            .with_context(|| format!("Failed to load {}", file.display()))?;
+        migrations.extend(items);
+    }
+    Ok(migrations)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 impl LocalRepo {
-    // load all the .sql files in subdirectories except the "_meta" directory
+    // load all the .sql files in subdirectories except the "_meta" directory and the hidden ones,
+    // once the save interrupted in the middle, if any, is completed
     pub fn files(&self) -> Result<Vec<PathBuf>> {
+        replay_journal(&self.path)?;
         let glob_path = self.path.join("**/*.sql");
-        let mut files = glob(glob_path.as_os_str().to_str().unwrap())?
+        let mut files = glob_with(glob_path.as_os_str().to_str().unwrap(), visible_files())?
             .filter_map(Result::ok)
-            .filter(|p| ignore_file(p, "_"))
+            // only the path under the repo counts, so that the repo itself could be e.g. `_base`
+            .filter(|p| ignore_file(p.strip_prefix(&self.path).unwrap_or(p), "_"))
             .collect::<Vec<PathBuf>>();
 
         files.sort();
@@ -0,0 +1,98 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tracing::info;
+
+/// Concatenate the up migrations of a flyway (`V1.2__init.sql`, `R__views.sql`) or
+/// golang-migrate (`20230101_init.up.sql`) directory in the order they were applied: versioned
+/// migrations by version, then the repeatable ones by name. Undo / down migrations are skipped.
+pub(crate) async fn load_migrations(dir: &Path) -> Result<(usize, String)> {
+    let mut entries = fs::read_dir(dir)
+        .await
+        .with_context(|| format!("Failed to read migrations from {}", dir.display()))?;
+    let mut migrations: Vec<(MigrationOrder, PathBuf)> = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        match migration_order(&name) {
+            Some(order) => migrations.push((order, path)),
+            None => info!("ignore non migration file: {}", path.display()),
+        }
+    }
+    migrations.sort();
+
+    let mut sql = String::new();
+    for (_, path) in &migrations {
+        sql.push_str(&fs::read_to_string(path).await?);
+        sql.push_str(";\n");
+    }
+    Ok((migrations.len(), sql))
+}
+
+/// (repeatable, version, name) so that the migrations sort in the applied order
+type MigrationOrder = (bool, Vec<u64>, String);
+
+fn migration_order(name: &str) -> Option<MigrationOrder> {
+    if let Some(rest) = name.strip_suffix(".up.sql") {
+        let (version, title) = rest.split_once('_').unwrap_or((rest, ""));
+        let version = version.parse().ok()?;
+        return Some((false, vec![version], title.to_owned()));
+    }
+
+    let rest = name.strip_suffix(".sql")?;
+    if let Some(desc) = rest.strip_prefix("R__") {
+        return Some((true, vec![], desc.to_owned()));
+    }
+    let (version, desc) = rest.strip_prefix('V')?.split_once("__")?;
+    let version = version
+        .split(['.', '_'])
+        .map(|v| v.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    Some((false, version, desc.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn migrations_should_load_in_applied_order() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for (name, sql) in [
+            (
+                "V1.10__add_index.sql",
+                "CREATE INDEX todos_title_idx ON todos (title)",
+            ),
+            (
+                "V1.2__add_title.sql",
+                "ALTER TABLE todos ADD COLUMN title text",
+            ),
+            ("V1__init.sql", "CREATE TABLE todos (id int)"),
+            (
+                "R__views.sql",
+                "CREATE VIEW titles AS SELECT title FROM todos",
+            ),
+            ("U1.2__add_title.sql", "ALTER TABLE todos DROP COLUMN title"),
+            ("README.md", "not a migration"),
+        ] {
+            fs::write(dir.path().join(name), sql).await?;
+        }
+
+        let (count, sql) = load_migrations(dir.path()).await?;
+        assert_eq!(count, 4);
+        assert_eq!(
+            sql,
+            "CREATE TABLE todos (id int);\nALTER TABLE todos ADD COLUMN title text;\nCREATE INDEX todos_title_idx ON todos (title);\nCREATE VIEW titles AS SELECT title FROM todos;\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn golang_migrate_files_should_be_ordered_by_version() {
+        assert_eq!(
+            migration_order("20230102_add_title.up.sql"),
+            Some((false, vec![20230102], "add_title".to_owned()))
+        );
+        assert_eq!(migration_order("20230102_add_title.down.sql"), None);
+    }
+}
@@ -1,9 +1,53 @@
+#[cfg(feature = "db")]
 mod applier;
+#[cfg(not(target_arch = "wasm32"))]
+mod cache;
+#[cfg(feature = "db")]
+mod cockroach;
+mod dbml;
+#[cfg(feature = "git")]
 pub mod git;
+#[cfg(not(target_arch = "wasm32"))]
+mod journal;
 mod loader;
+#[cfg(feature = "db")]
+mod migrations;
+#[cfg(feature = "db")]
+mod notice;
 mod saver;
+#[cfg(not(target_arch = "wasm32"))]
+mod state;
+#[cfg(not(target_arch = "wasm32"))]
+mod stats;
+mod store;
+#[cfg(not(target_arch = "wasm32"))]
+mod tags;
+mod vcs;
 
-use crate::{DatabaseRepo, LocalRepo, RenovateConfig, SqlLoader};
+#[cfg(feature = "db")]
+pub(crate) use applier::TmpDb;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use cache::SchemaCache;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use loader::{check_strict, load_data_migrations, store_diagnostics, visible_files};
+#[cfg(feature = "db")]
+pub(crate) use migrations::load_migrations;
+#[cfg(feature = "db")]
+pub use notice::NOTICE_TARGET;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use saver::save_files_to_dir;
+#[cfg(feature = "db")]
+pub(crate) use saver::{file_changes, overlay_files};
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use state::{load_sync_states, save_sync_state, sync_base, DEFAULT_ENV};
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use stats::{load_stats, save_stats};
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use tags::{add_tag, load_tags, TAGS_FILE};
+
+#[cfg(feature = "db")]
+use crate::{DatabaseRepo, RenovateConfig};
+use crate::{LocalRepo, SqlLoader};
 use std::path::PathBuf;
 
 impl LocalRepo {
@@ -12,11 +56,18 @@ impl LocalRepo {
     }
 }
 
+#[cfg(feature = "db")]
 impl DatabaseRepo {
     pub fn new(config: &RenovateConfig) -> Self {
         Self {
             url: config.url.clone(),
             remote_url: config.remote_url.clone(),
+            output: config.output.clone(),
+            jobs: 1,
+            dialect: config.dialect,
+            pooler: config.connection.pooler,
+            batch_size: config.apply.batch_size.max(1),
+            strict: config.diff.strict,
         }
     }
 
@@ -24,8 +75,20 @@ impl DatabaseRepo {
         Self {
             url: url.clone(),
             remote_url: url,
+            output: Default::default(),
+            jobs: 1,
+            dialect: Default::default(),
+            pooler: Default::default(),
+            batch_size: 1,
+            strict: false,
         }
     }
+
+    /// Dump the schemas with up to `jobs` concurrent pg_dump processes. Default 1.
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
 }
 
 impl Default for LocalRepo {
@@ -0,0 +1,176 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+use tracing::{
+    field::{Field, Visit},
+    level_filters::LevelFilter,
+    span,
+    subscriber::Interest,
+    Dispatch, Event, Level, Metadata, Subscriber,
+};
+use tracing_log::{LogTracer, NormalizeEvent};
+
+/// sqlx doesn't expose the NOTICE / WARNING messages sent by the server, it only logs them
+/// with the `log` crate under this target
+pub const NOTICE_TARGET: &str = "sqlx::postgres::notice";
+
+/// Subscriber wrapping the current dispatcher which takes out the server notices logged by
+/// sqlx and forwards everything else. Install it for the futures talking to the server with
+/// `WithSubscriber::with_subscriber`. The `log` records reach it through `LogTracer`, which is
+/// installed as the logger unless another one already is, e.g. by `tracing_subscriber`'s `init`.
+#[derive(Clone)]
+pub(crate) struct NoticeCapture {
+    inner: Dispatch,
+    notices: Arc<Mutex<Vec<String>>>,
+}
+
+impl NoticeCapture {
+    pub fn new() -> Self {
+        // fails if a logger is already set, which is fine if it's a `LogTracer` too
+        let _ = LogTracer::init();
+        Self {
+            inner: tracing::dispatcher::get_default(|d| d.clone()),
+            notices: Default::default(),
+        }
+    }
+
+    /// the notices captured since the last call, e.g. `NOTICE: identifier ... will be truncated`
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.notices.lock().unwrap())
+    }
+}
+
+fn is_notice(metadata: &Metadata<'_>) -> bool {
+    metadata.target() == NOTICE_TARGET
+}
+
+impl Subscriber for NoticeCapture {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if is_notice(metadata) {
+            Interest::always()
+        } else {
+            self.inner.register_callsite(metadata)
+        }
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        is_notice(metadata) || self.inner.enabled(metadata)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        None
+    }
+
+    fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+        self.inner.new_span(span)
+    }
+
+    fn record(&self, span: &span::Id, values: &span::Record<'_>) {
+        self.inner.record(span, values)
+    }
+
+    fn record_follows_from(&self, span: &span::Id, follows: &span::Id) {
+        self.inner.record_follows_from(span, follows)
+    }
+
+    fn event(&self, event: &Event<'_>) {
+        // the events converted from `log` records all have the metadata of their level, the
+        // target of the record is only in the normalized one
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        if !is_notice(metadata) {
+            if self.inner.enabled(metadata) {
+                self.inner.event(event);
+            }
+            return;
+        }
+
+        let mut message = MessageVisitor(String::new());
+        event.record(&mut message);
+        let severity = match *metadata.level() {
+            Level::ERROR => "ERROR",
+            Level::WARN => "WARNING",
+            _ => "NOTICE",
+        };
+        self.notices
+            .lock()
+            .unwrap()
+            .push(format!("{}: {}", severity, message.0));
+    }
+
+    fn enter(&self, span: &span::Id) {
+        self.inner.enter(span)
+    }
+
+    fn exit(&self, span: &span::Id) {
+        self.inner.exit(span)
+    }
+
+    fn clone_span(&self, id: &span::Id) -> span::Id {
+        self.inner.clone_span(id)
+    }
+
+    fn try_close(&self, id: span::Id) -> bool {
+        self.inner.try_close(id)
+    }
+}
+
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.0 = value.to_owned();
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{:?}", value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::{Connection, Executor, PgConnection};
+    use tracing::instrument::WithSubscriber;
+
+    #[tokio::test]
+    async fn notices_should_be_captured() {
+        let capture = NoticeCapture::new();
+        async {
+            tracing::info!(target: NOTICE_TARGET, "identifier will be truncated");
+            tracing::warn!(target: NOTICE_TARGET, "there is no transaction in progress");
+            tracing::info!("not a notice");
+        }
+        .with_subscriber(capture.clone())
+        .await;
+        assert_eq!(
+            capture.take(),
+            vec![
+                "NOTICE: identifier will be truncated",
+                "WARNING: there is no transaction in progress"
+            ]
+        );
+        assert!(capture.take().is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a postgres server at RENOVATE_TEST_SERVER_URL"]
+    async fn server_notices_should_be_captured() -> anyhow::Result<()> {
+        let url = std::env::var(crate::testing::TEST_SERVER_URL_ENV)?;
+        let mut conn = PgConnection::connect(&url).await?;
+        let capture = NoticeCapture::new();
+        conn.execute("DO $$ BEGIN RAISE NOTICE 'truncated'; RAISE WARNING 'careful'; END $$")
+            .with_subscriber(capture.clone())
+            .await?;
+        assert_eq!(
+            capture.take(),
+            vec!["NOTICE: truncated", "WARNING: careful"]
+        );
+        Ok(())
+    }
+}
@@ -1,123 +1,321 @@
+#[cfg(not(target_arch = "wasm32"))]
+use crate::plan::{extract_ignored_statements, ignored_statements, IGNORE_FILE};
 use crate::{
     config::{RenovateFormatConfig, RenovateOutputConfig},
     parser::SchemaId,
-    DatabaseSchema, LocalRepo, MigrationPlanner, NodeDiff, NodeItem, SqlSaver,
+    schema::Drift,
+    ChangeKind, DatabaseSchema, FileChange, MigrationPlanner, NodeDiff, NodeItem, SchemaFiles,
+    SchemaStore, SqlLoader,
 };
-use anyhow::Result;
-use async_trait::async_trait;
 use itertools::Itertools;
 use std::{
     collections::{BTreeMap, BTreeSet},
     fmt,
     hash::Hash,
-    path::Path,
+    path::{Path, PathBuf},
     str::FromStr,
 };
-use tokio::fs;
 
-#[async_trait]
-impl SqlSaver for DatabaseSchema {
+/// the markers `render_merge` puts around the conflicting objects, the same as git's
+pub(crate) const CONFLICT_START: &str = "<<<<<<<";
+const CONFLICT_SEPARATOR: &str = "=======";
+const CONFLICT_END: &str = ">>>>>>>";
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait::async_trait]
+impl crate::SqlSaver for DatabaseSchema {
     async fn save(&self, config: &RenovateOutputConfig) -> anyhow::Result<()> {
-        use crate::config::Layout;
+        self.save_to_dir(&config.path, config).await?;
+        Ok(())
+    }
+}
 
-        // remove all existing sql files in the local repo
-        let local_repo = LocalRepo::new(&config.path);
-        for file in local_repo.files()? {
-            fs::remove_file(file).await?;
+#[cfg(not(target_arch = "wasm32"))]
+impl DatabaseSchema {
+    /// Same as `save_to` the local repo at `path`, but the files are rendered to a temporary
+    /// workspace first, and only moved in place once all of them are written and the moves are
+    /// journaled. A failed save leaves the old files, an interrupted one is completed by the next
+    /// load, and each save has its own workspace so that concurrent ones don't mix their files.
+    /// The statements annotated with `-- renovate:ignore` in the rewritten files are moved to
+    /// `_meta/ignored.sql`, since the rendered files have no comments.
+    pub async fn save_to_dir(
+        &self,
+        path: &Path,
+        config: &RenovateOutputConfig,
+    ) -> anyhow::Result<usize> {
+        save_files_to_dir(path, self.render(config)).await
+    }
+}
+
+/// Replace the sql files of the local repo at `path` with the given ones, see `save_to_dir`
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn save_files_to_dir(path: &Path, files: SchemaFiles) -> anyhow::Result<usize> {
+    let mut changes = file_changes(&crate::LocalRepo::new(path), files).await?;
+    if changes.is_empty() {
+        return Ok(0);
+    }
+    changes.extend(keep_ignored(path, &changes)?);
+    super::journal::apply_changes(path, &changes).await?;
+    Ok(changes.len())
+}
+
+/// the change appending to `IGNORE_FILE` the annotated statements which the changes rewrite
+/// without their annotation, so that they stay ignored
+#[cfg(not(target_arch = "wasm32"))]
+fn keep_ignored(path: &Path, changes: &[FileChange]) -> anyhow::Result<Option<FileChange>> {
+    let file = PathBuf::from(IGNORE_FILE);
+    let old = match std::fs::read_to_string(path.join(&file)) {
+        Ok(content) => Some(content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let mut kept = ignored_statements(&SchemaFiles::from([(
+        file.clone(),
+        old.clone().unwrap_or_default(),
+    )]))?;
+    let mut new = old.clone().unwrap_or_default();
+    for change in changes {
+        // the annotations are only read leniently here, a broken one fails the next load anyway
+        let annotated = |content: &str| extract_ignored_statements(content).unwrap_or_default();
+        let still_annotated = annotated(&change.new);
+        for statement in annotated(&change.old) {
+            if still_annotated.contains(&statement) || kept.contains(&statement) {
+                continue;
+            }
+            if !new.is_empty() && !new.ends_with('\n') {
+                new.push('\n');
+            }
+            new.push_str(&format!("{};\n", statement));
+            kept.push(statement);
         }
+    }
+    Ok(match old {
+        Some(old) if old != new => Some(FileChange {
+            path: file,
+            kind: ChangeKind::Changed,
+            old,
+            new,
+        }),
+        None if !new.is_empty() => Some(FileChange {
+            path: file,
+            kind: ChangeKind::Added,
+            old: String::new(),
+            new,
+        }),
+        _ => None,
+    })
+}
 
-        match config.layout {
-            Layout::Normal => self.normal(config).await,
-            Layout::Flat => self.flat(config).await,
-            Layout::Nested => self.nested(config).await,
+/// The files to save to a local repo layered on `base`, see `OverlayStore`, for the merged
+/// files to load the same schema as the given ones: the files whose schema differs from the base
+/// file at the same path, and an empty one hiding each base file which isn't rendered anymore.
+#[cfg(feature = "db")]
+pub(crate) fn overlay_files(files: SchemaFiles, base: &SchemaFiles) -> SchemaFiles {
+    let same = |old: &str, new: &str| {
+        old == new
+            || matches!(
+                (SqlLoader::new(old).parse(), SqlLoader::new(new).parse()),
+                (Ok(old), Ok(new)) if old.sql(true) == new.sql(true)
+            )
+    };
+    let mut overlay: SchemaFiles = base
+        .keys()
+        .filter(|path| !files.contains_key(*path))
+        .map(|path| (path.clone(), String::new()))
+        .collect();
+    overlay.extend(
+        files
+            .into_iter()
+            .filter(|(path, new)| base.get(path).map_or(true, |old| !same(old, new))),
+    );
+    overlay
+}
+
+/// the files to create, modify or delete in the store for it to have the given files
+pub(crate) async fn file_changes(
+    store: &dyn SchemaStore,
+    mut files: SchemaFiles,
+) -> anyhow::Result<Vec<FileChange>> {
+    let mut changes = Vec::new();
+
+    for path in store.list().await? {
+        let old = store.read(&path).await?;
+        match files.remove(&path) {
+            // the existing sql files which are no longer rendered
+            None => changes.push(FileChange {
+                path,
+                kind: ChangeKind::Removed,
+                old,
+                new: String::new(),
+            }),
+            Some(new) if new != old => changes.push(FileChange {
+                path,
+                kind: ChangeKind::Changed,
+                old,
+                new,
+            }),
+            Some(_) => {}
         }
     }
+    changes.extend(files.into_iter().map(|(path, new)| FileChange {
+        path,
+        kind: ChangeKind::Added,
+        old: String::new(),
+        new,
+    }));
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
 }
 
 impl DatabaseSchema {
-    pub async fn flat(&self, config: &RenovateOutputConfig) -> anyhow::Result<()> {
-        let content = self.to_string();
-        let filename = config.path.join("all.sql");
-        Self::write(filename, &content, config.format).await?;
-        Ok(())
+    /// Replace the sql files in the store with the rendered schema, and return the number of
+    /// files written or removed. Files with the same content are left untouched.
+    pub async fn save_to(
+        &self,
+        store: &dyn SchemaStore,
+        config: &RenovateOutputConfig,
+    ) -> anyhow::Result<usize> {
+        let changes = self.preview_save(store, config).await?;
+        for change in &changes {
+            match change.kind {
+                ChangeKind::Removed => store.remove(&change.path).await?,
+                _ => store.write(&change.path, &change.new).await?,
+            }
+        }
+        Ok(changes.len())
+    }
+
+    /// The files `save_to` would create, modify or delete in the store, sorted by path. Nothing
+    /// is written.
+    pub async fn preview_save(
+        &self,
+        store: &dyn SchemaStore,
+        config: &RenovateOutputConfig,
+    ) -> anyhow::Result<Vec<FileChange>> {
+        file_changes(store, self.render(config)).await
+    }
+
+    /// Render the merged schema like `render`, with each conflicting object added to the file it
+    /// belongs to between git style conflict markers, the local version first. The files don't
+    /// load until the conflicts are resolved by hand.
+    pub(crate) fn render_merge(
+        &self,
+        conflicts: &[Drift],
+        config: &RenovateOutputConfig,
+    ) -> anyhow::Result<SchemaFiles> {
+        let mut files = self.render(config);
+        for conflict in conflicts {
+            // the object rendered alone, to find its file
+            let render = |sql: &str| -> anyhow::Result<Option<(PathBuf, String)>> {
+                if sql.is_empty() {
+                    return Ok(None);
+                }
+                let schema = crate::SqlLoader::new(format!("{};", sql)).parse()?;
+                Ok(schema.render(config).into_iter().next())
+            };
+            let (local, remote) = (render(&conflict.local)?, render(&conflict.remote)?);
+            let path = match local.as_ref().or(remote.as_ref()) {
+                Some((path, _)) => path.clone(),
+                None => continue,
+            };
+            let content = |file: Option<(PathBuf, String)>| {
+                let content = file.map(|(_, content)| content).unwrap_or_default();
+                match content.ends_with('\n') || content.is_empty() {
+                    true => content,
+                    false => content + "\n",
+                }
+            };
+            let file = files.entry(path).or_default();
+            if !file.is_empty() && !file.ends_with('\n') {
+                file.push('\n');
+            }
+            file.push_str(&format!(
+                "{} local: {} {}\n{}{}\n{}{} remote\n",
+                CONFLICT_START,
+                conflict.type_name,
+                conflict.id,
+                content(local),
+                CONFLICT_SEPARATOR,
+                content(remote),
+                CONFLICT_END
+            ));
+        }
+        Ok(files)
+    }
+
+    /// Render the schema to sql files based on the layout and format of the output config.
+    pub fn render(&self, config: &RenovateOutputConfig) -> SchemaFiles {
+        use crate::config::Layout;
+
+        let mut files = SchemaFiles::new();
+        match config.layout {
+            Layout::Normal => self.normal(&mut files),
+            Layout::Flat => self.flat(&mut files),
+            Layout::Nested => self.nested(&mut files),
+        }
+
+        files
+            .into_iter()
+            .map(|(file, content)| (file, format_sql(content, config.format)))
+            .collect()
+    }
+
+    fn flat(&self, files: &mut SchemaFiles) {
+        files.insert(PathBuf::from("all.sql"), self.to_string());
     }
 
-    pub async fn nested(&self, config: &RenovateOutputConfig) -> anyhow::Result<()> {
-        write_schema_files(&self.composite_types, "types", "01", vec![], config).await?;
-        write_schema_files(&self.enum_types, "enums", "02", vec![], config).await?;
+    fn nested(&self, files: &mut SchemaFiles) {
+        render_schema_files(&self.composite_types, "types", "01", vec![], files);
+        render_schema_files(&self.enum_types, "enums", "02", vec![], files);
 
-        write_schema_files(
+        render_schema_files(
             &self.sequences,
             "sequences",
             "03",
             self.sequence_embedded_resources(),
-            config,
-        )
-        .await?;
-        write_schema_files(
+            files,
+        );
+        render_schema_files(
             &self.tables,
             "tables",
             "04",
             self.table_embedded_resources(),
-            config,
-        )
-        .await?;
-
-        write_schema_files(&self.views, "views", "05", vec![], config).await?;
-        write_schema_files(&self.mviews, "mviews", "06", vec![], config).await?;
-        write_schema_files(&self.functions, "functions", "07", vec![], config).await?;
+            files,
+        );
 
-        write_privilege_file(&self.privileges, "privileges", "10", config).await?;
+        render_schema_files(&self.views, "views", "05", vec![], files);
+        render_schema_files(&self.mviews, "mviews", "06", vec![], files);
+        render_schema_files(&self.functions, "functions", "07", vec![], files);
 
-        Ok(())
+        render_privilege_file(&self.privileges, "privileges", "10", files);
+        render_custom_files(&self.custom_nodes, "11", files);
     }
 
-    pub async fn normal(&self, config: &RenovateOutputConfig) -> anyhow::Result<()> {
-        write_schema_file(&self.composite_types, "types", "01", vec![], config).await?;
-        write_schema_file(&self.enum_types, "enums", "02", vec![], config).await?;
+    fn normal(&self, files: &mut SchemaFiles) {
+        render_schema_file(&self.composite_types, "types", "01", vec![], files);
+        render_schema_file(&self.enum_types, "enums", "02", vec![], files);
 
-        write_schema_file(
+        render_schema_file(
             &self.sequences,
             "sequences",
             "03",
             self.sequence_embedded_resources(),
-            config,
-        )
-        .await?;
-        write_schema_file(
+            files,
+        );
+        render_schema_file(
             &self.tables,
             "tables",
             "04",
             self.table_embedded_resources(),
-            config,
-        )
-        .await?;
-
-        write_schema_file(&self.views, "views", "05", vec![], config).await?;
-        write_schema_file(&self.mviews, "mviews", "06", vec![], config).await?;
-        write_schema_file(&self.functions, "functions", "07", vec![], config).await?;
+            files,
+        );
 
-        write_privilege_file(&self.privileges, "privileges", "10", config).await?;
+        render_schema_file(&self.views, "views", "05", vec![], files);
+        render_schema_file(&self.mviews, "mviews", "06", vec![], files);
+        render_schema_file(&self.functions, "functions", "07", vec![], files);
 
-        Ok(())
-    }
-
-    async fn write(
-        filename: impl AsRef<Path>,
-        content: &str,
-        format: Option<RenovateFormatConfig>,
-    ) -> anyhow::Result<()> {
-        if let Some(format) = format {
-            let content = sqlformat::format(content, &Default::default(), format.into());
-            // TODO(hack): sqlformat adds a space before the dollar sign in $$, which is not valid SQL
-            let mut content = content.replace("$ $", "$$");
-            content.push('\n');
-            fs::write(filename, content).await?;
-        } else {
-            fs::write(filename, content).await?;
-        };
-
-        Ok(())
+        render_privilege_file(&self.privileges, "privileges", "10", files);
+        render_custom_files(&self.custom_nodes, "11", files);
     }
 
     fn table_embedded_resources(&self) -> Vec<BTreeMap<SchemaId, BTreeMap<String, String>>> {
@@ -129,6 +327,7 @@ impl DatabaseSchema {
             convert(&self.table_triggers),
             convert1(&self.table_rls),
             convert1(&self.table_owners),
+            convert1(&self.table_hypertables),
         ]
     }
 
@@ -156,32 +355,30 @@ impl fmt::Display for DatabaseSchema {
 
         result.push_str(&join_items(&self.table_rls));
         result.push_str(&join_items(&self.table_owners));
+        result.push_str(&join_items(&self.table_hypertables));
 
         join_nested_items(&self.table_triggers, &mut result);
         result.push_str(&join_privileges(&self.privileges));
+        join_nested_items(&self.custom_nodes, &mut result);
 
         write!(f, "{}", result)
     }
 }
 
-async fn write_schema_files<T>(
+fn render_schema_files<T>(
     source: &BTreeMap<String, BTreeMap<String, T>>,
     name: &str,
     prefix: &str,
     embedded_sources: Vec<BTreeMap<SchemaId, BTreeMap<String, String>>>,
-    config: &RenovateOutputConfig,
-) -> Result<()>
-where
+    files: &mut SchemaFiles,
+) where
     T: NodeItem + Clone + FromStr<Err = anyhow::Error> + PartialEq + Eq + 'static,
     NodeDiff<T>: MigrationPlanner<Migration = String>,
 {
     for (schema, items) in source {
-        let path = config.path.join(schema);
-        fs::create_dir_all(&path).await?;
+        let path = Path::new(schema).join(name);
         for (n, content) in items {
-            let p = path.join(name);
-            fs::create_dir_all(&p).await?;
-            let filename = p.join(format!("{}_{}.sql", prefix, n));
+            let filename = path.join(format!("{}_{}.sql", prefix, n));
             let item_content = format!("{};\n\n", content.to_string());
             let content = if embedded_sources.is_empty() {
                 item_content
@@ -193,10 +390,9 @@ where
                 )
             };
 
-            DatabaseSchema::write(&filename, &content, config.format).await?;
+            files.insert(filename, content);
         }
     }
-    Ok(())
 }
 
 fn join_embedded_sources(
@@ -212,20 +408,17 @@ fn join_embedded_sources(
     result
 }
 
-async fn write_schema_file<T>(
+fn render_schema_file<T>(
     source: &BTreeMap<String, BTreeMap<String, T>>,
     name: &str,
     prefix: &str,
     embedded_sources: Vec<BTreeMap<SchemaId, BTreeMap<String, String>>>,
-    config: &RenovateOutputConfig,
-) -> Result<()>
-where
+    files: &mut SchemaFiles,
+) where
     T: NodeItem + Clone + FromStr<Err = anyhow::Error> + PartialEq + Eq + 'static,
     NodeDiff<T>: MigrationPlanner<Migration = String>,
 {
     for (schema, items) in source {
-        let path = config.path.join(schema);
-        fs::create_dir_all(&path).await?;
         let mut content = String::new();
         for (n, item) in items {
             let item_content = format!("{};\n\n", item.to_string());
@@ -242,28 +435,53 @@ where
             content.push_str(&s);
         }
 
-        let filename = path.join(format!("{}_{}.sql", prefix, name));
-        DatabaseSchema::write(&filename, &content, config.format).await?;
+        let filename = Path::new(schema).join(format!("{}_{}.sql", prefix, name));
+        files.insert(filename, content);
     }
-
-    Ok(())
 }
 
-async fn write_privilege_file<T>(
+fn render_privilege_file<T>(
     source: &BTreeMap<String, BTreeSet<T>>,
     name: &str,
     prefix: &str,
-    config: &RenovateOutputConfig,
-) -> Result<()>
-where
+    files: &mut SchemaFiles,
+) where
     T: ToString,
 {
     let content = join_privileges(source);
     if !content.is_empty() {
-        let path = config.path.join(format!("{}_{}.sql", prefix, name));
-        DatabaseSchema::write(&path, &content, config.format).await?;
+        files.insert(PathBuf::from(format!("{}_{}.sql", prefix, name)), content);
+    }
+}
+
+fn render_custom_files<T>(
+    source: &BTreeMap<String, BTreeMap<String, T>>,
+    prefix: &str,
+    files: &mut SchemaFiles,
+) where
+    T: ToString,
+{
+    for (type_name, items) in source {
+        let content = join_items(items);
+        if !content.is_empty() {
+            files.insert(
+                PathBuf::from(format!("{}_{}.sql", prefix, type_name)),
+                content,
+            );
+        }
+    }
+}
+
+fn format_sql(content: String, format: Option<RenovateFormatConfig>) -> String {
+    if let Some(format) = format {
+        let content = sqlformat::format(&content, &Default::default(), format.into());
+        // TODO(hack): sqlformat adds a space before the dollar sign in $$, which is not valid SQL
+        let mut content = content.replace("$ $", "$$");
+        content.push('\n');
+        content
+    } else {
+        content
     }
-    Ok(())
 }
 
 fn join_items<K, T>(source: &BTreeMap<K, T>) -> String
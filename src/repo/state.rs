@@ -0,0 +1,141 @@
+use crate::{SchemaFiles, SyncState, Vcs};
+use anyhow::{Context, Result};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use tracing::warn;
+
+/// the directory of the files about the checkout rather than the schema, under the local repo.
+/// It ignores itself in git, and the loader skips it.
+pub(crate) const STATE_DIR: &str = ".renovate";
+
+/// the last sync of each environment, under the local repo
+pub(crate) const STATE_FILE: &str = ".renovate/state.json";
+
+/// the environment the top level config is recorded under, i.e. without `--env`
+pub(crate) const DEFAULT_ENV: &str = "default";
+
+/// The last sync of each environment recorded in the local repo at `path`, keyed by environment.
+pub(crate) fn load_sync_states(path: &Path) -> Result<BTreeMap<String, SyncState>> {
+    let file = path.join(STATE_FILE);
+    match fs::read_to_string(&file) {
+        Ok(content) => serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", file.display())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Record the last sync of `env` in the local repo at `path`, replacing the previous one, via a
+/// rename so that an interrupted save keeps the old state.
+pub(crate) fn save_sync_state(path: &Path, env: &str, state: SyncState) -> Result<()> {
+    let mut states = load_sync_states(path)?;
+    states.insert(env.to_owned(), state);
+
+    state_dir(path)?;
+    let file = path.join(STATE_FILE);
+    let tmp = file.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(&states)? + "\n")?;
+    fs::rename(tmp, file)?;
+    Ok(())
+}
+
+/// Create the state directory of the local repo at `path` if it's not there yet, along with the
+/// `.gitignore` which ignores it.
+pub(crate) fn state_dir(path: &Path) -> Result<PathBuf> {
+    let dir = path.join(STATE_DIR);
+    fs::create_dir_all(&dir)?;
+    let ignore = dir.join(".gitignore");
+    if !ignore.exists() {
+        fs::write(ignore, "*\n")?;
+    }
+    Ok(dir)
+}
+
+/// The files to merge the local changes and the remote ones of `env` from: the commit synced
+/// last if it's still there, or else the last commit if the local repo has uncommitted changes.
+/// None if the local repo is unchanged since, so the fetched schema is taken as is.
+pub(crate) fn sync_base(path: &Path, env: &str, vcs: &dyn Vcs) -> Result<Option<SchemaFiles>> {
+    let dirty = vcs.is_dirty()?;
+    let head = vcs.head()?;
+    let synced = load_sync_states(path)?.remove(env).and_then(|s| s.commit);
+    if let Some(commit) = synced.filter(|c| dirty || Some(c) != head.as_ref()) {
+        match vcs.files_at(&commit) {
+            Ok(files) => return Ok(Some(files)),
+            // e.g. the history is rewritten since
+            Err(e) => warn!(
+                "the last synced commit {} can't be read, merge from the last commit instead: {:#}",
+                commit, e
+            ),
+        }
+    }
+    match (dirty, head) {
+        (true, Some(head)) => Ok(Some(vcs.files_at(&head)?)),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn sync_state_should_be_kept_per_env() -> Result<()> {
+        let dir = tempdir()?;
+        let state = |synced_at| SyncState {
+            fingerprint: Some("0123456789abcdef".to_owned()),
+            remote: true,
+            commit: None,
+            synced_at,
+        };
+        assert!(load_sync_states(dir.path())?.is_empty());
+
+        save_sync_state(dir.path(), "prod", state(1700000000))?;
+        save_sync_state(dir.path(), DEFAULT_ENV, state(1700000100))?;
+        save_sync_state(dir.path(), "prod", state(1700000200))?;
+
+        let states = load_sync_states(dir.path())?;
+        assert_eq!(states.len(), 2);
+        assert_eq!(states["prod"], state(1700000200));
+        assert_eq!(states[DEFAULT_ENV], state(1700000100));
+        assert!(dir.path().join(".renovate/.gitignore").exists());
+        Ok(())
+    }
+
+    #[cfg(feature = "git")]
+    #[test]
+    fn sync_base_should_be_the_synced_commit() -> Result<()> {
+        let dir = tempdir()?;
+        let vcs = crate::GitVcs::new(dir.path());
+        let file = dir.path().join("public/tables.sql");
+        fs::create_dir_all(file.parent().unwrap())?;
+
+        // fetched and committed, then recorded
+        fs::write(&file, "CREATE TABLE public.todos (id int);\n")?;
+        vcs.commit("sync the schema fetched from the database")?;
+        let state = SyncState {
+            fingerprint: None,
+            remote: false,
+            commit: vcs.head()?,
+            synced_at: 1700000000,
+        };
+        save_sync_state(dir.path(), DEFAULT_ENV, state)?;
+        assert!(!vcs.is_dirty()?);
+        assert_eq!(sync_base(dir.path(), DEFAULT_ENV, &vcs)?, None);
+
+        // a local change committed before the next fetch is merged from the synced commit
+        fs::write(&file, "CREATE TABLE public.todos (id bigint);\n")?;
+        vcs.commit("widen todos.id")?;
+        let base = sync_base(dir.path(), DEFAULT_ENV, &vcs)?.unwrap();
+        let (_, content) = base
+            .iter()
+            .find(|(path, _)| path.ends_with("public/tables.sql"))
+            .unwrap();
+        assert_eq!(content, "CREATE TABLE public.todos (id int);\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use crate::TableStats;
+use anyhow::{Context, Result};
+use std::{collections::BTreeMap, fs, io::ErrorKind, path::Path};
+
+/// the stats of the tables as of the last fetch, under `_meta` of the local repo which the loader
+/// skips, keyed by the qualified name of the table
+pub(crate) const STATS_FILE: &str = "_meta/stats.json";
+
+/// relative change of the rows or the size of a table under which the saved stats are kept, so
+/// that the estimates drifting between fetches don't churn the repo
+const STATS_TOLERANCE: f64 = 0.1;
+
+/// The stats saved in the local repo at `path`, empty if there are none.
+pub(crate) fn load_stats(path: &Path) -> Result<BTreeMap<String, TableStats>> {
+    let file = path.join(STATS_FILE);
+    match fs::read_to_string(&file) {
+        Ok(content) => serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", file.display())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Replace the stats saved in the local repo at `path`, via a rename so that an interrupted save
+/// keeps the old ones. They are left as is if they have the same tables, none of which changed by
+/// more than `STATS_TOLERANCE`.
+pub(crate) fn save_stats(path: &Path, stats: &BTreeMap<String, TableStats>) -> Result<()> {
+    // unreadable stats are replaced
+    let saved = load_stats(path).unwrap_or_default();
+    let unchanged = saved.len() == stats.len()
+        && stats.iter().all(|(name, new)| {
+            saved.get(name).map_or(false, |old| {
+                !changed(old.rows, new.rows) && !changed(old.bytes, new.bytes)
+            })
+        });
+    if unchanged {
+        return Ok(());
+    }
+
+    let file = path.join(STATS_FILE);
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = file.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(stats)? + "\n")?;
+    fs::rename(tmp, file)?;
+    Ok(())
+}
+
+/// whether the value moved by more than `STATS_TOLERANCE`
+fn changed(old: u64, new: u64) -> bool {
+    old.abs_diff(new) as f64 > old.max(new) as f64 * STATS_TOLERANCE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn stats_should_be_replaced_on_save() -> Result<()> {
+        let dir = tempdir()?;
+        assert!(load_stats(dir.path())?.is_empty());
+
+        let stats = |rows| {
+            [("public.todos".to_owned(), TableStats { rows, bytes: 8192 })]
+                .into_iter()
+                .collect::<BTreeMap<_, _>>()
+        };
+        save_stats(dir.path(), &stats(10))?;
+        save_stats(dir.path(), &stats(20))?;
+        assert_eq!(load_stats(dir.path())?, stats(20));
+        Ok(())
+    }
+
+    #[test]
+    fn drifting_estimates_should_not_rewrite_the_stats() -> Result<()> {
+        let dir = tempdir()?;
+        let stats = |rows, bytes| {
+            [("public.todos".to_owned(), TableStats { rows, bytes })]
+                .into_iter()
+                .collect::<BTreeMap<_, _>>()
+        };
+        save_stats(dir.path(), &stats(1000, 81920))?;
+        save_stats(dir.path(), &stats(1040, 90112))?;
+        assert_eq!(load_stats(dir.path())?, stats(1000, 81920));
+
+        // a new table is always saved
+        let mut more = stats(1040, 90112);
+        more.insert("public.tags".to_owned(), TableStats { rows: 0, bytes: 0 });
+        save_stats(dir.path(), &more)?;
+        assert_eq!(load_stats(dir.path())?, more);
+        Ok(())
+    }
+}
@@ -0,0 +1,510 @@
+use crate::{utils::ignore_file, MemoryStore, OverlayStore, SchemaFiles, SchemaStore};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{LocalRepo, ObjectStore};
+#[cfg(not(target_arch = "wasm32"))]
+use anyhow::Context;
+use anyhow::{bail, Result};
+#[cfg(not(target_arch = "wasm32"))]
+use async_process::Command;
+use async_trait::async_trait;
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::fs;
+#[cfg(not(target_arch = "wasm32"))]
+use url::Url;
+#[cfg(not(target_arch = "wasm32"))]
+use uuid::Uuid;
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait]
+impl SchemaStore for LocalRepo {
+    async fn list(&self) -> Result<Vec<PathBuf>> {
+        let files = self
+            .files()?
+            .into_iter()
+            .map(|p| {
+                p.strip_prefix(&self.path)
+                    .map(Path::to_path_buf)
+                    .unwrap_or(p)
+            })
+            .collect();
+        Ok(files)
+    }
+
+    async fn read(&self, path: &Path) -> Result<String> {
+        let file = self.path.join(path);
+        fs::read_to_string(&file)
+            .await
+            .with_context(|| format!("Failed to read file: {:?}", file))
+    }
+
+    async fn write(&self, path: &Path, content: &str) -> Result<()> {
+        let file = self.path.join(path);
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(file, content).await?;
+        Ok(())
+    }
+
+    async fn remove(&self, path: &Path) -> Result<()> {
+        fs::remove_file(self.path.join(path)).await?;
+        Ok(())
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// a snapshot of all the files in the store
+    pub fn files(&self) -> SchemaFiles {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl From<SchemaFiles> for MemoryStore {
+    fn from(files: SchemaFiles) -> Self {
+        Self(Arc::new(Mutex::new(files)))
+    }
+}
+
+#[async_trait]
+impl SchemaStore for MemoryStore {
+    async fn list(&self) -> Result<Vec<PathBuf>> {
+        let files = self.0.lock().unwrap();
+        Ok(files.keys().filter(|p| is_sql_file(p)).cloned().collect())
+    }
+
+    async fn read(&self, path: &Path) -> Result<String> {
+        let files = self.0.lock().unwrap();
+        match files.get(path) {
+            Some(content) => Ok(content.clone()),
+            None => bail!("File not found in memory store: {:?}", path),
+        }
+    }
+
+    async fn write(&self, path: &Path, content: &str) -> Result<()> {
+        let mut files = self.0.lock().unwrap();
+        files.insert(path.to_path_buf(), content.to_owned());
+        Ok(())
+    }
+
+    async fn remove(&self, path: &Path) -> Result<()> {
+        let mut files = self.0.lock().unwrap();
+        files.remove(path);
+        Ok(())
+    }
+}
+
+impl OverlayStore {
+    pub fn new(base: impl SchemaStore + 'static, overlay: impl SchemaStore + 'static) -> Self {
+        Self {
+            base: Box::new(base),
+            overlay: Box::new(overlay),
+        }
+    }
+}
+
+#[async_trait]
+impl SchemaStore for OverlayStore {
+    async fn list(&self) -> Result<Vec<PathBuf>> {
+        let mut files = self.base.list().await?;
+        files.extend(self.overlay.list().await?);
+        files.sort();
+        files.dedup();
+        Ok(files)
+    }
+
+    async fn read(&self, path: &Path) -> Result<String> {
+        if self.overlay.list().await?.iter().any(|p| p == path) {
+            self.overlay.read(path).await
+        } else {
+            self.base.read(path).await
+        }
+    }
+
+    async fn write(&self, path: &Path, content: &str) -> Result<()> {
+        self.overlay.write(path, content).await
+    }
+
+    async fn remove(&self, path: &Path) -> Result<()> {
+        self.overlay.remove(path).await
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ObjectStore {
+    pub fn new(url: &str) -> Result<Self> {
+        let url = Url::parse(url).with_context(|| format!("Invalid object store url: {}", url))?;
+        match url.scheme() {
+            "s3" | "gs" => Ok(Self { url }),
+            scheme => bail!("Unsupported object store scheme: {}", scheme),
+        }
+    }
+
+    fn object_url(&self, path: &Path) -> String {
+        let path = path
+            .components()
+            .filter_map(|c| c.as_os_str().to_str())
+            .collect::<Vec<_>>()
+            .join("/");
+        format!("{}/{}", self.url.as_str().trim_end_matches('/'), path)
+    }
+
+    fn command(&self) -> Command {
+        match self.url.scheme() {
+            "s3" => {
+                let mut cmd = Command::new("aws");
+                cmd.arg("s3");
+                cmd
+            }
+            _ => Command::new("gsutil"),
+        }
+    }
+
+    async fn exec(&self, cmd: &mut Command) -> Result<String> {
+        let output = cmd.output().await?;
+        if !output.status.success() {
+            bail!("{}", String::from_utf8(output.stderr)?);
+        }
+        Ok(String::from_utf8(output.stdout)?)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait]
+impl SchemaStore for ObjectStore {
+    async fn list(&self) -> Result<Vec<PathBuf>> {
+        let base = self.object_url(Path::new(""));
+        let mut cmd = self.command();
+        if self.url.scheme() == "s3" {
+            cmd.args(["ls", "--recursive", base.as_str()]);
+        } else {
+            cmd.args(["ls", "-r", format!("{}**", base).as_str()]);
+        }
+
+        let output = cmd.output().await?;
+        let stderr = String::from_utf8(output.stderr)?;
+        // both cli fail when there's no object under the prefix
+        if !output.status.success() {
+            if stderr.trim().is_empty() || stderr.contains("matched no objects") {
+                return Ok(vec![]);
+            }
+            bail!("{}", stderr);
+        }
+
+        // `aws s3 ls` prints `<date> <time> <size> <key>`, `gsutil ls` prints the object urls
+        let prefix = if self.url.scheme() == "s3" {
+            self.url.path().trim_start_matches('/').to_owned()
+        } else {
+            base.clone()
+        };
+        let mut files = String::from_utf8(output.stdout)?
+            .lines()
+            .filter_map(|line| {
+                let name = line.split_whitespace().last()?;
+                let name = name.strip_prefix(prefix.as_str())?;
+                Some(PathBuf::from(name.trim_start_matches('/')))
+            })
+            .filter(|p| is_sql_file(p))
+            .collect::<Vec<_>>();
+        files.sort();
+        Ok(files)
+    }
+
+    async fn read(&self, path: &Path) -> Result<String> {
+        let url = self.object_url(path);
+        let mut cmd = self.command();
+        if self.url.scheme() == "s3" {
+            cmd.args(["cp", url.as_str(), "-"]);
+        } else {
+            cmd.args(["cat", url.as_str()]);
+        }
+        self.exec(&mut cmd)
+            .await
+            .with_context(|| format!("Failed to read object: {}", url))
+    }
+
+    async fn write(&self, path: &Path, content: &str) -> Result<()> {
+        // stage the content in a temp file so that both cli could simply copy it
+        let tmp = std::env::temp_dir().join(format!("renovate-{}.sql", Uuid::new_v4()));
+        fs::write(&tmp, content).await?;
+
+        let url = self.object_url(path);
+        let mut cmd = self.command();
+        cmd.arg("cp").arg(&tmp).arg(&url);
+        let ret = self.exec(&mut cmd).await;
+        fs::remove_file(&tmp).await?;
+
+        ret.with_context(|| format!("Failed to write object: {}", url))?;
+        Ok(())
+    }
+
+    async fn remove(&self, path: &Path) -> Result<()> {
+        let url = self.object_url(path);
+        let mut cmd = self.command();
+        cmd.args(["rm", url.as_str()]);
+        self.exec(&mut cmd)
+            .await
+            .with_context(|| format!("Failed to remove object: {}", url))?;
+        Ok(())
+    }
+}
+
+fn is_sql_file(p: &Path) -> bool {
+    p.extension().map(|ext| ext == "sql").unwrap_or(false) && ignore_file(p, "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::RenovateOutputConfig, SchemaLoader, SqlLoader};
+
+    #[tokio::test]
+    async fn memory_store_should_round_trip_schema() -> Result<()> {
+        let sql =
+            "CREATE TABLE public.todos (id uuid, title text); CREATE TABLE other.users (id uuid);";
+        let schema = SqlLoader::new(sql).load().await?;
+        let store = MemoryStore::new();
+        store.write(Path::new("_meta/notes.sql"), "garbage").await?;
+        store
+            .write(Path::new("public/00_stale.sql"), "SELECT 1")
+            .await?;
+
+        schema
+            .save_to(&store, &RenovateOutputConfig::new("."))
+            .await?;
+        let files = store.list().await?;
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from("other/04_tables.sql"),
+                PathBuf::from("public/04_tables.sql")
+            ]
+        );
+        assert!(store.files().contains_key(Path::new("_meta/notes.sql")));
+
+        let loaded = store.load().await?;
+        assert_eq!(loaded.sql(true), schema.sql(true));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn save_to_should_only_touch_changed_files() -> Result<()> {
+        let config = RenovateOutputConfig::new(".");
+        let store = MemoryStore::new();
+        store
+            .write(Path::new("public/00_stale.sql"), "SELECT 1")
+            .await?;
+        let schema = SqlLoader::new(
+            "CREATE TABLE public.todos (id uuid); CREATE TABLE other.users (id uuid);",
+        )
+        .load()
+        .await?;
+        assert_eq!(schema.save_to(&store, &config).await?, 3);
+        assert_eq!(schema.save_to(&store, &config).await?, 0);
+
+        let schema = SqlLoader::new(
+            "CREATE TABLE public.todos (id uuid, title text); CREATE TABLE other.users (id uuid);",
+        )
+        .load()
+        .await?;
+        assert_eq!(schema.save_to(&store, &config).await?, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn save_to_dir_should_leave_no_workspace() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = RenovateOutputConfig::new(dir.path());
+        let repo = LocalRepo::new(dir.path());
+        repo.write(Path::new("public/00_stale.sql"), "SELECT 1")
+            .await?;
+        let schema = SqlLoader::new("CREATE TABLE public.todos (id uuid);")
+            .load()
+            .await?;
+        assert_eq!(schema.save_to_dir(dir.path(), &config).await?, 2);
+        assert_eq!(schema.save_to_dir(dir.path(), &config).await?, 0);
+
+        assert_eq!(repo.load().await?.sql(true), schema.sql(true));
+        assert!(!dir.path().join("public/00_stale.sql").exists());
+        let state: Vec<_> = std::fs::read_dir(dir.path().join(".renovate"))?
+            .map(|entry| entry.map(|e| e.file_name()))
+            .collect::<std::io::Result<_>>()?;
+        assert_eq!(state, vec![".gitignore"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn save_to_dir_should_keep_ignored_statements() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = RenovateOutputConfig::new(dir.path());
+        let repo = LocalRepo::new(dir.path());
+        repo.write(
+            Path::new("public/tables.sql"),
+            "-- renovate:ignore\nCREATE TABLE public.audit (id int);\n",
+        )
+        .await?;
+        let schema = SqlLoader::new(
+            "CREATE TABLE public.audit (id int); CREATE TABLE public.todos (id int);",
+        )
+        .load()
+        .await?;
+        schema.save_to_dir(dir.path(), &config).await?;
+
+        let ignored = std::fs::read_to_string(dir.path().join(crate::plan::IGNORE_FILE))?;
+        assert_eq!(ignored, "CREATE TABLE public.audit (id int);\n");
+        // already kept, so the next save leaves it alone
+        assert_eq!(schema.save_to_dir(dir.path(), &config).await?, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn preview_save_should_not_write() -> Result<()> {
+        let config = RenovateOutputConfig::new(".");
+        let store = MemoryStore::new();
+        store
+            .write(Path::new("public/00_stale.sql"), "SELECT 1")
+            .await?;
+        let schema = SqlLoader::new("CREATE TABLE public.todos (id uuid);")
+            .load()
+            .await?;
+
+        let changes = schema.preview_save(&store, &config).await?;
+        let kinds = changes
+            .iter()
+            .map(|c| (c.path.to_str().unwrap(), c.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                ("public/00_stale.sql", crate::ChangeKind::Removed),
+                ("public/04_tables.sql", crate::ChangeKind::Added)
+            ]
+        );
+        assert_eq!(
+            store.list().await?,
+            vec![PathBuf::from("public/00_stale.sql")]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unresolved_conflicts_should_not_load() -> Result<()> {
+        let config = RenovateOutputConfig::new(".");
+        let base = SqlLoader::new("CREATE TABLE public.todos (id uuid);").parse()?;
+        let local = SqlLoader::new("CREATE TABLE public.todos (id uuid, title text);").parse()?;
+        let remote = SqlLoader::new("CREATE TABLE public.todos (id uuid, done bool);").parse()?;
+        let merge = local.merge(&base, &remote)?;
+        assert_eq!(merge.conflicts.len(), 1);
+
+        let store = MemoryStore::from(merge.schema.render_merge(&merge.conflicts, &config)?);
+        let content = store.read(Path::new("public/04_tables.sql")).await?;
+        assert!(content.starts_with("<<<<<<< local: table public.todos\n"));
+        assert!(content.contains("title"));
+        assert!(content.contains("=======\n"));
+        assert!(content.contains("done"));
+        assert!(content.ends_with(">>>>>>> remote\n"));
+        assert!(store.load().await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn overlay_store_should_layer_files_on_base() -> Result<()> {
+        let base = MemoryStore::new();
+        base.write(
+            Path::new("public/04_tables.sql"),
+            "CREATE TABLE public.todos (id int);",
+        )
+        .await?;
+        base.write(
+            Path::new("public/05_extra.sql"),
+            "CREATE TABLE public.legacy (id int);",
+        )
+        .await?;
+        let tenant = MemoryStore::new();
+        tenant
+            .write(
+                Path::new("public/05_extra.sql"),
+                "CREATE TABLE public.notes (id int);",
+            )
+            .await?;
+        tenant
+            .write(
+                Path::new("tenant/04_tables.sql"),
+                "CREATE TABLE tenant.notes (id int);",
+            )
+            .await?;
+
+        let store = OverlayStore::new(base, tenant.clone());
+        assert_eq!(store.list().await?.len(), 3);
+        let schema = store.load().await?;
+        assert!(schema.tables["public"].contains_key("todos"));
+        assert!(schema.tables["public"].contains_key("notes"));
+        assert!(!schema.tables["public"].contains_key("legacy"));
+        assert!(schema.tables["tenant"].contains_key("notes"));
+
+        store
+            .write(
+                Path::new("public/06_more.sql"),
+                "CREATE TABLE public.more (id int);",
+            )
+            .await?;
+        assert!(tenant.files().contains_key(Path::new("public/06_more.sql")));
+        Ok(())
+    }
+
+    #[cfg(feature = "db")]
+    #[tokio::test]
+    async fn overlay_files_should_only_differ_from_base() -> Result<()> {
+        let base = crate::SchemaFiles::from([
+            (
+                "public/04_tables.sql".into(),
+                "CREATE TABLE public.todos (id int);\n".to_owned(),
+            ),
+            (
+                "public/05_views.sql".into(),
+                "CREATE VIEW public.v AS SELECT 1;\n".to_owned(),
+            ),
+            (
+                "tenant/04_tables.sql".into(),
+                "CREATE TABLE tenant.notes (id int);".to_owned(),
+            ),
+        ]);
+        let schema = SqlLoader::new(
+            "CREATE TABLE public.todos (id int, title text); CREATE TABLE tenant.notes (id int);",
+        )
+        .load()
+        .await?;
+        let files = super::super::overlay_files(schema.render(&Default::default()), &base);
+        assert_eq!(
+            files.keys().collect::<Vec<_>>(),
+            vec![
+                Path::new("public/04_tables.sql"),
+                Path::new("public/05_views.sql")
+            ]
+        );
+        // hides the view of the base, which is gone
+        assert!(files[Path::new("public/05_views.sql")].is_empty());
+
+        let store = OverlayStore::new(MemoryStore::from(base), MemoryStore::from(files));
+        assert_eq!(store.load().await?.sql(true), schema.sql(true));
+        Ok(())
+    }
+
+    #[test]
+    fn object_store_should_only_accept_s3_and_gs() {
+        let store = ObjectStore::new("s3://bucket/schemas/").unwrap();
+        assert_eq!(
+            store.object_url(Path::new("public/04_tables.sql")),
+            "s3://bucket/schemas/public/04_tables.sql"
+        );
+        assert!(ObjectStore::new("gs://bucket").is_ok());
+        assert!(ObjectStore::new("https://example.com/schemas").is_err());
+    }
+}
@@ -0,0 +1,59 @@
+use crate::SchemaTag;
+use anyhow::{bail, Context, Result};
+use std::{fs, io::ErrorKind, path::Path};
+
+/// the tags of the schema, under `_meta` of the local repo which the loader skips
+pub(crate) const TAGS_FILE: &str = "_meta/tags.yml";
+
+/// The tags recorded in the local repo at `path`, oldest first.
+pub(crate) fn load_tags(path: &Path) -> Result<Vec<SchemaTag>> {
+    let file = path.join(TAGS_FILE);
+    match fs::read_to_string(&file) {
+        Ok(content) => serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", file.display())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Record a new tag in the local repo at `path`. The names of the tags are unique.
+pub(crate) fn add_tag(path: &Path, tag: SchemaTag) -> Result<()> {
+    let mut tags = load_tags(path)?;
+    if tags.iter().any(|t| t.name == tag.name) {
+        bail!("Tag {} already exists", tag.name);
+    }
+    tags.push(tag);
+
+    let file = path.join(TAGS_FILE);
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(file, serde_yaml::to_string(&tags)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn tags_should_be_recorded_once() -> Result<()> {
+        let dir = tempdir()?;
+        let tag = |name: &str| SchemaTag {
+            name: name.to_owned(),
+            fingerprint: "0123456789abcdef".to_owned(),
+            commit: "4b825dc642cb6eb9a060e54bf8d69288fbee4904".to_owned(),
+            created_at: 1700000000,
+        };
+        assert!(load_tags(dir.path())?.is_empty());
+
+        add_tag(dir.path(), tag("v1.3.0"))?;
+        add_tag(dir.path(), tag("v1.4.0"))?;
+        assert!(add_tag(dir.path(), tag("v1.3.0")).is_err());
+
+        let tags = load_tags(dir.path())?;
+        assert_eq!(tags, vec![tag("v1.3.0"), tag("v1.4.0")]);
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+use crate::{config::VcsBackend, NoopVcs, SchemaFiles, Vcs};
+#[cfg(feature = "git")]
+use crate::{GitRepo, GitVcs};
+use anyhow::Result;
+#[cfg(feature = "git")]
+use anyhow::{bail, Context};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+impl VcsBackend {
+    /// Open the vcs for the repo at the given path.
+    pub fn open(self, path: impl Into<PathBuf>) -> Box<dyn Vcs> {
+        let path = path.into();
+        match self {
+            VcsBackend::Git => git_vcs(path),
+            VcsBackend::None => Box::new(NoopVcs),
+            VcsBackend::Auto => {
+                if is_foreign(&path) {
+                    info!("repo is not managed by git, skip vcs operations");
+                    Box::new(NoopVcs)
+                } else {
+                    git_vcs(path)
+                }
+            }
+        }
+    }
+}
+
+/// Whether the nearest repo enclosing `path` is managed by another vcs, e.g. jj or hg. The
+/// schema dir could be nested in the repo, so the markers are looked up in its ancestors too; a
+/// jj repo colocated with git is a git repo.
+fn is_foreign(path: &Path) -> bool {
+    let path = std::env::current_dir()
+        .map(|cwd| cwd.join(path))
+        .unwrap_or_else(|_| path.to_path_buf());
+    let path = path.canonicalize().unwrap_or(path);
+    let marker = path.ancestors().find_map(|dir| {
+        [".git", ".jj", ".hg"]
+            .into_iter()
+            .find(|m| dir.join(m).exists())
+    });
+    matches!(marker, Some(".jj" | ".hg"))
+}
+
+#[cfg(feature = "git")]
+fn git_vcs(path: PathBuf) -> Box<dyn Vcs> {
+    Box::new(GitVcs::new(path))
+}
+
+#[cfg(not(feature = "git"))]
+fn git_vcs(_path: PathBuf) -> Box<dyn Vcs> {
+    tracing::warn!("renovate is built without the git feature, skip vcs operations");
+    Box::new(NoopVcs)
+}
+
+#[cfg(feature = "git")]
+impl GitVcs {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+// git2::Repository is not Sync, so the repo is opened on demand instead of being kept around
+#[cfg(feature = "git")]
+impl Vcs for GitVcs {
+    fn is_dirty(&self) -> Result<bool> {
+        // nothing is written yet
+        if !self.path.exists() {
+            return Ok(false);
+        }
+        match GitRepo::open(&self.path) {
+            Ok(repo) => Ok(repo.is_dirty_under(&self.path)?),
+            Err(e) if e.code() == git2::ErrorCode::NotFound => {
+                info!(
+                    "{} is not in a git repo, skip the dirty check",
+                    self.path.display()
+                );
+                Ok(false)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn commit(&self, message: &str) -> Result<()> {
+        // the schema dir could be nested in a larger repo, so only its own changes are committed
+        let repo = match GitRepo::open(&self.path) {
+            Ok(repo) => repo,
+            Err(_) => GitRepo::init(&self.path)?,
+        };
+        if repo.is_dirty_under(&self.path)? {
+            repo.commit_under(message, &self.path)?;
+        }
+        Ok(())
+    }
+
+    fn check_branch(&self, name: &str) -> Result<()> {
+        let repo = GitRepo::open(&self.path)
+            .with_context(|| format!("cannot create branch {}: not in a git repo", name))?;
+        if !git2::Reference::is_valid_name(&format!("refs/heads/{}", name)) {
+            bail!("cannot create branch {}: invalid branch name", name);
+        }
+        if repo.find_last_commit().is_err() {
+            bail!("cannot create branch {}: the repo has no commit yet", name);
+        }
+        if repo.has_branch(name) {
+            bail!("cannot create branch {}: it already exists", name);
+        }
+        Ok(())
+    }
+
+    fn branch(&self, name: &str) -> Result<()> {
+        self.check_branch(name)?;
+        let repo = GitRepo::open(&self.path)?;
+        repo.create_branch(name)?;
+        Ok(())
+    }
+
+    fn head(&self) -> Result<Option<String>> {
+        let repo = match GitRepo::open(&self.path) {
+            Ok(repo) => repo,
+            Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        match repo.head_id() {
+            Ok(id) => Ok(Some(id)),
+            // no commit yet
+            Err(e) if e.code() == git2::ErrorCode::UnbornBranch => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn files_at(&self, commit: &str) -> Result<SchemaFiles> {
+        let repo = GitRepo::open(&self.path)?;
+        Ok(repo.files_under_at(commit, &self.path)?)
+    }
+}
+
+impl Vcs for NoopVcs {
+    fn is_dirty(&self) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn commit(&self, _message: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn auto_backend_should_skip_foreign_vcs() -> Result<()> {
+        let dir = tempdir()?;
+        std::fs::create_dir(dir.path().join(".jj"))?;
+        std::fs::write(dir.path().join("all.sql"), "CREATE TABLE foo (id int);")?;
+
+        let vcs = VcsBackend::Auto.open(dir.path());
+        vcs.commit("should be ignored")?;
+        assert!(!dir.path().join(".git").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn auto_backend_should_detect_foreign_vcs_above_the_schema_dir() -> Result<()> {
+        let dir = tempdir()?;
+        std::fs::create_dir(dir.path().join(".jj"))?;
+        let schema = dir.path().join("db/schema");
+        std::fs::create_dir_all(&schema)?;
+        std::fs::write(schema.join("all.sql"), "CREATE TABLE foo (id int);")?;
+
+        let vcs = VcsBackend::Auto.open(&schema);
+        vcs.commit("should be ignored")?;
+        assert!(!schema.join(".git").exists());
+        assert!(!dir.path().join(".git").exists());
+
+        // colocated with git
+        std::fs::create_dir(dir.path().join(".git"))?;
+        assert!(!is_foreign(&schema));
+        Ok(())
+    }
+
+    #[cfg(feature = "git")]
+    #[test]
+    fn git_backend_should_commit_changes() -> Result<()> {
+        let dir = tempdir()?;
+        std::fs::write(dir.path().join("all.sql"), "CREATE TABLE foo (id int);")?;
+
+        let vcs = VcsBackend::Git.open(dir.path());
+        vcs.commit("init")?;
+        assert!(!vcs.is_dirty()?);
+
+        std::fs::write(dir.path().join("all.sql"), "CREATE TABLE foo (id bigint);")?;
+        assert!(vcs.is_dirty()?);
+        Ok(())
+    }
+
+    #[test]
+    fn branch_should_be_checked_before_writing() -> Result<()> {
+        assert!(NoopVcs.check_branch("schema-sync/2023-01-01").is_err());
+        assert!(NoopVcs.branch("schema-sync/2023-01-01").is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "git")]
+    #[test]
+    fn git_branch_should_need_a_commit() -> Result<()> {
+        let dir = tempdir()?;
+        let vcs = VcsBackend::Git.open(dir.path());
+        assert!(vcs.check_branch("sync").is_err());
+
+        std::fs::write(dir.path().join("all.sql"), "CREATE TABLE foo (id int);")?;
+        vcs.commit("init")?;
+        vcs.check_branch("sync")?;
+        assert!(vcs.check_branch("bad..name").is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "git")]
+    #[test]
+    fn non_git_directory_should_not_be_dirty() -> Result<()> {
+        let dir = tempdir()?;
+        std::fs::write(dir.path().join("all.sql"), "CREATE TABLE foo (id int);")?;
+
+        let vcs = VcsBackend::Git.open(dir.path());
+        assert!(!vcs.is_dirty()?);
+        Ok(())
+    }
+}
@@ -1,15 +1,78 @@
-use crate::{DatabaseSchema, Differ, MigrationPlanner, NodeDiff, NodeItem};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::observer::DiffBuffer;
+use crate::{
+    config::{
+        Preset, RenovateDiffConfig, RenovateDiffRenderConfig, RenovateOutputConfig, Unmanaged,
+    },
+    parser::{EnumType, Extension, Hypertable, Ident, SchemaId},
+    plan::{dropped_objects, make_idempotent, preserve_sequence_values, qualify_references},
+    utils::{diff_hunks, formatted_sql, render_diff, sha256_hex},
+    ChangeKind, DatabaseSchema, Diagnostic, DiagnosticKind, Differ, MigrationPlanner, NodeDiff,
+    NodeItem, NoopObserver, ObjectKind, Observer, SqlLoader,
+};
 use anyhow::Result;
+use pg_query::{protobuf::RangeVar, NodeEnum, NodeRef};
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::prelude::*;
 use std::{
+    borrow::Cow,
     collections::{BTreeMap, BTreeSet},
     hash::Hash,
+    path::PathBuf,
     str::FromStr,
 };
+use tracing::{debug, info, instrument};
+
+/// schemas created by the timescaledb extension, including the chunks of the hypertables
+const TIMESCALE_SCHEMAS: &[&str] = &[
+    "_timescaledb_cache",
+    "_timescaledb_catalog",
+    "_timescaledb_config",
+    "_timescaledb_internal",
+    "timescaledb_experimental",
+    "timescaledb_information",
+];
+
+/// schemas created by the postgis topology and tiger geocoder extensions, by extension
+const POSTGIS_SCHEMAS: &[(&str, &[&str])] = &[
+    ("postgis_tiger_geocoder", &["tiger", "tiger_data"]),
+    ("postgis_topology", &["topology"]),
+];
+
+/// schema of the ledger renovate keeps in the target database, e.g. the checkpoint of a two-phase
+/// apply
+pub(crate) const LEDGER_SCHEMA: &str = "_renovate";
+
+/// relations created by the postgis extension, which pg_dump dumps as the user's
+const POSTGIS_RELATIONS: &[&str] = &[
+    "geography_columns",
+    "geometry_columns",
+    "raster_columns",
+    "raster_overviews",
+    "spatial_ref_sys",
+];
 
 trait SchemaPlan {
-    fn diff_altered(&self, remote: &Self, verbose: bool) -> Result<Vec<String>>;
-    fn diff_added(&self, verbose: bool) -> Result<Vec<String>>;
-    fn diff_removed(&self, verbose: bool) -> Result<Vec<String>>;
+    fn diff_altered(&self, remote: &Self, ctx: PlanContext) -> Result<Vec<String>>;
+    fn diff_added(&self, ctx: PlanContext) -> Result<Vec<String>>;
+    fn diff_removed(&self, ctx: PlanContext) -> Result<Vec<String>>;
+}
+
+/// What to plan beyond the diff config, see `Renovate::kinds`, `Renovate::schemas` and
+/// `Renovate::detect_renames`
+#[derive(Default, Clone, Copy)]
+pub(crate) struct PlanOptions<'a> {
+    pub kinds: Option<&'a BTreeSet<ObjectKind>>,
+    pub schemas: Option<&'a BTreeSet<String>>,
+    /// whether to plan a table as renamed from another one, see `plan_renames`
+    pub renames: Option<&'a (dyn Fn(&str, &str) -> bool + Send + Sync)>,
+}
+
+/// where the diffs are reported to while planning, and how they are rendered
+#[derive(Clone, Copy)]
+struct PlanContext<'a> {
+    observer: &'a dyn Observer,
+    render: &'a RenovateDiffRenderConfig,
 }
 
 impl DatabaseSchema {
@@ -30,84 +93,1000 @@ impl DatabaseSchema {
         let mut sql = String::new();
         if include_schema {
             for schema in &self.schemas {
-                sql.push_str(&format!("CREATE SCHEMA IF NOT EXISTS {};\n", schema));
+                sql.push_str(&format!("CREATE SCHEMA IF NOT EXISTS {};\n", Ident(schema)));
             }
         }
 
         format!("{}{}", sql, self)
     }
 
+    /// Generate the migration plan, printing the diff of every changed object if `verbose`,
+    /// which needs the `console` feature.
     pub fn plan(&self, other: &Self, verbose: bool) -> anyhow::Result<Vec<String>> {
+        #[cfg(feature = "console")]
+        let observer: &dyn Observer = if verbose {
+            &crate::ConsoleObserver
+        } else {
+            &NoopObserver
+        };
+        #[cfg(not(feature = "console"))]
+        let observer: &dyn Observer = {
+            let _ = verbose;
+            &NoopObserver
+        };
+        self.plan_with(other, &RenovateDiffConfig::default(), observer)
+    }
+
+    /// generate the migration plan with the given diff config, reporting every diff and warning to
+    /// the observer
+    pub fn plan_with(
+        &self,
+        other: &Self,
+        config: &RenovateDiffConfig,
+        observer: &dyn Observer,
+    ) -> anyhow::Result<Vec<String>> {
+        let (migrations, diagnostics) = self.plan_with_diagnostics(other, config, observer)?;
+        for diagnostic in &diagnostics {
+            observer.on_diagnostic(diagnostic);
+        }
+        Ok(migrations)
+    }
+
+    /// Same as `plan_with`, with the warnings returned instead, e.g. the unmanaged objects left
+    /// untouched, or the enum types dropped and created again.
+    pub fn plan_with_diagnostics(
+        &self,
+        other: &Self,
+        config: &RenovateDiffConfig,
+        observer: &dyn Observer,
+    ) -> anyhow::Result<(Vec<String>, Vec<Diagnostic>)> {
+        self.plan_with_options(other, config, PlanOptions::default(), observer)
+    }
+
+    /// same as `plan_with_diagnostics`, for the objects within the kinds and schemas of `options`
+    /// only
+    #[instrument(skip_all)]
+    pub(crate) fn plan_with_options(
+        &self,
+        other: &Self,
+        config: &RenovateDiffConfig,
+        options: PlanOptions,
+        observer: &dyn Observer,
+    ) -> anyhow::Result<(Vec<String>, Vec<Diagnostic>)> {
+        if let Some(preset) = config.preset {
+            let config = RenovateDiffConfig {
+                preset: None,
+                ..config.clone()
+            };
+            return self.without_platform(preset).plan_with_options(
+                &other.without_platform(preset),
+                &config,
+                options,
+                observer,
+            );
+        }
+
+        let mut diagnostics = Vec::new();
+        let other = match config.unmanaged {
+            Unmanaged::Drop => Cow::Borrowed(other),
+            policy => {
+                let mut remote = other.clone();
+                for item in remote.retain_managed(self) {
+                    if policy == Unmanaged::Warn {
+                        let message =
+                            format!("{} is not managed by the local repo, left untouched", item);
+                        diagnostics.push(Diagnostic::new(DiagnosticKind::Unmanaged, message));
+                    } else {
+                        info!("ignore unmanaged object: {}", item);
+                    }
+                }
+                Cow::Owned(remote)
+            }
+        };
+
+        // scoped after the unmanaged objects are told apart, which takes every local object
+        if options.kinds.is_none() && options.schemas.is_none() {
+            let migrations = self.plan_diffs(
+                other.as_ref(),
+                config,
+                options.renames,
+                observer,
+                &mut diagnostics,
+            )?;
+            return Ok((migrations, diagnostics));
+        }
+        let (mut local, mut remote) = (self.clone(), other.into_owned());
+        if let Some(kinds) = options.kinds {
+            local.retain_kinds(kinds);
+            remote.retain_kinds(kinds);
+            // the schema may still have objects of the skipped kinds
+            remote.schemas.retain(|name| local.schemas.contains(name));
+        }
+        if let Some(schemas) = options.schemas {
+            local.retain_schemas(schemas);
+            remote.retain_schemas(schemas);
+        }
+        let migrations =
+            local.plan_diffs(&remote, config, options.renames, observer, &mut diagnostics)?;
+        Ok((migrations, diagnostics))
+    }
+
+    /// the migration plan of every object of both schemas
+    fn plan_diffs(
+        &self,
+        other: &Self,
+        config: &RenovateDiffConfig,
+        renames: Option<&(dyn Fn(&str, &str) -> bool + Send + Sync)>,
+        observer: &dyn Observer,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> anyhow::Result<Vec<String>> {
+        let ctx = PlanContext {
+            observer,
+            render: &config.render,
+        };
         let mut migrations: Vec<String> = Vec::new();
 
+        // rename the tables, then diff against the remote as it is after
+        let renamed = match renames {
+            Some(confirm) => {
+                let (renames, renamed) = self.plan_renames(other, confirm, observer)?;
+                migrations.extend(renames);
+                renamed
+            }
+            None => None,
+        };
+        let other = renamed.as_ref().unwrap_or(other);
+
         // add schema names
         migrations.extend(schema_name_added(&self.schemas, &other.schemas)?);
 
+        // move the relations to their new schema, then diff against the remote as it is after
+        let (moves, moved) = self.plan_moves(other, observer)?;
+        migrations.extend(moves);
+        let other = moved.as_ref().unwrap_or(other);
+
         // diff on composite types
         migrations.extend(schema_diff(
             &self.composite_types,
             &other.composite_types,
-            verbose,
+            ctx,
         )?);
-        migrations.extend(schema_diff(&self.enum_types, &other.enum_types, verbose)?);
+        migrations.extend(schema_diff(&self.enum_types, &other.enum_types, ctx)?);
+        diagnostics.extend(recreated_enums(&self.enum_types, &other.enum_types));
         // diff on sequences
-        migrations.extend(schema_diff(&self.sequences, &other.sequences, verbose)?);
+        migrations.extend(schema_diff(&self.sequences, &other.sequences, ctx)?);
         // diff on tables
-        migrations.extend(schema_diff(&self.tables, &other.tables, verbose)?);
+        migrations.extend(schema_diff(&self.tables, &other.tables, ctx)?);
 
         // diff on table related stuff
         migrations.extend(schema_diff(
             &self.table_sequences,
             &other.table_sequences,
-            verbose,
+            ctx,
         )?);
         migrations.extend(schema_diff(
             &self.table_constraints,
             &other.table_constraints,
-            verbose,
-        )?);
-        migrations.extend(schema_diff(
-            &self.table_indexes,
-            &other.table_indexes,
-            verbose,
+            ctx,
         )?);
+        migrations.extend(schema_diff(&self.table_indexes, &other.table_indexes, ctx)?);
         migrations.extend(schema_diff(
             &self.table_policies,
             &other.table_policies,
-            verbose,
+            ctx,
         )?);
 
         // diff on rls
-        migrations.extend(schema_diff(&self.table_rls, &other.table_rls, verbose)?);
+        migrations.extend(schema_diff(&self.table_rls, &other.table_rls, ctx)?);
         // diff on table owners
-        migrations.extend(schema_diff(
-            &self.table_owners,
-            &other.table_owners,
-            verbose,
-        )?);
+        migrations.extend(schema_diff(&self.table_owners, &other.table_owners, ctx)?);
+
+        // diff on timescaledb hypertables
+        let (local_hypertables, remote_hypertables) =
+            changed_hypertables(&self.table_hypertables, &other.table_hypertables);
+        migrations.extend(schema_diff(&local_hypertables, &remote_hypertables, ctx)?);
 
         // diff on views
-        migrations.extend(schema_diff(&self.views, &other.views, verbose)?);
+        migrations.extend(schema_diff(&self.views, &other.views, ctx)?);
         // diff on materialized views
-        migrations.extend(schema_diff(&self.mviews, &other.mviews, verbose)?);
-        // diff on functions
-        migrations.extend(schema_diff(&self.functions, &other.functions, verbose)?);
+        migrations.extend(schema_diff(&self.mviews, &other.mviews, ctx)?);
+        // diff on functions. The triggers executing the functions to drop are dropped first, and
+        // like the triggers of the dropped tables, created again after the functions
+        let function_migrations = schema_diff(&self.functions, &other.functions, ctx)?;
+        let dropped = dropped_objects(&function_migrations);
+        let mut remote_triggers = other.table_triggers.clone();
+        for triggers in remote_triggers.values_mut() {
+            let cascaded: Vec<_> = triggers
+                .iter()
+                .filter(|(_, trigger)| trigger.function().map_or(false, |f| dropped.contains(&f)))
+                .map(|(name, _)| name.clone())
+                .collect();
+            for name in cascaded {
+                if let Some(trigger) = triggers.remove(&name) {
+                    migrations.push(trigger.revert()?.deparse()?);
+                }
+            }
+        }
+        migrations.extend(function_migrations);
 
         // diff on triggers
-        migrations.extend(schema_diff(
-            &self.table_triggers,
-            &other.table_triggers,
-            verbose,
-        )?);
+        let dropped = dropped_objects(&migrations);
+        remote_triggers.retain(|table, _| !dropped.contains(&table.to_string()));
+        migrations.extend(schema_diff(&self.table_triggers, &remote_triggers, ctx)?);
 
-        // diff on privileges
-        migrations.extend(schema_diff(&self.privileges, &other.privileges, verbose)?);
+        // diff on objects handled by the registered handlers
+        migrations.extend(schema_diff(&self.custom_nodes, &other.custom_nodes, ctx)?);
+
+        // diff on privileges. The grants of the objects dropped (and recreated) by the plan are
+        // gone, so the local ones are granted again after the objects are created
+        let dropped = dropped_objects(&migrations);
+        let remote_privileges = if dropped.is_empty() {
+            Cow::Borrowed(&other.privileges)
+        } else {
+            Cow::Owned(
+                other
+                    .privileges
+                    .iter()
+                    .filter(|(id, _)| !dropped.contains(*id))
+                    .map(|(id, items)| (id.clone(), items.clone()))
+                    .collect(),
+            )
+        };
+        migrations.extend(schema_diff(&self.privileges, &remote_privileges, ctx)?);
 
         // finally, drop the schema names
         migrations.extend(schema_name_removed(&self.schemas, &other.schemas)?);
 
+        // never rely on the search_path of the role applying the plan
+        migrations = migrations
+            .iter()
+            .map(|sql| qualify_references(sql, config.default_schema()))
+            .collect::<Result<_>>()?;
+        if config.idempotent {
+            migrations = migrations
+                .iter()
+                .map(|sql| make_idempotent(sql))
+                .collect::<Result<_>>()?;
+        }
+        if config.preserve_sequence_values {
+            migrations = preserve_sequence_values(migrations)?;
+        }
+
+        info!(statements = migrations.len(), "migration planned");
         Ok(migrations)
     }
+
+    /// Remove the objects of the kinds not in `kinds`. The schema names are left as is.
+    pub fn retain_kinds(&mut self, kinds: &BTreeSet<ObjectKind>) {
+        let skipped = |kind| !kinds.contains(&kind);
+        if skipped(ObjectKind::Types) {
+            self.composite_types.clear();
+        }
+        if skipped(ObjectKind::Enums) {
+            self.enum_types.clear();
+        }
+        if skipped(ObjectKind::Sequences) {
+            self.sequences.clear();
+        }
+        if skipped(ObjectKind::Tables) {
+            self.tables.clear();
+            self.table_sequences.clear();
+        }
+        if skipped(ObjectKind::Constraints) {
+            self.table_constraints.clear();
+        }
+        if skipped(ObjectKind::Indexes) {
+            self.table_indexes.clear();
+        }
+        if skipped(ObjectKind::Policies) {
+            self.table_policies.clear();
+            self.table_rls.clear();
+        }
+        if skipped(ObjectKind::Owners) {
+            self.table_owners.clear();
+        }
+        if skipped(ObjectKind::Hypertables) {
+            self.table_hypertables.clear();
+        }
+        if skipped(ObjectKind::Views) {
+            self.views.clear();
+        }
+        if skipped(ObjectKind::Mviews) {
+            self.mviews.clear();
+        }
+        if skipped(ObjectKind::Functions) {
+            self.functions.clear();
+        }
+        if skipped(ObjectKind::Triggers) {
+            self.table_triggers.clear();
+        }
+        if skipped(ObjectKind::Privileges) {
+            self.privileges.clear();
+        }
+        if skipped(ObjectKind::Custom) {
+            self.custom_nodes.clear();
+        }
+    }
+
+    /// sha256 of the normalized sql of the schema, to detect whether a database changed between
+    /// two points in time. Comparable across renovate builds as long as they render the same sql.
+    pub fn fingerprint(&self) -> String {
+        sha256_hex(self.sql(true))
+    }
+
+    /// report every object in the schema to the observer as fetched
+    pub fn notify_fetched(&self, observer: &dyn Observer) {
+        notify_schema_items(&self.composite_types, observer);
+        notify_schema_items(&self.enum_types, observer);
+        notify_schema_items(&self.sequences, observer);
+        notify_schema_items(&self.tables, observer);
+        notify_schema_items(&self.views, observer);
+        notify_schema_items(&self.mviews, observer);
+        notify_schema_items(&self.functions, observer);
+        notify_schema_items(&self.table_sequences, observer);
+        notify_schema_items(&self.table_constraints, observer);
+        notify_schema_items(&self.table_indexes, observer);
+        notify_schema_items(&self.table_policies, observer);
+        notify_schema_items(&self.table_triggers, observer);
+        notify_items(self.table_rls.values(), observer);
+        notify_items(self.table_owners.values(), observer);
+        notify_items(self.table_hypertables.values(), observer);
+        notify_items(self.privileges.values().flatten(), observer);
+        notify_schema_items(&self.custom_nodes, observer);
+    }
+
+    /// The objects which differ between this (local) schema and the remote one, in the order
+    /// they're rendered. `Added` means the object is only on the remote.
+    pub(crate) fn drift(&self, remote: &Self) -> Vec<Drift> {
+        let local = self.objects();
+        let remote = remote.objects();
+        let find = |objects: &[SchemaObject], key: &str| {
+            objects.iter().find(|o| o.key == key).map(|o| o.sql.clone())
+        };
+
+        let mut drift = Vec::new();
+        for object in &local {
+            match find(&remote, &object.key) {
+                None => drift.push(object.drift(ChangeKind::Removed, object.sql.clone(), "")),
+                Some(sql) if sql != object.sql => {
+                    drift.push(object.drift(ChangeKind::Changed, object.sql.clone(), sql))
+                }
+                Some(_) => {}
+            }
+        }
+        for object in &remote {
+            if find(&local, &object.key).is_none() {
+                drift.push(object.drift(ChangeKind::Added, "", object.sql.clone()));
+            }
+        }
+        drift
+    }
+
+    /// sql of this (local) schema with the accepted drift taken in
+    pub(crate) fn accept_drift(&self, accepted: &[Drift]) -> String {
+        let find = |key: &str| accepted.iter().find(|d| d.key == key);
+        let mut sql = String::new();
+        for object in self.objects() {
+            match find(&object.key) {
+                Some(d) if d.kind == ChangeKind::Removed => {}
+                Some(d) => sql.push_str(&format!("{};\n", d.remote)),
+                None => sql.push_str(&format!("{};\n", object.sql)),
+            }
+        }
+        for d in accepted.iter().filter(|d| d.kind == ChangeKind::Added) {
+            sql.push_str(&format!("{};\n", d.remote));
+        }
+        sql
+    }
+
+    /// Three-way merge of this (local) schema and the remote one, object by object, from the
+    /// `base` both of them changed from, e.g. the last commit. An object changed on one side only
+    /// takes that change; one changed differently on both sides is a conflict, left out of the
+    /// merged schema. The schema names and extensions are merged the same way.
+    pub(crate) fn merge(&self, base: &Self, remote: &Self) -> Result<Merge> {
+        let (local, base_objects, remote_objects) =
+            (self.objects(), base.objects(), remote.objects());
+        let keyed = |objects: &[SchemaObject]| -> BTreeMap<String, String> {
+            objects
+                .iter()
+                .map(|o| (o.key.clone(), o.sql.clone()))
+                .collect()
+        };
+        let (l_sql, b_sql, r_sql) = (keyed(&local), keyed(&base_objects), keyed(&remote_objects));
+
+        let mut sql = String::new();
+        let mut conflicts = Vec::new();
+        let remote_only = remote_objects
+            .iter()
+            .filter(|o| !l_sql.contains_key(&o.key));
+        for object in local.iter().chain(remote_only) {
+            let (l, b, r) = (
+                l_sql.get(&object.key),
+                b_sql.get(&object.key),
+                r_sql.get(&object.key),
+            );
+            let merged = if l == r || r == b {
+                l
+            } else if l == b {
+                r
+            } else {
+                let kind = match (&l, &r) {
+                    (None, _) => ChangeKind::Added,
+                    (_, None) => ChangeKind::Removed,
+                    _ => ChangeKind::Changed,
+                };
+                let (l, r) = (l.cloned(), r.cloned());
+                conflicts.push(object.drift(kind, l.unwrap_or_default(), r.unwrap_or_default()));
+                continue;
+            };
+            if let Some(merged) = merged {
+                sql.push_str(&format!("{};\n", merged));
+            }
+        }
+
+        let mut schema = SqlLoader::new(sql).parse()?;
+        schema
+            .schemas
+            .extend(merge_keys(&self.schemas, &base.schemas, &remote.schemas));
+        // the extensions have no sql of their own, so they're merged by name only
+        let (l_ext, b_ext, r_ext) = (
+            self.extension_items(),
+            base.extension_items(),
+            remote.extension_items(),
+        );
+        let keys = |items: &BTreeMap<_, _>| items.keys().cloned().collect::<BTreeSet<_>>();
+        for key in merge_keys(&keys(&l_ext), &keys(&b_ext), &keys(&r_ext)) {
+            if let Some(item) = l_ext.get(&key).or_else(|| r_ext.get(&key)) {
+                let (s, name) = key;
+                schema
+                    .extensions
+                    .entry(s)
+                    .or_default()
+                    .insert(name, (*item).clone());
+            }
+        }
+        Ok(Merge { schema, conflicts })
+    }
+
+    /// The objects whose sql doesn't load back to the same object, e.g. the definitions of
+    /// partitioned indexes or inherited constraints which come back from introspection in a
+    /// form the parser can't round-trip. Saving them would write files which break the loader.
+    pub(crate) fn unparsable_objects(&self) -> Vec<UnparsableObject> {
+        let mut result = Vec::new();
+        for object in self.objects() {
+            let reason = match SqlLoader::new(format!("{};", object.sql)).parse() {
+                Err(e) => Some(format!("{:#}", e)),
+                Ok(schema) => match schema.objects().into_iter().find(|o| o.key == object.key) {
+                    None => Some("it doesn't load back to the same object".to_owned()),
+                    Some(o) if o.sql != object.sql => Some(format!("it loads back as: {}", o.sql)),
+                    Some(_) => None,
+                },
+            };
+            if let Some(reason) = reason {
+                result.push(UnparsableObject {
+                    type_name: object.type_name,
+                    id: object.id,
+                    reason,
+                    key: object.key,
+                });
+            }
+        }
+        result
+    }
+
+    /// The schema with the `unparsable` objects (see `unparsable_objects`) as they are in `local`
+    /// instead, or left out if `local` doesn't have them, so that it could be saved.
+    pub(crate) fn with_local_unparsable(
+        &self,
+        local: &Self,
+        unparsable: &[UnparsableObject],
+    ) -> Result<Self> {
+        let keys: BTreeSet<&str> = unparsable.iter().map(|o| o.key.as_str()).collect();
+        let mut sql = String::new();
+        let fetched = self.objects().into_iter();
+        let kept = local.objects().into_iter();
+        for object in fetched
+            .filter(|o| !keys.contains(o.key.as_str()))
+            .chain(kept.filter(|o| keys.contains(o.key.as_str())))
+        {
+            sql.push_str(&format!("{};\n", object.sql));
+        }
+        let mut schema = SqlLoader::new(sql).parse()?;
+        schema.schemas.extend(self.schemas.iter().cloned());
+        schema.extensions = self.extensions.clone();
+        Ok(schema)
+    }
+
+    /// The objects whose sql contains `pattern`, case-insensitively, with the file each of them
+    /// is saved to under the output config. A pattern which is an identifier, e.g. a column name,
+    /// only matches whole identifiers, so that `id` doesn't match `valid`.
+    pub(crate) fn grep(&self, pattern: &str, config: &RenovateOutputConfig) -> Vec<SchemaMatch> {
+        let objects: Vec<_> = self
+            .objects()
+            .into_iter()
+            .filter_map(|object| find_match(&object.sql, pattern).map(|pos| (object, pos)))
+            .collect();
+        if objects.is_empty() {
+            return Vec::new();
+        }
+
+        // the sql in the rendered files is only the same as the one of the objects unformatted
+        let files = self.render(&RenovateOutputConfig {
+            format: None,
+            ..config.clone()
+        });
+        objects
+            .into_iter()
+            .map(|(object, pos)| SchemaMatch {
+                type_name: object.type_name,
+                id: object.id,
+                file: files
+                    .iter()
+                    .find(|(_, content)| content.contains(&object.sql))
+                    .map(|(path, _)| path.clone()),
+                excerpt: excerpt(&object.sql, pos, pattern.len()),
+            })
+            .collect()
+    }
+
+    /// The schema without the objects of `ignored`, whatever their definition
+    pub(crate) fn without(&self, ignored: &DatabaseSchema) -> DatabaseSchema {
+        let mut schema = self.clone();
+        remove_nested(&mut schema.composite_types, &ignored.composite_types);
+        remove_nested(&mut schema.enum_types, &ignored.enum_types);
+        remove_nested(&mut schema.sequences, &ignored.sequences);
+        remove_nested(&mut schema.tables, &ignored.tables);
+        remove_nested(&mut schema.views, &ignored.views);
+        remove_nested(&mut schema.mviews, &ignored.mviews);
+        remove_nested(&mut schema.functions, &ignored.functions);
+        remove_nested(&mut schema.table_indexes, &ignored.table_indexes);
+        remove_nested(&mut schema.table_constraints, &ignored.table_constraints);
+        remove_nested(&mut schema.table_sequences, &ignored.table_sequences);
+        remove_nested(&mut schema.table_triggers, &ignored.table_triggers);
+        remove_nested(&mut schema.table_policies, &ignored.table_policies);
+        remove_nested(&mut schema.custom_nodes, &ignored.custom_nodes);
+        remove_items(&mut schema.table_rls, &ignored.table_rls);
+        remove_items(&mut schema.table_owners, &ignored.table_owners);
+        remove_items(&mut schema.table_hypertables, &ignored.table_hypertables);
+        for (key, privileges) in &ignored.privileges {
+            if let Some(existing) = schema.privileges.get_mut(key) {
+                existing.retain(|p| !privileges.contains(p));
+            }
+        }
+        schema
+            .privileges
+            .retain(|_, privileges| !privileges.is_empty());
+        schema
+    }
+
+    /// the kind and id of every object, e.g. `function public.audit`
+    pub(crate) fn object_names(&self) -> Vec<String> {
+        self.objects()
+            .into_iter()
+            .map(|o| format!("{} {}", o.type_name, o.id))
+            .collect()
+    }
+
+    /// The objects added, changed or removed since `old`, by kind and id, e.g. the objects a
+    /// change of a file touches
+    pub(crate) fn changed_objects(
+        &self,
+        old: &DatabaseSchema,
+    ) -> Vec<(ChangeKind, &'static str, String)> {
+        let old: BTreeMap<_, _> = old
+            .objects()
+            .into_iter()
+            .map(|o| (o.key.clone(), o))
+            .collect();
+        let mut new: BTreeMap<_, _> = self
+            .objects()
+            .into_iter()
+            .map(|o| (o.key.clone(), o))
+            .collect();
+        let mut changes = Vec::new();
+        for (key, object) in old {
+            match new.remove(&key) {
+                None => changes.push((ChangeKind::Removed, object.type_name, object.id)),
+                Some(o) if o.sql != object.sql => {
+                    changes.push((ChangeKind::Changed, o.type_name, o.id))
+                }
+                Some(_) => {}
+            }
+        }
+        changes.extend(
+            new.into_values()
+                .map(|o| (ChangeKind::Added, o.type_name, o.id)),
+        );
+        changes
+    }
+
+    /// every object of the schema, in the order they're rendered
+    fn objects(&self) -> Vec<SchemaObject> {
+        let mut objects = Vec::new();
+        collect_schema_objects(&self.composite_types, &mut objects);
+        collect_schema_objects(&self.enum_types, &mut objects);
+        collect_schema_objects(&self.sequences, &mut objects);
+        collect_schema_objects(&self.tables, &mut objects);
+        collect_schema_objects(&self.table_sequences, &mut objects);
+        collect_schema_objects(&self.table_constraints, &mut objects);
+        collect_schema_objects(&self.table_indexes, &mut objects);
+        collect_schema_objects(&self.table_policies, &mut objects);
+        collect_schema_objects(&self.views, &mut objects);
+        collect_schema_objects(&self.mviews, &mut objects);
+        collect_schema_objects(&self.functions, &mut objects);
+        collect_objects(&self.table_rls, &mut objects);
+        collect_objects(&self.table_owners, &mut objects);
+        collect_objects(&self.table_hypertables, &mut objects);
+        collect_schema_objects(&self.table_triggers, &mut objects);
+        // keyed by the grantee rather than the sql, so that granting it more is a change
+        for (key, privileges) in &self.privileges {
+            for item in privileges {
+                let columns: BTreeSet<&str> = item
+                    .privileges
+                    .values()
+                    .flat_map(|p| p.cols.iter().map(String::as_str))
+                    .collect();
+                objects.push(SchemaObject {
+                    type_name: item.type_name(),
+                    key: format!(
+                        "{} {} {} {} {} {}",
+                        item.type_name(),
+                        key,
+                        item.grantee,
+                        item.grant,
+                        item.grant_option,
+                        columns.into_iter().collect::<Vec<_>>().join(",")
+                    ),
+                    id: item.id(),
+                    sql: item.to_string(),
+                });
+            }
+        }
+        collect_schema_objects(&self.custom_nodes, &mut objects);
+        objects
+    }
+
+    /// the extensions of the schema keyed by (schema, name)
+    fn extension_items(&self) -> BTreeMap<(String, String), &Extension> {
+        self.extensions
+            .iter()
+            .flat_map(|(s, items)| {
+                items
+                    .iter()
+                    .map(move |(name, item)| ((s.clone(), name.clone()), item))
+            })
+            .collect()
+    }
+
+    /// whether the extension is installed in any schema
+    pub(crate) fn has_extension(&self, name: &str) -> bool {
+        self.extensions
+            .values()
+            .any(|items| items.contains_key(name))
+    }
+
+    /// a copy of the schema without the objects owned by the managed platform
+    fn without_platform(&self, preset: Preset) -> Self {
+        let mut schema = self.clone();
+        schema.remove_schemas(preset.schemas());
+        for items in schema.extensions.values_mut() {
+            items.retain(|name, _| !preset.extensions().contains(&name.as_str()));
+        }
+
+        let roles = preset.roles();
+        for items in schema.privileges.values_mut() {
+            items.retain(|p| !roles.contains(&p.grantee.as_str()));
+        }
+        schema.privileges.retain(|_, items| !items.is_empty());
+
+        // the handlers' ids are free form: a node is the platform's if its id is qualified with
+        // a platform schema, or if its statement targets a relation in one
+        let schemas = preset.schemas();
+        for items in schema.custom_nodes.values_mut() {
+            items.retain(|id, node| {
+                let qualifier = id.split_once('.').map(|(s, _)| s);
+                let in_platform = qualifier.map_or(false, |s| schemas.contains(&s))
+                    || node.node.nodes().into_iter().any(|(n, _, _)| {
+                        matches!(n, NodeRef::RangeVar(v) if schemas.contains(&v.schemaname.as_str()))
+                    });
+                !in_platform
+            });
+        }
+        schema.custom_nodes.retain(|_, items| !items.is_empty());
+        schema
+    }
+
+    /// remove the objects managed by the extensions themselves, e.g. the chunks of the
+    /// timescaledb hypertables or the postgis `spatial_ref_sys` table, and the ledger of renovate
+    pub(crate) fn remove_extension_objects(&mut self) {
+        self.remove_schemas(&[LEDGER_SCHEMA]);
+        // only when installed, so that the user's objects with the same names are kept
+        if self.has_extension("timescaledb") {
+            self.remove_schemas(TIMESCALE_SCHEMAS);
+            for triggers in self.table_triggers.values_mut() {
+                triggers.remove("ts_insert_blocker");
+            }
+            self.table_triggers
+                .retain(|_, triggers| !triggers.is_empty());
+        }
+
+        for (extension, schemas) in POSTGIS_SCHEMAS {
+            if self.has_extension(extension) {
+                self.remove_schemas(schemas);
+            }
+        }
+        if self.has_extension("postgis") {
+            self.remove_relations(POSTGIS_RELATIONS);
+        }
+    }
+
+    /// remove the relations with the given names in any schema, along with their table level
+    /// objects and privileges
+    fn remove_relations(&mut self, names: &[&str]) {
+        let excluded = |name: &String| names.contains(&name.as_str());
+        let excluded_id = |id: &SchemaId| excluded(&id.name);
+
+        for items in self.tables.values_mut() {
+            items.retain(|name, _| !excluded(name));
+        }
+        for items in self.views.values_mut() {
+            items.retain(|name, _| !excluded(name));
+        }
+
+        self.table_indexes.retain(|id, _| !excluded_id(id));
+        self.table_constraints.retain(|id, _| !excluded_id(id));
+        self.table_sequences.retain(|id, _| !excluded_id(id));
+        self.table_triggers.retain(|id, _| !excluded_id(id));
+        self.table_policies.retain(|id, _| !excluded_id(id));
+        self.table_rls.retain(|id, _| !excluded_id(id));
+        self.table_owners.retain(|id, _| !excluded_id(id));
+        self.privileges.retain(|id, _| match id.split_once('.') {
+            Some((_, name)) => !names.contains(&name),
+            None => true,
+        });
+    }
+
+    /// remove all the objects in the given schemas
+    fn remove_schemas(&mut self, schemas: &[&str]) {
+        self.retain_schemas_by(|s| !schemas.contains(&s));
+    }
+
+    /// Remove the schemas not in `schemas`, along with their objects, e.g. to plan the schemas
+    /// owned by a team only.
+    pub fn retain_schemas(&mut self, schemas: &BTreeSet<String>) {
+        self.retain_schemas_by(|s| schemas.contains(s));
+    }
+
+    fn retain_schemas_by(&mut self, keep: impl Fn(&str) -> bool) {
+        let kept = |s: &String| keep(s);
+        let kept_id = |id: &SchemaId| keep(&id.schema);
+
+        self.schemas.retain(|s| kept(s));
+        self.extensions.retain(|s, _| kept(s));
+        self.composite_types.retain(|s, _| kept(s));
+        self.enum_types.retain(|s, _| kept(s));
+        self.sequences.retain(|s, _| kept(s));
+        self.tables.retain(|s, _| kept(s));
+        self.views.retain(|s, _| kept(s));
+        self.mviews.retain(|s, _| kept(s));
+        self.functions.retain(|s, _| kept(s));
+
+        self.table_indexes.retain(|id, _| kept_id(id));
+        self.table_constraints.retain(|id, _| kept_id(id));
+        self.table_sequences.retain(|id, _| kept_id(id));
+        self.table_triggers.retain(|id, _| kept_id(id));
+        self.table_policies.retain(|id, _| kept_id(id));
+        self.table_rls.retain(|id, _| kept_id(id));
+        self.table_owners.retain(|id, _| kept_id(id));
+        self.table_hypertables.retain(|id, _| kept_id(id));
+
+        // privileges are keyed by the schema or the `schema.name` of the object
+        self.privileges.retain(|id, _| {
+            let s = id.split_once('.').map(|(s, _)| s).unwrap_or(id);
+            keep(s)
+        });
+    }
+
+    /// remove the objects which are not defined in the local schema, and return their names
+    fn retain_managed(&mut self, local: &Self) -> Vec<String> {
+        self.retain_managed_items(local)
+            .into_iter()
+            .map(|(type_name, id)| format!("{} {}", type_name, id))
+            .collect()
+    }
+
+    /// same as `retain_managed`, with the unmanaged objects as (type name, id)
+    fn retain_managed_items(&mut self, local: &Self) -> Vec<(&'static str, String)> {
+        let mut unmanaged = Vec::new();
+
+        let schemas = &local.schemas;
+        self.schemas.retain(|s| {
+            let managed = schemas.contains(s);
+            if !managed {
+                unmanaged.push(("schema", s.clone()));
+            }
+            managed
+        });
+
+        retain_schema_items(
+            &mut self.composite_types,
+            &local.composite_types,
+            &mut unmanaged,
+        );
+        retain_schema_items(&mut self.enum_types, &local.enum_types, &mut unmanaged);
+        retain_schema_items(&mut self.sequences, &local.sequences, &mut unmanaged);
+        retain_schema_items(&mut self.tables, &local.tables, &mut unmanaged);
+        retain_schema_items(&mut self.views, &local.views, &mut unmanaged);
+        retain_schema_items(&mut self.mviews, &local.mviews, &mut unmanaged);
+        retain_schema_items(&mut self.functions, &local.functions, &mut unmanaged);
+        retain_schema_items(&mut self.custom_nodes, &local.custom_nodes, &mut unmanaged);
+
+        // table level objects are managed as long as their relation is managed
+        let relations = local.relation_ids();
+        retain_relation_items(&mut self.table_sequences, &relations, &mut unmanaged);
+        retain_relation_items(&mut self.table_constraints, &relations, &mut unmanaged);
+        retain_relation_items(&mut self.table_indexes, &relations, &mut unmanaged);
+        retain_relation_items(&mut self.table_policies, &relations, &mut unmanaged);
+        retain_relation_items(&mut self.table_triggers, &relations, &mut unmanaged);
+        retain_relation_item(&mut self.table_rls, &relations, &mut unmanaged);
+        retain_relation_item(&mut self.table_owners, &relations, &mut unmanaged);
+        retain_relation_item(&mut self.table_hypertables, &relations, &mut unmanaged);
+
+        let objects: BTreeSet<String> = relations
+            .iter()
+            .map(|id| id.to_string())
+            .chain(local.schemas.iter().cloned())
+            .collect();
+        // explicit grants / revokes in the local schema are managed, e.g. `REVOKE ... FROM PUBLIC`
+        // on a function
+        self.privileges.retain(|id, items| {
+            let managed = objects.contains(id) || local.privileges.contains_key(id);
+            if !managed {
+                unmanaged.extend(items.iter().map(|item| (item.type_name(), item.id())));
+            }
+            managed
+        });
+
+        unmanaged
+    }
+
+    /// The objects in the schema which `other` has no counterpart of, grouped by type name.
+    /// Tables are annotated with their owner, e.g. `public.legacy (owner: postgres)`. The
+    /// indexes, constraints, triggers and policies count on the relations of both sides too.
+    pub fn missing_in(&self, other: &Self) -> BTreeMap<String, Vec<String>> {
+        let mut schema = self.clone();
+        let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (type_name, id) in schema.retain_managed_items(other) {
+            let owner = match type_name {
+                // the owner of an unmanaged table is shown along with the table
+                "table owner" => continue,
+                "table" => id
+                    .parse::<SchemaId>()
+                    .ok()
+                    .and_then(|id| self.table_owners.get(&id)),
+                _ => None,
+            };
+            let id = match owner {
+                Some(owner) => format!("{} (owner: {})", id, owner.owner),
+                None => id,
+            };
+            groups.entry(type_name.to_owned()).or_default().push(id);
+        }
+        // e.g. an index created by hand on a table of the local repo
+        missing_relation_items(&schema.table_indexes, &other.table_indexes, &mut groups);
+        missing_relation_items(
+            &schema.table_constraints,
+            &other.table_constraints,
+            &mut groups,
+        );
+        missing_relation_items(&schema.table_triggers, &other.table_triggers, &mut groups);
+        missing_relation_items(&schema.table_policies, &other.table_policies, &mut groups);
+        groups
+    }
+
+    /// Find the tables, views and sequences moved to another schema with the definition otherwise
+    /// identical, e.g. their file moved from one schema directory to another, and plan them as
+    /// `SET SCHEMA` instead of a drop and a create. Return the statements, and the remote schema
+    /// as it is after them, with the table level objects and privileges moved along, if any
+    /// relation is moved.
+    fn plan_moves(
+        &self,
+        remote: &Self,
+        observer: &dyn Observer,
+    ) -> Result<(Vec<String>, Option<Self>)> {
+        let tables = find_moves(&self.tables, &remote.tables);
+        let views = find_moves(&self.views, &remote.views);
+        let sequences = find_moves(&self.sequences, &remote.sequences);
+        if tables.is_empty() && views.is_empty() && sequences.is_empty() {
+            return Ok((Vec::new(), None));
+        }
+
+        let mut moved = remote.clone();
+        let mut migrations = Vec::new();
+        for (from, to) in &tables {
+            move_item(&mut moved.tables, &self.tables, from, to);
+            move_relation_items(&mut moved, from, to)?;
+            migrations.push(format!(
+                "ALTER TABLE {} SET SCHEMA {}",
+                from,
+                Ident(&to.schema)
+            ));
+        }
+        for (from, to) in &views {
+            move_item(&mut moved.views, &self.views, from, to);
+            move_relation_items(&mut moved, from, to)?;
+            migrations.push(format!(
+                "ALTER VIEW {} SET SCHEMA {}",
+                from,
+                Ident(&to.schema)
+            ));
+        }
+        // the sequences owned by a column are moved along with the table already
+        for (from, to) in &sequences {
+            move_item(&mut moved.sequences, &self.sequences, from, to);
+            move_relation_items(&mut moved, from, to)?;
+            migrations.push(format!(
+                "ALTER SEQUENCE IF EXISTS {} SET SCHEMA {}",
+                from,
+                Ident(&to.schema)
+            ));
+        }
+        for (type_name, items) in [
+            ("table", &tables),
+            ("view", &views),
+            ("sequence", &sequences),
+        ] {
+            for (from, to) in items {
+                let diff = format!("-{}\n+{}\n", from, to);
+                observer.on_diff_computed(type_name, &to.to_string(), ChangeKind::Changed, &diff);
+            }
+        }
+        Ok((migrations, Some(moved)))
+    }
+
+    /// Find the tables dropped from a schema while a table with the same definition is created in
+    /// it under another name, and plan the ones `confirm(old, new)` accepts as `RENAME TO`
+    /// instead of a drop and a create. Return the statements, and the remote schema as it is
+    /// after them if any table is renamed.
+    pub(crate) fn plan_renames(
+        &self,
+        remote: &Self,
+        confirm: impl Fn(&str, &str) -> bool,
+        observer: &dyn Observer,
+    ) -> Result<(Vec<String>, Option<Self>)> {
+        let renames: Vec<_> = find_renames(&self.tables, &remote.tables)
+            .into_iter()
+            .filter(|(from, to)| confirm(&from.to_string(), &to.to_string()))
+            .collect();
+        if renames.is_empty() {
+            return Ok((Vec::new(), None));
+        }
+
+        let mut renamed = remote.clone();
+        let mut migrations = Vec::new();
+        for (from, to) in &renames {
+            move_item(&mut renamed.tables, &self.tables, from, to);
+            move_relation_items(&mut renamed, from, to)?;
+            migrations.push(format!(
+                "ALTER TABLE {} RENAME TO {}",
+                from,
+                Ident(&to.name)
+            ));
+            let diff = format!("-{}\n+{}\n", from, to);
+            observer.on_diff_computed("table", &to.to_string(), ChangeKind::Changed, &diff);
+        }
+        Ok((migrations, Some(renamed)))
+    }
+
+    /// ids of all the relations (tables, views, materialized views and sequences) in the schema
+    fn relation_ids(&self) -> BTreeSet<SchemaId> {
+        let mut ids = BTreeSet::new();
+        collect_ids(&self.tables, &mut ids);
+        collect_ids(&self.views, &mut ids);
+        collect_ids(&self.mviews, &mut ids);
+        collect_ids(&self.sequences, &mut ids);
+        ids
+    }
 }
 
 impl<T> SchemaPlan for T
@@ -115,56 +1094,32 @@ where
     T: NodeItem + Clone + FromStr<Err = anyhow::Error> + PartialEq + Eq + 'static,
     NodeDiff<T>: MigrationPlanner<Migration = String>,
 {
-    fn diff_altered(&self, remote: &Self, verbose: bool) -> Result<Vec<String>> {
+    fn diff_altered(&self, remote: &Self, ctx: PlanContext) -> Result<Vec<String>> {
         let diff = remote.diff(self)?;
         if let Some(diff) = diff {
-            if verbose && atty::is(atty::Stream::Stdout) {
-                println!(
-                    "{} {} is changed:\n\n{}",
-                    self.type_name(),
-                    self.id(),
-                    diff.diff
-                );
-            }
-            diff.plan()
+            plan_diff(diff, self.type_name(), &self.id(), ChangeKind::Changed, ctx)
         } else {
             Ok(Vec::new())
         }
     }
 
-    fn diff_added(&self, verbose: bool) -> Result<Vec<String>> {
+    fn diff_added(&self, ctx: PlanContext) -> Result<Vec<String>> {
         let diff = NodeDiff::with_new(self.clone());
-        if verbose && atty::is(atty::Stream::Stdout) {
-            println!(
-                "{} {} is added:\n\n{}",
-                self.type_name(),
-                self.id(),
-                diff.diff,
-            );
-        }
-        diff.plan()
+        plan_diff(diff, self.type_name(), &self.id(), ChangeKind::Added, ctx)
     }
 
-    fn diff_removed(&self, verbose: bool) -> Result<Vec<String>> {
+    fn diff_removed(&self, ctx: PlanContext) -> Result<Vec<String>> {
         let diff = NodeDiff::with_old(self.clone());
-        if verbose && atty::is(atty::Stream::Stdout) {
-            println!(
-                "{} {} is removed:\n\n{}",
-                self.type_name(),
-                self.id(),
-                diff.diff,
-            );
-        }
-        diff.plan()
+        plan_diff(diff, self.type_name(), &self.id(), ChangeKind::Removed, ctx)
     }
 }
 
 impl<T> SchemaPlan for BTreeMap<String, T>
 where
-    T: NodeItem + Clone + FromStr<Err = anyhow::Error> + PartialEq + Eq + 'static,
+    T: NodeItem + Clone + FromStr<Err = anyhow::Error> + PartialEq + Eq + Send + Sync + 'static,
     NodeDiff<T>: MigrationPlanner<Migration = String>,
 {
-    fn diff_altered(&self, remote: &Self, verbose: bool) -> Result<Vec<String>> {
+    fn diff_altered(&self, remote: &Self, ctx: PlanContext) -> Result<Vec<String>> {
         let mut migrations: Vec<String> = Vec::new();
         let keys: BTreeSet<_> = self.keys().collect();
         let other_keys: BTreeSet<_> = remote.keys().collect();
@@ -173,44 +1128,38 @@ where
             let v = self.get(*key).unwrap().clone();
             let (id, t) = (v.id(), v.type_name());
             let diff = NodeDiff::with_new(v);
-            if verbose && atty::is(atty::Stream::Stdout) {
-                println!("{} {} is added:\n\n{}", t, id, diff.diff);
-            }
-            migrations.extend(diff.plan()?);
+            migrations.extend(plan_diff(diff, t, &id, ChangeKind::Added, ctx)?);
         }
         let removed = other_keys.difference(&keys);
         for key in removed {
             let v = remote.get(*key).unwrap().clone();
             let (id, t) = (v.id(), v.type_name());
             let diff = NodeDiff::with_old(v);
-            if verbose && atty::is(atty::Stream::Stdout) {
-                println!("{} {} is removed:\n\n{}", t, id, diff.diff);
-            }
-            migrations.extend(diff.plan()?);
+            migrations.extend(plan_diff(diff, t, &id, ChangeKind::Removed, ctx)?);
         }
-        let intersection = keys.intersection(&other_keys);
-        for key in intersection {
+        let intersection: Vec<_> = keys.intersection(&other_keys).collect();
+        migrations.extend(par_plan(intersection, ctx, |key, ctx| {
             let local: T = self.get(*key).unwrap().to_string().parse()?;
             let remote: T = remote.get(*key).unwrap().to_string().parse()?;
-            migrations.extend(local.diff_altered(&remote, verbose)?);
-        }
+            local.diff_altered(&remote, ctx)
+        })?);
 
         Ok(migrations)
     }
 
-    fn diff_added(&self, verbose: bool) -> Result<Vec<String>> {
+    fn diff_added(&self, ctx: PlanContext) -> Result<Vec<String>> {
         let mut migrations: Vec<String> = Vec::new();
         for item in self.values() {
-            migrations.extend(item.diff_added(verbose)?);
+            migrations.extend(item.diff_added(ctx)?);
         }
 
         Ok(migrations)
     }
 
-    fn diff_removed(&self, verbose: bool) -> Result<Vec<String>> {
+    fn diff_removed(&self, ctx: PlanContext) -> Result<Vec<String>> {
         let mut migrations: Vec<String> = Vec::new();
         for item in self.values() {
-            migrations.extend(item.diff_removed(verbose)?);
+            migrations.extend(item.diff_removed(ctx)?);
         }
         Ok(migrations)
     }
@@ -221,43 +1170,46 @@ where
     T: NodeItem + Clone + FromStr<Err = anyhow::Error> + PartialEq + Eq + Ord + Hash + 'static,
     NodeDiff<T>: MigrationPlanner<Migration = String>,
 {
-    fn diff_altered(&self, remote: &Self, verbose: bool) -> Result<Vec<String>> {
+    fn diff_altered(&self, remote: &Self, ctx: PlanContext) -> Result<Vec<String>> {
         let mut migrations: Vec<String> = Vec::new();
+        let mut removed: Vec<&T> = remote.difference(self).collect();
         let added = self.difference(remote);
         for v in added {
             let (id, t) = (v.id(), v.type_name());
-            let diff = NodeDiff::with_new(v.clone());
-            if verbose && atty::is(atty::Stream::Stdout) {
-                println!("{} {} is added:\n\n{}", t, id, diff.diff);
+            // the same item with different content, e.g. a grant to the same role with another
+            // privilege list: plan the delta instead of dropping and recreating it
+            if let Some(pos) = removed.iter().position(|old| old.id() == id) {
+                let old = removed.remove(pos);
+                if let Some(diff) = old.diff(v)? {
+                    migrations.extend(plan_diff(diff, t, &id, ChangeKind::Changed, ctx)?);
+                }
+                continue;
             }
-            migrations.extend(diff.plan()?);
+            let diff = NodeDiff::with_new(v.clone());
+            migrations.extend(plan_diff(diff, t, &id, ChangeKind::Added, ctx)?);
         }
-        let removed = remote.difference(self);
         for v in removed {
             let (id, t) = (v.id(), v.type_name());
             let diff = NodeDiff::with_old(v.clone());
-            if verbose && atty::is(atty::Stream::Stdout) {
-                println!("{} {} is removed:\n\n{}", t, id, diff.diff);
-            }
-            migrations.extend(diff.plan()?);
+            migrations.extend(plan_diff(diff, t, &id, ChangeKind::Removed, ctx)?);
         }
 
         Ok(migrations)
     }
 
-    fn diff_added(&self, verbose: bool) -> Result<Vec<String>> {
+    fn diff_added(&self, ctx: PlanContext) -> Result<Vec<String>> {
         let mut migrations: Vec<String> = Vec::new();
         for item in self {
-            migrations.extend(item.diff_added(verbose)?);
+            migrations.extend(item.diff_added(ctx)?);
         }
 
         Ok(migrations)
     }
 
-    fn diff_removed(&self, verbose: bool) -> Result<Vec<String>> {
+    fn diff_removed(&self, ctx: PlanContext) -> Result<Vec<String>> {
         let mut migrations: Vec<String> = Vec::new();
         for item in self {
-            migrations.extend(item.diff_removed(verbose)?);
+            migrations.extend(item.diff_removed(ctx)?);
         }
         Ok(migrations)
     }
@@ -268,7 +1220,7 @@ fn schema_name_added(local: &BTreeSet<String>, remote: &BTreeSet<String>) -> Res
 
     let added = local.difference(remote);
     for key in added {
-        migrations.push(format!("CREATE SCHEMA IF NOT EXISTS {}", key));
+        migrations.push(format!("CREATE SCHEMA IF NOT EXISTS {}", Ident(key)));
     }
 
     Ok(migrations)
@@ -279,77 +1231,657 @@ fn schema_name_removed(local: &BTreeSet<String>, remote: &BTreeSet<String>) -> R
 
     let removed = remote.difference(local);
     for key in removed {
-        migrations.push(format!("DROP SCHEMA {}", key));
+        migrations.push(format!("DROP SCHEMA {}", Ident(key)));
     }
 
     Ok(migrations)
 }
 
-fn schema_diff<K, T>(
-    local: &BTreeMap<K, T>,
-    remote: &BTreeMap<K, T>,
-    verbose: bool,
+/// report the diff of an object to the observer, rendered as configured, then generate the
+/// migration for it
+fn plan_diff<T>(
+    diff: NodeDiff<T>,
+    type_name: &str,
+    id: &str,
+    kind: ChangeKind,
+    ctx: PlanContext,
 ) -> Result<Vec<String>>
 where
-    K: Hash + Eq + Ord,
-    T: SchemaPlan,
+    T: NodeItem,
+    NodeDiff<T>: MigrationPlanner<Migration = String>,
 {
-    let mut migrations: Vec<String> = Vec::new();
-    let keys: BTreeSet<_> = local.keys().collect();
-    let other_keys: BTreeSet<_> = remote.keys().collect();
+    let old = formatted_sql(diff.old.as_ref());
+    let new = formatted_sql(diff.new.as_ref());
+    let rendered = if *ctx.render == RenovateDiffRenderConfig::default() {
+        None
+    } else {
+        Some(render_diff(&old, &new, ctx.render)?)
+    };
+    let text = rendered.as_deref().unwrap_or(&diff.diff);
+    ctx.observer.on_diff_computed(type_name, id, kind, text);
+    ctx.observer
+        .on_diff_hunks(type_name, id, &diff_hunks(&old, &new, ctx.render));
+    let statements = diff.plan()?;
+    debug!(
+        id,
+        type_name,
+        change = ?kind,
+        statements = statements.len(),
+        "object planned"
+    );
+    Ok(statements)
+}
 
-    // process intersection
-    let intersection = keys.intersection(&other_keys);
-    for key in intersection {
-        let local = local.get(*key).unwrap();
-        let remote = remote.get(*key).unwrap();
-        migrations.extend(local.diff_altered(remote, verbose)?);
-    }
+/// Plan the items in parallel. The migrations and the diffs reported to the observer keep the
+/// order of the items, so the plan is stable regardless of the scheduling.
+#[cfg(not(target_arch = "wasm32"))]
+fn par_plan<I, F>(items: Vec<I>, ctx: PlanContext, f: F) -> Result<Vec<String>>
+where
+    I: Send,
+    F: Fn(I, PlanContext) -> Result<Vec<String>> + Sync,
+{
+    let results: Vec<_> = items
+        .into_par_iter()
+        .map(|item| {
+            let buffer = DiffBuffer::default();
+            let ret = f(
+                item,
+                PlanContext {
+                    observer: &buffer,
+                    ..ctx
+                },
+            );
+            (ret, buffer)
+        })
+        .collect();
 
-    // process added
-    let added = keys.difference(&other_keys);
-    for key in added {
-        let local = local.get(*key).unwrap();
-        migrations.extend(local.diff_added(verbose)?);
+    let mut migrations = Vec::new();
+    for (ret, buffer) in results {
+        buffer.replay(ctx.observer);
+        migrations.extend(ret?);
     }
+    Ok(migrations)
+}
 
-    // process removed
-    let removed = other_keys.difference(&keys);
-    for key in removed {
-        let remote = remote.get(*key).unwrap();
-        migrations.extend(remote.diff_removed(verbose)?);
+/// no threads on wasm, plan the items one by one
+#[cfg(target_arch = "wasm32")]
+fn par_plan<I, F>(items: Vec<I>, ctx: PlanContext, f: F) -> Result<Vec<String>>
+where
+    F: Fn(I, PlanContext) -> Result<Vec<String>>,
+{
+    let mut migrations = Vec::new();
+    for item in items {
+        migrations.extend(f(item, ctx)?);
     }
     Ok(migrations)
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::{SchemaLoader, SqlLoader};
+fn notify_schema_items<K, T: NodeItem>(
+    source: &BTreeMap<K, BTreeMap<String, T>>,
+    observer: &dyn Observer,
+) {
+    notify_items(source.values().flat_map(|items| items.values()), observer);
+}
 
-    use super::*;
+fn notify_items<'a, T: NodeItem + 'a>(items: impl Iterator<Item = &'a T>, observer: &dyn Observer) {
+    for item in items {
+        observer.on_object_fetched(item.type_name(), &item.id());
+    }
+}
 
-    #[tokio::test]
-    async fn database_schema_plan_should_work() -> Result<()> {
-        let loader = SqlLoader::new(
-            r#"
-            CREATE TYPE public.test_type AS (id uuid, name text);
-            CREATE TABLE public.test_table (id uuid, name text);
-            CREATE VIEW public.test_view AS SELECT * FROM public.test_table;
-            CREATE FUNCTION public.test_function(a text) RETURNS text AS $$ SELECT 'test', a $$ LANGUAGE SQL;
-            "#,
-        );
-        let remote = loader.load().await?;
-        let loader = SqlLoader::new(
-            r#"
-            CREATE TYPE public.test_type AS (id uuid, name text);
-            CREATE TABLE public.test_table (id uuid, name text, created_at timestamptz);
-            CREATE VIEW public.test_view AS SELECT * FROM public.test_table where created_at > now();
-            CREATE FUNCTION public.test_function(a text) RETURNS text AS $$ SELECT a, 'test1' $$ LANGUAGE SQL;
-            "#,
-        );
-        let local = loader.load().await?;
-        let migrations = local.plan(&remote, false).unwrap();
-        assert_eq!(migrations.len(), 4);
+/// An object of the schema, keyed by its type and where it is stored in the schema
+struct SchemaObject {
+    type_name: &'static str,
+    key: String,
+    id: String,
+    sql: String,
+}
+
+impl SchemaObject {
+    fn drift(
+        &self,
+        kind: ChangeKind,
+        local: impl Into<String>,
+        remote: impl Into<String>,
+    ) -> Drift {
+        Drift {
+            type_name: self.type_name,
+            id: self.id.clone(),
+            kind,
+            local: local.into(),
+            remote: remote.into(),
+            key: self.key.clone(),
+        }
+    }
+}
+
+/// An object which differs between the local schema and the remote one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Drift {
+    pub type_name: &'static str,
+    pub id: String,
+    pub kind: ChangeKind,
+    /// sql of the local object, empty if it is only on the remote
+    pub local: String,
+    /// sql of the remote object, empty if it is only in the local schema
+    pub remote: String,
+    key: String,
+}
+
+/// The result of `DatabaseSchema::merge`
+#[derive(Debug, Clone)]
+pub(crate) struct Merge {
+    /// the merged schema, without the conflicting objects
+    pub schema: DatabaseSchema,
+    /// the objects changed differently in the local schema and on the remote
+    pub conflicts: Vec<Drift>,
+}
+
+/// A fetched object which can't be loaded back from its sql
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct UnparsableObject {
+    pub type_name: &'static str,
+    pub id: String,
+    pub reason: String,
+    key: String,
+}
+
+/// An object matching the pattern of `DatabaseSchema::grep`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SchemaMatch {
+    pub type_name: &'static str,
+    pub id: String,
+    /// the file the object is saved to, relative to the local repo
+    pub file: Option<PathBuf>,
+    /// the sql around the first match
+    pub excerpt: String,
+}
+
+/// the byte offset of the first match of the pattern in the sql, see `DatabaseSchema::grep`
+fn find_match(sql: &str, pattern: &str) -> Option<usize> {
+    if pattern.is_empty() {
+        return None;
+    }
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+    let identifier = pattern.chars().all(is_ident_char);
+    // ascii lowercasing keeps the byte offsets
+    let (sql_lower, pattern) = (sql.to_ascii_lowercase(), pattern.to_ascii_lowercase());
+    sql_lower
+        .match_indices(&pattern)
+        .map(|(pos, _)| pos)
+        .find(|&pos| {
+            !identifier
+                || (!sql[..pos].ends_with(is_ident_char)
+                    && !sql[pos + pattern.len()..].starts_with(is_ident_char))
+        })
+}
+
+/// up to 40 bytes of the sql on each side of the match, on one line
+fn excerpt(sql: &str, pos: usize, len: usize) -> String {
+    let mut start = pos.saturating_sub(40);
+    while !sql.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (pos + len + 40).min(sql.len());
+    while !sql.is_char_boundary(end) {
+        end += 1;
+    }
+    let mut result = sql[start..end]
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if start > 0 {
+        result.insert_str(0, "...");
+    }
+    if end < sql.len() {
+        result.push_str("...");
+    }
+    result
+}
+
+fn remove_nested<K: Ord, T>(
+    source: &mut BTreeMap<K, BTreeMap<String, T>>,
+    ignored: &BTreeMap<K, BTreeMap<String, T>>,
+) {
+    for (key, items) in ignored {
+        if let Some(existing) = source.get_mut(key) {
+            existing.retain(|name, _| !items.contains_key(name));
+        }
+    }
+    source.retain(|_, items| !items.is_empty());
+}
+
+fn remove_items<K: Ord, T>(source: &mut BTreeMap<K, T>, ignored: &BTreeMap<K, T>) {
+    source.retain(|key, _| !ignored.contains_key(key));
+}
+
+fn collect_schema_objects<K: ToString, T: NodeItem>(
+    source: &BTreeMap<K, BTreeMap<String, T>>,
+    objects: &mut Vec<SchemaObject>,
+) {
+    for (outer, items) in source {
+        for (name, item) in items {
+            objects.push(SchemaObject {
+                type_name: item.type_name(),
+                key: format!("{} {} {}", item.type_name(), outer.to_string(), name),
+                id: item.id(),
+                sql: item.to_string(),
+            });
+        }
+    }
+}
+
+fn collect_objects<K: ToString, T: NodeItem>(
+    source: &BTreeMap<K, T>,
+    objects: &mut Vec<SchemaObject>,
+) {
+    for (key, item) in source {
+        objects.push(SchemaObject {
+            type_name: item.type_name(),
+            key: format!("{} {}", item.type_name(), key.to_string()),
+            id: item.id(),
+            sql: item.to_string(),
+        });
+    }
+}
+
+/// The relations only in `remote` whose definition, without the schema of the relation, is the
+/// same as the one of a relation only in `local`, e.g. `CREATE TABLE todos (...)`. Pairs with more
+/// than one candidate on either side are left to the normal diff.
+fn find_moves<T: NodeItem>(
+    local: &BTreeMap<String, BTreeMap<String, T>>,
+    remote: &BTreeMap<String, BTreeMap<String, T>>,
+) -> Vec<(SchemaId, SchemaId)> {
+    find_pairs(local, remote, |relation| relation.schemaname.clear())
+}
+
+/// Same as `find_moves`, for the relations of the same schema whose definition only differs by
+/// the name
+/// the keys of a three-way merge: the ones on both sides, and the ones added on one side
+fn merge_keys<K: Ord + Clone>(
+    local: &BTreeSet<K>,
+    base: &BTreeSet<K>,
+    remote: &BTreeSet<K>,
+) -> BTreeSet<K> {
+    local
+        .union(remote)
+        .filter(|key| (local.contains(key) && remote.contains(key)) || !base.contains(key))
+        .cloned()
+        .collect()
+}
+
+/// the hypertables of both sides but the ones partitioning the same table by the same column.
+/// The hypertables of a database are rebuilt from the timescaledb catalog without the other
+/// arguments of `create_hypertable`, so they can't be diffed on their sql.
+fn changed_hypertables(
+    local: &BTreeMap<SchemaId, Hypertable>,
+    remote: &BTreeMap<SchemaId, Hypertable>,
+) -> (
+    BTreeMap<SchemaId, Hypertable>,
+    BTreeMap<SchemaId, Hypertable>,
+) {
+    let same = |id: &SchemaId| match (local.get(id), remote.get(id)) {
+        (Some(l), Some(r)) => l.time_column == r.time_column,
+        _ => false,
+    };
+    let changed = |items: &BTreeMap<SchemaId, Hypertable>| {
+        items
+            .iter()
+            .filter(|(id, _)| !same(id))
+            .map(|(id, item)| (id.clone(), item.clone()))
+            .collect()
+    };
+    (changed(local), changed(remote))
+}
+
+/// the warnings of the enum types planned to be dropped and created again, see
+/// `EnumType::recreated_as`
+fn recreated_enums(
+    local: &BTreeMap<String, BTreeMap<String, EnumType>>,
+    remote: &BTreeMap<String, BTreeMap<String, EnumType>>,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for (schema, types) in local {
+        for (name, new) in types {
+            let old = match remote.get(schema).and_then(|types| types.get(name)) {
+                Some(old) => old,
+                None => continue,
+            };
+            if old.recreated_as(new) {
+                let message = format!(
+                    "enum type {} is dropped and created again because of incompatible changes, which fails if the type is used elsewhere",
+                    new.id
+                );
+                diagnostics.push(Diagnostic::new(DiagnosticKind::Recreated, message));
+            }
+        }
+    }
+    diagnostics
+}
+
+fn find_renames<T: NodeItem>(
+    local: &BTreeMap<String, BTreeMap<String, T>>,
+    remote: &BTreeMap<String, BTreeMap<String, T>>,
+) -> Vec<(SchemaId, SchemaId)> {
+    find_pairs(local, remote, |relation| relation.relname.clear())
+}
+
+/// pair the relations only in `remote` with the ones only in `local` by their sql, once
+/// `strip` took the schema or the name off the relation
+fn find_pairs<T: NodeItem>(
+    local: &BTreeMap<String, BTreeMap<String, T>>,
+    remote: &BTreeMap<String, BTreeMap<String, T>>,
+    strip: impl Fn(&mut RangeVar),
+) -> Vec<(SchemaId, SchemaId)> {
+    let mut local_ids = BTreeSet::new();
+    let mut remote_ids = BTreeSet::new();
+    collect_ids(local, &mut local_ids);
+    collect_ids(remote, &mut remote_ids);
+
+    let group = |source: &BTreeMap<String, BTreeMap<String, T>>, ids: Vec<&SchemaId>| {
+        let mut groups: BTreeMap<String, Vec<SchemaId>> = BTreeMap::new();
+        for id in ids {
+            let item = &source[&id.schema][&id.name];
+            if let Some(sql) = stripped_sql(item.node(), &strip) {
+                groups.entry(sql).or_default().push(id.clone());
+            }
+        }
+        groups
+    };
+    let added = group(local, local_ids.difference(&remote_ids).collect());
+    let removed = group(remote, remote_ids.difference(&local_ids).collect());
+    added
+        .into_iter()
+        .filter_map(
+            |(sql, to)| match (removed.get(&sql).map(|v| v.as_slice()), to.as_slice()) {
+                (Some([from]), [to]) => Some((from.clone(), to.clone())),
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+/// the sql of the relation once `strip` changed its name
+fn stripped_sql(node: &NodeEnum, strip: impl Fn(&mut RangeVar)) -> Option<String> {
+    let mut node = node.clone();
+    let relation = match &mut node {
+        NodeEnum::CreateStmt(stmt) => stmt.relation.as_mut(),
+        NodeEnum::ViewStmt(stmt) => stmt.view.as_mut(),
+        NodeEnum::CreateSeqStmt(stmt) => stmt.sequence.as_mut(),
+        _ => None,
+    }?;
+    strip(relation);
+    node.deparse().ok()
+}
+
+/// move the relation from its old schema to the new one, as it is defined locally
+fn move_item<T: Clone>(
+    remote: &mut BTreeMap<String, BTreeMap<String, T>>,
+    local: &BTreeMap<String, BTreeMap<String, T>>,
+    from: &SchemaId,
+    to: &SchemaId,
+) {
+    if let Some(items) = remote.get_mut(&from.schema) {
+        items.remove(&from.name);
+    }
+    remote.retain(|_, items| !items.is_empty());
+    if let Some(item) = local.get(&to.schema).and_then(|items| items.get(&to.name)) {
+        remote
+            .entry(to.schema.clone())
+            .or_default()
+            .insert(to.name.clone(), item.clone());
+    }
+}
+
+/// move the table level objects and the privileges of the relation along with it
+fn move_relation_items(schema: &mut DatabaseSchema, from: &SchemaId, to: &SchemaId) -> Result<()> {
+    move_nested(&mut schema.table_indexes, from, to)?;
+    move_nested(&mut schema.table_constraints, from, to)?;
+    move_nested(&mut schema.table_sequences, from, to)?;
+    move_nested(&mut schema.table_triggers, from, to)?;
+    move_nested(&mut schema.table_policies, from, to)?;
+    move_single(&mut schema.table_rls, from, to)?;
+    move_single(&mut schema.table_owners, from, to)?;
+    move_single(&mut schema.table_hypertables, from, to)?;
+    if let Some(items) = schema.privileges.remove(&from.to_string()) {
+        let items = items
+            .iter()
+            .map(|item| requalify(item, from, to))
+            .collect::<Result<_>>()?;
+        schema.privileges.insert(to.to_string(), items);
+    }
+    Ok(())
+}
+
+fn move_nested<T>(
+    source: &mut BTreeMap<SchemaId, BTreeMap<String, T>>,
+    from: &SchemaId,
+    to: &SchemaId,
+) -> Result<()>
+where
+    T: ToString + FromStr<Err = anyhow::Error>,
+{
+    if let Some(items) = source.remove(from) {
+        let items = items
+            .into_iter()
+            .map(|(name, item)| requalify(&item, from, to).map(|item| (name, item)))
+            .collect::<Result<_>>()?;
+        source.insert(to.clone(), items);
+    }
+    Ok(())
+}
+
+fn move_single<T>(source: &mut BTreeMap<SchemaId, T>, from: &SchemaId, to: &SchemaId) -> Result<()>
+where
+    T: ToString + FromStr<Err = anyhow::Error>,
+{
+    if let Some(item) = source.remove(from) {
+        source.insert(to.clone(), requalify(&item, from, to)?);
+    }
+    Ok(())
+}
+
+/// parse the item again with the references to the relation moved to its new schema or name.
+/// `public.todo` in `public.todo_tags` or `mypublic.todo` is not a reference to `public.todo`.
+fn requalify<T>(item: &T, from: &SchemaId, to: &SchemaId) -> Result<T>
+where
+    T: ToString + FromStr<Err = anyhow::Error>,
+{
+    let sql = item.to_string();
+    let (from, to) = (from.to_string(), to.to_string());
+    let mut result = String::with_capacity(sql.len());
+    let ident = |c: Option<char>| c.map_or(false, |c| c.is_alphanumeric() || c == '_' || c == '$');
+    let mut last = 0;
+    for (pos, _) in sql.match_indices(&from) {
+        let end = pos + from.len();
+        if ident(sql[..pos].chars().next_back()) || ident(sql[end..].chars().next()) {
+            continue;
+        }
+        result.push_str(&sql[last..pos]);
+        result.push_str(&to);
+        last = end;
+    }
+    result.push_str(&sql[last..]);
+    result.parse()
+}
+
+fn collect_ids<T>(source: &BTreeMap<String, BTreeMap<String, T>>, ids: &mut BTreeSet<SchemaId>) {
+    for (schema, items) in source {
+        ids.extend(items.keys().map(|name| SchemaId::new(schema, name)));
+    }
+}
+
+fn retain_schema_items<T: NodeItem>(
+    remote: &mut BTreeMap<String, BTreeMap<String, T>>,
+    local: &BTreeMap<String, BTreeMap<String, T>>,
+    unmanaged: &mut Vec<(&'static str, String)>,
+) {
+    for (schema, items) in remote.iter_mut() {
+        let local_items = local.get(schema);
+        items.retain(|name, item| {
+            let managed = local_items.map(|v| v.contains_key(name)).unwrap_or(false);
+            if !managed {
+                unmanaged.push((item.type_name(), item.id()));
+            }
+            managed
+        });
+    }
+    remote.retain(|_, items| !items.is_empty());
+}
+
+fn retain_relation_items<T: NodeItem>(
+    remote: &mut BTreeMap<SchemaId, BTreeMap<String, T>>,
+    relations: &BTreeSet<SchemaId>,
+    unmanaged: &mut Vec<(&'static str, String)>,
+) {
+    remote.retain(|id, items| {
+        let managed = relations.contains(id);
+        if !managed {
+            unmanaged.extend(
+                items
+                    .values()
+                    .map(|item| (item.type_name(), format!("{} on {}", item.id(), id))),
+            );
+        }
+        managed
+    });
+}
+
+/// add the table level objects of `source` which `other` doesn't have on the same relation
+fn missing_relation_items<T: NodeItem>(
+    source: &BTreeMap<SchemaId, BTreeMap<String, T>>,
+    other: &BTreeMap<SchemaId, BTreeMap<String, T>>,
+    groups: &mut BTreeMap<String, Vec<String>>,
+) {
+    for (id, items) in source {
+        let others = other.get(id);
+        for (name, item) in items {
+            if others.map_or(true, |o| !o.contains_key(name)) {
+                groups
+                    .entry(item.type_name().to_owned())
+                    .or_default()
+                    .push(format!("{} on {}", item.id(), id));
+            }
+        }
+    }
+}
+
+fn retain_relation_item<T: NodeItem>(
+    remote: &mut BTreeMap<SchemaId, T>,
+    relations: &BTreeSet<SchemaId>,
+    unmanaged: &mut Vec<(&'static str, String)>,
+) {
+    remote.retain(|id, item| {
+        let managed = relations.contains(id);
+        if !managed {
+            unmanaged.push((item.type_name(), item.id()));
+        }
+        managed
+    });
+}
+
+/// Diff the objects of a kind, by schema and then by name: the altered ones first, then the
+/// added and the removed ones. Only ordered maps and sets are used while planning, so the same
+/// inputs always give the same plan, byte for byte, whatever order the objects were loaded in.
+fn schema_diff<K, T>(
+    local: &BTreeMap<K, T>,
+    remote: &BTreeMap<K, T>,
+    ctx: PlanContext,
+) -> Result<Vec<String>>
+where
+    K: Hash + Eq + Ord,
+    T: SchemaPlan,
+{
+    let mut migrations: Vec<String> = Vec::new();
+    let keys: BTreeSet<_> = local.keys().collect();
+    let other_keys: BTreeSet<_> = remote.keys().collect();
+
+    // process intersection
+    let intersection = keys.intersection(&other_keys);
+    for key in intersection {
+        let local = local.get(*key).unwrap();
+        let remote = remote.get(*key).unwrap();
+        migrations.extend(local.diff_altered(remote, ctx)?);
+    }
+
+    // process added
+    let added = keys.difference(&other_keys);
+    for key in added {
+        let local = local.get(*key).unwrap();
+        migrations.extend(local.diff_added(ctx)?);
+    }
+
+    // process removed
+    let removed = other_keys.difference(&keys);
+    for key in removed {
+        let remote = remote.get(*key).unwrap();
+        migrations.extend(remote.diff_removed(ctx)?);
+    }
+    Ok(migrations)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parser::Literal, repo::check_strict, DiagnosticKind, SchemaLoader, SqlLoader};
+    use proptest::prelude::*;
+
+    use super::*;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn plan_with_any_names_should_be_single_statements(
+            schema in "[^\\x00]{1,15}",
+            name in "[^\\x00]{1,15}",
+            value in "[^\\x00]{1,15}",
+        ) {
+            let table = format!("{}.{}", Ident(&schema), Ident(&name));
+            let sql = |v: &str| {
+                format!(
+                    "CREATE TYPE {}.status AS ENUM ('new', {});
+                    CREATE TABLE {} (id int, status {}.status);
+                    ALTER TABLE ONLY {} ADD CONSTRAINT {} CHECK (id > 0);",
+                    Ident(&schema), Literal(v), table, Ident(&schema), table, Ident(&name)
+                )
+            };
+            let old = SqlLoader::new(sql(&value)).parse().unwrap();
+            let new = SqlLoader::new(sql(&format!("{}'", value))).parse().unwrap();
+            prop_assert!(old.tables.get(&schema).map_or(false, |t| t.contains_key(&name)));
+
+            let empty = DatabaseSchema::default();
+            for (from, to) in [(&empty, &old), (&old, &new), (&old, &empty)] {
+                for sql in to.plan(from, false).unwrap() {
+                    let parsed = pg_query::parse(&sql);
+                    prop_assert!(parsed.is_ok(), "{}", sql);
+                    prop_assert_eq!(parsed.unwrap().protobuf.stmts.len(), 1, "{}", sql);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn database_schema_plan_should_work() -> Result<()> {
+        let loader = SqlLoader::new(
+            r#"
+            CREATE TYPE public.test_type AS (id uuid, name text);
+            CREATE TABLE public.test_table (id uuid, name text);
+            CREATE VIEW public.test_view AS SELECT * FROM public.test_table;
+            CREATE FUNCTION public.test_function(a text) RETURNS text AS $$ SELECT 'test', a $$ LANGUAGE SQL;
+            "#,
+        );
+        let remote = loader.load().await?;
+        let loader = SqlLoader::new(
+            r#"
+            CREATE TYPE public.test_type AS (id uuid, name text);
+            CREATE TABLE public.test_table (id uuid, name text, created_at timestamptz);
+            CREATE VIEW public.test_view AS SELECT * FROM public.test_table where created_at > now();
+            CREATE FUNCTION public.test_function(a text) RETURNS text AS $$ SELECT a, 'test1' $$ LANGUAGE SQL;
+            "#,
+        );
+        let local = loader.load().await?;
+        let migrations = local.plan(&remote, false).unwrap();
+        assert_eq!(migrations.len(), 4);
         assert_eq!(
             migrations[0],
             "ALTER TABLE ONLY public.test_table ADD COLUMN created_at timestamptz"
@@ -366,4 +1898,885 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn database_schema_should_survive_json_round_trip() -> Result<()> {
+        let schema = SqlLoader::new(
+            r#"
+            CREATE TYPE public.status AS ENUM ('active', 'inactive');
+            CREATE TABLE public.todos (id uuid PRIMARY KEY, title text NOT NULL DEFAULT 'todo', status public.status);
+            CREATE INDEX todos_title_idx ON public.todos (title);
+            CREATE VIEW public.active_todos AS SELECT * FROM public.todos WHERE status = 'active';
+            GRANT SELECT ON public.todos TO readonly;
+            CREATE TRIGGER todos_audit AFTER UPDATE ON public.todos FOR EACH ROW EXECUTE FUNCTION public.audit();
+            "#,
+        )
+        .load()
+        .await?;
+        let json = serde_json::to_string(&schema)?;
+        let value: serde_json::Value = serde_json::from_str(&json)?;
+        assert_eq!(
+            value["tables"]["public"]["todos"]["columns"]["title"]["default"]["sql"],
+            "DEFAULT 'todo'"
+        );
+        assert_eq!(
+            value["table_triggers"]["public.todos"]["todos_audit"]["id"],
+            "public.todos.todos_audit"
+        );
+
+        let restored: DatabaseSchema = serde_json::from_str(&json)?;
+        assert_eq!(restored.sql(true), schema.sql(true));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn plan_should_report_diffs_to_observer() -> Result<()> {
+        #[derive(Default)]
+        struct Recorder(std::sync::Mutex<Vec<(String, ChangeKind)>>);
+
+        impl Observer for Recorder {
+            fn on_diff_computed(&self, _type_name: &str, id: &str, kind: ChangeKind, _diff: &str) {
+                self.0.lock().unwrap().push((id.to_owned(), kind));
+            }
+        }
+
+        let remote = SqlLoader::new(
+            "CREATE TABLE public.todos (id uuid); CREATE TABLE public.old (id uuid);",
+        )
+        .load()
+        .await?;
+        let local = SqlLoader::new(
+            "CREATE TABLE public.todos (id uuid, title text); CREATE TABLE public.new (id uuid);",
+        )
+        .load()
+        .await?;
+        let recorder = Recorder::default();
+        local.plan_with(&remote, &RenovateDiffConfig::default(), &recorder)?;
+        assert_eq!(
+            recorder.0.into_inner().unwrap(),
+            vec![
+                ("public.new".to_owned(), ChangeKind::Added),
+                ("public.old".to_owned(), ChangeKind::Removed),
+                ("public.todos".to_owned(), ChangeKind::Changed),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pg_dump_output_should_be_diffable() -> Result<()> {
+        let dump = r#"
+            SET statement_timeout = 0;
+            SET client_encoding = 'UTF8';
+            SELECT pg_catalog.set_config('search_path', '', false);
+            SET default_table_access_method = heap;
+            CREATE TABLE public.todos (id uuid, title text);
+            ALTER TABLE public.todos OWNER TO postgres;
+            COMMENT ON TABLE public.todos IS 'things to do';
+            "#;
+        let remote = SqlLoader::new(dump).parse()?;
+        let local = SqlLoader::new(
+            "CREATE TABLE public.todos (id uuid, title text); ALTER TABLE public.todos OWNER TO postgres;",
+        )
+        .parse()?;
+        assert!(local.plan(&remote, false)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn fingerprint_should_change_with_the_schema() -> Result<()> {
+        let old = SqlLoader::new("CREATE TABLE public.todos (id uuid);").parse()?;
+        let same = SqlLoader::new("CREATE TABLE todos (id uuid);").parse()?;
+        let new = SqlLoader::new("CREATE TABLE public.todos (id uuid, title text);").parse()?;
+        assert_eq!(old.fingerprint(), same.fingerprint());
+        assert_ne!(old.fingerprint(), new.fingerprint());
+        Ok(())
+    }
+
+    #[test]
+    fn missing_objects_should_be_grouped_by_type() -> Result<()> {
+        let remote = SqlLoader::new(
+            r#"
+            CREATE TABLE public.todos (id uuid);
+            CREATE INDEX todos_id_idx ON public.todos (id);
+            CREATE TABLE public.legacy (id uuid);
+            ALTER TABLE public.legacy OWNER TO billing;
+            CREATE INDEX legacy_idx ON public.legacy (id);
+            CREATE VIEW public.old_todos AS SELECT id FROM public.todos;
+            "#,
+        )
+        .parse()?;
+        let local = SqlLoader::new(
+            "CREATE TABLE public.todos (id uuid); CREATE TABLE public.tags (id uuid);",
+        )
+        .parse()?;
+
+        let missing = remote.missing_in(&local);
+        assert_eq!(missing["table"], vec!["public.legacy (owner: billing)"]);
+        assert_eq!(missing["view"], vec!["public.old_todos"]);
+        assert_eq!(
+            missing["index"],
+            vec![
+                "legacy_idx on public.legacy",
+                "todos_id_idx on public.todos"
+            ]
+        );
+        assert!(!missing.contains_key("table owner"));
+
+        let missing = local.missing_in(&remote);
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing["table"], vec!["public.tags"]);
+        Ok(())
+    }
+
+    #[test]
+    fn changed_grantees_should_be_planned_incrementally() -> Result<()> {
+        let remote = SqlLoader::new("GRANT SELECT ON TABLE public.todos TO app_ro").parse()?;
+        let local =
+            SqlLoader::new("GRANT SELECT ON TABLE public.todos TO app_ro, analyst").parse()?;
+        let plan = local.plan(&remote, false)?;
+        assert_eq!(plan, vec!["GRANT select ON public.todos TO analyst"]);
+
+        let local =
+            SqlLoader::new("GRANT SELECT, INSERT ON TABLE public.todos TO app_ro").parse()?;
+        let plan = local.plan(&remote, false)?;
+        assert_eq!(plan, vec!["GRANT insert ON public.todos TO app_ro"]);
+        Ok(())
+    }
+
+    #[test]
+    fn revoke_from_public_should_be_planned_as_desired_state() -> Result<()> {
+        let function =
+            "CREATE FUNCTION public.add(a int, b int) RETURNS int LANGUAGE sql AS $$ SELECT a + b $$;";
+        let remote = SqlLoader::new(function).parse()?;
+        let local = SqlLoader::new(format!(
+            "{} REVOKE EXECUTE ON FUNCTION public.add(int, int) FROM PUBLIC;",
+            function
+        ))
+        .parse()?;
+        let plan = local.plan(&remote, false)?;
+        assert_eq!(
+            plan,
+            vec!["REVOKE execute ON FUNCTION public.add(int, int) FROM PUBLIC"]
+        );
+
+        // the remote has the privilege revoked already
+        let remote = SqlLoader::new(format!(
+            "{} REVOKE ALL ON FUNCTION public.add(integer, integer) FROM PUBLIC;",
+            function
+        ))
+        .parse()?;
+        assert!(local.plan(&remote, false)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn grants_should_be_planned_after_recreated_objects() -> Result<()> {
+        let remote = SqlLoader::new(
+            "CREATE VIEW public.active AS SELECT 1 AS id; GRANT SELECT ON TABLE public.active TO app_ro;",
+        )
+        .parse()?;
+        let local = SqlLoader::new(
+            "CREATE VIEW public.active AS SELECT 2 AS id; GRANT SELECT ON TABLE public.active TO app_ro;",
+        )
+        .parse()?;
+        let plan = local.plan(&remote, false)?;
+        assert_eq!(
+            plan,
+            vec![
+                "DROP VIEW public.active",
+                "CREATE VIEW public.active AS SELECT 2 AS id",
+                "GRANT select ON public.active TO app_ro",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn triggers_should_be_recreated_with_their_function() -> Result<()> {
+        let table = "CREATE TABLE public.todos (id int, updated_at timestamptz);";
+        let trigger = "CREATE TRIGGER todos_updated BEFORE UPDATE ON public.todos FOR EACH ROW EXECUTE FUNCTION public.touch();";
+        let remote = SqlLoader::new(format!(
+            "{} CREATE FUNCTION public.touch() RETURNS trigger LANGUAGE plpgsql AS $$ BEGIN RETURN NEW; END $$; {}",
+            table, trigger
+        ))
+        .parse()?;
+        // the changed return type requires the function to be dropped
+        let local = SqlLoader::new(format!(
+            "{} CREATE FUNCTION public.touch() RETURNS pg_catalog.trigger LANGUAGE plpgsql AS $$ BEGIN RETURN NEW; END $$; {}",
+            table, trigger
+        ))
+        .parse()?;
+        let plan = local.plan(&remote, false)?;
+        assert_eq!(plan.len(), 4);
+        assert_eq!(plan[0], "DROP TRIGGER todos_updated ON public.todos");
+        assert_eq!(plan[1], "DROP FUNCTION public.touch()");
+        assert!(plan[2].starts_with("CREATE FUNCTION public.touch()"));
+        assert!(plan[3].starts_with("CREATE TRIGGER todos_updated"));
+        Ok(())
+    }
+
+    #[test]
+    fn accepted_drift_should_be_merged_into_local_schema() -> Result<()> {
+        let local = SqlLoader::new(
+            "CREATE TABLE public.todos (id uuid); CREATE TABLE public.legacy (id uuid);",
+        )
+        .parse()?;
+        let remote = SqlLoader::new(
+            "CREATE TABLE public.todos (id uuid, title text); CREATE TABLE public.notes (id uuid);",
+        )
+        .parse()?;
+
+        let drift = local.drift(&remote);
+        let summary = drift
+            .iter()
+            .map(|d| (d.id.as_str(), d.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("public.legacy", ChangeKind::Removed),
+                ("public.todos", ChangeKind::Changed),
+                ("public.notes", ChangeKind::Added),
+            ]
+        );
+
+        // take in the new table and the new column, but keep the local only table
+        let accepted = drift
+            .into_iter()
+            .filter(|d| d.kind != ChangeKind::Removed)
+            .collect::<Vec<_>>();
+        let merged = SqlLoader::new(local.accept_drift(&accepted)).parse()?;
+        let tables = &merged.tables["public"];
+        assert!(tables.contains_key("legacy"));
+        assert!(tables.contains_key("notes"));
+        assert!(tables["todos"].to_string().contains("title"));
+        Ok(())
+    }
+
+    #[test]
+    fn privilege_drift_should_be_a_change() -> Result<()> {
+        let local = SqlLoader::new(
+            "CREATE TABLE public.todos (id uuid); GRANT SELECT ON TABLE public.todos TO reader;",
+        )
+        .parse()?;
+        let remote = SqlLoader::new(
+            "CREATE TABLE public.todos (id uuid); GRANT SELECT, INSERT ON TABLE public.todos TO reader;",
+        )
+        .parse()?;
+
+        let drift = local.drift(&remote);
+        assert_eq!(drift.len(), 1);
+        assert_eq!(drift[0].type_name, "privilege");
+        assert_eq!(drift[0].kind, ChangeKind::Changed);
+        Ok(())
+    }
+
+    #[test]
+    fn merge_should_take_one_sided_changes_and_report_conflicts() -> Result<()> {
+        let base = SqlLoader::new(
+            "CREATE TABLE public.todos (id uuid); CREATE TABLE public.users (id uuid); CREATE TABLE public.legacy (id uuid);",
+        )
+        .parse()?;
+        // todos changed locally, users differently on both sides, legacy dropped on the remote
+        let local = SqlLoader::new(
+            "CREATE TABLE public.todos (id uuid, title text); CREATE TABLE public.users (id uuid, name text); CREATE TABLE public.legacy (id uuid);",
+        )
+        .parse()?;
+        let remote = SqlLoader::new(
+            "CREATE TABLE public.todos (id uuid); CREATE TABLE public.users (id uuid, email text); CREATE TABLE public.notes (id uuid);",
+        )
+        .parse()?;
+        // a schema without objects yet, created locally
+        let mut local = local;
+        local.schemas.insert("reporting".to_owned());
+
+        let merge = local.merge(&base, &remote)?;
+        assert!(merge.schema.schemas.contains("reporting"));
+        let tables = &merge.schema.tables["public"];
+        assert!(tables["todos"].to_string().contains("title"));
+        assert!(tables.contains_key("notes"));
+        assert!(!tables.contains_key("legacy"));
+        assert!(!tables.contains_key("users"));
+        let conflicts = merge
+            .conflicts
+            .iter()
+            .map(|d| (d.id.as_str(), d.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(conflicts, vec![("public.users", ChangeKind::Changed)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unmanaged_remote_objects_should_follow_policy() -> Result<()> {
+        let remote = SqlLoader::new(
+            r#"
+            CREATE TABLE public.todos (id uuid, title text);
+            CREATE TABLE public.legacy (id uuid);
+            CREATE INDEX legacy_idx ON public.legacy (id);
+            CREATE TABLE other.users (id uuid);
+            "#,
+        )
+        .load()
+        .await?;
+        let local = SqlLoader::new("CREATE TABLE public.todos (id uuid, title text);")
+            .load()
+            .await?;
+
+        let migrations = local.plan(&remote, false)?;
+        assert_eq!(
+            migrations,
+            vec![
+                "DROP TABLE public.legacy",
+                "DROP TABLE other.users",
+                "DROP INDEX public.legacy_idx",
+                "DROP SCHEMA other"
+            ]
+        );
+
+        let config = RenovateDiffConfig {
+            unmanaged: Unmanaged::Ignore,
+            ..Default::default()
+        };
+        let migrations = local.plan_with(&remote, &config, &NoopObserver)?;
+        assert!(migrations.is_empty());
+
+        let mut remote = remote;
+        let unmanaged = remote.retain_managed(&local);
+        assert_eq!(
+            unmanaged,
+            vec![
+                "schema other",
+                "table other.users",
+                "table public.legacy",
+                "index legacy_idx on public.legacy"
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scoped_plan_should_keep_managed_relation_items() -> Result<()> {
+        let remote = SqlLoader::new(
+            r#"
+            CREATE TABLE public.todos (id uuid PRIMARY KEY, title text);
+            CREATE INDEX todos_title_idx ON public.todos (title);
+            CREATE TABLE public.legacy (id uuid);
+            CREATE INDEX legacy_idx ON public.legacy (id);
+            "#,
+        )
+        .load()
+        .await?;
+        let local = SqlLoader::new(
+            r#"
+            CREATE TABLE public.todos (id uuid PRIMARY KEY, title text);
+            CREATE INDEX todos_title_idx ON public.todos (title);
+            CREATE INDEX todos_id_title_idx ON public.todos (id, title);
+            "#,
+        )
+        .load()
+        .await?;
+
+        let kinds = BTreeSet::from([ObjectKind::Indexes, ObjectKind::Constraints]);
+        let options = PlanOptions {
+            kinds: Some(&kinds),
+            ..Default::default()
+        };
+        for unmanaged in [Unmanaged::Ignore, Unmanaged::Warn] {
+            let config = RenovateDiffConfig {
+                unmanaged,
+                ..Default::default()
+            };
+            let (migrations, diagnostics) =
+                local.plan_with_options(&remote, &config, options, &NoopObserver)?;
+            assert_eq!(
+                migrations,
+                vec!["CREATE INDEX todos_id_title_idx ON public.todos USING btree (id, title)"]
+            );
+            let warned = diagnostics
+                .iter()
+                .filter(|d| d.kind == DiagnosticKind::Unmanaged)
+                .count();
+            assert_eq!(warned, if unmanaged == Unmanaged::Warn { 2 } else { 0 });
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn recreated_enum_should_be_reported() -> Result<()> {
+        let remote =
+            SqlLoader::new("CREATE TYPE public.status AS ENUM ('a', 'b', 'c');").parse()?;
+        let local = SqlLoader::new("CREATE TYPE public.status AS ENUM ('a', 'd', 'e');").parse()?;
+        let config = RenovateDiffConfig::default();
+        let (migrations, diagnostics) =
+            local.plan_with_diagnostics(&remote, &config, &NoopObserver)?;
+        assert_eq!(
+            migrations,
+            vec![
+                "DROP TYPE public.status",
+                "CREATE TYPE public.status AS ENUM ('a', 'd', 'e')"
+            ]
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::Recreated);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn preset_should_ignore_platform_objects() -> Result<()> {
+        let remote = SqlLoader::new(
+            r#"
+            CREATE TABLE public.todos (id uuid, title text);
+            CREATE TABLE auth.users (id uuid);
+            CREATE TABLE storage.objects (id uuid);
+            GRANT SELECT ON TABLE public.todos TO supabase_admin;
+            "#,
+        )
+        .load()
+        .await?;
+        let local = SqlLoader::new("CREATE TABLE public.todos (id uuid, title text);")
+            .load()
+            .await?;
+        assert!(!local.plan(&remote, false)?.is_empty());
+
+        let config = RenovateDiffConfig {
+            preset: Some(Preset::Supabase),
+            ..Default::default()
+        };
+        let migrations = local.plan_with(&remote, &config, &NoopObserver)?;
+        assert!(migrations.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hypertables_should_be_diffed_without_chunks() -> Result<()> {
+        let chunks = r#"
+            CREATE TABLE public.metrics (ts timestamptz NOT NULL, value int);
+            CREATE TABLE _timescaledb_internal._hyper_1_1_chunk (ts timestamptz NOT NULL, value int) INHERITS (public.metrics);
+            CREATE INDEX _hyper_1_1_chunk_metrics_ts_idx ON _timescaledb_internal._hyper_1_1_chunk (ts DESC);
+            "#;
+        // without timescaledb, the schemas are the user's own
+        let remote = SqlLoader::new(chunks).load().await?;
+        assert!(remote.schemas.contains("_timescaledb_internal"));
+
+        let remote = SqlLoader::new(format!(
+            "CREATE EXTENSION IF NOT EXISTS timescaledb WITH SCHEMA public;\n{}",
+            chunks
+        ))
+        .load()
+        .await?;
+        assert!(!remote.schemas.contains("_timescaledb_internal"));
+
+        let local = SqlLoader::new(
+            "CREATE TABLE public.metrics (ts timestamptz NOT NULL, value int); SELECT create_hypertable('public.metrics', 'ts');",
+        )
+        .load()
+        .await?;
+        assert_eq!(
+            local.plan(&remote, false)?,
+            vec!["SELECT create_hypertable('public.metrics', 'ts')"]
+        );
+        assert!(remote.plan(&local, false).is_err());
+
+        // rebuilt from the catalog without the other arguments, but the same hypertable
+        let local = SqlLoader::new(
+            "CREATE TABLE public.metrics (ts timestamptz NOT NULL, value int); SELECT create_hypertable('public.metrics', 'ts', chunk_time_interval => interval '1 day');",
+        )
+        .load()
+        .await?;
+        let fetched = SqlLoader::new(
+            "CREATE TABLE public.metrics (ts timestamptz NOT NULL, value int); SELECT create_hypertable('public.metrics', 'ts');",
+        )
+        .load()
+        .await?;
+        assert!(local.plan(&fetched, false)?.is_empty());
+        assert!(fetched.plan(&local, false)?.is_empty());
+
+        let files = local.render(&Default::default());
+        let tables = &files[&std::path::PathBuf::from("public/04_tables.sql")];
+        assert!(tables.contains("create_hypertable"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unchanged_postgis_schema_should_have_empty_plan() -> Result<()> {
+        let remote = SqlLoader::new(
+            r#"
+            CREATE EXTENSION IF NOT EXISTS postgis WITH SCHEMA public;
+            CREATE EXTENSION IF NOT EXISTS postgis_tiger_geocoder WITH SCHEMA tiger;
+            CREATE TABLE public.spatial_ref_sys (srid int NOT NULL, auth_name varchar(256), srtext varchar(2048));
+            ALTER TABLE ONLY public.spatial_ref_sys ADD CONSTRAINT spatial_ref_sys_pkey PRIMARY KEY (srid);
+            GRANT SELECT ON TABLE public.spatial_ref_sys TO PUBLIC;
+            CREATE TABLE tiger.addr (gid int, tlid bigint);
+            CREATE TABLE public.places (id int, geom public.geometry(Point,4326), area public.geography(Polygon,4326));
+            CREATE INDEX places_geom_idx ON public.places USING gist (geom);
+            "#,
+        )
+        .load()
+        .await?;
+        assert!(!remote.schemas.contains("tiger"));
+
+        let local = SqlLoader::new(
+            r#"
+            CREATE TABLE public.places (id int, geom public.geometry(Point, 4326), area public.geography(Polygon, 4326));
+            CREATE INDEX places_geom_idx ON public.places USING gist (geom);
+            "#,
+        )
+        .load()
+        .await?;
+        assert!(local.plan(&remote, false)?.is_empty());
+
+        let restored = SqlLoader::new(local.sql(true)).load().await?;
+        assert!(restored.plan(&local, false)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn postgis_names_should_be_kept_without_postgis() -> Result<()> {
+        let schema = SqlLoader::new(
+            r#"
+            CREATE TABLE public.spatial_ref_sys (srid int NOT NULL);
+            CREATE TABLE topology.layers (id int);
+            "#,
+        )
+        .parse()?;
+        assert!(schema.tables["public"].contains_key("spatial_ref_sys"));
+        assert!(schema.schemas.contains("topology"));
+        Ok(())
+    }
+
+    #[test]
+    fn plan_should_not_depend_on_the_order_of_the_statements() -> Result<()> {
+        let statements = [
+            "CREATE TYPE public.status AS ENUM ('active', 'inactive')",
+            "CREATE TABLE public.todos (id uuid PRIMARY KEY, title text NOT NULL, status public.status)",
+            "CREATE TABLE app.users (id uuid, name text)",
+            "CREATE INDEX todos_title_idx ON public.todos (title)",
+            "CREATE VIEW public.active_todos AS SELECT * FROM public.todos WHERE status = 'active'",
+            "CREATE FUNCTION app.greet(name text) RETURNS text AS $$ SELECT 'hi ' || name $$ LANGUAGE sql",
+            "GRANT SELECT ON public.todos TO readonly",
+            "GRANT INSERT, UPDATE ON public.todos TO writer",
+            "GRANT SELECT (name) ON app.users TO readonly",
+        ];
+        let remote = SqlLoader::new("CREATE TABLE public.legacy (id int)").parse()?;
+        let plan = |statements: &[&str]| -> Result<Vec<String>> {
+            SqlLoader::new(statements.join(";\n"))
+                .parse()?
+                .plan(&remote, false)
+        };
+
+        let expected = plan(&statements)?;
+        assert_eq!(plan(&statements)?, expected);
+        let mut reversed = statements;
+        reversed.reverse();
+        assert_eq!(plan(&reversed)?, expected);
+        let mut rotated = statements;
+        rotated.rotate_left(4);
+        assert_eq!(plan(&rotated)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn relations_moved_to_another_schema_should_be_altered() -> Result<()> {
+        let remote = SqlLoader::new(
+            r#"
+            CREATE TABLE app.todos (id int, title text);
+            CREATE INDEX todos_title_idx ON app.todos (title);
+            GRANT SELECT ON app.todos TO readonly;
+            "#,
+        )
+        .parse()?;
+        let local = SqlLoader::new(
+            r#"
+            CREATE TABLE billing.todos (id int, title text);
+            CREATE INDEX todos_title_idx ON billing.todos (title);
+            GRANT SELECT ON billing.todos TO readonly;
+            "#,
+        )
+        .parse()?;
+        assert_eq!(
+            local.plan(&remote, false)?,
+            vec![
+                "CREATE SCHEMA IF NOT EXISTS billing",
+                "ALTER TABLE app.todos SET SCHEMA billing",
+                "DROP SCHEMA app",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn moved_relation_should_keep_references_sharing_its_prefix() -> Result<()> {
+        let remote = SqlLoader::new(
+            r#"
+            CREATE TABLE app.todos (id int, archive_id int);
+            CREATE TABLE app.todos_archive (id int);
+            ALTER TABLE ONLY app.todos_archive ADD CONSTRAINT todos_archive_pkey PRIMARY KEY (id);
+            ALTER TABLE ONLY app.todos ADD CONSTRAINT todos_archive_fkey FOREIGN KEY (archive_id) REFERENCES app.todos_archive(id);
+            "#,
+        )
+        .parse()?;
+        let local = SqlLoader::new(
+            r#"
+            CREATE TABLE billing.todos (id int, archive_id int);
+            CREATE TABLE app.todos_archive (id int);
+            ALTER TABLE ONLY app.todos_archive ADD CONSTRAINT todos_archive_pkey PRIMARY KEY (id);
+            ALTER TABLE ONLY billing.todos ADD CONSTRAINT todos_archive_fkey FOREIGN KEY (archive_id) REFERENCES app.todos_archive(id);
+            "#,
+        )
+        .parse()?;
+        // `app.todos_archive` is not a reference to `app.todos`, so the constraint is unchanged
+        assert_eq!(
+            local.plan(&remote, false)?,
+            vec![
+                "CREATE SCHEMA IF NOT EXISTS billing",
+                "ALTER TABLE app.todos SET SCHEMA billing",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn confirmed_renames_should_be_planned() -> Result<()> {
+        let remote = SqlLoader::new(
+            r#"
+            CREATE TABLE public.todo (id int PRIMARY KEY, title text);
+            CREATE INDEX todo_title_idx ON public.todo (title);
+            "#,
+        )
+        .parse()?;
+        let local = SqlLoader::new(
+            r#"
+            CREATE TABLE public.todos (id int PRIMARY KEY, title text);
+            CREATE INDEX todo_title_idx ON public.todos (title);
+            "#,
+        )
+        .parse()?;
+        let (renames, renamed) = local.plan_renames(
+            &remote,
+            |old, new| {
+                assert_eq!((old, new), ("public.todo", "public.todos"));
+                true
+            },
+            &NoopObserver,
+        )?;
+        assert_eq!(renames, vec!["ALTER TABLE public.todo RENAME TO todos"]);
+        assert!(local.plan(&renamed.unwrap(), false)?.is_empty());
+
+        let (renames, renamed) = local.plan_renames(&remote, |_, _| false, &NoopObserver)?;
+        assert!(renames.is_empty() && renamed.is_none());
+
+        let config = RenovateDiffConfig {
+            idempotent: true,
+            ..Default::default()
+        };
+        let options = PlanOptions {
+            renames: Some(&|_, _| true),
+            ..Default::default()
+        };
+        assert_eq!(
+            local
+                .plan_with_options(&remote, &config, options, &NoopObserver)?
+                .0,
+            vec!["ALTER TABLE IF EXISTS public.todo RENAME TO todos"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn skipped_kinds_should_not_be_planned() -> Result<()> {
+        let mut remote = SqlLoader::new("CREATE TABLE public.todos (id int)").parse()?;
+        let mut local = SqlLoader::new(
+            r#"
+            CREATE TABLE public.todos (id int, title text);
+            CREATE FUNCTION public.one() RETURNS int AS $$ SELECT 1 $$ LANGUAGE sql;
+            GRANT SELECT ON public.todos TO readonly;
+            "#,
+        )
+        .parse()?;
+        let kinds = BTreeSet::from([ObjectKind::Privileges]);
+        local.retain_kinds(&kinds);
+        remote.retain_kinds(&kinds);
+        assert_eq!(
+            local.plan(&remote, false)?,
+            vec!["GRANT select ON public.todos TO readonly"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn schemas_out_of_scope_should_not_be_planned() -> Result<()> {
+        let mut remote = SqlLoader::new(
+            "CREATE TABLE billing.invoices (id int); CREATE TABLE public.legacy (id int);",
+        )
+        .parse()?;
+        let mut local = SqlLoader::new(
+            r#"
+            CREATE TABLE billing.invoices (id int, total numeric);
+            GRANT SELECT ON billing.invoices TO accountant;
+            CREATE TABLE public.todos (id int);
+            "#,
+        )
+        .parse()?;
+        let scope = BTreeSet::from(["billing".to_owned()]);
+        local.retain_schemas(&scope);
+        remote.retain_schemas(&scope);
+        assert_eq!(
+            local.plan(&remote, false)?,
+            vec![
+                "ALTER TABLE ONLY billing.invoices ADD COLUMN total numeric",
+                "GRANT select ON billing.invoices TO accountant",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn grep_should_find_the_usages_of_a_column() -> Result<()> {
+        let schema = SqlLoader::new(
+            r#"
+            CREATE TABLE public.todos (id int PRIMARY KEY, title text, valid bool);
+            CREATE INDEX todos_title_idx ON public.todos (title);
+            CREATE VIEW public.titles AS SELECT title FROM public.todos;
+            CREATE VIEW public.valid_todos AS SELECT id FROM public.todos WHERE valid;
+            "#,
+        )
+        .parse()?;
+        let config = RenovateOutputConfig::default();
+
+        let matches = schema.grep("TITLE", &config);
+        let found: Vec<_> = matches
+            .iter()
+            .map(|m| (m.type_name, m.id.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("table", "public.todos"),
+                ("index", "todos_title_idx"),
+                ("view", "public.titles")
+            ]
+        );
+        assert_eq!(
+            matches[2].file.as_deref(),
+            Some(std::path::Path::new("public/05_views.sql"))
+        );
+
+        // `id` is an identifier, so `valid` doesn't match
+        let found: Vec<_> = schema
+            .grep("id", &config)
+            .into_iter()
+            .map(|m| m.id)
+            .collect();
+        assert_eq!(found, vec!["public.todos", "public.valid_todos"]);
+        assert_eq!(schema.grep("where valid", &config).len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn ignored_objects_should_be_left_out_of_the_plan() -> Result<()> {
+        let local = SqlLoader::new(
+            "CREATE TABLE public.todos (id int); CREATE FUNCTION public.audit() RETURNS int AS $$ SELECT 1 $$ LANGUAGE sql;",
+        )
+        .parse()?;
+        let remote = SqlLoader::new(
+            "CREATE FUNCTION public.audit() RETURNS int AS $$ SELECT 2 $$ LANGUAGE sql;",
+        )
+        .parse()?;
+        let ignored = SqlLoader::new(
+            "CREATE FUNCTION public.audit() RETURNS int AS $$ SELECT 1 $$ LANGUAGE sql;",
+        )
+        .parse()?;
+        assert_eq!(ignored.object_names(), vec!["function public.audit"]);
+
+        let plan = local
+            .without(&ignored)
+            .plan(&remote.without(&ignored), false)?;
+        assert_eq!(plan.len(), 1);
+        assert!(plan[0].starts_with("CREATE TABLE public.todos"));
+        Ok(())
+    }
+
+    #[test]
+    fn unhandled_statements_should_be_reported_with_their_line() -> Result<()> {
+        let loader = SqlLoader::new(
+            "SET statement_timeout = 0;\nCREATE TABLE public.todos (id int);\n\n-- the domain of the emails\nCREATE DOMAIN public.email AS text;\nALTER FUNCTION public.audit() OWNER TO bob;\n",
+        );
+        assert_eq!(
+            loader.skipped_statements()?,
+            vec![
+                (
+                    5,
+                    DiagnosticKind::SkippedStatement,
+                    "CREATE DOMAIN public.email AS text;".to_owned()
+                ),
+                (
+                    6,
+                    DiagnosticKind::OwnershipIgnored,
+                    "ALTER FUNCTION public.audit() OWNER TO bob;".to_owned()
+                ),
+            ]
+        );
+
+        let (_, diagnostics) = loader.parse_with_diagnostics("public/types.sql")?;
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "warning: public/types.sql:5: not supported by renovate, skipped\n  CREATE DOMAIN public.email AS text;"
+        );
+        assert!(check_strict(&diagnostics).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn changed_objects_should_be_listed_by_kind() -> Result<()> {
+        let old = SqlLoader::new(
+            "CREATE TABLE public.todos (id int, title text); CREATE VIEW public.titles AS SELECT title FROM public.todos;",
+        )
+        .parse()?;
+        let new = SqlLoader::new(
+            "CREATE TABLE public.todos (id int, title text, done bool); CREATE INDEX todos_done_idx ON public.todos (done);",
+        )
+        .parse()?;
+        assert_eq!(
+            new.changed_objects(&old),
+            vec![
+                (ChangeKind::Changed, "table", "public.todos".to_owned()),
+                (ChangeKind::Removed, "view", "public.titles".to_owned()),
+                (ChangeKind::Added, "index", "todos_done_idx".to_owned()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn objects_not_loading_back_should_be_reported() -> Result<()> {
+        let mut schema = SqlLoader::new(
+            r#"
+            CREATE TABLE public.todos (id int PRIMARY KEY, title text);
+            CREATE INDEX todos_title_idx ON public.todos (title);
+            GRANT SELECT ON public.todos TO reader;
+            "#,
+        )
+        .parse()?;
+        assert_eq!(schema.unparsable_objects(), vec![]);
+        let local = schema.clone();
+
+        // an index stored under another name than its own, as if introspection misrepresented it
+        let indexes = schema
+            .table_indexes
+            .get_mut(&SchemaId::new("public", "todos"))
+            .unwrap();
+        let index = indexes.remove("todos_title_idx").unwrap();
+        indexes.insert("todos_title_part_idx".to_owned(), index);
+
+        let unparsable = schema.unparsable_objects();
+        assert_eq!(unparsable.len(), 1);
+        assert_eq!(unparsable[0].type_name, "index");
+        assert_eq!(unparsable[0].id, "todos_title_idx");
+
+        let kept = schema.with_local_unparsable(&local, &unparsable)?;
+        assert_eq!(kept.sql(true), local.sql(true));
+        Ok(())
+    }
 }
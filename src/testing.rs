@@ -0,0 +1,306 @@
+//! Helpers for application test suites: validate the declarative schema against a real database
+//! (requires the `db` feature), check that sql survives a parse → save → load round trip, and
+//! generate synthetic schemas for load tests and fuzzing (the `arb_*` proptest strategies require
+//! the `proptest-strategies` feature).
+//!
+//! ```no_run
+//! # async fn run() -> anyhow::Result<()> {
+//! let db = renovate::testing::apply_schema("db").await?;
+//! db.assert_no_drift().await?;
+//! renovate::testing::roundtrip("CREATE TABLE public.todos (id int, title text);").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(any(test, feature = "proptest-strategies"))]
+use crate::parser::Ident;
+#[cfg(feature = "db")]
+use crate::{
+    repo::TmpDb, DatabaseRepo, DatabaseSchema, LocalRepo, Pooler, SchemaLoader, SqlLoader,
+};
+use crate::{DiagnosticKind, MemoryStore, RenovateOutputConfig};
+#[cfg(feature = "db")]
+use anyhow::{bail, Context, Result};
+#[cfg(any(test, feature = "proptest-strategies"))]
+use proptest::{collection, prelude::*, sample};
+use std::fmt::Write;
+#[cfg(feature = "db")]
+use std::{env, path::Path};
+
+#[cfg(feature = "db")]
+/// env var for the admin url of the postgres server used by the tests
+pub const TEST_SERVER_URL_ENV: &str = "RENOVATE_TEST_SERVER_URL";
+#[cfg(feature = "db")]
+const DEFAULT_TEST_SERVER_URL: &str = "postgres://localhost:5432";
+
+/// A temporary database with the schema applied. It is dropped when the value goes out of scope.
+#[cfg(feature = "db")]
+#[derive(Debug)]
+pub struct TestDb {
+    db: TmpDb,
+    schema: DatabaseSchema,
+}
+
+/// Apply the schema in the given directory to a temporary database on the server given by
+/// `RENOVATE_TEST_SERVER_URL` (default `postgres://localhost:5432`).
+#[cfg(feature = "db")]
+pub async fn apply_schema(dir: impl AsRef<Path>) -> Result<TestDb> {
+    let server_url =
+        env::var(TEST_SERVER_URL_ENV).unwrap_or_else(|_| DEFAULT_TEST_SERVER_URL.to_owned());
+    TestDb::new(server_url, dir).await
+}
+
+#[cfg(feature = "db")]
+impl TestDb {
+    /// Apply the schema in the given directory to a temporary database on the given server.
+    pub async fn new(server_url: impl Into<String>, dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let sql = LocalRepo::new(dir).load_sql().await?;
+        let schema = SqlLoader::new(&sql).parse()?;
+        let db = TmpDb::new(server_url.into(), &sql, Pooler::None)
+            .await
+            .with_context(|| format!("Failed to apply schema in {}", dir.display()))?;
+        Ok(Self { db, schema })
+    }
+
+    /// url of the temporary database
+    pub fn url(&self) -> String {
+        self.db.url()
+    }
+
+    /// the schema parsed from the local sql files
+    pub fn schema(&self) -> &DatabaseSchema {
+        &self.schema
+    }
+
+    /// Fail if the schema in the database differs from the local sql files as renovate reads
+    /// them, e.g. some statements are not supported by renovate. The schema renovate parsed is
+    /// applied to another temporary database, so that both sides are normalized by postgres and
+    /// the casts or parentheses it adds don't count.
+    pub async fn assert_no_drift(&self) -> Result<()> {
+        let repo = DatabaseRepo::new_with(self.url());
+        let remote = repo.load().await?;
+        let local = repo.normalize(&self.schema.sql(true)).await?;
+        let plan = local.plan(&remote, false)?;
+        if !plan.is_empty() {
+            bail!(
+                "The schema in the database drifts from the local sql files:\n\n{}",
+                plan.join("\n")
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Generate a synthetic schema with `tables` tables in the public schema for load tests and
+/// benchmarks. Each table has a primary key, `indexes` indexes and a grant.
+pub fn generate_sql(tables: usize, indexes: usize) -> String {
+    let mut sql = String::new();
+    for i in 0..tables {
+        let name = format!("public.table_{}", i);
+        write!(
+            sql,
+            "CREATE TABLE {} (id bigint NOT NULL, name text, created_at timestamptz DEFAULT now()",
+            name
+        )
+        .unwrap();
+        for j in 0..indexes {
+            write!(sql, ", col_{} text", j).unwrap();
+        }
+        sql.push_str(");\n");
+        writeln!(
+            sql,
+            "ALTER TABLE ONLY {} ADD CONSTRAINT table_{}_pkey PRIMARY KEY (id);",
+            name, i
+        )
+        .unwrap();
+        for j in 0..indexes {
+            writeln!(
+                sql,
+                "CREATE INDEX table_{}_col_{}_idx ON {} USING btree (col_{});",
+                i, j, name, j
+            )
+            .unwrap();
+        }
+        writeln!(sql, "GRANT SELECT ON TABLE {} TO readonly;", name).unwrap();
+    }
+    sql
+}
+
+/// Parse the sql, save it to an in-memory store with the default output config, load it back and
+/// fail if the plan between the two, either way, isn't empty, i.e. saving and loading the schema
+/// changes it. The statements no NodeItem handles fail too, since they'd be lost silently.
+pub async fn roundtrip(sql: &str) -> anyhow::Result<()> {
+    let loader = crate::SqlLoader::new(sql);
+    let skipped: Vec<_> = loader
+        .skipped_statements()?
+        .into_iter()
+        .filter(|(_, kind, _)| *kind == DiagnosticKind::SkippedStatement)
+        .map(|(line, _, sql)| format!("  line {}: {}", line, sql))
+        .collect();
+    if !skipped.is_empty() {
+        anyhow::bail!(
+            "{} statement(s) are not supported by renovate:\n{}",
+            skipped.len(),
+            skipped.join("\n")
+        );
+    }
+
+    let schema = loader.parse()?;
+    let store = MemoryStore::new();
+    schema
+        .save_to(&store, &RenovateOutputConfig::default())
+        .await?;
+    let loaded = crate::SchemaLoader::load(&store).await?;
+    let mut plan = loaded.plan(&schema, false)?;
+    plan.extend(schema.plan(&loaded, false)?);
+    if !plan.is_empty() {
+        anyhow::bail!(
+            "The schema changes after being saved and loaded back:\n\n{}",
+            plan.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// A name of a table, column, index or role, not quoted, e.g. `todos` or `Todos`
+#[cfg(any(test, feature = "proptest-strategies"))]
+pub fn arb_ident() -> impl Strategy<Value = String> {
+    "[a-zA-Z_][a-zA-Z0-9_]{0,11}"
+}
+
+/// A column type
+#[cfg(any(test, feature = "proptest-strategies"))]
+pub fn arb_column_type() -> impl Strategy<Value = &'static str> {
+    sample::select(vec![
+        "integer",
+        "bigint",
+        "text",
+        "boolean",
+        "uuid",
+        "jsonb",
+        "timestamp with time zone",
+        "numeric(10,2)",
+    ])
+}
+
+/// `CREATE INDEX` of the given name on one of the columns of the table
+#[cfg(any(test, feature = "proptest-strategies"))]
+pub fn arb_index(
+    table: String,
+    name: String,
+    columns: Vec<String>,
+) -> impl Strategy<Value = String> {
+    (sample::select(columns), any::<bool>()).prop_map(move |(column, unique)| {
+        format!(
+            "CREATE {}INDEX {} ON public.{} USING btree ({});",
+            if unique { "UNIQUE " } else { "" },
+            Ident(&name),
+            Ident(&table),
+            Ident(&column)
+        )
+    })
+}
+
+/// `GRANT` of some privileges on the table to the role
+#[cfg(any(test, feature = "proptest-strategies"))]
+pub fn arb_grant(table: String, role: String) -> impl Strategy<Value = String> {
+    sample::subsequence(vec!["SELECT", "INSERT", "UPDATE", "DELETE"], 1..=4).prop_map(
+        move |privileges| {
+            format!(
+                "GRANT {} ON TABLE public.{} TO {};",
+                privileges.join(", "),
+                Ident(&table),
+                Ident(&role)
+            )
+        },
+    )
+}
+
+/// The table of the given name in the public schema, with 1 to 5 columns and the primary key on
+/// the first one, up to 2 indexes named `<table>_idx<n>` and grants to up to 2 roles
+#[cfg(any(test, feature = "proptest-strategies"))]
+pub fn arb_table(name: String) -> impl Strategy<Value = String> {
+    let columns = collection::btree_map(arb_ident(), arb_column_type(), 1..6);
+    let roles = collection::btree_set(arb_ident(), 0..3);
+    (columns, 0..3usize, roles).prop_flat_map(move |(columns, indexes, roles)| {
+        let names: Vec<_> = columns.keys().cloned().collect();
+        let mut sql = format!(
+            "CREATE TABLE public.{} ({});\n",
+            Ident(&name),
+            columns
+                .iter()
+                .map(|(column, ty)| format!("{} {}", Ident(column), ty))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        writeln!(
+            sql,
+            "ALTER TABLE ONLY public.{} ADD CONSTRAINT {} PRIMARY KEY ({});",
+            Ident(&name),
+            Ident(&format!("{}_pkey", name)),
+            Ident(&names[0])
+        )
+        .unwrap();
+
+        let indexes: Vec<_> = (0..indexes)
+            .map(|i| arb_index(name.clone(), format!("{}_idx{}", name, i), names.clone()))
+            .collect();
+        let grants: Vec<_> = roles
+            .into_iter()
+            .map(|role| arb_grant(name.clone(), role))
+            .collect();
+        (indexes, grants).prop_map(move |(indexes, grants)| {
+            let mut sql = sql.clone();
+            for stmt in indexes.iter().chain(&grants) {
+                writeln!(sql, "{}", stmt).unwrap();
+            }
+            sql
+        })
+    })
+}
+
+/// A schema of 1 to 4 tables of distinct names, see `arb_table`
+#[cfg(any(test, feature = "proptest-strategies"))]
+pub fn arb_schema() -> impl Strategy<Value = String> {
+    collection::btree_set(arb_ident(), 1..5).prop_flat_map(|names| {
+        names
+            .into_iter()
+            .map(arb_table)
+            .collect::<Vec<_>>()
+            .prop_map(|tables| tables.concat())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SqlLoader;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn generated_schema_should_roundtrip(sql in arb_schema()) {
+            let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            let result = rt.block_on(roundtrip(&sql));
+            prop_assert!(result.is_ok(), "{:?}\n{}", result, sql);
+        }
+    }
+
+    #[test]
+    fn generated_sql_should_parse() -> anyhow::Result<()> {
+        let schema = SqlLoader::new(generate_sql(10, 2)).parse()?;
+        assert_eq!(schema.tables["public"].len(), 10);
+        assert_eq!(
+            schema
+                .table_indexes
+                .values()
+                .map(|v| v.len())
+                .sum::<usize>(),
+            20
+        );
+        assert_eq!(schema.privileges.len(), 10);
+        Ok(())
+    }
+}
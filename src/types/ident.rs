@@ -0,0 +1,242 @@
+use crate::parser::{Ident, Literal};
+use std::fmt;
+
+/// reserved and type_func_name keywords, which can't be used as a bare identifier
+const KEYWORDS: &[&str] = &[
+    "all",
+    "analyse",
+    "analyze",
+    "and",
+    "any",
+    "array",
+    "as",
+    "asc",
+    "asymmetric",
+    "authorization",
+    "binary",
+    "both",
+    "case",
+    "cast",
+    "check",
+    "collate",
+    "collation",
+    "column",
+    "concurrently",
+    "constraint",
+    "create",
+    "cross",
+    "current_catalog",
+    "current_date",
+    "current_role",
+    "current_schema",
+    "current_time",
+    "current_timestamp",
+    "current_user",
+    "default",
+    "deferrable",
+    "desc",
+    "distinct",
+    "do",
+    "else",
+    "end",
+    "except",
+    "false",
+    "fetch",
+    "for",
+    "foreign",
+    "freeze",
+    "from",
+    "full",
+    "grant",
+    "group",
+    "having",
+    "ilike",
+    "in",
+    "initially",
+    "inner",
+    "intersect",
+    "into",
+    "is",
+    "isnull",
+    "join",
+    "lateral",
+    "leading",
+    "left",
+    "like",
+    "limit",
+    "localtime",
+    "localtimestamp",
+    "natural",
+    "not",
+    "notnull",
+    "null",
+    "offset",
+    "on",
+    "only",
+    "or",
+    "order",
+    "outer",
+    "overlaps",
+    "placing",
+    "primary",
+    "references",
+    "returning",
+    "right",
+    "select",
+    "session_user",
+    "similar",
+    "some",
+    "symmetric",
+    "system_user",
+    "table",
+    "tablesample",
+    "then",
+    "to",
+    "trailing",
+    "true",
+    "union",
+    "unique",
+    "user",
+    "using",
+    "variadic",
+    "verbose",
+    "when",
+    "where",
+    "window",
+    "with",
+];
+
+impl<'a> Ident<'a> {
+    /// whether the identifier has to be quoted to keep its case and not be taken as a keyword
+    pub fn needs_quote(&self) -> bool {
+        let mut chars = self.0.chars();
+        let valid = match chars.next() {
+            Some(c) if c.is_ascii_lowercase() || c == '_' => {
+                chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '$')
+            }
+            _ => false,
+        };
+        !valid || KEYWORDS.contains(&self.0)
+    }
+}
+
+impl<'a> fmt::Display for Ident<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.needs_quote() {
+            write!(f, "\"{}\"", self.0.replace('"', "\"\""))
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+impl<'a> fmt::Display for Literal<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}'", self.0.replace('\'', "''"))
+    }
+}
+
+/// The value of the string literal at the start of `s`, e.g. `'it''s'`, and the rest of `s` after
+/// it. None if `s` doesn't start with a complete literal.
+pub(crate) fn split_literal(s: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = s.strip_prefix('\'')?.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\'' if chars.peek().map(|(_, c)| *c) == Some('\'') => {
+                chars.next();
+                value.push('\'');
+            }
+            '\'' => return Some((value, &s[i + 2..])),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Split a qualified name like `public."User"` the way postgres reads it: quoted parts are
+/// taken verbatim and the bare ones are folded to lower case.
+pub(super) fn split_qualified(s: &str) -> Vec<String> {
+    let mut parts = vec![];
+    let mut part = String::new();
+    let mut chars = s.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                part.push('"');
+            }
+            '"' => quoted = !quoted,
+            '.' if !quoted => parts.push(std::mem::take(&mut part)),
+            c if quoted => part.push(c),
+            c => part.extend(c.to_lowercase()),
+        }
+    }
+    parts.push(part);
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pg_query::{protobuf::ResTarget, NodeEnum, NodeRef};
+    use proptest::prelude::*;
+
+    /// the target of `SELECT ...`, checking the sql is a single statement
+    fn select_target(sql: &str) -> ResTarget {
+        let parsed = pg_query::parse(sql).unwrap();
+        assert_eq!(parsed.protobuf.stmts.len(), 1, "{}", sql);
+        parsed
+            .protobuf
+            .nodes()
+            .into_iter()
+            .find_map(|(node, _, _)| match node {
+                NodeRef::ResTarget(t) => Some(t.clone()),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    proptest! {
+        #[test]
+        fn any_ident_should_be_read_back_as_is(s in "[^\\x00]{1,15}") {
+            let target = select_target(&format!("SELECT 1 AS {}", Ident(&s)));
+            prop_assert_eq!(target.name, s);
+        }
+
+        #[test]
+        fn any_literal_should_be_read_back_as_is(s in "[^\\x00]{0,15}") {
+            let sql = format!("SELECT {}", Literal(&s));
+            let value = match select_target(&sql).val.and_then(|v| v.node) {
+                Some(NodeEnum::AConst(c)) => match c.val.and_then(|v| v.node) {
+                    Some(NodeEnum::String(v)) => Some(v.str),
+                    _ => None,
+                },
+                _ => None,
+            };
+            prop_assert_eq!(value.as_deref(), Some(s.as_str()));
+            let rest = format!("{}, 1)", Literal(&s));
+            prop_assert_eq!(split_literal(&rest), Some((s, ", 1)")));
+        }
+    }
+
+    #[test]
+    fn ident_should_quote_when_needed() {
+        assert_eq!(Ident("users").to_string(), "users");
+        assert_eq!(Ident("_v2$").to_string(), "_v2$");
+        assert_eq!(Ident("User").to_string(), "\"User\"");
+        assert_eq!(Ident("order").to_string(), "\"order\"");
+        assert_eq!(Ident("first name").to_string(), "\"first name\"");
+        assert_eq!(Ident("2fa").to_string(), "\"2fa\"");
+        assert_eq!(Ident("a\"b").to_string(), "\"a\"\"b\"");
+    }
+
+    #[test]
+    fn qualified_name_should_split_like_postgres() {
+        assert_eq!(split_qualified("public.users"), vec!["public", "users"]);
+        assert_eq!(split_qualified("Public.Users"), vec!["public", "users"]);
+        assert_eq!(split_qualified("app.\"My.Table\""), vec!["app", "My.Table"]);
+        assert_eq!(split_qualified("\"a\"\"b\""), vec!["a\"b"]);
+    }
+}
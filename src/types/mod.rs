@@ -1,4 +1,7 @@
 mod differ;
+mod ident;
 mod node_delta;
 mod relation_id;
 mod schema_id;
+
+pub(crate) use ident::split_literal;
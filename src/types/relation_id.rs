@@ -1,4 +1,7 @@
-use crate::parser::{RelationId, SchemaId};
+use super::ident::split_qualified;
+use crate::parser::{Ident, RelationId, SchemaId};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
 
 impl RelationId {
     pub fn new(
@@ -19,3 +22,54 @@ impl RelationId {
         }
     }
 }
+
+impl FromStr for RelationId {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = split_qualified(s);
+        if parts.len() < 3 {
+            anyhow::bail!(
+                "not a qualified relation item (schema.relation.name): {}",
+                s
+            );
+        }
+        let name = parts.pop().unwrap_or_default();
+        Ok(Self::new_with(
+            SchemaId::new_with(&parts.iter().map(|p| p.as_str()).collect::<Vec<_>>()),
+            name,
+        ))
+    }
+}
+
+impl fmt::Display for RelationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.schema_id, Ident(&self.name))
+    }
+}
+
+// serialized as `schema.relation.name` like `SchemaId`
+impl Serialize for RelationId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for RelationId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relation_id_should_quote_and_round_trip() {
+        let id = RelationId::new("app", "User", "user_email_idx");
+        assert_eq!(id.to_string(), "app.\"User\".user_email_idx");
+        assert_eq!(id.to_string().parse::<RelationId>().unwrap(), id);
+        assert!("todos".parse::<RelationId>().is_err());
+    }
+}
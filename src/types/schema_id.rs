@@ -1,5 +1,7 @@
-use crate::parser::SchemaId;
+use super::ident::split_qualified;
+use crate::parser::{Ident, SchemaId};
 use pg_query::protobuf::RangeVar;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::{fmt, str::FromStr};
 
 impl SchemaId {
@@ -46,13 +48,44 @@ impl From<Option<&RangeVar>> for SchemaId {
 impl FromStr for SchemaId {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let parts: Vec<_> = s.split('.').collect();
-        Ok(Self::new_with(&parts))
+        let parts = split_qualified(s);
+        Ok(Self::new_with(
+            &parts.iter().map(|p| p.as_str()).collect::<Vec<_>>(),
+        ))
     }
 }
 
 impl fmt::Display for SchemaId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}.{}", self.schema, self.name)
+        write!(f, "{}.{}", Ident(&self.schema), Ident(&self.name))
+    }
+}
+
+// serialized as `schema.name` (quoted like in sql) so that it could be used as a map key in json
+impl Serialize for SchemaId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for SchemaId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema_id_should_quote_and_round_trip() {
+        let id = SchemaId::new("app", "User");
+        assert_eq!(id.to_string(), "app.\"User\"");
+        assert_eq!(id.to_string().parse::<SchemaId>().unwrap(), id);
+
+        let id: SchemaId = "Todos".parse().unwrap();
+        assert_eq!(id, SchemaId::new("public", "todos"));
     }
 }
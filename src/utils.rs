@@ -1,7 +1,14 @@
-use crate::{config::RenovateFormatConfig, NodeItem, RenovateConfig};
-use anyhow::{bail, Result};
+use crate::{
+    config::{DiffFormat, RenovateDiffRenderConfig, RenovateFormatConfig},
+    DiffHunk, DiffLine, DiffLineKind, NodeItem,
+};
+use anyhow::Result;
+#[cfg(feature = "console")]
 use console::{style, Style};
-use similar::{ChangeTag, TextDiff};
+#[cfg(not(feature = "console"))]
+use plain::{style, Style};
+use sha2::{Digest, Sha256};
+use similar::{ChangeTag, DiffTag, TextDiff};
 use std::{
     fmt::{self, Write},
     path::Path,
@@ -27,6 +34,24 @@ pub fn ignore_file(p: &Path, pat: &str) -> bool {
     })
 }
 
+/// hex sha256 of the data, for the hashes persisted or compared across renovate builds, e.g. the
+/// fingerprint of a saved plan
+pub(crate) fn sha256_hex(data: impl AsRef<[u8]>) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// checksum of a migration plan, recorded in the apply report and the commit of the apply
+pub(crate) fn plan_checksum(plan: &[String]) -> String {
+    sha256_hex(plan.join("\n"))
+}
+
+/// the sql of the item as formatted for the diffs, empty if there's no item
+pub(crate) fn formatted_sql<T: NodeItem>(item: Option<&T>) -> String {
+    let format = RenovateFormatConfig::default().into();
+    item.map(|item| sqlformat::format(&item.to_string(), &Default::default(), format))
+        .unwrap_or_default()
+}
+
 pub fn create_diff<T: NodeItem>(old: &T, new: &T) -> Result<String> {
     let format = RenovateFormatConfig::default().into();
 
@@ -54,33 +79,135 @@ pub fn create_diff_removed<T: NodeItem>(old: &T) -> Result<String> {
     diff_text(&old, &new)
 }
 
-pub(crate) async fn load_config() -> Result<RenovateConfig> {
+/// load renovate.yml for the `--db` and the `--env` of the command, with the credentials of the
+/// plan role resolved
+#[cfg(feature = "cli")]
+pub(crate) async fn load_config(args: &crate::commands::Args) -> Result<crate::RenovateConfig> {
+    let mut config = load_env_config(args).await?;
+    config.resolve_credentials().await?;
+    #[cfg(feature = "db")]
+    config.resolve_search_path().await;
+    Ok(config)
+}
+
+/// same as `load_config`, with the credentials of the apply role, for the commands which change
+/// the remote database
+#[cfg(feature = "cli")]
+pub(crate) async fn load_apply_config(
+    args: &crate::commands::Args,
+) -> Result<crate::RenovateConfig> {
+    let mut config = load_env_config(args).await?.for_apply();
+    config.resolve_credentials().await?;
+    #[cfg(feature = "db")]
+    config.resolve_search_path().await;
+    Ok(config)
+}
+
+#[cfg(feature = "cli")]
+async fn load_env_config(args: &crate::commands::Args) -> Result<crate::RenovateConfig> {
+    let config = load_db_config(args.db.as_deref()).await?;
+    match args.env.as_deref() {
+        Some(env) => config.for_env(env),
+        None => Ok(config),
+    }
+}
+
+/// load renovate.yml for the given database, or the top level one if None
+#[cfg(feature = "cli")]
+pub(crate) async fn load_db_config(db: Option<&str>) -> Result<crate::RenovateConfig> {
     let config_file = Path::new("renovate.yml");
     if !config_file.exists() {
-        bail!("config file renovate.yml not found in current directory");
+        anyhow::bail!("config file renovate.yml not found in current directory");
+    }
+    let config = crate::RenovateConfig::load(config_file).await?;
+    config.check_databases()?;
+    match db {
+        Some(db) => config.for_db(db),
+        None => Ok(config),
     }
-    let config = RenovateConfig::load(config_file).await?;
-    Ok(config)
 }
 
 /// generate the diff between two strings. TODO: this is just for console output for now
 pub(crate) fn diff_text(text1: &str, text2: &str) -> Result<String> {
+    render_diff(text1, text2, &RenovateDiffRenderConfig::default())
+}
+
+/// Render the diff between two strings in the configured format.
+pub(crate) fn render_diff(
+    text1: &str,
+    text2: &str,
+    config: &RenovateDiffRenderConfig,
+) -> Result<String> {
     let mut output = String::new();
-    let diff = TextDiff::from_lines(text1, text2);
+    match config.format {
+        DiffFormat::Unified => unified_diff(&line_diff(text1, text2, config), config, &mut output)?,
+        DiffFormat::Words => word_diff(text1, text2, config, &mut output)?,
+        DiffFormat::SideBySide => {
+            side_by_side_diff(&line_diff(text1, text2, config), config, &mut output)?
+        }
+    }
+    Ok(output)
+}
 
-    for (idx, group) in diff.grouped_ops(3).iter().enumerate() {
+/// The hunks of the line diff between two strings, e.g. for the JSON plan output
+pub(crate) fn diff_hunks(
+    text1: &str,
+    text2: &str,
+    config: &RenovateDiffRenderConfig,
+) -> Vec<DiffHunk> {
+    let diff = line_diff(text1, text2, config);
+    diff.grouped_ops(config.context)
+        .iter()
+        .filter_map(|group| {
+            let (first, last) = (group.first()?, group.last()?);
+            let (old, new) = (first.old_range().start, first.new_range().start);
+            let lines = group
+                .iter()
+                .flat_map(|op| diff.iter_changes(op))
+                .map(|change| DiffLine {
+                    kind: match change.tag() {
+                        ChangeTag::Delete => DiffLineKind::Removed,
+                        ChangeTag::Insert => DiffLineKind::Added,
+                        ChangeTag::Equal => DiffLineKind::Equal,
+                    },
+                    text: change.value().trim_end_matches('\n').to_owned(),
+                })
+                .collect();
+            Some(DiffHunk {
+                old_start: old + 1,
+                old_lines: last.old_range().end - old,
+                new_start: new + 1,
+                new_lines: last.new_range().end - new,
+                lines,
+            })
+        })
+        .collect()
+}
+
+fn line_diff<'a>(
+    text1: &'a str,
+    text2: &'a str,
+    config: &RenovateDiffRenderConfig,
+) -> TextDiff<'a, 'a, 'a, str> {
+    TextDiff::configure()
+        .algorithm(config.algorithm.into())
+        .diff_lines(text1, text2)
+}
+
+fn unified_diff(
+    diff: &TextDiff<str>,
+    config: &RenovateDiffRenderConfig,
+    output: &mut String,
+) -> Result<()> {
+    for (idx, group) in diff.grouped_ops(config.context).iter().enumerate() {
         if idx > 0 {
-            writeln!(&mut output, "{:-^1$}", "-", 80)?;
+            writeln!(output, "{:-^1$}", "-", 80)?;
         }
         for op in group {
             for change in diff.iter_inline_changes(op) {
-                let (sign, s) = match change.tag() {
-                    ChangeTag::Delete => ("-", Style::new().red()),
-                    ChangeTag::Insert => ("+", Style::new().green()),
-                    ChangeTag::Equal => (" ", Style::new().dim()),
-                };
+                let (sign, s) = change_style(change.tag());
                 write!(
-                    &mut output,
+                    output,
                     "{}{} |{}",
                     style(Line(change.old_index())).dim(),
                     style(Line(change.new_index())).dim(),
@@ -88,17 +215,153 @@ pub(crate) fn diff_text(text1: &str, text2: &str) -> Result<String> {
                 )?;
                 for (emphasized, value) in change.iter_strings_lossy() {
                     if emphasized {
-                        write!(&mut output, "{}", s.apply_to(value).underlined().on_black())?;
+                        write!(output, "{}", s.apply_to(value).underlined().on_black())?;
                     } else {
-                        write!(&mut output, "{}", s.apply_to(value))?;
+                        write!(output, "{}", s.apply_to(value))?;
                     }
                 }
                 if change.missing_newline() {
-                    writeln!(&mut output)?;
+                    writeln!(output)?;
                 }
             }
         }
     }
+    Ok(())
+}
 
-    Ok(output)
+/// the whole text, with the removed words as `[-word-]` and the added ones as `{+word+}`
+fn word_diff(
+    text1: &str,
+    text2: &str,
+    config: &RenovateDiffRenderConfig,
+    output: &mut String,
+) -> Result<()> {
+    let diff = TextDiff::configure()
+        .algorithm(config.algorithm.into())
+        .diff_words(text1, text2);
+    for change in diff.iter_all_changes() {
+        let (_, s) = change_style(change.tag());
+        match change.tag() {
+            ChangeTag::Delete => write!(output, "{}", s.apply_to(format!("[-{}-]", change)))?,
+            ChangeTag::Insert => write!(output, "{}", s.apply_to(format!("{{+{}+}}", change)))?,
+            ChangeTag::Equal => write!(output, "{}", change)?,
+        }
+    }
+    if !output.ends_with('\n') {
+        writeln!(output)?;
+    }
+    Ok(())
+}
+
+/// the old lines on the left and the new ones on the right, `config.width` columns in total
+fn side_by_side_diff(
+    diff: &TextDiff<str>,
+    config: &RenovateDiffRenderConfig,
+    output: &mut String,
+) -> Result<()> {
+    let column = config.width.saturating_sub(3) / 2;
+    let cell = |line: Option<&str>| -> String {
+        let line = line.unwrap_or_default().trim_end_matches('\n');
+        line.chars().take(column).collect()
+    };
+    let (old, new) = (diff.old_slices(), diff.new_slices());
+    for (idx, group) in diff.grouped_ops(config.context).iter().enumerate() {
+        if idx > 0 {
+            writeln!(output, "{:-^1$}", "-", config.width)?;
+        }
+        for op in group {
+            let (tag, old_range, new_range) = op.as_tag_tuple();
+            let rows = old_range.len().max(new_range.len());
+            for i in 0..rows {
+                let left = old_range.clone().nth(i).map(|n| old[n]);
+                let right = new_range.clone().nth(i).map(|n| new[n]);
+                let (marker, left_style, right_style) = match tag {
+                    DiffTag::Equal => (" ", Style::new().dim(), Style::new().dim()),
+                    _ => match (left, right) {
+                        (Some(_), Some(_)) => ("|", Style::new().red(), Style::new().green()),
+                        (Some(_), None) => ("<", Style::new().red(), Style::new()),
+                        _ => (">", Style::new(), Style::new().green()),
+                    },
+                };
+                writeln!(
+                    output,
+                    "{} {} {}",
+                    left_style.apply_to(format!("{:<1$}", cell(left), column)),
+                    marker,
+                    right_style.apply_to(cell(right))
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn change_style(tag: ChangeTag) -> (&'static str, Style) {
+    match tag {
+        ChangeTag::Delete => ("-", Style::new().red()),
+        ChangeTag::Insert => ("+", Style::new().green()),
+        ChangeTag::Equal => (" ", Style::new().dim()),
+    }
+}
+
+/// the styles of `console` without the `console` feature, which render the text as is like
+/// `console` does when the output isn't a terminal
+#[cfg(not(feature = "console"))]
+mod plain {
+    use std::fmt;
+
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct Style;
+
+    pub struct Styled<D>(D);
+
+    pub fn style<D>(value: D) -> Styled<D> {
+        Styled(value)
+    }
+
+    impl Style {
+        pub fn new() -> Self {
+            Self
+        }
+
+        pub fn apply_to<D>(&self, value: D) -> Styled<D> {
+            Styled(value)
+        }
+
+        pub fn red(self) -> Self {
+            self
+        }
+
+        pub fn green(self) -> Self {
+            self
+        }
+
+        pub fn dim(self) -> Self {
+            self
+        }
+    }
+
+    impl<D> Styled<D> {
+        pub fn dim(self) -> Self {
+            self
+        }
+
+        pub fn bold(self) -> Self {
+            self
+        }
+
+        pub fn underlined(self) -> Self {
+            self
+        }
+
+        pub fn on_black(self) -> Self {
+            self
+        }
+    }
+
+    impl<D: fmt::Display> fmt::Display for Styled<D> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.0.fmt(f)
+        }
+    }
 }
@@ -0,0 +1,8 @@
+use wasm_bindgen::prelude::*;
+
+/// Diff two sets of sql and return the migration plan as a json string.
+#[wasm_bindgen(js_name = diffSql)]
+pub fn diff_sql(old: &str, new: &str) -> Result<String, JsValue> {
+    let plan = crate::diff_sql(old, new).map_err(|e| JsValue::from_str(&format!("{:#}", e)))?;
+    serde_json::to_string(&plan).map_err(|e| JsValue::from_str(&e.to_string()))
+}